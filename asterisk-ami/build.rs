@@ -0,0 +1,20 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    ffi_header();
+}
+
+/// Writes the C header of the `ffi` module to `OUT_DIR`
+///
+/// The copy in `include/asterisk_ami.h` is checked against it by the tests of the module.
+#[cfg(feature = "ffi")]
+fn ffi_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+        .expect("Invalid cbindgen.toml");
+    cbindgen::generate_with_config(&dir, config)
+        .expect("Cannot generate the C header")
+        .write_to_file(format!("{}/asterisk_ami.h", out_dir));
+}
@@ -0,0 +1,108 @@
+#ifndef ASTERISK_AMI_H
+#define ASTERISK_AMI_H
+
+/* Generated from src/ffi.rs by the build with the `ffi` feature, do not edit */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * A connection to an Asterisk server
+ */
+typedef struct AmiConnection AmiConnection;
+
+/**
+ * Events delivered to a callback registered using `ami_subscribe`
+ */
+typedef struct AmiSubscription AmiSubscription;
+
+/**
+ * A line of a packet, both strings are NUL terminated
+ */
+typedef struct AmiTag {
+  const char *key;
+  const char *value;
+} AmiTag;
+
+/**
+ * A packet of `len` tags
+ */
+typedef struct AmiPacket {
+  const struct AmiTag *tags;
+  size_t len;
+} AmiPacket;
+
+/**
+ * Called with the packets of a response, `count` is 0 if the connection has been closed
+ */
+typedef void (*AmiResponseCallback)(void *user_data, const struct AmiPacket *packets, size_t count);
+
+/**
+ * Called with each event, `event` is NULL once the connection has been closed
+ */
+typedef void (*AmiEventCallback)(void *user_data, const struct AmiPacket *event);
+
+/**
+ * Connects to `address` (`host:port`), returns NULL if the connection fails
+ *
+ * # Safety
+ *
+ * `address` must be a NUL terminated string.
+ */
+struct AmiConnection *ami_connect(const char *address);
+
+/**
+ * Closes a connection returned by `ami_connect`
+ *
+ * Subscriptions of the connection stop receiving events and must still be freed using
+ * `ami_unsubscribe`. Callbacks may still be running when this returns, their user data has
+ * to stay valid until they are done.
+ *
+ * # Safety
+ *
+ * `connection` must have been returned by `ami_connect` and not yet been freed.
+ */
+void ami_free(struct AmiConnection *connection);
+
+/**
+ * Sends an action of `len` tags without waiting for the response, which is passed to
+ * `callback`
+ *
+ * An `ActionID` is added if the action does not contain one. Returns 0 if the action has
+ * been queued, and -1 if the arguments are invalid or `callback` is NULL.
+ *
+ * # Safety
+ *
+ * `connection` must be a live connection, `tags` must point to `len` tags of NUL
+ * terminated strings.
+ */
+int32_t ami_send(struct AmiConnection *connection,
+                 const struct AmiTag *tags,
+                 size_t len,
+                 AmiResponseCallback callback,
+                 void *user_data);
+
+/**
+ * Passes the events received from now on to `callback`, returns NULL if `connection` or
+ * `callback` is NULL
+ *
+ * # Safety
+ *
+ * `connection` must be a live connection.
+ */
+struct AmiSubscription *ami_subscribe(struct AmiConnection *connection,
+                                      AmiEventCallback callback,
+                                      void *user_data);
+
+/**
+ * Stops passing events to the callback of a subscription and frees it
+ *
+ * The callback may still be running when this returns.
+ *
+ * # Safety
+ *
+ * `subscription` must have been returned by `ami_subscribe` and not yet been freed.
+ */
+void ami_unsubscribe(struct AmiSubscription *subscription);
+
+#endif  /* ASTERISK_AMI_H */
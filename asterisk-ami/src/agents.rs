@@ -0,0 +1,186 @@
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::time::Duration;
+
+/// The status of an agent as reported by `Agents`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentStatus {
+    LoggedOff,
+    Idle,
+    OnCall,
+    Unknown,
+}
+
+impl AgentStatus {
+    /// Parses an agent status like `AGENT_IDLE`
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "AGENT_LOGGEDOFF" => AgentStatus::LoggedOff,
+            "AGENT_IDLE" => AgentStatus::Idle,
+            "AGENT_ONCALL" => AgentStatus::OnCall,
+            _ => AgentStatus::Unknown,
+        }
+    }
+}
+
+/// An agent as listed by `Agents`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Agent {
+    pub agent: String,
+    pub name: Option<String>,
+    pub status: AgentStatus,
+    /// The channel the agent is logged in with
+    pub channel: Option<String>,
+    /// The channel the agent is talking to
+    pub talking_to: Option<String>,
+    /// Unix timestamp of the agent's login
+    pub logged_in_time: Option<u64>,
+}
+
+impl Agent {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(Agent {
+            agent: find_tag(pkt, "Agent")?.clone(),
+            name: find_value(pkt, "Name"),
+            status: find_tag(pkt, "Status")
+                .map_or(AgentStatus::Unknown, |s| AgentStatus::parse(s)),
+            channel: find_value(pkt, "Channel"),
+            talking_to: find_value(pkt, "TalkingToChan").filter(|c| c != "n/a"),
+            logged_in_time: find_tag(pkt, "LoggedInTime")
+                .and_then(|t| t.trim().parse().ok())
+                .filter(|&t| t != 0),
+        })
+    }
+}
+
+/// Queue events concerning a call being offered to and handled by an agent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentEvent {
+    /// `AgentCalled`: the agent is being rung
+    Called {
+        queue: String,
+        interface: String,
+        member_name: Option<String>,
+        channel: Option<String>,
+    },
+    /// `AgentConnect`: the agent answered the call
+    Connect {
+        queue: String,
+        interface: String,
+        member_name: Option<String>,
+        channel: Option<String>,
+        hold_time: Duration,
+        ring_time: Duration,
+    },
+    /// `AgentComplete`: the call handled by the agent ended
+    Complete {
+        queue: String,
+        interface: String,
+        member_name: Option<String>,
+        channel: Option<String>,
+        hold_time: Duration,
+        talk_time: Duration,
+        /// Who hung up, `caller`, `agent`, or `transfer`
+        reason: Option<String>,
+    },
+}
+
+impl AgentEvent {
+    /// Parses an `AgentCalled`, `AgentConnect`, or `AgentComplete` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        let event = event_name(pkt)?.to_ascii_lowercase();
+        let queue = find_tag(pkt, "Queue")?.clone();
+        let interface = find_tag(pkt, "Interface")?.clone();
+        let member_name = find_value(pkt, "MemberName");
+        let channel = find_value(pkt, "Channel");
+        let seconds = |key| {
+            Duration::from_secs(
+                find_tag(pkt, key)
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0),
+            )
+        };
+        match event.as_str() {
+            "agentcalled" => Some(AgentEvent::Called {
+                queue,
+                interface,
+                member_name,
+                channel,
+            }),
+            "agentconnect" => Some(AgentEvent::Connect {
+                queue,
+                interface,
+                member_name,
+                channel,
+                hold_time: seconds("HoldTime"),
+                ring_time: seconds("RingTime"),
+            }),
+            "agentcomplete" => Some(AgentEvent::Complete {
+                queue,
+                interface,
+                member_name,
+                channel,
+                hold_time: seconds("HoldTime"),
+                talk_time: seconds("TalkTime"),
+                reason: find_value(pkt, "Reason"),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Lists all agents with their status
+    pub async fn agents(&self) -> Result<Vec<Agent>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "Agents")])
+            .await?;
+        Ok(list_items(&resp, "Agents")
+            .filter_map(Agent::from_list_item)
+            .collect())
+    }
+
+    /// Logs off an agent
+    ///
+    /// With `soft` set, the agent is logged off after finishing the current call.
+    pub async fn agent_logoff(
+        &self,
+        agent: &str,
+        soft: bool,
+    ) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "AgentLogoff"),
+            Tag::from("Agent", agent),
+            Tag::from("Soft", if soft { "true" } else { "false" }),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_agent_complete() {
+        let event = AgentEvent::from_event(&packet_from(&[
+            ("Event", "AgentComplete"),
+            ("Queue", "support"),
+            ("Interface", "Local/100@agents"),
+            ("MemberName", "Alice"),
+            ("HoldTime", "12"),
+            ("TalkTime", "95"),
+            ("Reason", "caller"),
+        ]));
+        match event {
+            Some(AgentEvent::Complete { talk_time, .. }) => {
+                assert_eq!(talk_time, Duration::from_secs(95))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{find_tag, system_name, AmiConnection, Packet};
+use log::{trace, warn};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Instant;
+
+/// An event received from one of the connections of an `Aggregator`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerEvent {
+    /// The name the connection has been added to the aggregator with, or the `SystemName`
+    pub server: String,
+    pub event: Packet,
+}
+
+/// The order in which an `Aggregator` hands out the events of its connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeOrder {
+    /// As soon as they have been received
+    Arrival,
+    /// Ordered by their `Timestamp` header, holding back each event for `window`
+    ///
+    /// Events received up to `window` after a later one are still handed out before it, so
+    /// the window should cover the differences in latency between the servers. The servers
+    /// need `timestampevents = yes` in `manager.conf` and synchronized clocks; events without
+    /// a `Timestamp` are ordered by the moment they have been received.
+    Timestamp { window: Duration },
+}
+
+/// Combines the events of connections to several servers into a single stream
+///
+/// This allows following calls that span servers, e.g. when Local channels or trunks hop
+/// between the nodes of a cluster.
+pub struct Aggregator {
+    events_rx: mpsc::Receiver<ServerEvent>,
+}
+
+impl Aggregator {
+    /// Starts receiving the events of `connections`, given as `(server name, connection)`
+    pub fn start<'a>(
+        connections: impl IntoIterator<Item = (&'a str, &'a AmiConnection)>,
+        order: MergeOrder,
+    ) -> Aggregator {
+        let connections = connections
+            .into_iter()
+            .map(|(server, connection)| (Some(server.to_string()), connection));
+        Self::spawn(connections, order)
+    }
+
+    /// Starts receiving the events of `connections`, naming the servers by the `SystemName`
+    /// of their events
+    ///
+    /// Events without a `SystemName` are attributed to the position of their connection, e.g.
+    /// `#1` for the first one, so `systemname` should be set on all servers.
+    pub fn start_by_system_name<'a>(
+        connections: impl IntoIterator<Item = &'a AmiConnection>,
+        order: MergeOrder,
+    ) -> Aggregator {
+        Self::spawn(connections.into_iter().map(|c| (None, c)), order)
+    }
+
+    fn spawn<'a>(
+        connections: impl Iterator<Item = (Option<String>, &'a AmiConnection)>,
+        order: MergeOrder,
+    ) -> Aggregator {
+        let (events_tx, events_rx) = mpsc::channel(32);
+        let received_tx = match order {
+            MergeOrder::Arrival => events_tx,
+            MergeOrder::Timestamp { window } => {
+                let (received_tx, received_rx) = mpsc::channel(32);
+                tokio::spawn(merge(received_rx, events_tx, window));
+                received_tx
+            }
+        };
+        for (n, (server, connection)) in connections.enumerate() {
+            let events = connection.events();
+            let label = Label {
+                server,
+                position: format!("#{}", n + 1),
+            };
+            tokio::spawn(forward(label, events, received_tx.clone()));
+        }
+        Aggregator { events_rx }
+    }
+
+    /// Waits for the next event of any of the connections
+    ///
+    /// Returns `None` once all connections have been closed and their events handed out.
+    pub async fn next_event(&mut self) -> Option<ServerEvent> {
+        self.events_rx.recv().await
+    }
+}
+
+/// How the events of a connection are attributed to their server
+struct Label {
+    /// The name given explicitly, otherwise the `SystemName` of the events is used
+    server: Option<String>,
+    /// The name used for events without a `SystemName`
+    position: String,
+}
+
+impl Label {
+    fn of(&self, event: &Packet) -> String {
+        match &self.server {
+            Some(server) => server.clone(),
+            None => system_name(event).unwrap_or(&self.position).clone(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.server.as_deref().unwrap_or(&self.position)
+    }
+}
+
+/// Passes the events of a single connection on to the aggregator
+async fn forward(
+    label: Label,
+    mut events: broadcast::Receiver<Option<Packet>>,
+    events_tx: mpsc::Sender<ServerEvent>,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => event,
+            _ = events_tx.closed() => break,
+        };
+        let event = match event {
+            Ok(Some(event)) => event,
+            Ok(None) | Err(RecvError::Closed) => break,
+            Err(RecvError::Lagged(n)) => {
+                warn!("Aggregator missed {} events of {}", n, label.name());
+                crate::metrics::events_lagged(n);
+                continue;
+            }
+        };
+        let event = ServerEvent {
+            server: label.of(&event),
+            event,
+        };
+        if events_tx.send(event).await.is_err() {
+            break;
+        }
+    }
+    trace!("Aggregator stopped receiving events of {}", label.name());
+}
+
+/// An event held back by `merge`, ordered by its timestamp and then by its arrival
+struct Pending {
+    order: (Duration, u64),
+    release_at: Instant,
+    event: ServerEvent,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.order == other.order
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order.cmp(&other.order)
+    }
+}
+
+/// Parses a `Timestamp` header like `1700000000.123456` as the time since the epoch
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let nanos = format!("{:0<9}", fraction).get(..9)?.parse().ok()?;
+    Some(Duration::new(seconds.parse().ok()?, nanos))
+}
+
+/// Hands out the received events ordered by their timestamps, holding each one for `window`
+async fn merge(
+    mut received_rx: mpsc::Receiver<ServerEvent>,
+    events_tx: mpsc::Sender<ServerEvent>,
+    window: Duration,
+) {
+    let mut pending = BinaryHeap::<Reverse<Pending>>::new();
+    let mut received = 0;
+    let mut open = true;
+    loop {
+        while let Some(Reverse(next)) = pending.peek() {
+            if next.release_at > Instant::now() {
+                break;
+            }
+            let Reverse(next) = pending.pop().unwrap();
+            if events_tx.send(next.event).await.is_err() {
+                return;
+            }
+        }
+        let release_at = pending.peek().map(|Reverse(next)| next.release_at);
+        if release_at.is_none() && !open {
+            break;
+        }
+        tokio::select! {
+            event = received_rx.recv(), if open => match event {
+                Some(event) => {
+                    let timestamp = find_tag(&event.event, "Timestamp")
+                        .and_then(|t| parse_timestamp(t))
+                        .unwrap_or_else(|| {
+                            SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                        });
+                    received += 1;
+                    pending.push(Reverse(Pending {
+                        order: (timestamp, received),
+                        release_at: Instant::now() + window,
+                        event,
+                    }));
+                }
+                None => open = false,
+            },
+            _ = tokio::time::sleep_until(
+                release_at.unwrap_or_else(Instant::now)
+            ), if release_at.is_some() => {}
+        }
+    }
+    trace!("Aggregator stopped merging events");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(
+            parse_timestamp("1700000000.123456"),
+            Some(Duration::new(1_700_000_000, 123_456_000))
+        );
+        assert_eq!(parse_timestamp("42"), Some(Duration::from_secs(42)));
+        assert_eq!(parse_timestamp("now"), None);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn orders_events_by_timestamp() {
+        use crate::packet_from as event;
+        let (a, mut server_a) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (b, mut server_b) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut aggregator = Aggregator::start(
+            vec![("a", &a), ("b", &b)],
+            MergeOrder::Timestamp {
+                window: Duration::from_millis(100),
+            },
+        );
+        let dial = |timestamp| {
+            event(&[("Event", "DialBegin"), ("Timestamp", timestamp)])
+        };
+        server_a
+            .send_packet(&dial("1700000000.200000"))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        server_b
+            .send_packet(&dial("1700000000.100000"))
+            .await
+            .unwrap();
+
+        let first = aggregator.next_event().await.unwrap();
+        let second = aggregator.next_event().await.unwrap();
+        assert_eq!((first.server.as_str(), second.server.as_str()), ("b", "a"));
+
+        drop((server_a, server_b));
+        assert_eq!(aggregator.next_event().await, None);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn labels_events_by_system_name() {
+        use crate::packet_from as event;
+        let (a, mut server_a) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (b, mut server_b) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut aggregator =
+            Aggregator::start_by_system_name(vec![&a, &b], MergeOrder::Arrival);
+        let named = event(&[("Event", "FullyBooted"), ("SystemName", "pbx-a")]);
+        server_a.send_packet(&named).await.unwrap();
+        let received = aggregator.next_event().await.unwrap();
+        assert_eq!(received.server, "pbx-a");
+        assert!(crate::is_from_system(&received.event, "pbx-a"));
+
+        let unnamed = event(&[("Event", "FullyBooted")]);
+        server_b.send_packet(&unnamed).await.unwrap();
+        assert_eq!(aggregator.next_event().await.unwrap().server, "#2");
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{
+    event_name, find_tag, find_value, AmiConnection, Error, Packet, Tag,
+};
+
+/// Whether an AOC message is sent during (AOC-D) or at the end of a call (AOC-E)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AocType {
+    During,
+    End,
+}
+
+/// The multiplier of a currency amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AocMultiplier {
+    OneThousandth,
+    OneHundredth,
+    OneTenth,
+    One,
+    Ten,
+    Hundred,
+    Thousand,
+}
+
+impl AocMultiplier {
+    /// Parses a multiplier as sent in AOC events, e.g. `1/100`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "1/1000" => Some(AocMultiplier::OneThousandth),
+            "1/100" => Some(AocMultiplier::OneHundredth),
+            "1/10" => Some(AocMultiplier::OneTenth),
+            "1" => Some(AocMultiplier::One),
+            "10" => Some(AocMultiplier::Ten),
+            "100" => Some(AocMultiplier::Hundred),
+            "1000" => Some(AocMultiplier::Thousand),
+            _ => None,
+        }
+    }
+
+    /// The factor an amount has to be multiplied with
+    pub fn factor(&self) -> f64 {
+        match self {
+            AocMultiplier::OneThousandth => 0.001,
+            AocMultiplier::OneHundredth => 0.01,
+            AocMultiplier::OneTenth => 0.1,
+            AocMultiplier::One => 1.0,
+            AocMultiplier::Ten => 10.0,
+            AocMultiplier::Hundred => 100.0,
+            AocMultiplier::Thousand => 1000.0,
+        }
+    }
+
+    fn action_value(&self) -> &'static str {
+        match self {
+            AocMultiplier::OneThousandth => "OneThousandth",
+            AocMultiplier::OneHundredth => "OneHundredth",
+            AocMultiplier::OneTenth => "OneTenth",
+            AocMultiplier::One => "One",
+            AocMultiplier::Ten => "Ten",
+            AocMultiplier::Hundred => "Hundred",
+            AocMultiplier::Thousand => "Thousand",
+        }
+    }
+}
+
+/// The charge reported by or sent in an AOC message
+#[derive(Debug, Clone, PartialEq)]
+pub enum AocCharge {
+    NotAvailable,
+    Free,
+    Currency {
+        name: String,
+        amount: u32,
+        multiplier: AocMultiplier,
+    },
+    /// Charging units as `(number of units, unit type)` pairs
+    Units(Vec<(u32, Option<u32>)>),
+}
+
+impl AocCharge {
+    /// The charged amount in the currency, if the charge is given in a currency
+    pub fn currency_value(&self) -> Option<f64> {
+        match self {
+            AocCharge::Currency {
+                amount, multiplier, ..
+            } => Some(*amount as f64 * multiplier.factor()),
+            _ => None,
+        }
+    }
+}
+
+/// A charge record parsed from an `AOC-D` or `AOC-E` event
+#[derive(Debug, Clone, PartialEq)]
+pub struct AocRecord {
+    pub aoc_type: AocType,
+    pub channel: String,
+    pub unique_id: Option<String>,
+    pub charge: AocCharge,
+    pub billing_id: Option<String>,
+    /// `Total` or `SubTotal` for AOC-D messages
+    pub total_type: Option<String>,
+}
+
+impl AocRecord {
+    /// Parses an `AOC-D` or `AOC-E` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        let event = event_name(pkt)?;
+        let aoc_type = if event.eq_ignore_ascii_case("AOC-D") {
+            AocType::During
+        } else if event.eq_ignore_ascii_case("AOC-E") {
+            AocType::End
+        } else {
+            return None;
+        };
+        let number = |key: &str| {
+            find_tag(pkt, key).and_then(|v| v.trim().parse::<u32>().ok())
+        };
+        let charge =
+            match find_tag(pkt, "ChargeType")?.to_ascii_lowercase().as_str() {
+                "free" => AocCharge::Free,
+                "currency" => AocCharge::Currency {
+                    name: find_value(pkt, "Currency").unwrap_or_default(),
+                    amount: number("Currency/Amount/Cost").unwrap_or(0),
+                    multiplier: find_tag(pkt, "Currency/Amount/Multiplier")
+                        .and_then(|m| AocMultiplier::parse(m))
+                        .unwrap_or(AocMultiplier::One),
+                },
+                "units" | "unit" => AocCharge::Units(
+                    (0..number("Units/NumberItems").unwrap_or(0))
+                        .filter_map(|i| {
+                            let item = format!("Units/Item({})", i);
+                            Some((
+                                number(&format!("{}/NumberOf", item))?,
+                                number(&format!("{}/TypeOf", item)),
+                            ))
+                        })
+                        .collect(),
+                ),
+                _ => AocCharge::NotAvailable,
+            };
+        Some(AocRecord {
+            aoc_type,
+            channel: find_tag(pkt, "Channel")?.clone(),
+            unique_id: find_value(pkt, "Uniqueid"),
+            charge,
+            billing_id: find_value(pkt, "BillingID"),
+            total_type: find_value(pkt, "TotalType"),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Sends an AOC-D or AOC-E message on a channel
+    ///
+    /// Unit charges need at least one unit entry, each one is sent as `UnitAmount(n)` and
+    /// `UnitType(n)`.
+    pub async fn aoc_message(
+        &self,
+        channel: &str,
+        aoc_type: AocType,
+        charge: &AocCharge,
+        billing_id: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut pkt = vec![
+            Tag::from("Action", "AOCMessage"),
+            Tag::from("Channel", channel),
+            Tag::from(
+                "MsgType",
+                match aoc_type {
+                    AocType::During => "D",
+                    AocType::End => "E",
+                },
+            ),
+        ];
+        match charge {
+            AocCharge::NotAvailable => pkt.push(Tag::from("ChargeType", "NA")),
+            AocCharge::Free => pkt.push(Tag::from("ChargeType", "FREE")),
+            AocCharge::Currency {
+                name,
+                amount,
+                multiplier,
+            } => {
+                pkt.push(Tag::from("ChargeType", "Currency"));
+                pkt.push(Tag::from("CurrencyName", name));
+                pkt.push(Tag::of(
+                    "CurrencyAmount".to_string(),
+                    amount.to_string(),
+                ));
+                pkt.push(Tag::from(
+                    "CurrencyMultiplier",
+                    multiplier.action_value(),
+                ));
+            }
+            AocCharge::Units(units) => {
+                if units.is_empty() {
+                    return Err(Error::InvalidArgument(
+                        "no charging units given".to_string(),
+                    ));
+                }
+                pkt.push(Tag::from("ChargeType", "Unit"));
+                for (n, (amount, unit_type)) in units.iter().enumerate() {
+                    pkt.push(Tag::of(
+                        format!("UnitAmount({})", n),
+                        amount.to_string(),
+                    ));
+                    if let Some(unit_type) = unit_type {
+                        pkt.push(Tag::of(
+                            format!("UnitType({})", n),
+                            unit_type.to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        if let Some(billing_id) = billing_id {
+            pkt.push(Tag::from("AOCBillingId", billing_id));
+        }
+        self.send_action(pkt).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_currency_charge() {
+        let record = AocRecord::from_event(&packet_from(&[
+            ("Event", "AOC-E"),
+            ("Channel", "DAHDI/1-1"),
+            ("ChargeType", "Currency"),
+            ("BillingID", "Normal"),
+            ("Currency", "EUR"),
+            ("Currency/Amount/Cost", "153"),
+            ("Currency/Amount/Multiplier", "1/100"),
+        ]))
+        .unwrap();
+        assert_eq!(record.aoc_type, AocType::End);
+        let value = record.charge.currency_value().unwrap();
+        assert!((value - 1.53).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_unit_charge() {
+        let record = AocRecord::from_event(&packet_from(&[
+            ("Event", "AOC-D"),
+            ("Channel", "DAHDI/1-1"),
+            ("ChargeType", "Units"),
+            ("Units/NumberItems", "1"),
+            ("Units/Item(0)/NumberOf", "4"),
+            ("Units/Item(0)/TypeOf", "1"),
+        ]))
+        .unwrap();
+        assert_eq!(record.charge, AocCharge::Units(vec![(4, Some(1))]));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn sends_all_unit_entries() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let charge = AocCharge::Units(vec![(4, Some(1)), (2, None)]);
+        let send =
+            connection.aoc_message("DAHDI/1-1", AocType::During, &charge, None);
+        let (sent, action) = tokio::join!(send, async {
+            let action = server.read_packet().await.unwrap();
+            let resp = packet_from(&[
+                ("Response", "Success"),
+                ("ActionID", find_tag(&action, "ActionID").unwrap()),
+            ]);
+            server.send_packet(&resp).await.unwrap();
+            action
+        });
+        sent.unwrap();
+        let tag = |key| find_tag(&action, key).map(String::as_str);
+        assert_eq!(tag("UnitAmount(0)"), Some("4"));
+        assert_eq!(tag("UnitType(0)"), Some("1"));
+        assert_eq!(tag("UnitAmount(1)"), Some("2"));
+        assert_eq!(tag("UnitType(1)"), None);
+    }
+}
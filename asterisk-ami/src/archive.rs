@@ -0,0 +1,307 @@
+//! Archiving events and call records to a SQLite database
+//!
+//! The database is created on first use with the following schema:
+//!
+//! ```sql
+//! -- the events selected when starting the Archiver
+//! CREATE TABLE events (
+//!     id INTEGER PRIMARY KEY,
+//!     received_at REAL NOT NULL,  -- seconds since the epoch
+//!     event TEXT NOT NULL,
+//!     channel TEXT,
+//!     uniqueid TEXT,
+//!     linkedid TEXT,
+//!     headers TEXT NOT NULL       -- all headers as "Key: Value" lines
+//! );
+//! -- one row per Cdr event, i.e. per finished call leg
+//! CREATE TABLE calls (
+//!     uniqueid TEXT PRIMARY KEY,
+//!     received_at REAL NOT NULL,
+//!     source TEXT,
+//!     destination TEXT,
+//!     context TEXT,
+//!     caller_id TEXT,
+//!     channel TEXT,
+//!     destination_channel TEXT,
+//!     start_time TEXT,            -- as sent by Asterisk, in its local time zone
+//!     answer_time TEXT,
+//!     end_time TEXT,
+//!     duration INTEGER,           -- seconds from start to end
+//!     billable_seconds INTEGER,   -- seconds from answer to end
+//!     disposition TEXT,           -- e.g. ANSWERED, NO ANSWER, BUSY, FAILED
+//!     account_code TEXT,
+//!     user_field TEXT
+//! );
+//! ```
+//!
+//! Both tables are indexed by `received_at`. With a retention period set, older rows are
+//! deleted when the archiver starts and once an hour afterwards.
+
+use crate::{
+    event_name, find_tag, find_value, packet_to_string, AmiConnection, Packet,
+};
+use log::warn;
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        received_at REAL NOT NULL,
+        event TEXT NOT NULL,
+        channel TEXT,
+        uniqueid TEXT,
+        linkedid TEXT,
+        headers TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS events_received_at ON events (received_at);
+    CREATE INDEX IF NOT EXISTS events_linkedid ON events (linkedid);
+    CREATE TABLE IF NOT EXISTS calls (
+        uniqueid TEXT PRIMARY KEY,
+        received_at REAL NOT NULL,
+        source TEXT,
+        destination TEXT,
+        context TEXT,
+        caller_id TEXT,
+        channel TEXT,
+        destination_channel TEXT,
+        start_time TEXT,
+        answer_time TEXT,
+        end_time TEXT,
+        duration INTEGER,
+        billable_seconds INTEGER,
+        disposition TEXT,
+        account_code TEXT,
+        user_field TEXT
+    );
+    CREATE INDEX IF NOT EXISTS calls_received_at ON calls (received_at);
+";
+
+/// How often rows past the retention period are deleted
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(3600);
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// A SQLite database holding archived events and call records
+pub struct Archive {
+    db: Connection,
+    retention: Option<Duration>,
+}
+
+impl Archive {
+    /// Opens the database at `path`, creating it and its tables if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Archive> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Opens a database that only exists in memory, e.g. for tests
+    pub fn open_in_memory() -> rusqlite::Result<Archive> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(db: Connection) -> rusqlite::Result<Archive> {
+        db.execute_batch(SCHEMA)?;
+        Ok(Archive {
+            db,
+            retention: None,
+        })
+    }
+
+    /// Keeps rows only for the given time after they have been received
+    pub fn retention(mut self, retention: Duration) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// The underlying database connection, to query the archive
+    pub fn db(&self) -> &Connection {
+        &self.db
+    }
+
+    /// Writes an event to the `events` table
+    pub fn insert_event(&self, pkt: &Packet) -> rusqlite::Result<()> {
+        self.db.execute(
+            "INSERT INTO events (received_at, event, channel, uniqueid, linkedid, headers)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                now(),
+                event_name(pkt).map(String::as_str).unwrap_or_default(),
+                find_value(pkt, "Channel"),
+                find_value(pkt, "Uniqueid"),
+                find_value(pkt, "Linkedid"),
+                packet_to_string(pkt),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Writes a `Cdr` event to the `calls` table, returns `false` for other packets
+    pub fn insert_call(&self, pkt: &Packet) -> rusqlite::Result<bool> {
+        if !event_name(pkt).is_some_and(|e| e.eq_ignore_ascii_case("Cdr")) {
+            return Ok(false);
+        }
+        let unique_id = match find_value(pkt, "UniqueID") {
+            Some(unique_id) => unique_id,
+            None => return Ok(false),
+        };
+        let seconds =
+            |key| find_tag(pkt, key).and_then(|v| v.trim().parse::<i64>().ok());
+        self.db.execute(
+            "INSERT OR REPLACE INTO calls (uniqueid, received_at, source, destination, context,
+                 caller_id, channel, destination_channel, start_time, answer_time, end_time,
+                 duration, billable_seconds, disposition, account_code, user_field)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            params![
+                unique_id,
+                now(),
+                find_value(pkt, "Source"),
+                find_value(pkt, "Destination"),
+                find_value(pkt, "DestinationContext"),
+                find_value(pkt, "CallerID"),
+                find_value(pkt, "Channel"),
+                find_value(pkt, "DestinationChannel"),
+                find_value(pkt, "StartTime"),
+                find_value(pkt, "AnswerTime"),
+                find_value(pkt, "EndTime"),
+                seconds("Duration"),
+                seconds("BillableSeconds"),
+                find_value(pkt, "Disposition"),
+                find_value(pkt, "AccountCode"),
+                find_value(pkt, "UserField"),
+            ],
+        )?;
+        Ok(true)
+    }
+
+    /// Deletes the rows past the retention period, returns the number of rows deleted
+    pub fn expire(&self) -> rusqlite::Result<usize> {
+        let retention = match self.retention {
+            Some(retention) => retention,
+            None => return Ok(0),
+        };
+        let before = now() - retention.as_secs_f64();
+        let events = self
+            .db
+            .execute("DELETE FROM events WHERE received_at < ?1", [before])?;
+        let calls = self
+            .db
+            .execute("DELETE FROM calls WHERE received_at < ?1", [before])?;
+        Ok(events + calls)
+    }
+}
+
+/// Writes the events of a connection to an `Archive`
+///
+/// Every `Cdr` event is written to the `calls` table; the events `select` returns `true` for
+/// are written to the `events` table as well, e.g. those matched by a filter expression.
+pub struct Archiver {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<rusqlite::Result<()>>,
+}
+
+impl Archiver {
+    /// Starts archiving the events of `connection`
+    pub fn start<F>(
+        connection: &AmiConnection,
+        archive: Archive,
+        select: F,
+    ) -> Archiver
+    where
+        F: Fn(&Packet) -> bool + Send + 'static,
+    {
+        let mut events = connection.events();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            let mut expire = tokio::time::interval(EXPIRE_INTERVAL);
+            loop {
+                let pkt = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    _ = expire.tick() => {
+                        archive.expire()?;
+                        continue;
+                    }
+                    event = events.recv() => match event {
+                        Ok(Some(pkt)) => pkt,
+                        Ok(None) | Err(RecvError::Closed) => break,
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("Archiver missed {} events", n);
+                            crate::metrics::events_lagged(n);
+                            continue;
+                        }
+                    },
+                };
+                archive.insert_call(&pkt)?;
+                if select(&pkt) {
+                    archive.insert_event(&pkt)?;
+                }
+            }
+            Ok(())
+        });
+        Archiver { stop_tx, task }
+    }
+
+    /// Stops archiving, reporting the error that stopped it early if there was one
+    pub async fn stop(self) -> rusqlite::Result<()> {
+        let _ = self.stop_tx.send(());
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn archives_events_and_calls() {
+        let archive = Archive::open_in_memory().unwrap();
+        let cdr = event(&[
+            ("Event", "Cdr"),
+            ("Source", "100"),
+            ("Destination", "200"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("Duration", "42"),
+            ("BillableSeconds", "40"),
+            ("Disposition", "ANSWERED"),
+            ("UniqueID", "1621.1"),
+        ]);
+        assert!(archive.insert_call(&cdr).unwrap());
+        assert!(!archive.insert_call(&event(&[("Event", "Hangup")])).unwrap());
+        archive.insert_event(&cdr).unwrap();
+
+        let (destination, billable): (String, i64) = archive
+            .db()
+            .query_row(
+                "SELECT destination, billable_seconds FROM calls WHERE uniqueid = ?1",
+                ["1621.1"],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((destination.as_str(), billable), ("200", 40));
+        let channel: String = archive
+            .db()
+            .query_row(
+                "SELECT channel FROM events WHERE event = 'Cdr'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(channel, "PJSIP/100-00000001");
+
+        let archive = archive.retention(Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(archive.expire().unwrap(), 2);
+    }
+}
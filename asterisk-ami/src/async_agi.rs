@@ -0,0 +1,321 @@
+use crate::{event_name, find_tag, AmiConnection, Error, Packet, Tag};
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// The result of an AGI command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgiResult {
+    /// The status code, `200` on success
+    pub code: u16,
+    /// The value of `result=`
+    pub result: Option<String>,
+    /// Additional data given in parentheses, e.g. `timeout`
+    pub data: Option<String>,
+    /// The complete result line
+    pub raw: String,
+}
+
+impl AgiResult {
+    /// Parses an AGI result line like `200 result=1 (timeout) endpos=1234`
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let (code, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let result = rest
+            .split_whitespace()
+            .find_map(|w| w.strip_prefix("result="))
+            .map(String::from);
+        let data = rest
+            .split_once('(')
+            .and_then(|(_, d)| d.split_once(')'))
+            .map(|(d, _)| d.to_string());
+        Some(AgiResult {
+            code: code.parse().ok()?,
+            result,
+            data,
+            raw: line.to_string(),
+        })
+    }
+
+    /// Checks if the command has been executed successfully
+    pub fn is_success(&self) -> bool {
+        self.code == 200
+    }
+}
+
+type PendingCommands =
+    HashMap<String, (String, oneshot::Sender<Result<AgiResult, Error>>)>;
+
+/// The commands waiting for their result, `None` once the dispatcher has stopped
+type SharedPending = Arc<Mutex<Option<PendingCommands>>>;
+
+/// A channel that entered the `AGI(agi:async)` dialplan application
+///
+/// Commands are executed using `exec`. The handle stays usable until the channel leaves
+/// AsyncAGI, afterwards `exec` fails with `Error::Hangup`. Once the connection has been closed
+/// or the `AsyncAgi` has been dropped, it fails with `Error::ConnectionClosed`.
+pub struct AgiChannel {
+    connection: AmiConnection,
+    channel: String,
+    env: Vec<(String, String)>,
+    pending: SharedPending,
+}
+
+impl AgiChannel {
+    /// The name of the channel
+    pub fn name(&self) -> &str {
+        &self.channel
+    }
+
+    /// The AGI environment, e.g. `agi_callerid` or `agi_extension`
+    pub fn env(&self) -> &[(String, String)] {
+        &self.env
+    }
+
+    /// Returns a single variable of the AGI environment
+    pub fn env_var(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Executes an AGI command, e.g. `STREAM FILE hello-world ""`, and waits for its result
+    pub async fn exec(&self, command: &str) -> Result<AgiResult, Error> {
+        let command_id = self.connection.next_action_id();
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => {
+                pending.insert(command_id.clone(), (self.channel.clone(), tx))
+            }
+            None => return Err(Error::ConnectionClosed),
+        };
+
+        let sent = self
+            .connection
+            .send_action(vec![
+                Tag::from("Action", "AGI"),
+                Tag::from("Channel", &self.channel),
+                Tag::from("Command", command),
+                Tag::from("CommandID", &command_id),
+            ])
+            .await;
+        if let Err(e) = sent {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&command_id);
+            }
+            return Err(e);
+        }
+
+        rx.await.unwrap_or(Err(Error::ConnectionClosed))
+    }
+}
+
+/// Hands out the channels entering AsyncAGI and routes the results of their commands
+///
+/// To be used with `AGI(agi:async)` in the dialplan, so that channels can be controlled entirely
+/// over the AMI without running a FastAGI server.
+pub struct AsyncAgi {
+    channels_rx: mpsc::Receiver<AgiChannel>,
+}
+
+impl AsyncAgi {
+    /// Starts listening for channels entering AsyncAGI on `connection`
+    pub fn start(connection: &AmiConnection) -> AsyncAgi {
+        let (channels_tx, channels_rx) = mpsc::channel(32);
+        let connection = connection.clone();
+        let events = connection.events();
+        tokio::spawn(async move {
+            Self::dispatch(connection, events, channels_tx).await;
+        });
+        AsyncAgi { channels_rx }
+    }
+
+    /// Waits for the next channel entering AsyncAGI
+    ///
+    /// Returns `None` once the connection has been closed.
+    pub async fn next_channel(&mut self) -> Option<AgiChannel> {
+        self.channels_rx.recv().await
+    }
+
+    async fn dispatch(
+        connection: AmiConnection,
+        mut events: broadcast::Receiver<Option<Packet>>,
+        channels_tx: mpsc::Sender<AgiChannel>,
+    ) {
+        let pending: SharedPending =
+            Arc::new(Mutex::new(Some(PendingCommands::new())));
+        loop {
+            let received = tokio::select! {
+                received = events.recv() => received,
+                _ = channels_tx.closed() => break,
+            };
+            let pkt = match received {
+                Ok(Some(pkt)) => pkt,
+                Ok(None) | Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("AsyncAGI dispatcher missed {} events", n);
+                    crate::metrics::events_lagged(n);
+                    continue;
+                }
+            };
+            let event = match event_name(&pkt) {
+                Some(event) => event.to_ascii_lowercase(),
+                None => continue,
+            };
+            let channel = match find_tag(&pkt, "Channel") {
+                Some(channel) => channel.clone(),
+                None => continue,
+            };
+            match event.as_str() {
+                "asyncagistart" => {
+                    let agi_channel = AgiChannel {
+                        connection: connection.clone(),
+                        env: find_tag(&pkt, "Env")
+                            .map(|env| parse_env(&url_decode(env)))
+                            .unwrap_or_default(),
+                        channel,
+                        pending: pending.clone(),
+                    };
+                    if channels_tx.send(agi_channel).await.is_err() {
+                        break;
+                    }
+                }
+                "asyncagiexec" => {
+                    let result = find_tag(&pkt, "Result")
+                        .and_then(|r| AgiResult::parse(&url_decode(r)));
+                    let waiting = find_tag(&pkt, "CommandId").and_then(|id| {
+                        pending.lock().unwrap().as_mut()?.remove(id)
+                    });
+                    if let (Some(result), Some((_, tx))) = (result, waiting) {
+                        let _ = tx.send(Ok(result));
+                    }
+                }
+                "asyncagiend" => {
+                    let mut guard = pending.lock().unwrap();
+                    let pending = match guard.as_mut() {
+                        Some(pending) => pending,
+                        None => continue,
+                    };
+                    let ended = pending
+                        .iter()
+                        .filter(|(_, (c, _))| *c == channel)
+                        .map(|(id, _)| id.clone())
+                        .collect::<Vec<_>>();
+                    for id in ended {
+                        if let Some((_, tx)) = pending.remove(&id) {
+                            let _ = tx.send(Err(Error::Hangup));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        // the channels handed out outlive the dispatcher, fail their commands
+        let stopped = pending.lock().unwrap().take();
+        for (_, (_, tx)) in stopped.into_iter().flatten() {
+            let _ = tx.send(Err(Error::ConnectionClosed));
+        }
+        trace!("AsyncAGI dispatcher stopped");
+    }
+}
+
+/// Parses the `key: value` lines of the AGI environment
+fn parse_env(env: &str) -> Vec<(String, String)> {
+    env.lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Decodes the percent-encoding used for the `Env` and `Result` headers
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_environment() {
+        let env = parse_env(&url_decode(
+            "agi_request%3A%20async%0Aagi_channel%3A%20PJSIP%2F100-00000001%0A",
+        ));
+        assert_eq!(
+            env[1],
+            ("agi_channel".to_string(), "PJSIP/100-00000001".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_results() {
+        let result = AgiResult::parse(&url_decode(
+            "200%20result%3D0%20(timeout)%20endpos%3D1234%0A",
+        ))
+        .unwrap();
+        assert!(result.is_success());
+        assert_eq!(result.result.as_deref(), Some("0"));
+        assert_eq!(result.data.as_deref(), Some("timeout"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn fails_commands_when_connection_closes() {
+        use crate::packet_from as event;
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut agi = AsyncAgi::start(&connection);
+        server
+            .send_packet(&event(&[
+                ("Event", "AsyncAGIStart"),
+                ("Channel", "PJSIP/100-00000001"),
+                ("Env", "agi_request%3A%20async%0A"),
+            ]))
+            .await
+            .unwrap();
+        let channel = agi.next_channel().await.unwrap();
+        let (result, _) = tokio::join!(channel.exec("ANSWER"), async {
+            let request = server.read_packet().await.unwrap();
+            let id = find_tag(&request, "ActionID").unwrap().clone();
+            server
+                .send_packet(&event(&[
+                    ("Response", "Success"),
+                    ("ActionID", &id),
+                    ("Message", "Added AGI command to queue"),
+                ]))
+                .await
+                .unwrap();
+            drop(server);
+        });
+        assert_eq!(result, Err(Error::ConnectionClosed));
+        assert_eq!(channel.exec("HANGUP").await, Err(Error::ConnectionClosed));
+    }
+}
@@ -0,0 +1,385 @@
+use crate::tracking::{publish, reconcile, spawn_tracker};
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+
+/// A channel that is part of a bridge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeMember {
+    pub channel: String,
+    pub unique_id: String,
+}
+
+impl BridgeMember {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        Some(BridgeMember {
+            channel: find_tag(pkt, "Channel")?.clone(),
+            unique_id: find_tag(pkt, "Uniqueid")?.clone(),
+        })
+    }
+}
+
+/// A bridge currently known to a `BridgeTracker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bridge {
+    pub unique_id: String,
+    pub bridge_type: Option<String>,
+    pub technology: Option<String>,
+    pub creator: Option<String>,
+    pub name: Option<String>,
+    pub members: Vec<BridgeMember>,
+}
+
+impl Bridge {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        Some(Bridge {
+            unique_id: find_tag(pkt, "BridgeUniqueid")?.clone(),
+            bridge_type: find_value(pkt, "BridgeType"),
+            technology: find_value(pkt, "BridgeTechnology"),
+            creator: find_value(pkt, "BridgeCreator"),
+            name: find_value(pkt, "BridgeName"),
+            members: vec![],
+        })
+    }
+}
+
+/// A change of the set of bridges observed by a `BridgeTracker`
+///
+/// Channels entering or leaving a bridge are reported as `Updated`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BridgeChange {
+    Created(Bridge),
+    Updated(Bridge),
+    Destroyed(Bridge),
+}
+
+/// Keeps track of all bridges on the Asterisk server and the channels in them
+///
+/// The tracker is seeded using `BridgeList` and `BridgeInfo`, and afterwards kept up to date
+/// from the `BridgeCreate`, `BridgeEnter`, `BridgeLeave` and `BridgeDestroy` events.
+pub struct BridgeTracker {
+    bridges: Arc<RwLock<HashMap<String, Bridge>>>,
+    changes_tx: broadcast::Sender<BridgeChange>,
+    task: Mutex<AbortHandle>,
+}
+
+impl BridgeTracker {
+    /// Starts tracking the bridges of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<BridgeTracker, Error> {
+        let events = connection.events();
+        let bridges = Arc::new(RwLock::new(seed(connection).await?));
+        let (changes_tx, _) = broadcast::channel::<BridgeChange>(32);
+
+        let task = spawn_tracker(
+            "Bridge tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&bridges),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(BridgeTracker {
+            bridges,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// The tracker is seeded again and the differences are published as changes: bridges that
+    /// have been torn down in the meantime are `Destroyed`, new ones `Created`, and those whose
+    /// members changed `Updated`. Afterwards the events of `connection` are followed instead of
+    /// the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let fresh = seed(connection).await?;
+        let changes =
+            reconcile(&mut self.bridges.write().unwrap(), fresh, |_, _| {});
+        let destroyed =
+            changes.removed.into_iter().map(BridgeChange::Destroyed);
+        let changes = destroyed
+            .chain(changes.added.into_iter().map(BridgeChange::Created))
+            .chain(changes.updated.into_iter().map(BridgeChange::Updated));
+        for change in changes {
+            publish(&self.changes_tx, change);
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Bridge tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.bridges),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of all currently known bridges
+    pub fn snapshot(&self) -> Vec<Bridge> {
+        self.bridges.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the bridge with the given `BridgeUniqueid`
+    pub fn get(&self, bridge_id: &str) -> Option<Bridge> {
+        self.bridges.read().unwrap().get(bridge_id).cloned()
+    }
+
+    /// Returns the bridge the channel with the given `Uniqueid` is part of
+    pub fn bridge_of(&self, unique_id: &str) -> Option<Bridge> {
+        self.bridges
+            .read()
+            .unwrap()
+            .values()
+            .find(|b| b.members.iter().any(|m| m.unique_id == unique_id))
+            .cloned()
+    }
+
+    /// Returns the channels bridged with the channel with the given `Uniqueid`
+    pub fn peers_of(&self, unique_id: &str) -> Vec<BridgeMember> {
+        self.bridge_of(unique_id)
+            .map(|b| {
+                b.members
+                    .into_iter()
+                    .filter(|m| m.unique_id != unique_id)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Subscribes to changes of the tracked bridges
+    pub fn changes(&self) -> broadcast::Receiver<BridgeChange> {
+        self.changes_tx.subscribe()
+    }
+}
+
+impl Drop for BridgeTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists all bridges with their members
+async fn seed(
+    connection: &AmiConnection,
+) -> Result<HashMap<String, Bridge>, Error> {
+    let resp = connection
+        .send_action(vec![Tag::from("Action", "BridgeList")])
+        .await?;
+    let mut bridges = HashMap::new();
+    for mut bridge in
+        list_items(&resp, "BridgeListItem").filter_map(Bridge::from_packet)
+    {
+        let info = connection
+            .send_action(vec![
+                Tag::from("Action", "BridgeInfo"),
+                Tag::from("BridgeUniqueid", &bridge.unique_id),
+            ])
+            .await;
+        // the bridge may have been destroyed in the meantime
+        if let Ok(info) = info {
+            bridge.members = list_items(&info, "BridgeInfoChannel")
+                .filter_map(BridgeMember::from_packet)
+                .collect();
+        }
+        bridges.insert(bridge.unique_id.clone(), bridge);
+    }
+    Ok(bridges)
+}
+
+fn apply_event(
+    bridges: &mut HashMap<String, Bridge>,
+    pkt: &Packet,
+) -> Option<BridgeChange> {
+    let event = event_name(pkt)?.to_ascii_lowercase();
+    let bridge_id = find_tag(pkt, "BridgeUniqueid")?;
+    match event.as_str() {
+        "bridgecreate" => {
+            let bridge = Bridge::from_packet(pkt)?;
+            bridges.insert(bridge_id.clone(), bridge.clone());
+            Some(BridgeChange::Created(bridge))
+        }
+        "bridgeenter" => {
+            let member = BridgeMember::from_packet(pkt)?;
+            if !bridges.contains_key(bridge_id) {
+                bridges.insert(bridge_id.clone(), Bridge::from_packet(pkt)?);
+            }
+            let bridge = bridges.get_mut(bridge_id)?;
+            if !bridge.members.contains(&member) {
+                bridge.members.push(member);
+            }
+            Some(BridgeChange::Updated(bridge.clone()))
+        }
+        "bridgeleave" => {
+            let unique_id = find_tag(pkt, "Uniqueid")?;
+            let bridge = bridges.get_mut(bridge_id)?;
+            bridge.members.retain(|m| &m.unique_id != unique_id);
+            Some(BridgeChange::Updated(bridge.clone()))
+        }
+        "bridgedestroy" => {
+            bridges.remove(bridge_id).map(BridgeChange::Destroyed)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_bridge_members() {
+        let mut bridges = HashMap::new();
+        apply_event(
+            &mut bridges,
+            &event(&[
+                ("Event", "BridgeCreate"),
+                ("BridgeUniqueid", "b-1"),
+                ("BridgeType", "basic"),
+            ]),
+        );
+        for (channel, unique_id) in &[("PJSIP/a", "1.1"), ("PJSIP/b", "1.2")] {
+            apply_event(
+                &mut bridges,
+                &event(&[
+                    ("Event", "BridgeEnter"),
+                    ("BridgeUniqueid", "b-1"),
+                    ("Channel", channel),
+                    ("Uniqueid", unique_id),
+                ]),
+            );
+        }
+        assert_eq!(bridges["b-1"].members.len(), 2);
+
+        apply_event(
+            &mut bridges,
+            &event(&[
+                ("Event", "BridgeLeave"),
+                ("BridgeUniqueid", "b-1"),
+                ("Channel", "PJSIP/a"),
+                ("Uniqueid", "1.1"),
+            ]),
+        );
+        assert_eq!(bridges["b-1"].members[0].channel, "PJSIP/b");
+
+        let destroyed = apply_event(
+            &mut bridges,
+            &event(&[("Event", "BridgeDestroy"), ("BridgeUniqueid", "b-1")]),
+        );
+        assert!(matches!(destroyed, Some(BridgeChange::Destroyed(_))));
+        assert!(bridges.is_empty());
+    }
+
+    /// Answers the `BridgeList` and `BridgeInfo` actions seeding a tracker
+    #[cfg(feature = "testing")]
+    async fn list_bridges(
+        server: &mut crate::testing::ServerSide,
+        bridges: &[(&str, &[&str])],
+    ) {
+        let answer = |action: Packet, items: Vec<Packet>| {
+            let action_id = find_tag(&action, "ActionID").unwrap().clone();
+            let start =
+                event(&[("Response", "Success"), ("EventList", "start")]);
+            let complete =
+                event(&[("Event", "ListComplete"), ("EventList", "Complete")]);
+            std::iter::once(start)
+                .chain(items)
+                .chain(std::iter::once(complete))
+                .map(move |mut pkt| {
+                    pkt.push(Tag::from("ActionID", &action_id));
+                    pkt
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let action = server.read_packet().await.unwrap();
+        let items = bridges
+            .iter()
+            .map(|(bridge_id, _)| {
+                event(&[
+                    ("Event", "BridgeListItem"),
+                    ("BridgeUniqueid", bridge_id),
+                    ("BridgeType", "basic"),
+                ])
+            })
+            .collect();
+        for pkt in answer(action, items) {
+            server.send_packet(&pkt).await.unwrap();
+        }
+        for (_, members) in bridges {
+            let action = server.read_packet().await.unwrap();
+            let items = members
+                .iter()
+                .map(|unique_id| {
+                    let channel = format!("PJSIP/{}", unique_id);
+                    event(&[
+                        ("Event", "BridgeInfoChannel"),
+                        ("Channel", &channel),
+                        ("Uniqueid", unique_id),
+                    ])
+                })
+                .collect();
+            for pkt in answer(action, items) {
+                server.send_packet(&pkt).await.unwrap();
+            }
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn resyncs_on_new_connection() {
+        let (old, mut old_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (tracker, _) = tokio::join!(
+            BridgeTracker::start(&old),
+            list_bridges(&mut old_server, &[("b-1", &["1.1"])])
+        );
+        let tracker = tracker.unwrap();
+        let mut changes = tracker.changes();
+
+        let (new, mut new_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (resynced, _) = tokio::join!(
+            tracker.resync(&new),
+            list_bridges(&mut new_server, &[("b-2", &["1.2", "1.3"])])
+        );
+        resynced.unwrap();
+        let destroyed = changes.recv().await.unwrap();
+        assert!(
+            matches!(destroyed, BridgeChange::Destroyed(b) if b.unique_id == "b-1")
+        );
+        let created = changes.recv().await.unwrap();
+        assert!(
+            matches!(created, BridgeChange::Created(b) if b.members.len() == 2)
+        );
+        assert_eq!(tracker.bridge_of("1.3").unwrap().unique_id, "b-2");
+
+        // only the events of the new connection are followed
+        let destroy =
+            event(&[("Event", "BridgeDestroy"), ("BridgeUniqueid", "b-2")]);
+        old_server.send_packet(&destroy).await.unwrap();
+        let create =
+            event(&[("Event", "BridgeCreate"), ("BridgeUniqueid", "b-3")]);
+        new_server.send_packet(&create).await.unwrap();
+        let created = changes.recv().await.unwrap();
+        assert!(
+            matches!(created, BridgeChange::Created(b) if b.unique_id == "b-3")
+        );
+        assert!(tracker.get("b-2").is_some());
+    }
+}
@@ -0,0 +1,233 @@
+//! Caching the responses of read-only actions
+//!
+//! Dashboards refreshing every second send the same list actions over and over, each of which
+//! makes Asterisk walk all its peers, channels, or queues. Responses of actions a TTL has been
+//! set for using `AmiConnection::cache_responses` are kept and handed out again until the TTL
+//! expires. While trackers are running, the events they follow also drop the cached responses
+//! they make outdated, e.g. a `PeerStatus` drops those of `SIPpeers` and `PJSIPShowEndpoints`.
+//!
+//! Only the actions sent by the typed methods of `AmiConnection`, like `sip_peers` or
+//! `queue_status`, are answered from the cache. Packets passed to `AmiConnection::send` always
+//! reach the server. A cached response carries the `ActionID` of the action it answers. As the
+//! action is not sent, the outgoing interceptors are not invoked for it.
+
+use crate::{event_name, find_tag, AmiConnection, Packet};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The actions whose responses are outdated by an event, all in lower case
+fn invalidated_by(event: &str) -> &'static [&'static str] {
+    match event {
+        "peerstatus" => &[
+            "sippeers",
+            "iaxpeers",
+            "pjsipshowendpoints",
+            "pjsipshowcontacts",
+        ],
+        "contactstatus" => &["pjsipshowendpoints", "pjsipshowcontacts"],
+        "devicestatechange" => &["devicestatelist", "pjsipshowendpoints"],
+        "queuememberadded" | "queuememberremoved" | "queuememberstatus"
+        | "queuememberpause" | "queuememberpenalty" | "queuecallerjoin"
+        | "queuecallerleave" | "queuecallerabandon" | "agentconnect"
+        | "agentcomplete" => &["queuestatus", "queuesummary"],
+        "newchannel" | "newstate" | "hangup" | "rename" => {
+            &["coreshowchannels"]
+        }
+        "parkedcall" | "unparkedcall" | "parkedcalltimeout"
+        | "parkedcallgiveup" | "parkedcallswap" => &["parkedcalls"],
+        "bridgecreate" | "bridgedestroy" | "bridgeenter" | "bridgeleave" => {
+            &["bridgelist"]
+        }
+        "registry" => &["sipshowregistry", "pjsipshowregistrationsoutbound"],
+        _ => &[],
+    }
+}
+
+/// The parameters of an action, all but its `ActionID`, identifying its cached response
+fn request_key(action: &Packet) -> String {
+    action
+        .iter()
+        .filter(|tag| !tag.key.eq_ignore_ascii_case("ActionID"))
+        .map(|tag| format!("{}: {}\n", tag.key.to_ascii_lowercase(), tag.value))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Entry {
+    action: String,
+    expires_at: Instant,
+    resp: Vec<Packet>,
+}
+
+/// The cached responses of a connection, see `AmiConnection::cache_responses`
+#[derive(Debug, Default)]
+pub(crate) struct ResponseCache {
+    /// Keyed by the lower case action name
+    ttls: HashMap<String, Duration>,
+    entries: HashMap<String, Entry>,
+}
+
+impl ResponseCache {
+    /// The name of the action if its responses are cached
+    fn cached_action(&self, action: &Packet) -> Option<String> {
+        if self.ttls.is_empty() {
+            return None;
+        }
+        let name = find_tag(action, "Action")?.to_ascii_lowercase();
+        Some(name).filter(|name| self.ttls.contains_key(name))
+    }
+
+    /// Returns the cached response to `action`, if there is one that has not expired
+    ///
+    /// The `ActionID` of the response is replaced by the one of `action`.
+    pub(crate) fn get(&mut self, action: &Packet) -> Option<Vec<Packet>> {
+        self.cached_action(action)?;
+        let key = request_key(action);
+        let entry = self.entries.get(&key)?;
+        if entry.expires_at <= Instant::now() {
+            self.entries.remove(&key);
+            return None;
+        }
+        let mut resp = entry.resp.clone();
+        if let Some(action_id) = find_tag(action, "ActionID") {
+            let tags = resp.iter_mut().flatten();
+            for tag in tags.filter(|t| t.key.eq_ignore_ascii_case("ActionID")) {
+                tag.value = action_id.clone();
+            }
+        }
+        Some(resp)
+    }
+
+    /// Keeps the response to `action` if its responses are cached
+    pub(crate) fn store(&mut self, action: &Packet, resp: &[Packet]) {
+        if let Some(name) = self.cached_action(action) {
+            let entry = Entry {
+                expires_at: Instant::now() + self.ttls[&name],
+                action: name,
+                resp: resp.to_vec(),
+            };
+            self.entries.insert(request_key(action), entry);
+        }
+    }
+
+    /// Drops the cached responses outdated by an event
+    pub(crate) fn invalidate(&mut self, event: &Packet) {
+        if self.entries.is_empty() {
+            return;
+        }
+        if let Some(name) = event_name(event) {
+            let outdated = invalidated_by(&name.to_ascii_lowercase());
+            self.entries
+                .retain(|_, entry| !outdated.contains(&entry.action.as_str()));
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Caches the responses of `actions` for `ttl`, `None` stops caching them
+    ///
+    /// Only read-only actions like `SIPpeers`, `PJSIPShowEndpoints`, or `QueueStatus` should
+    /// be cached: as long as a response is cached, the action is not sent again with the same
+    /// parameters by the typed methods of the connection, while `send` bypasses the cache.
+    /// Responses are cached for all clones of the connection. Actions answered from the cache
+    /// are not passed to the interceptors of the connection.
+    pub fn cache_responses(&self, actions: &[&str], ttl: Option<Duration>) {
+        let mut cache = self.cache.lock().unwrap();
+        for action in actions {
+            let action = action.to_ascii_lowercase();
+            cache.entries.retain(|_, entry| entry.action != action);
+            match ttl {
+                Some(ttl) => cache.ttls.insert(action, ttl),
+                None => cache.ttls.remove(&action),
+            };
+        }
+    }
+
+    /// Drops all cached responses, so the next actions are answered by the server again
+    pub fn clear_cache(&self) {
+        self.cache.lock().unwrap().entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[tokio::test]
+    async fn expires_and_invalidates_responses() {
+        let mut cache = ResponseCache::default();
+        cache
+            .ttls
+            .insert("sippeers".to_string(), Duration::from_millis(20));
+        let action =
+            |id| packet_from(&[("Action", "SIPpeers"), ("ActionID", id)]);
+        let resp = |id| {
+            vec![packet_from(&[("Response", "Success"), ("ActionID", id)])]
+        };
+        cache.store(&action("1"), &resp("1"));
+        cache.store(&packet_from(&[("Action", "Status")]), &resp("1"));
+        assert_eq!(cache.entries.len(), 1);
+        assert_eq!(cache.get(&action("2")), Some(resp("2")));
+
+        cache.invalidate(&packet_from(&[("Event", "Hangup")]));
+        assert!(cache.get(&action("3")).is_some());
+        cache.invalidate(&packet_from(&[("Event", "PeerStatus")]));
+        assert_eq!(cache.get(&action("4")), None);
+
+        cache.store(&action("5"), &resp("5"));
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get(&action("6")), None);
+    }
+
+    #[test]
+    fn invalidates_registrations_on_registry_events() {
+        let mut cache = ResponseCache::default();
+        let ttl = Duration::from_secs(60);
+        for action in ["sipshowregistry", "pjsipshowregistrationsoutbound"] {
+            cache.ttls.insert(action.to_string(), ttl);
+        }
+        let resp = vec![packet_from(&[("Response", "Success")])];
+        cache.store(&packet_from(&[("Action", "SIPshowregistry")]), &resp);
+        let outbound =
+            packet_from(&[("Action", "PJSIPShowRegistrationsOutbound")]);
+        cache.store(&outbound, &resp);
+        assert_eq!(cache.entries.len(), 2);
+
+        cache.invalidate(&packet_from(&[("Event", "Registry")]));
+        assert!(cache.entries.is_empty());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn answers_cached_actions_locally() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        connection
+            .cache_responses(&["QueueStatus"], Some(Duration::from_secs(60)));
+        let action =
+            |id| packet_from(&[("Action", "QueueStatus"), ("ActionID", id)]);
+        let (first, _) =
+            tokio::join!(connection.send_action(action("1")), async {
+                let sent = server.read_packet().await.unwrap();
+                let resp = packet_from(&[
+                    ("Response", "Success"),
+                    ("ActionID", find_tag(&sent, "ActionID").unwrap()),
+                ]);
+                server.send_packet(&resp).await.unwrap();
+            });
+        let first = first.unwrap();
+        let second = connection.send_action(action("2")).await.unwrap();
+        assert_eq!(find_tag(&first[0], "ActionID").unwrap(), "1");
+        assert_eq!(find_tag(&second[0], "ActionID").unwrap(), "2");
+        assert_eq!(first[0].len(), second[0].len());
+
+        connection.cache_responses(&["QueueStatus"], None);
+        let third = tokio::time::timeout(
+            Duration::from_millis(50),
+            connection.send_action(action("3")),
+        );
+        assert!(third.await.is_err());
+    }
+}
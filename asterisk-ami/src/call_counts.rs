@@ -0,0 +1,141 @@
+use crate::channels::{Channel, ChannelTracker};
+use crate::tracking::derive_watch;
+use std::collections::HashMap;
+use tokio::sync::watch;
+
+/// The number of concurrent calls on the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallCounts {
+    /// Calls in progress; all channels sharing a `Linkedid` count as a single call
+    pub total: usize,
+    /// Channels whose name starts with one of the configured trunk prefixes
+    pub by_trunk: HashMap<String, usize>,
+    /// Calls by the dialplan context of the channel that started the call
+    pub by_context: HashMap<String, usize>,
+}
+
+impl CallCounts {
+    /// Counts the calls represented by `channels`
+    pub fn from_channels<'a>(
+        channels: impl IntoIterator<Item = &'a Channel>,
+        trunk_prefixes: &[String],
+    ) -> Self {
+        let mut counts = CallCounts {
+            by_trunk: trunk_prefixes.iter().map(|p| (p.clone(), 0)).collect(),
+            ..CallCounts::default()
+        };
+        // the channel that started a call is the one whose Uniqueid is the Linkedid
+        let mut calls = HashMap::<&str, &Channel>::new();
+        for channel in channels {
+            let call =
+                channel.linked_id.as_deref().unwrap_or(&channel.unique_id);
+            let first = calls.entry(call).or_insert(channel);
+            if channel.unique_id == call {
+                *first = channel;
+            }
+            for prefix in trunk_prefixes {
+                if channel.name.starts_with(prefix.as_str()) {
+                    *counts.by_trunk.entry(prefix.clone()).or_default() += 1;
+                }
+            }
+        }
+        counts.total = calls.len();
+        for channel in calls.values() {
+            if let Some(context) = &channel.context {
+                *counts.by_context.entry(context.clone()).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Returns the number of channels on a trunk, `0` for prefixes not being counted
+    pub fn trunk(&self, prefix: &str) -> usize {
+        self.by_trunk.get(prefix).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of calls started in a dialplan context
+    pub fn context(&self, context: &str) -> usize {
+        self.by_context.get(context).copied().unwrap_or(0)
+    }
+}
+
+/// Keeps the concurrent call counts derived from a `ChannelTracker` up to date
+///
+/// Trunks are identified by a prefix of their channel names, e.g. `PJSIP/provider-`. The counts
+/// are recalculated whenever the tracked channels change, so they can be used as a capacity
+/// guard before originating calls.
+pub struct CallCounter {
+    counts_rx: watch::Receiver<CallCounts>,
+}
+
+impl CallCounter {
+    /// Starts counting the calls of the channels known to `tracker`
+    pub fn start(tracker: &ChannelTracker, trunk_prefixes: &[&str]) -> Self {
+        let prefixes = trunk_prefixes
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        let counts_rx = derive_watch(
+            "Call counter",
+            &tracker.channels,
+            tracker.changes(),
+            move |channels| {
+                CallCounts::from_channels(channels.values(), &prefixes)
+            },
+        );
+        CallCounter { counts_rx }
+    }
+
+    /// Returns the current call counts
+    pub fn counts(&self) -> CallCounts {
+        self.counts_rx.borrow().clone()
+    }
+
+    /// Subscribes to changes of the call counts
+    pub fn watch(&self) -> watch::Receiver<CallCounts> {
+        self.counts_rx.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channels::ChannelState;
+    use std::time::Instant;
+
+    fn channel(name: &str, unique_id: &str, linked_id: &str) -> Channel {
+        Channel {
+            name: name.to_string(),
+            unique_id: unique_id.to_string(),
+            linked_id: Some(linked_id.to_string()),
+            state: ChannelState::Up,
+            caller_id_num: None,
+            caller_id_name: None,
+            connected_line_num: None,
+            connected_line_name: None,
+            bridge_id: None,
+            context: Some("from-internal".to_string()),
+            exten: None,
+            created: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn counts_calls_per_trunk_and_context() {
+        let mut outbound = channel("PJSIP/provider-00000002", "1.2", "1.1");
+        outbound.context = Some("from-trunk".to_string());
+        let channels = vec![
+            channel("PJSIP/100-00000001", "1.1", "1.1"),
+            outbound,
+            channel("PJSIP/101-00000003", "1.3", "1.3"),
+        ];
+        let counts = CallCounts::from_channels(
+            &channels,
+            &["PJSIP/provider-".to_string(), "PJSIP/backup-".to_string()],
+        );
+        assert_eq!(counts.total, 2);
+        assert_eq!(counts.trunk("PJSIP/provider-"), 1);
+        assert_eq!(counts.trunk("PJSIP/backup-"), 0);
+        assert_eq!(counts.context("from-internal"), 2);
+    }
+}
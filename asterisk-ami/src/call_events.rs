@@ -0,0 +1,168 @@
+use crate::{event_name, find_tag, AmiConnection, Packet};
+use log::{trace, warn};
+use std::collections::HashSet;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+/// Headers linking an event to the legs of a call
+const ID_HEADERS: &[&str] =
+    &["Uniqueid", "Linkedid", "DestUniqueid", "DestLinkedid"];
+
+/// Decides which events belong to a call and when the call has ended
+#[derive(Debug)]
+struct CallFilter {
+    linked_id: String,
+    /// The `Uniqueid`s of legs that have not been hung up yet
+    legs: HashSet<String>,
+    /// All `Uniqueid`s seen, so events arriving after a leg's `Hangup` still match
+    seen: HashSet<String>,
+}
+
+impl CallFilter {
+    fn new(linked_id: &str) -> Self {
+        CallFilter {
+            linked_id: linked_id.to_string(),
+            legs: HashSet::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Checks if an event belongs to the call, following the legs it names
+    fn matches(&mut self, pkt: &Packet) -> bool {
+        let header = |key| find_tag(pkt, key).map(String::as_str);
+        let matches = ID_HEADERS.iter().any(|key| {
+            header(key).is_some_and(|id| {
+                id == self.linked_id || self.seen.contains(id)
+            })
+        });
+        if !matches {
+            return false;
+        }
+        let legs = [("Uniqueid", "Linkedid"), ("DestUniqueid", "DestLinkedid")];
+        for (unique_id, linked_id) in legs.iter() {
+            let belongs = header(linked_id).is_none_or(|l| l == self.linked_id);
+            if let (Some(unique_id), true) = (header(unique_id), belongs) {
+                if self.seen.insert(unique_id.to_string()) {
+                    self.legs.insert(unique_id.to_string());
+                }
+            }
+        }
+        if event_name(pkt).is_some_and(|e| e.eq_ignore_ascii_case("Hangup")) {
+            if let Some(unique_id) = header("Uniqueid") {
+                self.legs.remove(unique_id);
+            }
+        }
+        true
+    }
+
+    /// Checks if the call has ended after the last event matched
+    fn has_ended(&self, pkt: &Packet) -> bool {
+        let is_linked_id_end = event_name(pkt)
+            .is_some_and(|e| e.eq_ignore_ascii_case("CEL"))
+            && find_tag(pkt, "EventName").is_some_and(|e| e == "LINKEDID_END");
+        is_linked_id_end || (!self.seen.is_empty() && self.legs.is_empty())
+    }
+}
+
+/// The events of a single call, as returned by `AmiConnection::events_for_call`
+pub struct CallEvents {
+    events_rx: mpsc::Receiver<Packet>,
+}
+
+impl CallEvents {
+    /// Waits for the next event of the call
+    ///
+    /// Returns `None` once the call has ended or the connection has been closed.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        self.events_rx.recv().await
+    }
+}
+
+impl AmiConnection {
+    /// Subscribes to the events of the call with the given `Linkedid`
+    ///
+    /// The `Linkedid` is the `Uniqueid` of the channel that started the call. Events are
+    /// selected by their `Uniqueid`, `Linkedid`, `DestUniqueid`, and `DestLinkedid` headers,
+    /// new legs are followed as they appear. The stream ends after the last known leg of the
+    /// call has been hung up or the `LINKEDID_END` CEL event has been received. Legs that
+    /// existed before subscribing only become known with their first event.
+    pub fn events_for_call(&self, linked_id: &str) -> CallEvents {
+        let (events_tx, events_rx) = mpsc::channel(32);
+        let mut events = self.events();
+        let mut filter = CallFilter::new(linked_id);
+        tokio::spawn(async move {
+            loop {
+                let pkt = tokio::select! {
+                    pkt = events.recv() => pkt,
+                    _ = events_tx.closed() => break,
+                };
+                let pkt = match pkt {
+                    Ok(Some(pkt)) => pkt,
+                    Ok(None) | Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Call event stream missed {} events", n);
+                        crate::metrics::events_lagged(n);
+                        continue;
+                    }
+                };
+                if !filter.matches(&pkt) {
+                    continue;
+                }
+                let ended = filter.has_ended(&pkt);
+                if events_tx.send(pkt).await.is_err() || ended {
+                    break;
+                }
+            }
+            trace!("Call event stream for {} stopped", filter.linked_id);
+        });
+        CallEvents { events_rx }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn follows_legs_until_call_ends() {
+        let mut filter = CallFilter::new("1.1");
+        let newchannel = |unique_id| {
+            event(&[
+                ("Event", "Newchannel"),
+                ("Uniqueid", unique_id),
+                ("Linkedid", "1.1"),
+            ])
+        };
+        let hangup = |unique_id| {
+            event(&[
+                ("Event", "Hangup"),
+                ("Uniqueid", unique_id),
+                ("Linkedid", "1.1"),
+            ])
+        };
+        assert!(filter.matches(&newchannel("1.1")));
+        let dial = event(&[
+            ("Event", "DialBegin"),
+            ("Uniqueid", "1.1"),
+            ("Linkedid", "1.1"),
+            ("DestUniqueid", "1.2"),
+            ("DestLinkedid", "1.1"),
+        ]);
+        assert!(filter.matches(&dial));
+        // events without a Linkedid are recognized by a known Uniqueid
+        let var_set = event(&[("Event", "VarSet"), ("Uniqueid", "1.2")]);
+        assert!(filter.matches(&var_set));
+        let other = event(&[
+            ("Event", "Newchannel"),
+            ("Uniqueid", "2.1"),
+            ("Linkedid", "2.1"),
+        ]);
+        assert!(!filter.matches(&other));
+
+        assert!(filter.matches(&hangup("1.1")));
+        assert!(!filter.has_ended(&hangup("1.1")));
+        assert!(filter.matches(&hangup("1.2")));
+        assert!(filter.has_ended(&hangup("1.2")));
+    }
+}
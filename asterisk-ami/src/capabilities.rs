@@ -0,0 +1,123 @@
+use crate::{find_tag, AmiConnection, Error, Packet, Tag};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Actions that are handled by the manager itself and thus always available
+const SESSION_ACTIONS: &[&str] =
+    &["Login", "Logoff", "Challenge", "ListCommands"];
+
+/// An action available to the logged in user as reported by `ListCommands`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionInfo {
+    pub name: String,
+    pub description: String,
+    /// The privileges required to use the action, empty if none are required
+    pub privileges: Vec<String>,
+}
+
+/// The set of actions the logged in user may use
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    actions: HashMap<String, ActionInfo>,
+}
+
+impl Capabilities {
+    fn from_packet(pkt: &Packet) -> Self {
+        let actions = pkt
+            .iter()
+            .filter(|t| {
+                !["Response", "ActionID", "Message"]
+                    .iter()
+                    .any(|k| t.key.eq_ignore_ascii_case(k))
+            })
+            .map(|t| {
+                let (description, privileges) = parse_description(&t.value);
+                let info = ActionInfo {
+                    name: t.key.clone(),
+                    description,
+                    privileges,
+                };
+                (t.key.to_ascii_lowercase(), info)
+            })
+            .collect();
+        Capabilities { actions }
+    }
+
+    /// Checks if an action may be used
+    pub fn supports(&self, action: &str) -> bool {
+        SESSION_ACTIONS
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(action))
+            || self.actions.contains_key(&action.to_ascii_lowercase())
+    }
+
+    /// Returns the details of an action
+    pub fn get(&self, action: &str) -> Option<&ActionInfo> {
+        self.actions.get(&action.to_ascii_lowercase())
+    }
+
+    /// Returns all available actions
+    pub fn actions(&self) -> impl Iterator<Item = &ActionInfo> {
+        self.actions.values()
+    }
+}
+
+/// Splits `Description.  (Priv: call,all)` into the description and privileges
+fn parse_description(value: &str) -> (String, Vec<String>) {
+    match value.rfind("(Priv:") {
+        Some(pos) => {
+            let privileges = value[pos + 6..]
+                .trim_end_matches(')')
+                .split(',')
+                .map(str::trim)
+                .filter(|p| !p.is_empty() && *p != "<none>")
+                .map(String::from)
+                .collect();
+            (value[..pos].trim().to_string(), privileges)
+        }
+        None => (value.trim().to_string(), vec![]),
+    }
+}
+
+impl AmiConnection {
+    /// Discovers the actions available to the logged in user using `ListCommands`
+    ///
+    /// The result is remembered by the connection. From then on, the helpers of this crate
+    /// fail with `Error::UnsupportedAction` instead of sending actions the user lacks.
+    pub async fn capabilities(&self) -> Result<Arc<Capabilities>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "ListCommands")])
+            .await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        if find_tag(first, "Response").is_none() {
+            return Err(Error::UnexpectedResponse);
+        }
+        let capabilities = Arc::new(Capabilities::from_packet(first));
+        *self.capabilities.write().unwrap() = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_list_commands() {
+        let capabilities = Capabilities::from_packet(&packet_from(&[
+            ("Response", "Success"),
+            ("ActionID", "3"),
+            ("Ping", "Keepalive command.  (Priv: <none>)"),
+            ("Originate", "Originate a call.  (Priv: originate,all)"),
+        ]));
+        assert!(capabilities.supports("originate"));
+        assert!(capabilities.supports("Logoff"));
+        assert!(!capabilities.supports("Command"));
+        assert_eq!(
+            capabilities.get("Originate").unwrap().privileges,
+            vec!["originate", "all"]
+        );
+        assert!(capabilities.get("Ping").unwrap().privileges.is_empty());
+    }
+}
@@ -0,0 +1,617 @@
+use crate::call_counts::CallCounts;
+use crate::tracking::{derive_watch, publish, reconcile, spawn_tracker};
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+/// The state of a channel as reported in the `ChannelState` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    Down,
+    Reserved,
+    OffHook,
+    Dialing,
+    Ring,
+    Ringing,
+    Up,
+    Busy,
+    DialingOffHook,
+    PreRing,
+    Unknown,
+}
+
+impl ChannelState {
+    /// Converts the numeric value of a `ChannelState` header
+    pub fn from_code(code: &str) -> Self {
+        match code.trim() {
+            "0" => ChannelState::Down,
+            "1" => ChannelState::Reserved,
+            "2" => ChannelState::OffHook,
+            "3" => ChannelState::Dialing,
+            "4" => ChannelState::Ring,
+            "5" => ChannelState::Ringing,
+            "6" => ChannelState::Up,
+            "7" => ChannelState::Busy,
+            "8" => ChannelState::DialingOffHook,
+            "9" => ChannelState::PreRing,
+            _ => ChannelState::Unknown,
+        }
+    }
+}
+
+/// A channel currently known to a `ChannelTracker`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    pub name: String,
+    pub unique_id: String,
+    pub linked_id: Option<String>,
+    pub state: ChannelState,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub connected_line_num: Option<String>,
+    pub connected_line_name: Option<String>,
+    pub bridge_id: Option<String>,
+    /// The dialplan context the channel is currently executing in
+    pub context: Option<String>,
+    pub exten: Option<String>,
+    pub created: Instant,
+}
+
+impl Channel {
+    /// Time elapsed since the channel has been created
+    pub fn duration(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    fn from_packet(pkt: &Packet, created: Instant) -> Option<Self> {
+        Some(Channel {
+            name: find_tag(pkt, "Channel")?.clone(),
+            unique_id: find_tag(pkt, "Uniqueid")?.clone(),
+            linked_id: find_value(pkt, "Linkedid"),
+            state: find_tag(pkt, "ChannelState")
+                .map_or(ChannelState::Unknown, |s| ChannelState::from_code(s)),
+            caller_id_num: find_value(pkt, "CallerIDNum"),
+            caller_id_name: find_value(pkt, "CallerIDName"),
+            connected_line_num: find_value(pkt, "ConnectedLineNum"),
+            connected_line_name: find_value(pkt, "ConnectedLineName"),
+            bridge_id: find_value(pkt, "BridgeId"),
+            context: find_value(pkt, "Context"),
+            exten: find_value(pkt, "Exten"),
+            created,
+        })
+    }
+
+    fn update_from(&mut self, pkt: &Packet) {
+        if let Some(state) = find_tag(pkt, "ChannelState") {
+            self.state = ChannelState::from_code(state);
+        }
+        if let Some(name) = find_tag(pkt, "Channel") {
+            self.name = name.clone();
+        }
+        self.caller_id_num = find_value(pkt, "CallerIDNum");
+        self.caller_id_name = find_value(pkt, "CallerIDName");
+        self.connected_line_num = find_value(pkt, "ConnectedLineNum");
+        self.connected_line_name = find_value(pkt, "ConnectedLineName");
+        if let Some(context) = find_value(pkt, "Context") {
+            self.context = Some(context);
+            self.exten = find_value(pkt, "Exten");
+        }
+    }
+}
+
+/// A channel as listed by `CoreShowChannels`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub name: String,
+    pub unique_id: String,
+    pub linked_id: Option<String>,
+    pub state: ChannelState,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub connected_line_num: Option<String>,
+    pub connected_line_name: Option<String>,
+    pub account_code: Option<String>,
+    pub context: Option<String>,
+    pub exten: Option<String>,
+    pub priority: Option<u32>,
+    pub application: Option<String>,
+    pub application_data: Option<String>,
+    pub duration: Duration,
+    pub bridge_id: Option<String>,
+}
+
+impl ChannelInfo {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(ChannelInfo {
+            name: find_tag(pkt, "Channel")?.clone(),
+            unique_id: find_tag(pkt, "Uniqueid")?.clone(),
+            linked_id: find_value(pkt, "Linkedid"),
+            state: find_tag(pkt, "ChannelState")
+                .map_or(ChannelState::Unknown, |s| ChannelState::from_code(s)),
+            caller_id_num: find_value(pkt, "CallerIDNum"),
+            caller_id_name: find_value(pkt, "CallerIDName"),
+            connected_line_num: find_value(pkt, "ConnectedLineNum"),
+            connected_line_name: find_value(pkt, "ConnectedLineName"),
+            account_code: find_value(pkt, "AccountCode"),
+            context: find_value(pkt, "Context"),
+            exten: find_value(pkt, "Exten"),
+            priority: find_tag(pkt, "Priority")
+                .and_then(|p| p.trim().parse().ok()),
+            application: find_value(pkt, "Application"),
+            application_data: find_value(pkt, "ApplicationData"),
+            duration: find_tag(pkt, "Duration")
+                .and_then(|d| parse_duration(d))
+                .unwrap_or_default(),
+            bridge_id: find_value(pkt, "BridgeId"),
+        })
+    }
+
+    fn into_channel(self, created: Instant) -> Channel {
+        Channel {
+            name: self.name,
+            unique_id: self.unique_id,
+            linked_id: self.linked_id,
+            state: self.state,
+            caller_id_num: self.caller_id_num,
+            caller_id_name: self.caller_id_name,
+            connected_line_num: self.connected_line_num,
+            connected_line_name: self.connected_line_name,
+            bridge_id: self.bridge_id,
+            context: self.context,
+            exten: self.exten,
+            created,
+        }
+    }
+}
+
+/// The status of a channel as reported by the `Status` action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelStatus {
+    pub name: String,
+    pub unique_id: String,
+    pub linked_id: Option<String>,
+    pub state: ChannelState,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub connected_line_num: Option<String>,
+    pub connected_line_name: Option<String>,
+    pub account_code: Option<String>,
+    pub context: Option<String>,
+    pub exten: Option<String>,
+    pub priority: Option<u32>,
+    pub application: Option<String>,
+    pub application_data: Option<String>,
+    /// Time since the channel has been created
+    pub duration: Option<Duration>,
+    pub bridge_id: Option<String>,
+    /// The channel variables requested, as `(name, value)` pairs
+    pub variables: Vec<(String, String)>,
+}
+
+impl ChannelStatus {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(ChannelStatus {
+            name: find_tag(pkt, "Channel")?.clone(),
+            unique_id: find_tag(pkt, "Uniqueid")?.clone(),
+            linked_id: find_value(pkt, "Linkedid"),
+            state: find_tag(pkt, "ChannelState")
+                .map_or(ChannelState::Unknown, |s| ChannelState::from_code(s)),
+            caller_id_num: find_value(pkt, "CallerIDNum"),
+            caller_id_name: find_value(pkt, "CallerIDName"),
+            connected_line_num: find_value(pkt, "ConnectedLineNum"),
+            connected_line_name: find_value(pkt, "ConnectedLineName"),
+            account_code: find_value(pkt, "AccountCode"),
+            context: find_value(pkt, "Context"),
+            exten: find_value(pkt, "Exten"),
+            priority: find_tag(pkt, "Priority")
+                .and_then(|p| p.trim().parse().ok()),
+            application: find_value(pkt, "Application"),
+            application_data: find_value(pkt, "Data"),
+            duration: find_tag(pkt, "Seconds")
+                .and_then(|s| s.trim().parse().ok())
+                .map(Duration::from_secs),
+            bridge_id: find_value(pkt, "BridgeID"),
+            variables: pkt
+                .iter()
+                .filter(|t| t.key.eq_ignore_ascii_case("Variable"))
+                .filter_map(|t| {
+                    let (name, value) = t.value.split_once('=')?;
+                    Some((name.to_string(), value.to_string()))
+                })
+                .collect(),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Queries the status of a single channel or, if `channel` is `None`, of all channels
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - name of the channel to query
+    /// * `variables` - names of channel variables to include in the result
+    pub async fn status(
+        &self,
+        channel: Option<&str>,
+        variables: &[&str],
+    ) -> Result<Vec<ChannelStatus>, Error> {
+        let action_id = self.next_action_id();
+        let mut pkt = vec![
+            Tag::from("Action", "Status"),
+            Tag::from("ActionID", &action_id),
+        ];
+        if let Some(channel) = channel {
+            pkt.push(Tag::from("Channel", channel));
+        }
+        if !variables.is_empty() {
+            pkt.push(Tag::of("Variables".to_string(), variables.join(",")));
+        }
+        let resp = self.send_action(pkt).await?;
+        Ok(list_items(&resp, "Status")
+            .filter(|pkt| {
+                find_tag(pkt, "ActionID").is_none_or(|id| *id == action_id)
+            })
+            .filter_map(ChannelStatus::from_list_item)
+            .collect())
+    }
+
+    /// Lists all active channels
+    pub async fn core_show_channels(&self) -> Result<Vec<ChannelInfo>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "CoreShowChannels")])
+            .await?;
+        Ok(list_items(&resp, "CoreShowChannel")
+            .filter_map(ChannelInfo::from_list_item)
+            .collect())
+    }
+}
+
+/// A change of the set of channels observed by a `ChannelTracker`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelChange {
+    Added(Channel),
+    Updated(Channel),
+    Removed(Channel),
+}
+
+/// Keeps track of all channels on the Asterisk server
+///
+/// The tracker is seeded using `CoreShowChannels` and afterwards kept up to date from the
+/// `Newchannel`, `Newstate`, `NewConnectedLine`, `Newexten`, `BridgeEnter`, `BridgeLeave`, and
+/// `Hangup` events. The connection has to be logged in with a user allowed to receive call
+/// events.
+pub struct ChannelTracker {
+    pub(crate) channels: Arc<RwLock<HashMap<String, Channel>>>,
+    changes_tx: broadcast::Sender<ChannelChange>,
+    task: Mutex<AbortHandle>,
+}
+
+impl ChannelTracker {
+    /// Starts tracking the channels of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<ChannelTracker, Error> {
+        let events = connection.events();
+        let channels = Arc::new(RwLock::new(seed(connection).await?));
+        let (changes_tx, _) = broadcast::channel::<ChannelChange>(32);
+
+        let task = spawn_tracker(
+            "Channel tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&channels),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(ChannelTracker {
+            channels,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// The tracker is seeded again and the differences are published as changes: channels that
+    /// have been hung up in the meantime are `Removed`, new ones `Added`, and those that changed
+    /// `Updated`. Afterwards the events of `connection` are followed instead of the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let fresh = seed(connection).await?;
+        let changes = reconcile(
+            &mut self.channels.write().unwrap(),
+            fresh,
+            |old, channel| channel.created = old.created,
+        );
+        let removed = changes.removed.into_iter().map(ChannelChange::Removed);
+        let changes = removed
+            .chain(changes.added.into_iter().map(ChannelChange::Added))
+            .chain(changes.updated.into_iter().map(ChannelChange::Updated));
+        for change in changes {
+            publish(&self.changes_tx, change);
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Channel tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.channels),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of all currently known channels
+    pub fn snapshot(&self) -> Vec<Channel> {
+        self.channels.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the channel with the given `Uniqueid`
+    pub fn get(&self, unique_id: &str) -> Option<Channel> {
+        self.channels.read().unwrap().get(unique_id).cloned()
+    }
+
+    /// Subscribes to changes of the tracked channels
+    pub fn changes(&self) -> broadcast::Receiver<ChannelChange> {
+        self.changes_tx.subscribe()
+    }
+
+    /// Watches the number of calls in progress, counted like `CallCounts::total`
+    pub fn watch_active_calls(&self) -> watch::Receiver<usize> {
+        derive_watch("Active call watch", &self.channels, self.changes(), |c| {
+            CallCounts::from_channels(c.values(), &[]).total
+        })
+    }
+}
+
+impl Drop for ChannelTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists the channels currently on the server, keyed by their `Uniqueid`
+async fn seed(
+    connection: &AmiConnection,
+) -> Result<HashMap<String, Channel>, Error> {
+    let now = Instant::now();
+    Ok(connection
+        .core_show_channels()
+        .await?
+        .into_iter()
+        .map(|info| {
+            let created = now.checked_sub(info.duration).unwrap_or(now);
+            (info.unique_id.clone(), info.into_channel(created))
+        })
+        .collect())
+}
+
+fn apply_event(
+    channels: &mut HashMap<String, Channel>,
+    pkt: &Packet,
+) -> Option<ChannelChange> {
+    let event = event_name(pkt)?.to_ascii_lowercase();
+    let unique_id = find_tag(pkt, "Uniqueid")?;
+    match event.as_str() {
+        "newchannel" | "newstate" | "newconnectedline" | "newexten" => {
+            match channels.get_mut(unique_id) {
+                Some(channel) => {
+                    channel.update_from(pkt);
+                    Some(ChannelChange::Updated(channel.clone()))
+                }
+                None => {
+                    let channel = Channel::from_packet(pkt, Instant::now())?;
+                    channels.insert(unique_id.clone(), channel.clone());
+                    Some(ChannelChange::Added(channel))
+                }
+            }
+        }
+        "bridgeenter" | "bridgeleave" => {
+            let channel = channels.get_mut(unique_id)?;
+            channel.bridge_id = if event == "bridgeenter" {
+                find_value(pkt, "BridgeUniqueid")
+            } else {
+                None
+            };
+            Some(ChannelChange::Updated(channel.clone()))
+        }
+        "hangup" => channels.remove(unique_id).map(ChannelChange::Removed),
+        _ => None,
+    }
+}
+
+/// Parses a duration given as `HH:MM:SS`
+pub(crate) fn parse_duration(value: &str) -> Option<Duration> {
+    let mut seconds = 0;
+    for part in value.trim().split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_channel_lifecycle() {
+        let mut channels = HashMap::new();
+        let added = apply_event(
+            &mut channels,
+            &event(&[
+                ("Event", "Newchannel"),
+                ("Channel", "PJSIP/100-00000001"),
+                ("ChannelState", "4"),
+                ("CallerIDNum", "100"),
+                ("CallerIDName", "<unknown>"),
+                ("Uniqueid", "1621.1"),
+            ]),
+        );
+        assert!(matches!(added, Some(ChannelChange::Added(_))));
+        assert_eq!(channels["1621.1"].caller_id_name, None);
+
+        apply_event(
+            &mut channels,
+            &event(&[
+                ("Event", "Newstate"),
+                ("Channel", "PJSIP/100-00000001"),
+                ("ChannelState", "6"),
+                ("CallerIDNum", "100"),
+                ("Uniqueid", "1621.1"),
+            ]),
+        );
+        assert_eq!(channels["1621.1"].state, ChannelState::Up);
+
+        apply_event(
+            &mut channels,
+            &event(&[
+                ("Event", "BridgeEnter"),
+                ("BridgeUniqueid", "b-1"),
+                ("Uniqueid", "1621.1"),
+            ]),
+        );
+        assert_eq!(channels["1621.1"].bridge_id.as_deref(), Some("b-1"));
+
+        let removed = apply_event(
+            &mut channels,
+            &event(&[("Event", "Hangup"), ("Uniqueid", "1621.1")]),
+        );
+        assert!(matches!(removed, Some(ChannelChange::Removed(_))));
+        assert!(channels.is_empty());
+    }
+
+    #[test]
+    fn parses_core_show_channel() {
+        let info = ChannelInfo::from_list_item(&event(&[
+            ("Event", "CoreShowChannel"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("Uniqueid", "1621.1"),
+            ("ChannelState", "6"),
+            ("Application", "Dial"),
+            ("ApplicationData", "PJSIP/200,30"),
+            ("Duration", "00:01:05"),
+            ("BridgeId", ""),
+        ]))
+        .unwrap();
+        assert_eq!(info.duration, Duration::from_secs(65));
+        assert_eq!(info.application.as_deref(), Some("Dial"));
+        assert_eq!(info.bridge_id, None);
+    }
+
+    #[test]
+    fn parses_status_variables() {
+        let status = ChannelStatus::from_list_item(&event(&[
+            ("Event", "Status"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("Uniqueid", "1621.1"),
+            ("Seconds", "42"),
+            ("Variable", "CDR(userfield)=vip"),
+            ("Variable", "QUEUE=sales"),
+        ]))
+        .unwrap();
+        assert_eq!(status.duration, Some(Duration::from_secs(42)));
+        assert_eq!(
+            status.variables,
+            vec![
+                ("CDR(userfield)".to_string(), "vip".to_string()),
+                ("QUEUE".to_string(), "sales".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("01:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("garbage"), None);
+    }
+
+    #[cfg(feature = "testing")]
+    async fn list_channels(
+        server: &mut crate::testing::ServerSide,
+        unique_ids: &[&str],
+    ) {
+        let action = server.read_packet().await.unwrap();
+        let action_id = find_tag(&action, "ActionID").unwrap().clone();
+        let reply = |tags: &[(&str, &str)]| {
+            let mut pkt = event(tags);
+            pkt.push(Tag::from("ActionID", &action_id));
+            pkt
+        };
+        let start = reply(&[("Response", "Success"), ("EventList", "start")]);
+        server.send_packet(&start).await.unwrap();
+        for unique_id in unique_ids {
+            let channel = format!("PJSIP/{}", unique_id);
+            let item = reply(&[
+                ("Event", "CoreShowChannel"),
+                ("Channel", &channel),
+                ("Uniqueid", unique_id),
+                ("ChannelState", "6"),
+                ("Duration", "00:00:10"),
+            ]);
+            server.send_packet(&item).await.unwrap();
+        }
+        let complete = reply(&[
+            ("Event", "CoreShowChannelsComplete"),
+            ("EventList", "Complete"),
+        ]);
+        server.send_packet(&complete).await.unwrap();
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn resyncs_on_new_connection() {
+        let (old, mut old_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (tracker, _) = tokio::join!(
+            ChannelTracker::start(&old),
+            list_channels(&mut old_server, &["1.1", "1.2"])
+        );
+        let tracker = tracker.unwrap();
+        let created = tracker.get("1.2").unwrap().created;
+        let mut changes = tracker.changes();
+
+        let (new, mut new_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (resynced, _) = tokio::join!(
+            tracker.resync(&new),
+            list_channels(&mut new_server, &["1.2", "1.3"])
+        );
+        resynced.unwrap();
+        let removed = changes.recv().await.unwrap();
+        assert!(
+            matches!(removed, ChannelChange::Removed(c) if c.unique_id == "1.1")
+        );
+        let added = changes.recv().await.unwrap();
+        assert!(
+            matches!(added, ChannelChange::Added(c) if c.unique_id == "1.3")
+        );
+        // the channel that survived is unchanged, including when it has been created
+        assert!(changes.try_recv().is_err());
+        assert_eq!(tracker.get("1.2").unwrap().created, created);
+
+        // only the events of the new connection are followed
+        let hangup =
+            |unique_id| event(&[("Event", "Hangup"), ("Uniqueid", unique_id)]);
+        old_server.send_packet(&hangup("1.2")).await.unwrap();
+        new_server.send_packet(&hangup("1.3")).await.unwrap();
+        let removed = changes.recv().await.unwrap();
+        assert!(
+            matches!(removed, ChannelChange::Removed(c) if c.unique_id == "1.3")
+        );
+        assert!(tracker.get("1.2").is_some());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{event_name, find_tag, AmiConnection, Packet};
+use log::{trace, warn};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Keys the events reporting the state of a device, peer, contact, extension, or presentity,
+/// e.g. `DeviceStateChange/PJSIP/100`, for use with `AmiConnection::coalesce_events`
+///
+/// Returns `None` for all other events, which are then passed on without delay.
+pub fn state_key(pkt: &Packet) -> Option<String> {
+    let event = event_name(pkt)?;
+    let subject = match event.to_ascii_lowercase().as_str() {
+        "devicestatechange" => find_tag(pkt, "Device")?.clone(),
+        "peerstatus" => find_tag(pkt, "Peer")?.clone(),
+        "contactstatus" => find_tag(pkt, "URI")?.clone(),
+        "extensionstatus" => {
+            format!("{}@{}", find_tag(pkt, "Exten")?, find_tag(pkt, "Context")?)
+        }
+        "presencestatechange" => find_tag(pkt, "Presentity")?.clone(),
+        _ => return None,
+    };
+    Some(format!("{}/{}", event, subject))
+}
+
+/// Holds back keyed events, keeping only the latest one per key
+struct Coalescer {
+    window: Duration,
+    latest: HashMap<String, Packet>,
+    /// The keys held back, with the time they are due at, oldest first
+    due: VecDeque<(Instant, String)>,
+}
+
+impl Coalescer {
+    fn new(window: Duration) -> Self {
+        Coalescer {
+            window,
+            latest: HashMap::new(),
+            due: VecDeque::new(),
+        }
+    }
+
+    /// Holds back an event, replacing the one held back for the same key
+    fn push(&mut self, key: String, pkt: Packet, now: Instant) {
+        if self.latest.insert(key.clone(), pkt).is_none() {
+            self.due.push_back((now + self.window, key));
+        }
+    }
+
+    /// The time the next event is due at
+    fn next_due(&self) -> Option<Instant> {
+        self.due.front().map(|(at, _)| *at)
+    }
+
+    /// Takes the next event that is due by `now`
+    fn pop_due(&mut self, now: Instant) -> Option<Packet> {
+        match self.due.front() {
+            Some((at, _)) if *at <= now => {
+                let (_, key) = self.due.pop_front()?;
+                self.latest.remove(&key)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The events of a connection with bursts coalesced, see `AmiConnection::coalesce_events`
+pub struct CoalescedEvents {
+    events_rx: mpsc::Receiver<Packet>,
+}
+
+impl CoalescedEvents {
+    /// Waits for the next event
+    ///
+    /// Returns `None` once the connection has been closed and the events held back have been
+    /// handed out.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        self.events_rx.recv().await
+    }
+}
+
+impl AmiConnection {
+    /// Subscribes to the events with bursts of updates of the same state coalesced
+    ///
+    /// Events `key` returns a key for are held back for `window` after the first one of the
+    /// key has been received; only the latest event of the key received in the meantime is
+    /// handed out. This reduces flapping peers or busy devices to one update per window. All
+    /// other events are handed out as soon as they have been received, possibly before
+    /// earlier events that are held back. `state_key` keys the common state events.
+    pub fn coalesce_events<F>(
+        &self,
+        window: Duration,
+        key: F,
+    ) -> CoalescedEvents
+    where
+        F: Fn(&Packet) -> Option<String> + Send + 'static,
+    {
+        let (events_tx, events_rx) = mpsc::channel(32);
+        let mut events = self.events();
+        let mut coalescer = Coalescer::new(window);
+        tokio::spawn(async move {
+            loop {
+                let due = coalescer.next_due();
+                let at = due.unwrap_or_else(Instant::now);
+                let pkt = tokio::select! {
+                    pkt = events.recv() => pkt,
+                    _ = tokio::time::sleep_until(at), if due.is_some() => {
+                        let now = Instant::now();
+                        while let Some(pkt) = coalescer.pop_due(now) {
+                            if events_tx.send(pkt).await.is_err() {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    _ = events_tx.closed() => return,
+                };
+                let pkt = match pkt {
+                    Ok(Some(pkt)) => pkt,
+                    Ok(None) | Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Coalesced event stream missed {} events", n);
+                        crate::metrics::events_lagged(n);
+                        continue;
+                    }
+                };
+                match key(&pkt) {
+                    Some(key) => coalescer.push(key, pkt, Instant::now()),
+                    None => {
+                        if events_tx.send(pkt).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            // hand out the events held back without waiting for their window
+            while let Some((_, key)) = coalescer.due.pop_front() {
+                let pkt = coalescer.latest.remove(&key);
+                if events_tx.send(pkt.unwrap()).await.is_err() {
+                    break;
+                }
+            }
+            trace!("Coalesced event stream stopped");
+        });
+        CoalescedEvents { events_rx }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn keeps_latest_event_per_key() {
+        let device = |state| {
+            event(&[
+                ("Event", "DeviceStateChange"),
+                ("Device", "PJSIP/100"),
+                ("State", state),
+            ])
+        };
+        let peer = event(&[
+            ("Event", "PeerStatus"),
+            ("Peer", "PJSIP/200"),
+            ("PeerStatus", "Reachable"),
+        ]);
+        assert_eq!(
+            state_key(&device("INUSE")).as_deref(),
+            Some("DeviceStateChange/PJSIP/100")
+        );
+        assert_eq!(state_key(&event(&[("Event", "Hangup")])), None);
+
+        let window = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut coalescer = Coalescer::new(window);
+        for (n, state) in ["RINGING", "INUSE", "NOT_INUSE"].iter().enumerate() {
+            let at = start + Duration::from_millis(10 * n as u64);
+            coalescer.push(
+                state_key(&device(state)).unwrap(),
+                device(state),
+                at,
+            );
+        }
+        coalescer.push(state_key(&peer).unwrap(), peer.clone(), start);
+        assert_eq!(coalescer.next_due(), Some(start + window));
+        assert_eq!(coalescer.pop_due(start), None);
+        let due = start + window;
+        assert_eq!(coalescer.pop_due(due), Some(device("NOT_INUSE")));
+        assert_eq!(coalescer.pop_due(due), Some(peer));
+        assert_eq!(coalescer.pop_due(due), None);
+    }
+}
@@ -0,0 +1,321 @@
+//! Normalizing the events of older Asterisk versions
+//!
+//! The typed events and trackers of this crate expect the events and headers of Asterisk 12
+//! and later, whose bridging and channel rework renamed many of them; Asterisk 13 to 20 kept
+//! those names for the common events. Servers running Asterisk 11 or older send, e.g., `Dial`
+//! with `SubEvent: Begin` and `Destination` instead of `DialBegin` with `DestChannel`. The
+//! `CompatShim` renames them for the detected version, so a fleet of mixed versions can be
+//! handled by the same code. Differences only in case, like `UniqueID` and `Uniqueid`, need
+//! no shim as `find_tag` ignores case.
+//!
+//! Events without an equivalent, like the `Bridge` event of Asterisk 11 with its `Channel1`
+//! and `CallerID1` headers, are passed on unchanged.
+
+use crate::interceptor::Interceptor;
+use crate::schema::major_version;
+use crate::{event_name, find_tag, AmiConnection, Packet};
+use std::sync::Arc;
+
+/// An event renamed, depending on the value of one of its headers, which is then removed
+struct EventRename {
+    /// The last major version sending the old name
+    until: u32,
+    from: &'static str,
+    condition: Option<(&'static str, &'static str)>,
+    to: &'static str,
+}
+
+const EVENT_RENAMES: &[EventRename] = &[
+    EventRename {
+        until: 11,
+        from: "Dial",
+        condition: Some(("SubEvent", "Begin")),
+        to: "DialBegin",
+    },
+    EventRename {
+        until: 11,
+        from: "Dial",
+        condition: Some(("SubEvent", "End")),
+        to: "DialEnd",
+    },
+    EventRename {
+        until: 11,
+        from: "Hold",
+        condition: Some(("Status", "Off")),
+        to: "Unhold",
+    },
+    EventRename {
+        until: 11,
+        from: "Hold",
+        condition: Some(("Status", "On")),
+        to: "Hold",
+    },
+    EventRename {
+        until: 11,
+        from: "Join",
+        condition: None,
+        to: "QueueCallerJoin",
+    },
+    EventRename {
+        until: 11,
+        from: "Leave",
+        condition: None,
+        to: "QueueCallerLeave",
+    },
+    EventRename {
+        until: 11,
+        from: "MusicOnHold",
+        condition: Some(("State", "Start")),
+        to: "MusicOnHoldStart",
+    },
+    EventRename {
+        until: 11,
+        from: "MusicOnHold",
+        condition: Some(("State", "Stop")),
+        to: "MusicOnHoldStop",
+    },
+    EventRename {
+        until: 11,
+        from: "QueueMemberPaused",
+        condition: None,
+        to: "QueueMemberPause",
+    },
+];
+
+/// Headers renamed in events, given by their new name
+struct HeaderRenames {
+    until: u32,
+    events: &'static [&'static str],
+    renames: &'static [(&'static str, &'static str)],
+}
+
+const HEADER_RENAMES: &[HeaderRenames] = &[
+    HeaderRenames {
+        until: 11,
+        events: &["AgentCalled"],
+        renames: &[
+            ("AgentCalled", "Interface"),
+            ("AgentName", "MemberName"),
+            ("ChannelCalling", "Channel"),
+            ("DestinationChannel", "DestChannel"),
+        ],
+    },
+    HeaderRenames {
+        until: 11,
+        events: &["DialBegin"],
+        renames: &[("Destination", "DestChannel")],
+    },
+    HeaderRenames {
+        until: 11,
+        events: &["Newexten"],
+        renames: &[("Extension", "Exten")],
+    },
+    HeaderRenames {
+        until: 11,
+        events: &[
+            "ParkedCall",
+            "ParkedCallGiveUp",
+            "ParkedCallTimeOut",
+            "UnParkedCall",
+        ],
+        renames: &[
+            ("Exten", "ParkingSpace"),
+            ("Channel", "ParkeeChannel"),
+            ("Uniqueid", "ParkeeUniqueid"),
+            ("CallerIDNum", "ParkeeCallerIDNum"),
+            ("CallerIDName", "ParkeeCallerIDName"),
+            ("Timeout", "ParkingTimeout"),
+            ("From", "ParkerDialString"),
+        ],
+    },
+    HeaderRenames {
+        until: 11,
+        events: &[
+            "QueueMemberAdded",
+            "QueueMemberPause",
+            "QueueMemberPenalty",
+            "QueueMemberRemoved",
+            "QueueMemberStatus",
+        ],
+        renames: &[("Location", "Interface")],
+    },
+];
+
+/// An `Interceptor` renaming the events and headers of older Asterisk versions to the names
+/// used since Asterisk 12
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatShim {
+    version: u32,
+}
+
+impl CompatShim {
+    /// Normalizes the events of the given major version of Asterisk, e.g. `11`
+    pub fn new(major_version: u32) -> Self {
+        CompatShim {
+            version: major_version,
+        }
+    }
+
+    /// Normalizes the events for the version reported by the server, e.g. the
+    /// `asterisk_version` of `CoreSettings`
+    pub fn for_version(version: &str) -> Option<Self> {
+        major_version(version).map(Self::new)
+    }
+
+    /// Normalizes the events for the AMI version of the server's greeting, see
+    /// `AmiConnection::ami_version`
+    ///
+    /// AMI 1.x stands for Asterisk 11 or older, since AMI 3 the major version of Asterisk
+    /// is the one of AMI plus 11.
+    pub fn for_ami_version(ami_version: &str) -> Option<Self> {
+        let version = match major_version(ami_version)? {
+            0 => return None,
+            1 => 11,
+            2 => 13,
+            ami => ami + 11,
+        };
+        Some(Self::new(version))
+    }
+
+    /// Whether the events of the version need renaming at all
+    pub fn is_needed(&self) -> bool {
+        EVENT_RENAMES.iter().any(|r| self.version <= r.until)
+            || HEADER_RENAMES.iter().any(|r| self.version <= r.until)
+    }
+
+    /// Renames the event and its headers to the names used since Asterisk 12
+    pub fn normalize(&self, pkt: &mut Packet) {
+        let event = match event_name(pkt) {
+            Some(event) => event.clone(),
+            None => return,
+        };
+        let rename = EVENT_RENAMES.iter().find(|r| {
+            self.version <= r.until
+                && r.from.eq_ignore_ascii_case(&event)
+                && r.condition.is_none_or(|(key, value)| {
+                    find_tag(pkt, key)
+                        .is_some_and(|v| v.eq_ignore_ascii_case(value))
+                })
+        });
+        let event = match rename {
+            Some(rename) => {
+                if let Some((key, _)) = rename.condition {
+                    pkt.retain(|tag| !tag.key.eq_ignore_ascii_case(key));
+                }
+                for tag in pkt.iter_mut() {
+                    if tag.key.eq_ignore_ascii_case("Event") {
+                        tag.value = rename.to.to_string();
+                    }
+                }
+                rename.to
+            }
+            None => event.as_str(),
+        };
+        let renames = HEADER_RENAMES.iter().filter(|r| {
+            self.version <= r.until
+                && r.events.iter().any(|e| e.eq_ignore_ascii_case(event))
+        });
+        for rename in renames.flat_map(|r| r.renames) {
+            let (from, to) = *rename;
+            // a server sending both keeps the new one
+            if find_tag(pkt, to).is_some() {
+                continue;
+            }
+            for tag in pkt.iter_mut() {
+                if tag.key.eq_ignore_ascii_case(from) {
+                    tag.key = to.to_string();
+                }
+            }
+        }
+    }
+}
+
+impl Interceptor for CompatShim {
+    fn incoming(&self, pkt: &mut Packet) -> bool {
+        self.normalize(pkt);
+        true
+    }
+}
+
+impl AmiConnection {
+    /// Adds a `CompatShim` for the AMI version of the server's greeting, if its events need
+    /// renaming
+    ///
+    /// Returns whether a shim has been added. It should be called before subscribing, events
+    /// already queued for subscribers are not renamed.
+    pub fn normalize_events(&self) -> bool {
+        let shim = self.ami_version().and_then(CompatShim::for_ami_version);
+        match shim {
+            Some(shim) if shim.is_needed() => {
+                self.add_interceptor(Arc::new(shim));
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn renames_events_of_asterisk_11() {
+        let shim = CompatShim::for_ami_version("1.3").unwrap();
+        assert!(shim.is_needed());
+        assert!(!CompatShim::for_ami_version("5.0.0").unwrap().is_needed());
+        assert_eq!(
+            CompatShim::for_ami_version("9.0.0"),
+            Some(CompatShim::new(20))
+        );
+
+        let mut dial = event(&[
+            ("Event", "Dial"),
+            ("SubEvent", "Begin"),
+            ("Channel", "SIP/100-00000001"),
+            ("Destination", "SIP/200-00000002"),
+            ("CallerIDNum", "100"),
+        ]);
+        shim.normalize(&mut dial);
+        assert_eq!(
+            dial,
+            event(&[
+                ("Event", "DialBegin"),
+                ("Channel", "SIP/100-00000001"),
+                ("DestChannel", "SIP/200-00000002"),
+                ("CallerIDNum", "100"),
+            ])
+        );
+
+        let mut parked = event(&[
+            ("Event", "ParkedCall"),
+            ("Exten", "701"),
+            ("Channel", "SIP/100-00000001"),
+        ]);
+        shim.normalize(&mut parked);
+        assert_eq!(find_tag(&parked, "ParkingSpace").unwrap(), "701");
+        assert_eq!(
+            find_tag(&parked, "ParkeeChannel").unwrap(),
+            "SIP/100-00000001"
+        );
+
+        let bridge = event(&[("Event", "Bridge"), ("CallerID1", "100")]);
+        let mut unchanged = bridge.clone();
+        shim.normalize(&mut unchanged);
+        assert_eq!(unchanged, bridge);
+
+        let mut newer = event(&[("Event", "Dial"), ("SubEvent", "Begin")]);
+        CompatShim::new(13).normalize(&mut newer);
+        assert_eq!(event_name(&newer).unwrap(), "Dial");
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn detects_version_from_greeting() {
+        let (connection, _server) =
+            crate::testing::duplex_connection().await.unwrap();
+        assert_eq!(connection.ami_version(), Some("5.0.0"));
+        assert!(!connection.normalize_events());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    event_name, find_flag, find_tag, find_value, list_items, AmiConnection,
+    Error, Packet, Tag,
+};
+
+/// A participant of a ConfBridge conference as listed by `ConfbridgeList`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfbridgeParticipant {
+    pub conference: String,
+    pub channel: String,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub admin: bool,
+    pub marked: bool,
+    pub muted: bool,
+    pub talking: bool,
+    pub waiting: bool,
+}
+
+impl ConfbridgeParticipant {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        Some(ConfbridgeParticipant {
+            conference: find_tag(pkt, "Conference")?.clone(),
+            channel: find_tag(pkt, "Channel")?.clone(),
+            caller_id_num: find_value(pkt, "CallerIDNum"),
+            caller_id_name: find_value(pkt, "CallerIDName"),
+            admin: find_flag(pkt, "Admin"),
+            marked: find_flag(pkt, "MarkedUser"),
+            muted: find_flag(pkt, "Muted"),
+            talking: find_flag(pkt, "Talking"),
+            waiting: find_flag(pkt, "Waiting"),
+        })
+    }
+}
+
+/// An active conference as listed by `ConfbridgeListRooms`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfbridgeRoom {
+    pub conference: String,
+    pub parties: u32,
+    pub marked: u32,
+    pub locked: bool,
+    pub muted: bool,
+}
+
+impl ConfbridgeRoom {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        let count = |key| {
+            find_tag(pkt, key)
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Some(ConfbridgeRoom {
+            conference: find_tag(pkt, "Conference")?.clone(),
+            parties: count("Parties"),
+            marked: count("Marked"),
+            locked: find_flag(pkt, "Locked"),
+            muted: find_flag(pkt, "Muted"),
+        })
+    }
+}
+
+/// Recording of a conference has been started or stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfbridgeRecording {
+    Started { conference: String },
+    Stopped { conference: String },
+}
+
+impl ConfbridgeRecording {
+    /// Parses a `ConfbridgeRecord` or `ConfbridgeStopRecord` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        let event = event_name(pkt)?;
+        let conference = find_tag(pkt, "Conference")?.clone();
+        if event.eq_ignore_ascii_case("ConfbridgeRecord") {
+            Some(ConfbridgeRecording::Started { conference })
+        } else if event.eq_ignore_ascii_case("ConfbridgeStopRecord") {
+            Some(ConfbridgeRecording::Stopped { conference })
+        } else {
+            None
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Lists the participants of a conference
+    ///
+    /// When there are no conferences at all the list is empty, while a conference that does not
+    /// exist next to others fails with `Error::ActionFailed`.
+    pub async fn confbridge_list(
+        &self,
+        conference: &str,
+    ) -> Result<Vec<ConfbridgeParticipant>, Error> {
+        let resp = match self
+            .send_action(vec![
+                Tag::from("Action", "ConfbridgeList"),
+                Tag::from("Conference", conference),
+            ])
+            .await
+        {
+            Ok(resp) => resp,
+            // Asterisk reports the absence of conferences as an error
+            Err(Error::ActionFailed(message))
+                if message.starts_with("No active conferences") =>
+            {
+                return Ok(vec![]);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(list_items(&resp, "ConfbridgeList")
+            .filter_map(ConfbridgeParticipant::from_packet)
+            .collect())
+    }
+
+    /// Lists all active conference rooms
+    pub async fn confbridge_list_rooms(
+        &self,
+    ) -> Result<Vec<ConfbridgeRoom>, Error> {
+        let resp = match self
+            .send_action(vec![Tag::from("Action", "ConfbridgeListRooms")])
+            .await
+        {
+            Ok(resp) => resp,
+            // Asterisk reports the absence of conferences as an error
+            Err(Error::ActionFailed(message))
+                if message.starts_with("No active conferences") =>
+            {
+                return Ok(vec![]);
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(list_items(&resp, "ConfbridgeListRooms")
+            .filter_map(ConfbridgeRoom::from_packet)
+            .collect())
+    }
+
+    /// Kicks a channel out of a conference
+    ///
+    /// `channel` may also be `all` or `participants` to kick all (non-admin) participants.
+    pub async fn confbridge_kick(
+        &self,
+        conference: &str,
+        channel: &str,
+    ) -> Result<(), Error> {
+        self.confbridge_channel_action("ConfbridgeKick", conference, channel)
+            .await
+    }
+
+    /// Mutes a channel in a conference
+    pub async fn confbridge_mute(
+        &self,
+        conference: &str,
+        channel: &str,
+    ) -> Result<(), Error> {
+        self.confbridge_channel_action("ConfbridgeMute", conference, channel)
+            .await
+    }
+
+    /// Unmutes a channel in a conference
+    pub async fn confbridge_unmute(
+        &self,
+        conference: &str,
+        channel: &str,
+    ) -> Result<(), Error> {
+        self.confbridge_channel_action("ConfbridgeUnmute", conference, channel)
+            .await
+    }
+
+    /// Locks a conference so that no further participants can join
+    pub async fn confbridge_lock(&self, conference: &str) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "ConfbridgeLock"),
+            Tag::from("Conference", conference),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Unlocks a previously locked conference
+    pub async fn confbridge_unlock(
+        &self,
+        conference: &str,
+    ) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "ConfbridgeUnlock"),
+            Tag::from("Conference", conference),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Starts recording a conference
+    ///
+    /// # Arguments
+    ///
+    /// * `conference` - name of the conference to record
+    /// * `file` - file to record to, the format is derived from its extension. If `None` the
+    ///   file configured in the bridge profile (or a generated name) is used.
+    pub async fn confbridge_start_record(
+        &self,
+        conference: &str,
+        file: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut pkt = vec![
+            Tag::from("Action", "ConfbridgeStartRecord"),
+            Tag::from("Conference", conference),
+        ];
+        if let Some(file) = file {
+            if file.trim().is_empty() || file.contains(&['\r', '\n'][..]) {
+                return Err(Error::InvalidArgument(format!(
+                    "not a valid recording file name: {:?}",
+                    file
+                )));
+            }
+            pkt.push(Tag::from("RecordFile", file));
+        }
+        self.send_action(pkt).await?;
+        Ok(())
+    }
+
+    /// Stops recording a conference
+    pub async fn confbridge_stop_record(
+        &self,
+        conference: &str,
+    ) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "ConfbridgeStopRecord"),
+            Tag::from("Conference", conference),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    async fn confbridge_channel_action(
+        &self,
+        action: &str,
+        conference: &str,
+        channel: &str,
+    ) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", action),
+            Tag::from("Conference", conference),
+            Tag::from("Channel", channel),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_participant() {
+        let participant = ConfbridgeParticipant::from_packet(&packet_from(&[
+            ("Event", "ConfbridgeList"),
+            ("Conference", "1000"),
+            ("CallerIDNum", "100"),
+            ("CallerIDName", "Alice"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("Admin", "Yes"),
+            ("MarkedUser", "No"),
+            ("Muted", "No"),
+        ]))
+        .unwrap();
+        assert!(participant.admin);
+        assert!(!participant.marked);
+        assert_eq!(participant.caller_id_name.as_deref(), Some("Alice"));
+    }
+
+    #[test]
+    fn parses_recording_events() {
+        let event = |name| {
+            packet_from(&[
+                ("Event", name),
+                ("Conference", "1000"),
+                ("BridgeUniqueid", "3a4c6e5b"),
+            ])
+        };
+        assert_eq!(
+            ConfbridgeRecording::from_event(&event("ConfbridgeRecord")),
+            Some(ConfbridgeRecording::Started {
+                conference: "1000".to_string()
+            })
+        );
+        assert_eq!(
+            ConfbridgeRecording::from_event(&event("ConfbridgeStopRecord")),
+            Some(ConfbridgeRecording::Stopped {
+                conference: "1000".to_string()
+            })
+        );
+        assert_eq!(
+            ConfbridgeRecording::from_event(&event("ConfbridgeJoin")),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_room() {
+        let room = ConfbridgeRoom::from_packet(&packet_from(&[
+            ("Event", "ConfbridgeListRooms"),
+            ("Conference", "1000"),
+            ("Parties", "3"),
+            ("Marked", "1"),
+            ("Locked", "Yes"),
+            ("Muted", "No"),
+        ]))
+        .unwrap();
+        assert_eq!(room.conference, "1000");
+        assert_eq!(room.parties, 3);
+        assert_eq!(room.marked, 1);
+        assert!(room.locked);
+        assert!(!room.muted);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn lists_no_participants_without_conferences() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let messages = [
+            "No active conferences.",
+            "No Conference by that name found.",
+        ];
+        let (results, _) = tokio::join!(
+            async {
+                vec![
+                    connection.confbridge_list("1000").await,
+                    connection.confbridge_list("1001").await,
+                ]
+            },
+            async {
+                for message in messages {
+                    let action = server.read_packet().await.unwrap();
+                    let mut pkt = packet_from(&[
+                        ("Response", "Error"),
+                        ("Message", message),
+                    ]);
+                    let action_id = find_tag(&action, "ActionID").unwrap();
+                    pkt.push(Tag::from("ActionID", action_id));
+                    server.send_packet(&pkt).await.unwrap();
+                }
+            }
+        );
+        let mut results = results.into_iter();
+        assert_eq!(results.next().unwrap().unwrap(), vec![]);
+        assert!(matches!(
+            results.next().unwrap(),
+            Err(Error::ActionFailed(m)) if m.starts_with("No Conference")
+        ));
+    }
+}
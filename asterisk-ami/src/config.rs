@@ -0,0 +1,117 @@
+use crate::{AmiConnection, Error, Packet, Tag};
+use std::collections::BTreeMap;
+
+/// A category of a configuration file as returned by `GetConfig`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ConfigCategory {
+    pub name: String,
+    /// The templates the category inherits from
+    pub templates: Vec<String>,
+    pub is_template: bool,
+    /// The `key=value` lines of the category in file order
+    pub lines: Vec<(String, String)>,
+}
+
+impl ConfigCategory {
+    /// Returns the value of the first line with the given key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Reassembles the numbered `Category-NNNNNN` and `Line-NNNNNN-NNNNNN` headers
+fn parse_categories(pkt: &Packet) -> Vec<ConfigCategory> {
+    let mut categories = BTreeMap::<u32, ConfigCategory>::new();
+    let mut lines = BTreeMap::<(u32, u32), (String, String)>::new();
+    for tag in pkt {
+        let mut parts = tag.key.split('-');
+        let kind = parts.next().unwrap_or_default();
+        let numbers = parts
+            .map(|n| n.parse::<u32>().ok())
+            .collect::<Option<Vec<_>>>();
+        match (kind.to_ascii_lowercase().as_str(), numbers.as_deref()) {
+            ("category", Some(&[n])) => {
+                categories.entry(n).or_default().name = tag.value.clone()
+            }
+            ("templates", Some(&[n])) => {
+                categories.entry(n).or_default().templates = tag
+                    .value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            ("istemplate", Some(&[n])) => {
+                categories.entry(n).or_default().is_template = tag.value == "1"
+            }
+            ("line", Some(&[c, l])) => {
+                let (key, value) =
+                    tag.value.split_once('=').unwrap_or((&tag.value, ""));
+                lines.insert(
+                    (c, l),
+                    (key.trim().to_string(), value.trim().to_string()),
+                );
+            }
+            _ => {}
+        }
+    }
+    for ((c, _), line) in lines {
+        if let Some(category) = categories.get_mut(&c) {
+            category.lines.push(line);
+        }
+    }
+    categories.into_values().collect()
+}
+
+impl AmiConnection {
+    /// Reads a configuration file, e.g. `pjsip.conf`
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - the configuration file to read
+    /// * `category` - only return the category with this name
+    pub async fn get_config(
+        &self,
+        filename: &str,
+        category: Option<&str>,
+    ) -> Result<Vec<ConfigCategory>, Error> {
+        let mut pkt = vec![
+            Tag::from("Action", "GetConfig"),
+            Tag::from("Filename", filename),
+        ];
+        if let Some(category) = category {
+            pkt.push(Tag::from("Category", category));
+        }
+        let resp = self.send_action(pkt).await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        Ok(parse_categories(first))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn reassembles_categories() {
+        let categories = parse_categories(&packet_from(&[
+            ("Response", "Success"),
+            ("Category-000000", "transport-udp"),
+            ("Line-000000-000000", "type=transport"),
+            ("Line-000000-000001", "bind=0.0.0.0"),
+            ("Category-000001", "100"),
+            ("Templates-000001", "endpoint-template"),
+            ("Line-000001-000000", "type=endpoint"),
+            ("Line-000001-000001", "allow=!all,ulaw"),
+        ]));
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories[0].get("bind"), Some("0.0.0.0"));
+        assert_eq!(categories[1].templates, vec!["endpoint-template"]);
+        assert_eq!(categories[1].get("allow"), Some("!all,ulaw"));
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{AmiConnection, Error, Tag};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone, PartialEq, Eq)]
+struct Secret {
+    username: String,
+    secret: String,
+}
+
+/// Login credentials that can be replaced while the application is running
+///
+/// Clones share the credentials: an application keeps one clone to log in with, e.g. every
+/// time it reconnects, and hands another one to whatever enforces the rotation policy, which
+/// calls `rotate` when a new secret has been issued. Connections that are already logged in
+/// are not affected. Transports like TLS are set up by the application before calling
+/// `AmiConnection::from_stream`, so it reloads their certificates and keys itself.
+#[derive(Clone)]
+pub struct Credentials {
+    current: Arc<RwLock<Secret>>,
+}
+
+impl Credentials {
+    /// Creates credentials for the manager user `username`, as configured in `manager.conf`
+    pub fn new(username: &str, secret: &str) -> Self {
+        Credentials {
+            current: Arc::new(RwLock::new(Secret {
+                username: username.to_string(),
+                secret: secret.to_string(),
+            })),
+        }
+    }
+
+    /// Replaces the credentials used by the next login
+    pub fn rotate(&self, username: &str, secret: &str) {
+        *self.current.write().unwrap() = Secret {
+            username: username.to_string(),
+            secret: secret.to_string(),
+        };
+    }
+
+    /// The user name the next login will use
+    pub fn username(&self) -> String {
+        self.current.read().unwrap().username.clone()
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username())
+            .field("secret", &"********")
+            .finish()
+    }
+}
+
+impl AmiConnection {
+    /// Logs in with the current `credentials`
+    ///
+    /// Fails with `Error::ActionFailed` carrying the server's message, usually
+    /// `Authentication failed`, if the server rejects them.
+    pub async fn login(&self, credentials: &Credentials) -> Result<(), Error> {
+        let Secret { username, secret } =
+            credentials.current.read().unwrap().clone();
+        self.send_action(vec![
+            Tag::from("Action", "Login"),
+            Tag::from("Username", &username),
+            Tag::from("Secret", &secret),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_secret() {
+        let credentials = Credentials::new("admin", "s3cr3t");
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("s3cr3t"));
+        assert!(debug.contains("admin"));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn logs_in_with_rotated_secret() {
+        use crate::{find_tag, packet_from};
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let credentials = Credentials::new("admin", "old");
+        let rotation = credentials.clone();
+        let mut secrets = vec![];
+        for secret in ["old", "new"].iter() {
+            let (result, _) =
+                tokio::join!(connection.login(&credentials), async {
+                    let login = server.read_packet().await.unwrap();
+                    secrets.push(find_tag(&login, "Secret").unwrap().clone());
+                    let resp = packet_from(&[
+                        ("Response", "Success"),
+                        ("ActionID", find_tag(&login, "ActionID").unwrap()),
+                    ]);
+                    server.send_packet(&resp).await.unwrap();
+                });
+            result.unwrap();
+            assert_eq!(secrets.last().unwrap(), secret);
+            rotation.rotate("admin", "new");
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::{find_tag, list_items, AmiConnection, Error, Tag};
+
+/// The state of a device as reported in the `State` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    Unknown,
+    NotInUse,
+    InUse,
+    Busy,
+    Invalid,
+    Unavailable,
+    Ringing,
+    RingInUse,
+    OnHold,
+}
+
+impl DeviceState {
+    /// Parses a device state as sent by Asterisk
+    ///
+    /// Both the constant form (e.g. `NOT_INUSE`) and the descriptive form used by PJSIP
+    /// listings (e.g. `Not in use`) are accepted.
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_uppercase().as_str() {
+            "NOT_INUSE" | "NOT IN USE" => DeviceState::NotInUse,
+            "INUSE" | "IN USE" => DeviceState::InUse,
+            "BUSY" => DeviceState::Busy,
+            "INVALID" => DeviceState::Invalid,
+            "UNAVAILABLE" => DeviceState::Unavailable,
+            "RINGING" => DeviceState::Ringing,
+            "RINGINUSE" | "RING+INUSE" => DeviceState::RingInUse,
+            "ONHOLD" | "ON HOLD" => DeviceState::OnHold,
+            _ => DeviceState::Unknown,
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Lists the states of all devices known to the server
+    pub async fn device_state_list(
+        &self,
+    ) -> Result<Vec<(String, DeviceState)>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "DeviceStateList")])
+            .await?;
+        Ok(list_items(&resp, "DeviceStateChange")
+            .filter_map(|pkt| {
+                let device = find_tag(pkt, "Device")?.clone();
+                let state = DeviceState::parse(find_tag(pkt, "State")?);
+                Some((device, state))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_forms_of_device_states() {
+        assert_eq!(DeviceState::parse("NOT_INUSE"), DeviceState::NotInUse);
+        assert_eq!(DeviceState::parse("Not in use"), DeviceState::NotInUse);
+        assert_eq!(DeviceState::parse("RING+INUSE"), DeviceState::RingInUse);
+        assert_eq!(DeviceState::parse("On Hold"), DeviceState::OnHold);
+        assert_eq!(DeviceState::parse("CUSTOM"), DeviceState::Unknown);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn lists_device_states() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (states, _) = tokio::join!(connection.device_state_list(), async {
+            let action = server.read_packet().await.unwrap();
+            let action_id = find_tag(&action, "ActionID").unwrap();
+            for tags in [
+                &[("Response", "Success"), ("EventList", "start")][..],
+                &[
+                    ("Event", "DeviceStateChange"),
+                    ("Device", "PJSIP/100"),
+                    ("State", "INUSE"),
+                ],
+                &[
+                    ("Event", "DeviceStateListComplete"),
+                    ("EventList", "Complete"),
+                ],
+            ] {
+                let mut pkt = crate::packet_from(tags);
+                pkt.push(Tag::from("ActionID", action_id));
+                server.send_packet(&pkt).await.unwrap();
+            }
+        });
+        assert_eq!(
+            states.unwrap(),
+            vec![("PJSIP/100".to_string(), DeviceState::InUse)]
+        );
+    }
+}
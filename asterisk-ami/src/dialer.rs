@@ -0,0 +1,221 @@
+use crate::originate::{CallProgress, OriginateFailure, OriginateRequest};
+use crate::{AmiConnection, Error};
+use log::{debug, trace};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
+
+/// How a call placed by the `Dialer` ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialOutcome {
+    Answered,
+    /// The server reported the call as not answered
+    Failed(OriginateFailure),
+    /// The originate has been rejected, or the connection was lost before its result was known
+    Error(Error),
+}
+
+/// The outcome of a single request of a `Dialer` run
+#[derive(Debug, Clone, PartialEq)]
+pub struct DialResult {
+    pub request: OriginateRequest,
+    pub outcome: DialOutcome,
+    /// Name of the originated channel, if it has been created
+    pub channel: Option<String>,
+}
+
+/// The results of a `Dialer` run, in the order of the requests
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DialSummary {
+    pub results: Vec<DialResult>,
+}
+
+impl DialSummary {
+    /// The number of calls that have been answered
+    pub fn answered(&self) -> usize {
+        self.count(|o| matches!(o, DialOutcome::Answered))
+    }
+
+    /// The number of calls the server reported as not answered
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, DialOutcome::Failed(_)))
+    }
+
+    /// The number of requests that could not be originated
+    pub fn errors(&self) -> usize {
+        self.count(|o| matches!(o, DialOutcome::Error(_)))
+    }
+
+    fn count(&self, f: impl Fn(&DialOutcome) -> bool) -> usize {
+        self.results.iter().filter(|r| f(&r.outcome)).count()
+    }
+}
+
+/// Originates a list of calls with pacing, e.g. for a campaign
+///
+/// New calls are started at most at `calls_per_second`, and no more than `max_concurrent`
+/// calls are in progress at once. A call counts as in progress until it has been answered or
+/// failed, so answered calls do not hold back the next ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dialer {
+    calls_per_second: f64,
+    max_concurrent: usize,
+}
+
+impl Default for Dialer {
+    fn default() -> Self {
+        Dialer {
+            calls_per_second: 1.0,
+            max_concurrent: 10,
+        }
+    }
+}
+
+impl Dialer {
+    /// Creates a dialer starting one call per second, with up to ten in progress
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the rate calls are started at, fractions like `0.5` are allowed
+    ///
+    /// A rate of `0` or less disables pacing, calls are then only limited by
+    /// `max_concurrent`.
+    pub fn calls_per_second(mut self, calls_per_second: f64) -> Self {
+        self.calls_per_second = calls_per_second;
+        self
+    }
+
+    /// Sets the number of calls in progress at once, at least one
+    pub fn max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent.max(1);
+        self
+    }
+
+    fn pace(&self) -> Option<Duration> {
+        if self.calls_per_second > 0.0 && self.calls_per_second.is_finite() {
+            Some(Duration::from_secs_f64(1.0 / self.calls_per_second))
+        } else {
+            None
+        }
+    }
+
+    /// Originates all `requests` and waits until each of them has been answered or failed
+    pub async fn run(
+        &self,
+        connection: &AmiConnection,
+        requests: impl IntoIterator<Item = OriginateRequest>,
+    ) -> DialSummary {
+        let slots = Arc::new(Semaphore::new(self.max_concurrent));
+        let mut pace = self.pace().map(|pace| {
+            let mut interval = tokio::time::interval(pace);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+        let mut calls = vec![];
+        for request in requests {
+            let slot = slots.clone().acquire_owned().await.unwrap();
+            if let Some(pace) = &mut pace {
+                pace.tick().await;
+            }
+            let connection = connection.clone();
+            calls.push(tokio::spawn(async move {
+                let (outcome, channel) = dial(&connection, &request).await;
+                drop(slot);
+                DialResult {
+                    request,
+                    outcome,
+                    channel,
+                }
+            }));
+        }
+        let mut summary = DialSummary::default();
+        for call in calls {
+            match call.await {
+                Ok(result) => summary.results.push(result),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+        debug!(
+            "Dialer finished: {} answered, {} failed, {} errors",
+            summary.answered(),
+            summary.failed(),
+            summary.errors()
+        );
+        summary
+    }
+}
+
+/// Places a single call and follows it to its final state
+async fn dial(
+    connection: &AmiConnection,
+    request: &OriginateRequest,
+) -> (DialOutcome, Option<String>) {
+    let mut call = match connection.originate(request).await {
+        Ok(call) => call,
+        Err(e) => return (DialOutcome::Error(e), None),
+    };
+    trace!("Dialer started originate {}", call.action_id());
+    let outcome = match call.wait().await {
+        Ok(CallProgress::Failed(failure)) => DialOutcome::Failed(failure),
+        Ok(_) => DialOutcome::Answered,
+        Err(e) => DialOutcome::Error(e),
+    };
+    (outcome, call.state().channel)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::{find_tag, packet_from as event};
+
+    #[tokio::test]
+    async fn limits_calls_in_progress() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let requests = ["PJSIP/100", "PJSIP/101"].iter().map(|channel| {
+            OriginateRequest::to_extension(channel, "default", "200", 1)
+        });
+        let dialer = Dialer::new().calls_per_second(1000.0).max_concurrent(1);
+        let (summary, _) =
+            tokio::join!(dialer.run(&connection, requests), async {
+                let first = server.read_packet().await.unwrap();
+                let action_id = find_tag(&first, "ActionID").unwrap();
+                let accepted =
+                    event(&[("Response", "Success"), ("ActionID", action_id)]);
+                server.send_packet(&accepted).await.unwrap();
+                let waiting = tokio::time::timeout(
+                    Duration::from_millis(50),
+                    server.read_packet(),
+                );
+                assert!(waiting.await.is_err());
+                let answered = event(&[
+                    ("Event", "OriginateResponse"),
+                    ("ActionID", action_id),
+                    ("Response", "Success"),
+                    ("Channel", "PJSIP/100-00000001"),
+                ]);
+                server.send_packet(&answered).await.unwrap();
+
+                let second = server.read_packet().await.unwrap();
+                assert_eq!(find_tag(&second, "Channel").unwrap(), "PJSIP/101");
+                let rejected = event(&[
+                    ("Response", "Error"),
+                    ("ActionID", find_tag(&second, "ActionID").unwrap()),
+                    ("Message", "Originate failed"),
+                ]);
+                server.send_packet(&rejected).await.unwrap();
+            });
+
+        assert_eq!((summary.answered(), summary.errors()), (1, 1));
+        let first = &summary.results[0];
+        assert_eq!(first.channel.as_deref(), Some("PJSIP/100-00000001"));
+        assert_eq!(
+            summary.results[1].outcome,
+            DialOutcome::Error(Error::ActionFailed(
+                "Originate failed".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// Errors reported by the higher level helpers of this crate
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The connection to the Asterisk server has been closed
+    ConnectionClosed,
+    /// The server answered an action with `Response: Error`, the value is
+    /// the `Message` sent along with it
+    ActionFailed(String),
+    /// The logged in user is not allowed to use the action, or the server does not know it
+    UnsupportedAction(String),
+    /// The channel an operation was performed on has been hung up
+    Hangup,
+    /// The server's response lacks information the helper expected
+    UnexpectedResponse,
+    /// An argument passed to a helper cannot be sent to the server
+    InvalidArgument(String),
+    /// The command queue is full, see `AmiConnection::try_send`
+    QueueFull,
+    /// No response has been received by the deadline, see `AmiConnection::send_by`
+    DeadlineExceeded,
+    /// The data does not fit into the memory budget, see `AmiConnection::set_memory_budget`
+    MemoryLimitExceeded,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConnectionClosed => write!(f, "connection closed"),
+            Error::ActionFailed(message) => {
+                write!(f, "action failed: {}", message)
+            }
+            Error::UnsupportedAction(action) => {
+                write!(f, "unsupported action: {}", action)
+            }
+            Error::Hangup => write!(f, "channel hung up"),
+            Error::UnexpectedResponse => write!(f, "unexpected response"),
+            Error::InvalidArgument(reason) => {
+                write!(f, "invalid argument: {}", reason)
+            }
+            Error::QueueFull => write!(f, "command queue full"),
+            Error::DeadlineExceeded => write!(f, "deadline exceeded"),
+            Error::MemoryLimitExceeded => write!(f, "memory limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
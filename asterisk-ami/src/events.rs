@@ -0,0 +1,259 @@
+//! The names of the standard events
+//!
+//! Every event is available as a constant, e.g. `events::NEWCHANNEL`, for comparing against
+//! `event_name`, and as a variant of `EventName` for matching on. Both are generated from
+//! the same list by the `events!` macro below, so adding an event is a single line.
+
+use crate::{event_name, Error, Packet};
+use std::fmt;
+use std::str::FromStr;
+
+macro_rules! events {
+    ($($variant:ident, $constant:ident = $name:literal;)*) => {
+        $(
+            #[doc = concat!("`", $name, "`")]
+            pub const $constant: &str = $name;
+        )*
+
+        /// A standard event
+        ///
+        /// Events added by later versions of Asterisk or by third-party modules have no
+        /// variant; `EventName::from_packet` returns `None` for them.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum EventName {
+            $(
+                #[doc = concat!("`", $name, "`")]
+                $variant,
+            )*
+        }
+
+        impl EventName {
+            /// All standard events
+            pub const ALL: &'static [EventName] = &[$(EventName::$variant,)*];
+
+            /// The name as sent in the `Event` header
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(EventName::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+events! {
+    AgentCalled, AGENT_CALLED = "AgentCalled";
+    AgentComplete, AGENT_COMPLETE = "AgentComplete";
+    AgentConnect, AGENT_CONNECT = "AgentConnect";
+    AgentDump, AGENT_DUMP = "AgentDump";
+    AgentLogin, AGENT_LOGIN = "AgentLogin";
+    AgentLogoff, AGENT_LOGOFF = "AgentLogoff";
+    AgentRingNoAnswer, AGENT_RING_NO_ANSWER = "AgentRingNoAnswer";
+    Agents, AGENTS = "Agents";
+    AgentsComplete, AGENTS_COMPLETE = "AgentsComplete";
+    AgiExecEnd, AGI_EXEC_END = "AGIExecEnd";
+    AgiExecStart, AGI_EXEC_START = "AGIExecStart";
+    Alarm, ALARM = "Alarm";
+    AlarmClear, ALARM_CLEAR = "AlarmClear";
+    AocD, AOC_D = "AOC-D";
+    AocE, AOC_E = "AOC-E";
+    AocS, AOC_S = "AOC-S";
+    AsyncAgiEnd, ASYNC_AGI_END = "AsyncAGIEnd";
+    AsyncAgiExec, ASYNC_AGI_EXEC = "AsyncAGIExec";
+    AsyncAgiStart, ASYNC_AGI_START = "AsyncAGIStart";
+    AttendedTransfer, ATTENDED_TRANSFER = "AttendedTransfer";
+    AuthMethodNotAllowed, AUTH_METHOD_NOT_ALLOWED = "AuthMethodNotAllowed";
+    BlindTransfer, BLIND_TRANSFER = "BlindTransfer";
+    BridgeCreate, BRIDGE_CREATE = "BridgeCreate";
+    BridgeDestroy, BRIDGE_DESTROY = "BridgeDestroy";
+    BridgeEnter, BRIDGE_ENTER = "BridgeEnter";
+    BridgeInfoChannel, BRIDGE_INFO_CHANNEL = "BridgeInfoChannel";
+    BridgeInfoComplete, BRIDGE_INFO_COMPLETE = "BridgeInfoComplete";
+    BridgeLeave, BRIDGE_LEAVE = "BridgeLeave";
+    BridgeListComplete, BRIDGE_LIST_COMPLETE = "BridgeListComplete";
+    BridgeListItem, BRIDGE_LIST_ITEM = "BridgeListItem";
+    BridgeMerge, BRIDGE_MERGE = "BridgeMerge";
+    Cdr, CDR = "Cdr";
+    Cel, CEL = "CEL";
+    ChallengeResponseFailed, CHALLENGE_RESPONSE_FAILED = "ChallengeResponseFailed";
+    ChallengeSent, CHALLENGE_SENT = "ChallengeSent";
+    ChanSpyStart, CHAN_SPY_START = "ChanSpyStart";
+    ChanSpyStop, CHAN_SPY_STOP = "ChanSpyStop";
+    ChannelTalkingStart, CHANNEL_TALKING_START = "ChannelTalkingStart";
+    ChannelTalkingStop, CHANNEL_TALKING_STOP = "ChannelTalkingStop";
+    ConfbridgeEnd, CONFBRIDGE_END = "ConfbridgeEnd";
+    ConfbridgeJoin, CONFBRIDGE_JOIN = "ConfbridgeJoin";
+    ConfbridgeLeave, CONFBRIDGE_LEAVE = "ConfbridgeLeave";
+    ConfbridgeList, CONFBRIDGE_LIST = "ConfbridgeList";
+    ConfbridgeListComplete, CONFBRIDGE_LIST_COMPLETE = "ConfbridgeListComplete";
+    ConfbridgeListRooms, CONFBRIDGE_LIST_ROOMS = "ConfbridgeListRooms";
+    ConfbridgeListRoomsComplete, CONFBRIDGE_LIST_ROOMS_COMPLETE = "ConfbridgeListRoomsComplete";
+    ConfbridgeMute, CONFBRIDGE_MUTE = "ConfbridgeMute";
+    ConfbridgeRecord, CONFBRIDGE_RECORD = "ConfbridgeRecord";
+    ConfbridgeStart, CONFBRIDGE_START = "ConfbridgeStart";
+    ConfbridgeStopRecord, CONFBRIDGE_STOP_RECORD = "ConfbridgeStopRecord";
+    ConfbridgeTalking, CONFBRIDGE_TALKING = "ConfbridgeTalking";
+    ConfbridgeUnmute, CONFBRIDGE_UNMUTE = "ConfbridgeUnmute";
+    ContactList, CONTACT_LIST = "ContactList";
+    ContactListComplete, CONTACT_LIST_COMPLETE = "ContactListComplete";
+    ContactStatus, CONTACT_STATUS = "ContactStatus";
+    ContactStatusDetail, CONTACT_STATUS_DETAIL = "ContactStatusDetail";
+    CoreShowChannel, CORE_SHOW_CHANNEL = "CoreShowChannel";
+    CoreShowChannelsComplete, CORE_SHOW_CHANNELS_COMPLETE = "CoreShowChannelsComplete";
+    DahdiChannel, DAHDI_CHANNEL = "DAHDIChannel";
+    DeviceStateChange, DEVICE_STATE_CHANGE = "DeviceStateChange";
+    DeviceStateListComplete, DEVICE_STATE_LIST_COMPLETE = "DeviceStateListComplete";
+    DialBegin, DIAL_BEGIN = "DialBegin";
+    DialEnd, DIAL_END = "DialEnd";
+    DialState, DIAL_STATE = "DialState";
+    DndState, DND_STATE = "DNDState";
+    DtmfBegin, DTMF_BEGIN = "DTMFBegin";
+    DtmfEnd, DTMF_END = "DTMFEnd";
+    EndpointDetail, ENDPOINT_DETAIL = "EndpointDetail";
+    EndpointDetailComplete, ENDPOINT_DETAIL_COMPLETE = "EndpointDetailComplete";
+    EndpointList, ENDPOINT_LIST = "EndpointList";
+    EndpointListComplete, ENDPOINT_LIST_COMPLETE = "EndpointListComplete";
+    ExtensionStateListComplete, EXTENSION_STATE_LIST_COMPLETE = "ExtensionStateListComplete";
+    ExtensionStatus, EXTENSION_STATUS = "ExtensionStatus";
+    FailedAcl, FAILED_ACL = "FailedACL";
+    FaxSession, FAX_SESSION = "FAXSession";
+    FaxSessionsComplete, FAX_SESSIONS_COMPLETE = "FAXSessionsComplete";
+    FaxStats, FAX_STATS = "FAXStats";
+    FaxStatus, FAX_STATUS = "FAXStatus";
+    Flash, FLASH = "Flash";
+    FullyBooted, FULLY_BOOTED = "FullyBooted";
+    Hangup, HANGUP = "Hangup";
+    HangupHandlerPop, HANGUP_HANDLER_POP = "HangupHandlerPop";
+    HangupHandlerPush, HANGUP_HANDLER_PUSH = "HangupHandlerPush";
+    HangupHandlerRun, HANGUP_HANDLER_RUN = "HangupHandlerRun";
+    HangupRequest, HANGUP_REQUEST = "HangupRequest";
+    Hold, HOLD = "Hold";
+    InvalidAccountId, INVALID_ACCOUNT_ID = "InvalidAccountID";
+    InvalidPassword, INVALID_PASSWORD = "InvalidPassword";
+    InvalidTransport, INVALID_TRANSPORT = "InvalidTransport";
+    LoadAverageLimit, LOAD_AVERAGE_LIMIT = "LoadAverageLimit";
+    LocalBridge, LOCAL_BRIDGE = "LocalBridge";
+    LocalOptimizationBegin, LOCAL_OPTIMIZATION_BEGIN = "LocalOptimizationBegin";
+    LocalOptimizationEnd, LOCAL_OPTIMIZATION_END = "LocalOptimizationEnd";
+    Mcid, MCID = "MCID";
+    MessageWaiting, MESSAGE_WAITING = "MessageWaiting";
+    MixMonitorMute, MIX_MONITOR_MUTE = "MixMonitorMute";
+    MixMonitorStart, MIX_MONITOR_START = "MixMonitorStart";
+    MixMonitorStop, MIX_MONITOR_STOP = "MixMonitorStop";
+    MusicOnHoldStart, MUSIC_ON_HOLD_START = "MusicOnHoldStart";
+    MusicOnHoldStop, MUSIC_ON_HOLD_STOP = "MusicOnHoldStop";
+    MwiGet, MWI_GET = "MWIGet";
+    MwiGetComplete, MWI_GET_COMPLETE = "MWIGetComplete";
+    NewAccountCode, NEW_ACCOUNT_CODE = "NewAccountCode";
+    NewCallerid, NEW_CALLERID = "NewCallerid";
+    NewConnectedLine, NEW_CONNECTED_LINE = "NewConnectedLine";
+    Newchannel, NEWCHANNEL = "Newchannel";
+    Newexten, NEWEXTEN = "Newexten";
+    Newstate, NEWSTATE = "Newstate";
+    OriginateResponse, ORIGINATE_RESPONSE = "OriginateResponse";
+    ParkedCall, PARKED_CALL = "ParkedCall";
+    ParkedCallGiveUp, PARKED_CALL_GIVE_UP = "ParkedCallGiveUp";
+    ParkedCallSwap, PARKED_CALL_SWAP = "ParkedCallSwap";
+    ParkedCallTimeOut, PARKED_CALL_TIME_OUT = "ParkedCallTimeOut";
+    ParkedCallsComplete, PARKED_CALLS_COMPLETE = "ParkedCallsComplete";
+    PeerEntry, PEER_ENTRY = "PeerEntry";
+    PeerStatus, PEER_STATUS = "PeerStatus";
+    PeerlistComplete, PEERLIST_COMPLETE = "PeerlistComplete";
+    Pickup, PICKUP = "Pickup";
+    PresenceStateChange, PRESENCE_STATE_CHANGE = "PresenceStateChange";
+    PresenceStateListComplete, PRESENCE_STATE_LIST_COMPLETE = "PresenceStateListComplete";
+    PresenceStatus, PRESENCE_STATUS = "PresenceStatus";
+    QueueCallerAbandon, QUEUE_CALLER_ABANDON = "QueueCallerAbandon";
+    QueueCallerJoin, QUEUE_CALLER_JOIN = "QueueCallerJoin";
+    QueueCallerLeave, QUEUE_CALLER_LEAVE = "QueueCallerLeave";
+    QueueEntry, QUEUE_ENTRY = "QueueEntry";
+    QueueMember, QUEUE_MEMBER = "QueueMember";
+    QueueMemberAdded, QUEUE_MEMBER_ADDED = "QueueMemberAdded";
+    QueueMemberPause, QUEUE_MEMBER_PAUSE = "QueueMemberPause";
+    QueueMemberPenalty, QUEUE_MEMBER_PENALTY = "QueueMemberPenalty";
+    QueueMemberRemoved, QUEUE_MEMBER_REMOVED = "QueueMemberRemoved";
+    QueueMemberRinginuse, QUEUE_MEMBER_RINGINUSE = "QueueMemberRinginuse";
+    QueueMemberStatus, QUEUE_MEMBER_STATUS = "QueueMemberStatus";
+    QueueParams, QUEUE_PARAMS = "QueueParams";
+    QueueStatusComplete, QUEUE_STATUS_COMPLETE = "QueueStatusComplete";
+    QueueSummary, QUEUE_SUMMARY = "QueueSummary";
+    QueueSummaryComplete, QUEUE_SUMMARY_COMPLETE = "QueueSummaryComplete";
+    ReceiveFax, RECEIVE_FAX = "ReceiveFAX";
+    RegistrationsComplete, REGISTRATIONS_COMPLETE = "RegistrationsComplete";
+    Registry, REGISTRY = "Registry";
+    RegistryEntry, REGISTRY_ENTRY = "RegistryEntry";
+    Reload, RELOAD = "Reload";
+    Rename, RENAME = "Rename";
+    RequestBadFormat, REQUEST_BAD_FORMAT = "RequestBadFormat";
+    RequestNotAllowed, REQUEST_NOT_ALLOWED = "RequestNotAllowed";
+    RtcpReceived, RTCP_RECEIVED = "RTCPReceived";
+    RtcpSent, RTCP_SENT = "RTCPSent";
+    SendFax, SEND_FAX = "SendFAX";
+    SessionTimeout, SESSION_TIMEOUT = "SessionTimeout";
+    Shutdown, SHUTDOWN = "Shutdown";
+    SoftHangupRequest, SOFT_HANGUP_REQUEST = "SoftHangupRequest";
+    SpanAlarm, SPAN_ALARM = "SpanAlarm";
+    SpanAlarmClear, SPAN_ALARM_CLEAR = "SpanAlarmClear";
+    Status, STATUS = "Status";
+    StatusComplete, STATUS_COMPLETE = "StatusComplete";
+    SuccessfulAuth, SUCCESSFUL_AUTH = "SuccessfulAuth";
+    UnParkedCall, UN_PARKED_CALL = "UnParkedCall";
+    UnexpectedAddress, UNEXPECTED_ADDRESS = "UnexpectedAddress";
+    Unhold, UNHOLD = "Unhold";
+    UserEvent, USER_EVENT = "UserEvent";
+    VarSet, VAR_SET = "VarSet";
+    VoicemailUserEntry, VOICEMAIL_USER_ENTRY = "VoicemailUserEntry";
+    VoicemailUserEntryComplete, VOICEMAIL_USER_ENTRY_COMPLETE = "VoicemailUserEntryComplete";
+    Wink, WINK = "Wink";
+}
+
+impl EventName {
+    /// The name of an event, `None` if it is not a standard event
+    pub fn from_packet(pkt: &Packet) -> Option<Self> {
+        event_name(pkt)?.parse().ok()
+    }
+}
+
+impl FromStr for EventName {
+    type Err = Error;
+
+    /// Parses an event name, ignoring case like `find_tag` does for keys
+    fn from_str(name: &str) -> Result<Self, Error> {
+        EventName::ALL
+            .iter()
+            .find(|event| event.as_str().eq_ignore_ascii_case(name))
+            .copied()
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("unknown event {}", name))
+            })
+    }
+}
+
+impl fmt::Display for EventName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn names_standard_events() {
+        let hangup = event(&[("Event", "Hangup"), ("Channel", "PJSIP/100")]);
+        assert_eq!(EventName::from_packet(&hangup), Some(EventName::Hangup));
+        assert_eq!(event_name(&hangup).unwrap(), HANGUP);
+        assert_eq!("aoc-d".parse::<EventName>(), Ok(EventName::AocD));
+        assert_eq!(EventName::RtcpSent.to_string(), "RTCPSent");
+        assert!("NoSuchEvent".parse::<EventName>().is_err());
+        for (n, event) in EventName::ALL.iter().enumerate() {
+            let duplicate = EventName::ALL[..n].iter().any(|earlier| {
+                earlier.as_str().eq_ignore_ascii_case(event.as_str())
+            });
+            assert!(!duplicate, "{} listed twice", event);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+
+/// A fax session as listed by `FAXSessions`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaxSession {
+    pub channel: String,
+    pub technology: Option<String>,
+    pub session_number: Option<u32>,
+    /// `G.711` or `T.38`
+    pub session_type: Option<String>,
+    /// `send`, `receive`, `gateway`, or `V.21`
+    pub operation: Option<String>,
+    pub state: Option<String>,
+    pub files: Vec<String>,
+}
+
+impl FaxSession {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(FaxSession {
+            channel: find_tag(pkt, "Channel")?.clone(),
+            technology: find_value(pkt, "Technology"),
+            session_number: find_tag(pkt, "SessionNumber")
+                .and_then(|n| n.trim().parse().ok()),
+            session_type: find_value(pkt, "SessionType"),
+            operation: find_value(pkt, "Operation"),
+            state: find_value(pkt, "State"),
+            files: find_value(pkt, "Files")
+                .map(|f| f.split(',').map(|f| f.trim().to_string()).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// Fax statistics as returned by `FAXStats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FaxStats {
+    pub current_sessions: u32,
+    pub reserved_sessions: u32,
+    pub transmit_attempts: u32,
+    pub receive_attempts: u32,
+    pub completed: u32,
+    pub failed: u32,
+}
+
+/// Whether a fax has been sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaxDirection {
+    Send,
+    Receive,
+}
+
+/// A `SendFAX` or `ReceiveFAX` event reporting the result of a fax transmission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaxResult {
+    pub direction: FaxDirection,
+    pub channel: String,
+    pub local_station_id: Option<String>,
+    pub remote_station_id: Option<String>,
+    pub pages_transferred: u32,
+    pub resolution: Option<String>,
+    pub transfer_rate: Option<u32>,
+    pub files: Vec<String>,
+}
+
+impl FaxResult {
+    /// Parses a `SendFAX` or `ReceiveFAX` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        let event = event_name(pkt)?;
+        let direction = if event.eq_ignore_ascii_case("SendFAX") {
+            FaxDirection::Send
+        } else if event.eq_ignore_ascii_case("ReceiveFAX") {
+            FaxDirection::Receive
+        } else {
+            return None;
+        };
+        Some(FaxResult {
+            direction,
+            channel: find_tag(pkt, "Channel")?.clone(),
+            local_station_id: find_value(pkt, "LocalStationID"),
+            remote_station_id: find_value(pkt, "RemoteStationID"),
+            pages_transferred: find_tag(pkt, "PagesTransferred")
+                .and_then(|p| p.trim().parse().ok())
+                .unwrap_or(0),
+            resolution: find_value(pkt, "Resolution"),
+            transfer_rate: find_tag(pkt, "TransferRate")
+                .and_then(|r| r.trim().parse().ok()),
+            files: pkt
+                .iter()
+                .filter(|t| t.key.eq_ignore_ascii_case("FileName"))
+                .map(|t| t.value.clone())
+                .collect(),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Lists all active fax sessions
+    pub async fn fax_sessions(&self) -> Result<Vec<FaxSession>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "FAXSessions")])
+            .await?;
+        Ok(list_items(&resp, "FAXSessionsEntry")
+            .filter_map(FaxSession::from_list_item)
+            .collect())
+    }
+
+    /// Queries the fax statistics
+    pub async fn fax_stats(&self) -> Result<FaxStats, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "FAXStats")])
+            .await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        let count = |key| {
+            find_tag(first, key)
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Ok(FaxStats {
+            current_sessions: count("CurrentSessions"),
+            reserved_sessions: count("ReservedSessions"),
+            transmit_attempts: count("TransmitAttempts"),
+            receive_attempts: count("ReceiveAttempts"),
+            completed: count("CompletedFAXes"),
+            failed: count("FailedFAXes"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_receive_fax_event() {
+        let result = FaxResult::from_event(&packet_from(&[
+            ("Event", "ReceiveFAX"),
+            ("Channel", "PJSIP/fax-00000003"),
+            ("RemoteStationID", "+49301234"),
+            ("PagesTransferred", "3"),
+            ("TransferRate", "14400"),
+            ("FileName", "/var/spool/fax/in.tif"),
+        ]))
+        .unwrap();
+        assert_eq!(result.direction, FaxDirection::Receive);
+        assert_eq!(result.pages_transferred, 3);
+        assert_eq!(result.files, vec!["/var/spool/fax/in.tif"]);
+    }
+}
@@ -0,0 +1,367 @@
+//! C API, enabled by the `ffi` feature
+//!
+//! The header is `include/asterisk_ami.h`, generated from this module by the build, link
+//! against the library built using `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! Each connection runs on a tokio runtime of its own. Callbacks are invoked on one of its
+//! threads, the packets passed to them are only valid until the callback returns.
+//!
+//! ```c
+//! static void on_response(void *user_data, const AmiPacket *packets, size_t count) {
+//!     for (size_t i = 0; i < count; i++)
+//!         for (size_t j = 0; j < packets[i].len; j++)
+//!             printf("%s: %s\n", packets[i].tags[j].key, packets[i].tags[j].value);
+//! }
+//!
+//! AmiConnection *conn = ami_connect("127.0.0.1:5038");
+//! AmiTag ping[] = {{"Action", "Ping"}};
+//! ami_send(conn, ping, 1, on_response, NULL);
+//! ```
+
+use crate::{Packet, Tag};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::ptr;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+/// A connection to an Asterisk server
+pub struct FfiConnection {
+    runtime: Runtime,
+    connection: crate::AmiConnection,
+}
+
+/// Events delivered to a callback registered using `ami_subscribe`
+pub struct FfiSubscription {
+    task: JoinHandle<()>,
+}
+
+/// A line of a packet, both strings are NUL terminated
+#[repr(C)]
+pub struct AmiTag {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+/// A packet of `len` tags
+#[repr(C)]
+pub struct AmiPacket {
+    pub tags: *const AmiTag,
+    pub len: usize,
+}
+
+/// Called with the packets of a response, `count` is 0 if the connection has been closed
+pub type AmiResponseCallback = Option<
+    extern "C" fn(
+        user_data: *mut c_void,
+        packets: *const AmiPacket,
+        count: usize,
+    ),
+>;
+
+/// Called with each event, `event` is NULL once the connection has been closed
+pub type AmiEventCallback =
+    Option<extern "C" fn(user_data: *mut c_void, event: *const AmiPacket)>;
+
+/// The user data of a callback, the caller is responsible for it being usable from any thread
+struct UserData(*mut c_void);
+
+unsafe impl Send for UserData {}
+
+/// A packet converted for C, the pointers in `tags` point into `strings`
+struct CPacket {
+    _strings: Vec<CString>,
+    tags: Vec<AmiTag>,
+}
+
+impl CPacket {
+    fn new(pkt: &Packet) -> CPacket {
+        // Lines read from the server cannot contain NUL bytes
+        let c_string = |s: &str| CString::new(s).unwrap_or_default();
+        let strings = pkt
+            .iter()
+            .flat_map(|tag| [c_string(&tag.key), c_string(&tag.value)])
+            .collect::<Vec<_>>();
+        let tags = strings
+            .chunks(2)
+            .map(|pair| AmiTag {
+                key: pair[0].as_ptr(),
+                value: pair[1].as_ptr(),
+            })
+            .collect();
+        CPacket {
+            _strings: strings,
+            tags,
+        }
+    }
+
+    fn as_packet(&self) -> AmiPacket {
+        AmiPacket {
+            tags: self.tags.as_ptr(),
+            len: self.tags.len(),
+        }
+    }
+}
+
+/// Connects to `address` (`host:port`), returns NULL if the connection fails
+///
+/// # Safety
+///
+/// `address` must be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ami_connect(
+    address: *const c_char,
+) -> *mut FfiConnection {
+    if address.is_null() {
+        return ptr::null_mut();
+    }
+    let address = match CStr::from_ptr(address).to_str() {
+        Ok(address) => address,
+        Err(_) => return ptr::null_mut(),
+    };
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(_) => return ptr::null_mut(),
+    };
+    match runtime.block_on(crate::AmiConnection::connect(address)) {
+        Ok(connection) => Box::into_raw(Box::new(FfiConnection {
+            runtime,
+            connection,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Closes a connection returned by `ami_connect`
+///
+/// Subscriptions of the connection stop receiving events and must still be freed using
+/// `ami_unsubscribe`. Callbacks may still be running when this returns, their user data has
+/// to stay valid until they are done.
+///
+/// # Safety
+///
+/// `connection` must have been returned by `ami_connect` and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ami_free(connection: *mut FfiConnection) {
+    if !connection.is_null() {
+        let FfiConnection {
+            runtime,
+            connection,
+        } = *Box::from_raw(connection);
+        drop(connection);
+        runtime.shutdown_background();
+    }
+}
+
+/// Sends an action of `len` tags without waiting for the response, which is passed to
+/// `callback`
+///
+/// An `ActionID` is added if the action does not contain one. Returns 0 if the action has
+/// been queued, and -1 if the arguments are invalid or `callback` is NULL.
+///
+/// # Safety
+///
+/// `connection` must be a live connection, `tags` must point to `len` tags of NUL
+/// terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn ami_send(
+    connection: *mut FfiConnection,
+    tags: *const AmiTag,
+    len: usize,
+    callback: AmiResponseCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    let (connection, callback) = match (connection.as_ref(), callback) {
+        (Some(connection), Some(callback)) => (connection, callback),
+        _ => return -1,
+    };
+    if tags.is_null() && len > 0 {
+        return -1;
+    }
+    let mut pkt = Vec::with_capacity(len);
+    for i in 0..len {
+        let tag = &*tags.add(i);
+        if tag.key.is_null() || tag.value.is_null() {
+            return -1;
+        }
+        match (
+            CStr::from_ptr(tag.key).to_str(),
+            CStr::from_ptr(tag.value).to_str(),
+        ) {
+            (Ok(key), Ok(value)) => pkt.push(Tag::from(key, value)),
+            _ => return -1,
+        }
+    }
+    let ami = connection.connection.clone();
+    ami.assign_action_id(&mut pkt);
+    let user_data = UserData(user_data);
+    connection.runtime.spawn(async move {
+        let user_data = user_data;
+        let resp = ami.send(pkt).await.unwrap_or_default();
+        let c_packets = resp.iter().map(CPacket::new).collect::<Vec<_>>();
+        let packets =
+            c_packets.iter().map(CPacket::as_packet).collect::<Vec<_>>();
+        callback(user_data.0, packets.as_ptr(), packets.len());
+    });
+    0
+}
+
+/// Passes the events received from now on to `callback`, returns NULL if `connection` or
+/// `callback` is NULL
+///
+/// # Safety
+///
+/// `connection` must be a live connection.
+#[no_mangle]
+pub unsafe extern "C" fn ami_subscribe(
+    connection: *mut FfiConnection,
+    callback: AmiEventCallback,
+    user_data: *mut c_void,
+) -> *mut FfiSubscription {
+    let (connection, callback) = match (connection.as_ref(), callback) {
+        (Some(connection), Some(callback)) => (connection, callback),
+        _ => return ptr::null_mut(),
+    };
+    let mut events = connection.connection.events();
+    let user_data = UserData(user_data);
+    let task = connection.runtime.spawn(async move {
+        let user_data = user_data;
+        loop {
+            match events.recv().await {
+                Ok(Some(event)) => {
+                    let event = CPacket::new(&event);
+                    callback(user_data.0, &event.as_packet());
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Ok(None) | Err(RecvError::Closed) => {
+                    callback(user_data.0, ptr::null());
+                    return;
+                }
+            }
+        }
+    });
+    Box::into_raw(Box::new(FfiSubscription { task }))
+}
+
+/// Stops passing events to the callback of a subscription and frees it
+///
+/// The callback may still be running when this returns.
+///
+/// # Safety
+///
+/// `subscription` must have been returned by `ami_subscribe` and not yet been freed.
+#[no_mangle]
+pub unsafe extern "C" fn ami_unsubscribe(subscription: *mut FfiSubscription) {
+    if !subscription.is_null() {
+        Box::from_raw(subscription).task.abort();
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::MockAmiServer;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    extern "C" fn collect(
+        user_data: *mut c_void,
+        packets: *const AmiPacket,
+        count: usize,
+    ) {
+        let tx = unsafe { &*(user_data as *const mpsc::Sender<Packet>) };
+        for i in 0..count {
+            let packet = unsafe { &*packets.add(i) };
+            let tags = (0..packet.len).map(|j| unsafe {
+                let tag = &*packet.tags.add(j);
+                Tag::from(
+                    CStr::from_ptr(tag.key).to_str().unwrap(),
+                    CStr::from_ptr(tag.value).to_str().unwrap(),
+                )
+            });
+            tx.send(tags.collect()).unwrap();
+        }
+    }
+
+    #[test]
+    fn sends_actions() {
+        let runtime = Runtime::new().unwrap();
+        let server = runtime.block_on(MockAmiServer::start()).unwrap();
+        server.respond(
+            "CoreStatus",
+            vec![vec![
+                Tag::from("Response", "Success"),
+                Tag::from("CoreCurrentCalls", "3"),
+            ]],
+        );
+
+        let address = CString::new(server.addr().to_string()).unwrap();
+        let key = CString::new("Action").unwrap();
+        let value = CString::new("CoreStatus").unwrap();
+        let action = [AmiTag {
+            key: key.as_ptr(),
+            value: value.as_ptr(),
+        }];
+        let (tx, rx) = mpsc::channel::<Packet>();
+        // the callback may still use the sender after the response has been received
+        let tx: &'static _ = Box::leak(Box::new(tx));
+        unsafe {
+            let connection = ami_connect(address.as_ptr());
+            assert!(!connection.is_null());
+            let user_data = tx as *const _ as *mut c_void;
+            assert_eq!(
+                ami_send(
+                    connection,
+                    action.as_ptr(),
+                    1,
+                    Some(collect),
+                    user_data
+                ),
+                0
+            );
+            let resp = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(
+                crate::find_tag(&resp, "CoreCurrentCalls").map(String::as_str),
+                Some("3")
+            );
+            let received = server.received();
+            assert!(crate::find_tag(&received[0], "ActionID").is_some());
+            assert_eq!(
+                ami_send(
+                    ptr::null_mut(),
+                    action.as_ptr(),
+                    1,
+                    Some(collect),
+                    user_data
+                ),
+                -1
+            );
+            assert_eq!(
+                ami_send(connection, action.as_ptr(), 1, None, user_data),
+                -1
+            );
+            assert!(ami_subscribe(connection, None, user_data).is_null());
+            ami_free(connection);
+        }
+    }
+}
+
+/// Checks the header in `include/`, also without the `testing` feature
+#[cfg(test)]
+mod header {
+    #[test]
+    fn is_up_to_date() {
+        let generated =
+            include_str!(concat!(env!("OUT_DIR"), "/asterisk_ami.h"));
+        assert_eq!(
+            include_str!("../include/asterisk_ami.h"),
+            generated,
+            "Copy the generated header from {} to include/",
+            env!("OUT_DIR")
+        );
+    }
+}
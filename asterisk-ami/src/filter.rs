@@ -0,0 +1,326 @@
+use crate::{AmiConnection, Error, Packet};
+use regex::Regex;
+use std::str::FromStr;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How a header is compared to a value
+#[derive(Debug, Clone)]
+enum Comparison {
+    Equals(String),
+    Matches(Regex),
+}
+
+/// A node of a parsed filter expression
+#[derive(Debug, Clone)]
+enum Node {
+    /// The event has a header with the given key
+    Exists(String),
+    /// One of the headers with the given key compares positively
+    Compare(String, Comparison),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn matches(&self, pkt: &Packet) -> bool {
+        let values = |key: &str| {
+            let key = key.to_string();
+            pkt.iter()
+                .filter(move |t| t.key.eq_ignore_ascii_case(&key))
+                .map(|t| t.value.as_str())
+        };
+        match self {
+            Node::Exists(key) => values(key).next().is_some(),
+            Node::Compare(key, Comparison::Equals(value)) => {
+                values(key).any(|v| v == value)
+            }
+            Node::Compare(key, Comparison::Matches(regex)) => {
+                values(key).any(|v| regex.is_match(v))
+            }
+            Node::Not(node) => !node.matches(pkt),
+            Node::And(a, b) => a.matches(pkt) && b.matches(pkt),
+            Node::Or(a, b) => a.matches(pkt) || b.matches(pkt),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Key(String),
+    Value(String),
+    Equals,
+    NotEquals,
+    Matches,
+    NotMatches,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let error = |at: usize, what: &str| {
+        Error::InvalidArgument(format!("{} at offset {}", what, at))
+    };
+    let mut tokens = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let mut next_is =
+            |expected| chars.next_if(|&(_, c)| c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Equals,
+            '=' if next_is('~') => Token::Matches,
+            '!' if next_is('=') => Token::NotEquals,
+            '!' if next_is('~') => Token::NotMatches,
+            '!' => Token::Not,
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => {
+                            value.extend(chars.next().map(|(_, c)| c))
+                        }
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(at, "unterminated string")),
+                    }
+                }
+                Token::Value(value)
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '-' => {
+                let mut key = c.to_string();
+                while let Some((_, c)) = chars.next_if(|&(_, c)| {
+                    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+                }) {
+                    key.push(c);
+                }
+                Token::Key(key)
+            }
+            _ => return Err(error(at, &format!("unexpected {:?}", c))),
+        };
+        tokens.push((at, token));
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of an expression
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>,
+    end: usize,
+}
+
+impl Parser {
+    fn error(&mut self, what: &str) -> Error {
+        let at = self.tokens.peek().map_or(self.end, |(at, _)| *at);
+        Error::InvalidArgument(format!("{} at offset {}", what, at))
+    }
+
+    fn next_is(&mut self, expected: &Token) -> bool {
+        self.tokens.next_if(|(_, t)| t == expected).is_some()
+    }
+
+    fn or(&mut self) -> Result<Node, Error> {
+        let mut node = self.and()?;
+        while self.next_is(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, Error> {
+        let mut node = self.unary()?;
+        while self.next_is(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.unary()?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        if self.next_is(&Token::Not) {
+            return Ok(Node::Not(Box::new(self.unary()?)));
+        }
+        if self.next_is(&Token::Open) {
+            let node = self.or()?;
+            if !self.next_is(&Token::Close) {
+                return Err(self.error("expected )"));
+            }
+            return Ok(node);
+        }
+        let key = match self.tokens.next_if(|(_, t)| matches!(t, Token::Key(_)))
+        {
+            Some((_, Token::Key(key))) => key,
+            _ => return Err(self.error("expected a header")),
+        };
+        let operator = self.tokens.next_if(|(_, t)| {
+            matches!(
+                t,
+                Token::Equals
+                    | Token::NotEquals
+                    | Token::Matches
+                    | Token::NotMatches
+            )
+        });
+        let operator = match operator {
+            Some((_, operator)) => operator,
+            None => return Ok(Node::Exists(key)),
+        };
+        let value = match self.tokens.next() {
+            Some((_, Token::Value(value))) => value,
+            _ => return Err(self.error("expected a quoted value")),
+        };
+        let comparison = match operator {
+            Token::Equals | Token::NotEquals => Comparison::Equals(value),
+            _ => Comparison::Matches(Regex::new(&value).map_err(|e| {
+                Error::InvalidArgument(format!("invalid regex: {}", e))
+            })?),
+        };
+        let node = Node::Compare(key, comparison);
+        Ok(match operator {
+            Token::NotEquals | Token::NotMatches => Node::Not(Box::new(node)),
+            _ => node,
+        })
+    }
+}
+
+/// A filter selecting packets by their headers, parsed from an expression
+///
+/// Comparisons take a header, an operator, and a quoted value: `==` and `!=` compare
+/// verbatim, `=~` and `!~` match a regular expression. A header without an operator checks
+/// that it is present at all. Header names are case-insensitive; if a header appears more
+/// than once, like `Variable`, a comparison holds if it holds for one of them. Comparisons are
+/// combined using `!`, `&&`, `||`, and parentheses:
+///
+/// ```
+/// # use asterisk_ami::filter::Filter;
+/// let filter: Filter = r#"Event == "Hangup" && Channel =~ "^PJSIP/trunk""#
+///     .parse()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expression: String,
+    root: Node,
+}
+
+impl Filter {
+    /// Parses a filter expression, failing with `Error::InvalidArgument` if it is malformed
+    pub fn parse(expression: &str) -> Result<Filter, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?.into_iter().peekable(),
+            end: expression.len(),
+        };
+        let root = parser.or()?;
+        if parser.tokens.peek().is_some() {
+            return Err(parser.error("unexpected token"));
+        }
+        Ok(Filter {
+            expression: expression.to_string(),
+            root,
+        })
+    }
+
+    /// Checks if a packet is selected by the filter
+    pub fn matches(&self, pkt: &Packet) -> bool {
+        self.root.matches(pkt)
+    }
+
+    /// The expression the filter has been parsed from
+    pub fn expression(&self) -> &str {
+        &self.expression
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Filter::parse(expression)
+    }
+}
+
+/// The events of a connection selected by a `Filter`
+pub struct FilteredEvents {
+    events: broadcast::Receiver<Option<Packet>>,
+    filter: Filter,
+}
+
+impl FilteredEvents {
+    /// Waits for the next event selected by the filter
+    ///
+    /// Returns `None` once the connection has been closed. Events missed because the
+    /// subscriber fell behind are skipped.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        loop {
+            match self.events.recv().await {
+                Ok(Some(pkt)) if self.filter.matches(&pkt) => return Some(pkt),
+                Ok(Some(_)) => {}
+                Err(RecvError::Lagged(n)) => crate::metrics::events_lagged(n),
+                Ok(None) | Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Subscribes to the events selected by `filter`
+    pub fn events_matching(&self, filter: Filter) -> FilteredEvents {
+        FilteredEvents {
+            events: self.events(),
+            filter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn matches_expressions() {
+        let hangup = event(&[
+            ("Event", "Hangup"),
+            ("Channel", "PJSIP/trunk-00000001"),
+            ("Cause", "16"),
+        ]);
+        let matches =
+            |expression| Filter::parse(expression).unwrap().matches(&hangup);
+        assert!(matches(r#"Event == "Hangup" && Channel =~ "^PJSIP/trunk""#));
+        assert!(matches(r#"event == "Hangup" && !(Cause != "16")"#));
+        assert!(matches(r#"Event == "Newstate" || Cause"#));
+        assert!(!matches(r#"Event == "Hangup" && Uniqueid"#));
+        assert!(!matches(r#"Channel !~ "trunk" || Event == "hangup""#));
+        assert!(matches(r#"Channel == "PJSIP/trunk-\"00000001\"" || Cause"#));
+    }
+
+    #[test]
+    fn reports_malformed_expressions() {
+        for expression in [
+            r#"Event == Hangup"#,
+            r#"Event == "Hangup"#,
+            r#"(Event == "Hangup""#,
+            r#"Event == "Hangup" &&"#,
+            r#"Event = "Hangup""#,
+            r#"Channel =~ "(""#,
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(
+                    Filter::parse(expression),
+                    Err(Error::InvalidArgument(_))
+                ),
+                "{} should be rejected",
+                expression
+            );
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! A corpus of AMI captures for parser tests
+//!
+//! The captures follow the output of the respective Asterisk versions, names, numbers and
+//! addresses are replaced by placeholders from documentation ranges.
+//! The captures cover the kinds of traffic that have caused parsing problems in the past:
+//! event floods, list responses with unrelated events in between and the `Response: Follows`
+//! output of older Asterisk versions. Downstream crates can use them (or their own captures
+//! via `parse_capture`) to test code consuming packets. Enabled by the `testing` feature.
+
+use crate::response::{Response, ResponseBuilder};
+use crate::Packet;
+
+/// A captured exchange with an Asterisk server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixture {
+    /// The name of the capture, e.g. `asterisk18-event-flood`
+    pub name: &'static str,
+    /// The Asterisk version the capture was taken from
+    pub asterisk: &'static str,
+    /// The data sent by the server, without the greeting
+    pub raw: &'static str,
+}
+
+macro_rules! fixture {
+    ($name:literal, $asterisk:literal) => {
+        Fixture {
+            name: $name,
+            asterisk: $asterisk,
+            raw: include_str!(concat!("../fixtures/", $name, ".ami")),
+        }
+    };
+}
+
+/// All bundled captures
+pub const FIXTURES: &[Fixture] = &[
+    fixture!("asterisk13-follows", "13"),
+    fixture!("asterisk16-core-show-channels", "16"),
+    fixture!("asterisk18-event-flood", "18"),
+    fixture!("asterisk20-command-output", "20"),
+];
+
+/// Returns the bundled capture with the given name
+pub fn fixture(name: &str) -> Option<&'static Fixture> {
+    FIXTURES.iter().find(|f| f.name == name)
+}
+
+impl Fixture {
+    /// Runs the capture through the parser used by `AmiConnection`
+    ///
+    /// See `parse_capture` for the meaning of `action_id`.
+    pub fn parse(&self, action_id: Option<&str>) -> ParsedCapture {
+        parse_capture(self.raw, action_id)
+    }
+}
+
+/// The packets the parser produced for a capture
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedCapture {
+    /// The responses in the order they were completed, each with all its packets
+    pub responses: Vec<Vec<Packet>>,
+    /// The standalone events
+    pub events: Vec<Packet>,
+}
+
+/// Runs raw server data through the parser used by `AmiConnection`
+///
+/// `action_id` is the ActionID of the command whose response is expected, events with a
+/// different ActionID are passed on as standalone events while a list response is read.
+pub fn parse_capture(raw: &str, action_id: Option<&str>) -> ParsedCapture {
+    let mut builder = ResponseBuilder::new();
+    builder.expect_action_id(action_id.map(String::from));
+    let mut parsed = ParsedCapture::default();
+    for line in raw.lines() {
+        match builder.add_line(line.trim()) {
+            Some(Response::CommandResponse(packets)) => {
+                parsed.responses.push(packets)
+            }
+            Some(Response::Event(event)) => parsed.events.push(event),
+            None => {}
+        }
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_name, find_tag};
+
+    #[test]
+    fn parses_all_fixtures() {
+        // (fixture, expected ActionID, packets per response, events)
+        let table: &[(&str, Option<&str>, &[usize], usize)] = &[
+            ("asterisk13-follows", None, &[1, 1], 0),
+            ("asterisk16-core-show-channels", Some("1"), &[4], 1),
+            ("asterisk18-event-flood", None, &[], 6),
+            ("asterisk20-command-output", None, &[1, 1], 1),
+        ];
+        assert_eq!(table.len(), FIXTURES.len());
+        for (name, action_id, packets, events) in table {
+            let parsed = fixture(name).unwrap().parse(*action_id);
+            let lengths = parsed.responses.iter().map(Vec::len);
+            assert_eq!(lengths.collect::<Vec<_>>(), *packets, "{}", name);
+            assert_eq!(parsed.events.len(), *events, "{}", name);
+            assert!(parsed.events.iter().all(|e| event_name(e).is_some()));
+        }
+    }
+
+    #[test]
+    fn keeps_follows_output_together() {
+        let parsed = fixture("asterisk13-follows").unwrap().parse(None);
+        let peers = &parsed.responses[1][0];
+        assert_eq!(find_tag(peers, "ActionID").map(String::as_str), Some("6"));
+        let output = peers
+            .iter()
+            .filter(|t| t.key == "Output")
+            .map(|t| t.value.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(output.len(), 4);
+        assert_eq!(output[1], "");
+        assert_eq!(output[3], "1 sip peers [Monitored: 1 online, 0 offline]");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{AmiConnection, Error, Tag};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Whether the connection to the server is still usable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The server connection has been lost, the `AmiConnection` has to be recreated
+    Closed,
+}
+
+/// A point-in-time view of the health of a connection, see `AmiConnection::health`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    pub state: ConnectionState,
+    /// When the last event has been received
+    pub last_event_at: Option<SystemTime>,
+    /// Round trip time of the last successful `ping`
+    pub last_ping_rtt: Option<Duration>,
+    /// Commands waiting to be sent to the server
+    pub pending_commands: usize,
+    /// Events buffered for the slowest subscriber
+    pub queued_events: usize,
+}
+
+/// Health related values updated by the connection
+#[derive(Debug)]
+pub(crate) struct HealthState {
+    /// Milliseconds since the epoch, `0` if no event has been received yet
+    last_event_at: AtomicU64,
+    /// Microseconds, `u64::MAX` if no ping has succeeded yet
+    last_ping_rtt: AtomicU64,
+}
+
+impl HealthState {
+    pub(crate) fn new() -> Self {
+        HealthState {
+            last_event_at: AtomicU64::new(0),
+            last_ping_rtt: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub(crate) fn event_received(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.last_event_at
+            .store(now.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn last_event_at(&self) -> Option<SystemTime> {
+        match self.last_event_at.load(Ordering::Relaxed) {
+            0 => None,
+            millis => Some(UNIX_EPOCH + Duration::from_millis(millis)),
+        }
+    }
+
+    fn last_ping_rtt(&self) -> Option<Duration> {
+        match self.last_ping_rtt.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            micros => Some(Duration::from_micros(micros)),
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Sends a `Ping` and returns the round trip time
+    pub async fn ping(&self) -> Result<Duration, Error> {
+        let started = Instant::now();
+        self.send_action(vec![Tag::from("Action", "Ping")]).await?;
+        let rtt = started.elapsed();
+        self.health
+            .last_ping_rtt
+            .store(rtt.as_micros() as u64, Ordering::Relaxed);
+        Ok(rtt)
+    }
+
+    /// Returns a snapshot of the health of the connection
+    ///
+    /// Meant for readiness and liveness checks. As the round trip time is only measured by
+    /// `ping`, services should ping periodically to keep it meaningful.
+    pub fn health(&self) -> Health {
+        Health {
+            state: if self.cmd_tx.is_closed() {
+                ConnectionState::Closed
+            } else {
+                ConnectionState::Connected
+            },
+            last_event_at: self.health.last_event_at(),
+            last_ping_rtt: self.health.last_ping_rtt(),
+            pending_commands: self.cmd_tx.max_capacity()
+                - self.cmd_tx.capacity(),
+            queued_events: self.events_tx.len(),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! A minimal HTTP endpoint exposing the health and statistics of a connection
+//!
+//! It serves two JSON documents and nothing else:
+//!
+//! - `GET /healthz`: the `Health` of the connection, with status `200` while it is connected
+//!   and `503` once it has been closed, for liveness probes of orchestrators
+//! - `GET /stats`: the `ConnectionStats` and the latencies of the actions sent
+//!
+//! Requests are parsed by hand, only what is needed to route them; this is not meant to be
+//! exposed outside of the host or cluster.
+
+use crate::health::ConnectionState;
+use crate::AmiConnection;
+use log::{debug, trace};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// Requests with larger headers are rejected
+const MAX_REQUEST: usize = 8192;
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Quotes a string for JSON
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Formats a duration as milliseconds, `null` if there is none
+fn json_millis(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{:.3}", duration.as_secs_f64() * 1000.0),
+        None => "null".to_string(),
+    }
+}
+
+/// The body of `/healthz` and whether the connection is healthy
+fn healthz(connection: &AmiConnection) -> (bool, String) {
+    let health = connection.health();
+    let connected = health.state == ConnectionState::Connected;
+    let last_event_age = health
+        .last_event_at
+        .map(|at| SystemTime::now().duration_since(at).unwrap_or_default());
+    let body = format!(
+        "{{\"state\":{},\"last_event_age_ms\":{},\"last_ping_rtt_ms\":{},\
+         \"pending_commands\":{},\"queued_events\":{}}}",
+        json_string(if connected { "connected" } else { "closed" }),
+        json_millis(last_event_age),
+        json_millis(health.last_ping_rtt),
+        health.pending_commands,
+        health.queued_events,
+    );
+    (connected, body)
+}
+
+/// The body of `/stats`
+fn stats(connection: &AmiConnection) -> String {
+    let stats = connection.connection_stats();
+    let actions = connection
+        .stats()
+        .iter()
+        .map(|action| {
+            format!(
+                "{{\"action\":{},\"count\":{},\"p50_ms\":{},\"p95_ms\":{},\
+                 \"max_ms\":{}}}",
+                json_string(&action.action),
+                action.count,
+                json_millis(Some(action.p50)),
+                json_millis(Some(action.p95)),
+                json_millis(Some(action.max)),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!(
+        "{{\"bytes_read\":{},\"bytes_written\":{},\"packets_parsed\":{},\
+         \"events_published\":{},\"events_dropped\":{},\
+         \"commands_completed\":{},\"commands_failed\":{},\
+         \"commands_expired\":{},\"uptime_ms\":{},\"actions\":[{}]}}",
+        stats.bytes_read,
+        stats.bytes_written,
+        stats.packets_parsed,
+        stats.events_published,
+        stats.events_dropped,
+        stats.commands_completed,
+        stats.commands_failed,
+        stats.commands_expired,
+        json_millis(Some(stats.uptime)),
+        actions,
+    )
+}
+
+/// Answers a request line like `GET /healthz HTTP/1.1` with a status and a JSON body
+fn route(connection: &AmiConnection, request_line: &str) -> (u16, String) {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return (400, "{\"error\":\"bad request\"}".to_string()),
+    };
+    let path = target.split('?').next().unwrap_or_default();
+    match (method, path) {
+        ("GET", "/healthz") => match healthz(connection) {
+            (true, body) => (200, body),
+            (false, body) => (503, body),
+        },
+        ("GET", "/stats") => (200, stats(connection)),
+        (_, "/healthz") | (_, "/stats") => {
+            (405, "{\"error\":\"method not allowed\"}".to_string())
+        }
+        _ => (404, "{\"error\":\"not found\"}".to_string()),
+    }
+}
+
+/// Reads the head of a request, returns its first line
+async fn read_request_line(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST {
+            return None;
+        }
+        let n = stream.read(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    request.lines().next().map(str::to_string)
+}
+
+async fn serve(connection: AmiConnection, mut stream: TcpStream) {
+    let request_line =
+        tokio::time::timeout(REQUEST_TIMEOUT, read_request_line(&mut stream));
+    let request_line = match request_line.await {
+        Ok(Some(request_line)) => request_line,
+        _ => return,
+    };
+    let (status, body) = route(&connection, &request_line);
+    trace!("{} answered with {}", request_line, status);
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Service Unavailable",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()).await {
+        debug!("Cannot answer health request: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
+
+/// An HTTP listener serving `/healthz` and `/stats` for a connection
+pub struct HealthServer {
+    local_addr: SocketAddr,
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl HealthServer {
+    /// Starts listening on `addr`, e.g. `127.0.0.1:9090`
+    pub async fn bind<A: ToSocketAddrs>(
+        addr: A,
+        connection: &AmiConnection,
+    ) -> std::io::Result<HealthServer> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let connection = connection.clone();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            debug!("Cannot accept health request: {}", e);
+                            continue;
+                        }
+                    },
+                };
+                tokio::spawn(serve(connection.clone(), stream));
+            }
+        });
+        Ok(HealthServer {
+            local_addr,
+            stop_tx,
+            task,
+        })
+    }
+
+    /// The address the server listens on, e.g. to find the port when bound to port `0`
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting requests
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(());
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_json_strings() {
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+        assert_eq!(json_millis(Some(Duration::from_micros(1500))), "1.500");
+        assert_eq!(json_millis(None), "null");
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn serves_health_and_stats() {
+        let (connection, server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let http = HealthServer::bind("127.0.0.1:0", &connection)
+            .await
+            .unwrap();
+        let addr = http.local_addr();
+        let get = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let healthz = get("GET /healthz HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert!(healthz.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(healthz.ends_with("\"queued_events\":0}"));
+        assert!(healthz.contains("{\"state\":\"connected\","));
+        let stats = get("GET /stats HTTP/1.1\r\n\r\n").await;
+        assert!(stats.contains("\"actions\":[]}"));
+        let missing = get("GET / HTTP/1.1\r\n\r\n").await;
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        drop(server);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let closed = get("GET /healthz HTTP/1.1\r\n\r\n").await;
+        assert!(closed.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        http.stop().await;
+    }
+}
@@ -0,0 +1,95 @@
+use crate::peers::Reachability;
+use crate::sip::{find_host, find_port, parse_qualify_status};
+use crate::{
+    find_flag, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::time::Duration;
+
+/// An IAX2 peer as listed by `IAXpeers` or `IAXpeerlist`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IaxPeer {
+    pub name: String,
+    pub username: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub dynamic: bool,
+    pub trunk: bool,
+    pub encryption: bool,
+    pub status: Reachability,
+    /// The status as sent by Asterisk, e.g. `OK (5 ms)`
+    pub status_text: Option<String>,
+    /// Round trip time of the last qualify
+    pub rtt: Option<Duration>,
+    pub description: Option<String>,
+}
+
+impl IaxPeer {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let status_text = find_value(pkt, "Status");
+        let (status, rtt) = parse_qualify_status(status_text.as_deref());
+        Some(IaxPeer {
+            name: find_tag(pkt, "ObjectName")?.clone(),
+            username: find_value(pkt, "ObjectUsername"),
+            host: find_host(pkt, "IPaddress"),
+            port: find_port(pkt, "IPport").or_else(|| find_port(pkt, "Port")),
+            dynamic: find_flag(pkt, "Dynamic"),
+            trunk: find_flag(pkt, "Trunk"),
+            encryption: find_tag(pkt, "Encryption")
+                .is_some_and(|e| !e.trim().eq_ignore_ascii_case("no")),
+            status,
+            status_text,
+            rtt,
+            description: find_value(pkt, "Description"),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Lists all IAX2 peers using `IAXpeers`
+    pub async fn iax_peers(&self) -> Result<Vec<IaxPeer>, Error> {
+        self.iax_peer_action("IAXpeers").await
+    }
+
+    /// Lists all IAX2 peers using `IAXpeerlist`, which also reports the peers' usernames
+    pub async fn iax_peer_list(&self) -> Result<Vec<IaxPeer>, Error> {
+        self.iax_peer_action("IAXpeerlist").await
+    }
+
+    async fn iax_peer_action(
+        &self,
+        action: &str,
+    ) -> Result<Vec<IaxPeer>, Error> {
+        let resp = self.send_action(vec![Tag::from("Action", action)]).await?;
+        Ok(list_items(&resp, "PeerEntry")
+            .filter_map(IaxPeer::from_list_item)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_peer_list_entry() {
+        let peer = IaxPeer::from_list_item(&packet_from(&[
+            ("Event", "PeerEntry"),
+            ("Channeltype", "IAX"),
+            ("ObjectName", "branch-office"),
+            ("ObjectUsername", "branch"),
+            ("IPaddress", "(null)"),
+            ("Port", "4569"),
+            ("Dynamic", "yes"),
+            ("Trunk", "yes"),
+            ("Encryption", "aes128"),
+            ("Status", "UNREACHABLE"),
+        ]))
+        .unwrap();
+        assert_eq!(peer.host, None);
+        assert_eq!(peer.port, Some(4569));
+        assert!(peer.trunk && peer.encryption);
+        assert_eq!(peer.status, Reachability::Unreachable);
+    }
+}
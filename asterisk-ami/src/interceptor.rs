@@ -0,0 +1,101 @@
+use crate::{Error, Packet};
+use std::sync::{Arc, RwLock};
+
+/// A hook observing or modifying the packets exchanged with the server
+///
+/// Interceptors are registered using `AmiConnection::add_interceptor` and called in the order
+/// they have been added. Both methods are called on the connection's hot path and should not
+/// block.
+pub trait Interceptor: Send + Sync {
+    /// Called for every action before it is sent
+    ///
+    /// The higher level helpers call it after assigning an `ActionID`. Actions passed to
+    /// `send`, `try_send`, or `send_by` are seen as given, with an `ActionID` only if the
+    /// caller has added one. Actions answered from the cache, see
+    /// `AmiConnection::cache_responses`, are not sent and not passed to it.
+    ///
+    /// Returning an error aborts the action; the remaining interceptors are not called.
+    fn outgoing(&self, _action: &mut Packet) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called for every packet received from the server, events as well as the packets of
+    /// responses
+    ///
+    /// Returning `false` drops an event before it reaches any subscriber. The packets of
+    /// responses are always passed on.
+    fn incoming(&self, _pkt: &mut Packet) -> bool {
+        true
+    }
+}
+
+/// The interceptors of a connection, shared with the task handling it
+#[derive(Default)]
+pub(crate) struct Interceptors {
+    chain: RwLock<Vec<Arc<dyn Interceptor>>>,
+}
+
+impl Interceptors {
+    pub(crate) fn add(&self, interceptor: Arc<dyn Interceptor>) {
+        self.chain.write().unwrap().push(interceptor);
+    }
+
+    pub(crate) fn outgoing(&self, action: &mut Packet) -> Result<(), Error> {
+        for interceptor in self.chain.read().unwrap().iter() {
+            interceptor.outgoing(action)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `false` if any interceptor wants the packet to be dropped
+    pub(crate) fn incoming(&self, pkt: &mut Packet) -> bool {
+        self.chain
+            .read()
+            .unwrap()
+            .iter()
+            .all(|interceptor| interceptor.incoming(pkt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{find_tag, packet_from, Tag};
+
+    struct Tenant;
+
+    impl Interceptor for Tenant {
+        fn outgoing(&self, action: &mut Packet) -> Result<(), Error> {
+            if find_tag(action, "Action").is_some_and(|a| a == "Command") {
+                return Err(Error::UnsupportedAction("Command".to_string()));
+            }
+            action.push(Tag::from("Variable", "TENANT=acme"));
+            Ok(())
+        }
+
+        fn incoming(&self, pkt: &mut Packet) -> bool {
+            pkt.retain(|tag| tag.key != "Privilege");
+            find_tag(pkt, "Event").is_none_or(|e| e != "RTCPSent")
+        }
+    }
+
+    #[test]
+    fn runs_interceptor_chain() {
+        let interceptors = Interceptors::default();
+        interceptors.add(Arc::new(Tenant));
+
+        let mut action = packet_from(&[("Action", "Originate")]);
+        interceptors.outgoing(&mut action).unwrap();
+        assert_eq!(find_tag(&action, "Variable").unwrap(), "TENANT=acme");
+        let mut command = packet_from(&[("Action", "Command")]);
+        assert!(interceptors.outgoing(&mut command).is_err());
+
+        let mut event =
+            packet_from(&[("Event", "Newchannel"), ("Privilege", "call,all")]);
+        assert!(interceptors.incoming(&mut event));
+        assert_eq!(event.len(), 1);
+        assert!(
+            !interceptors.incoming(&mut packet_from(&[("Event", "RTCPSent")]))
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! The keys of the standard headers of actions, responses, and events
+//!
+//! Asterisk is not consistent about their case, it's `Uniqueid` but `ActionID`, and while
+//! `find_tag` ignores case, actions built with a misspelt key are not understood. The
+//! constants are generated from the list below by the `keys!` macro.
+
+macro_rules! keys {
+    ($($constant:ident = $key:literal;)*) => {
+        $(
+            #[doc = concat!("`", $key, "`")]
+            pub const $constant: &str = $key;
+        )*
+
+        /// All standard keys
+        pub const ALL: &[&str] = &[$($key,)*];
+    };
+}
+
+keys! {
+    ACCOUNT_CODE = "AccountCode";
+    ACTION = "Action";
+    ACTION_ID = "ActionID";
+    ADDRESS = "Address";
+    AOR = "AOR";
+    APP_DATA = "AppData";
+    APPLICATION = "Application";
+    ASYNC = "Async";
+    BRIDGE_CREATOR = "BridgeCreator";
+    BRIDGE_NAME = "BridgeName";
+    BRIDGE_NUM_CHANNELS = "BridgeNumChannels";
+    BRIDGE_TECHNOLOGY = "BridgeTechnology";
+    BRIDGE_TYPE = "BridgeType";
+    BRIDGE_UNIQUEID = "BridgeUniqueid";
+    CALLER_ID = "CallerID";
+    CALLER_ID_NAME = "CallerIDName";
+    CALLER_ID_NUM = "CallerIDNum";
+    CALLS_TAKEN = "CallsTaken";
+    CAUSE = "Cause";
+    CAUSE_TXT = "Cause-txt";
+    CHANNEL = "Channel";
+    CHANNEL_STATE = "ChannelState";
+    CHANNEL_STATE_DESC = "ChannelStateDesc";
+    CHANNEL_TYPE = "ChannelType";
+    COMMAND = "Command";
+    CONFERENCE = "Conference";
+    CONNECTED_LINE_NAME = "ConnectedLineName";
+    CONNECTED_LINE_NUM = "ConnectedLineNum";
+    CONTEXT = "Context";
+    COUNT = "Count";
+    DATA = "Data";
+    DEST_CHANNEL = "DestChannel";
+    DEST_UNIQUEID = "DestUniqueid";
+    DEVICE = "Device";
+    DIAL_STATUS = "DialStatus";
+    DIGIT = "Digit";
+    DIRECTION = "Direction";
+    DURATION = "Duration";
+    ENDPOINT = "Endpoint";
+    EVENT = "Event";
+    EVENT_LIST = "EventList";
+    EVENT_MASK = "EventMask";
+    EXTEN = "Exten";
+    FAMILY = "Family";
+    FILENAME = "Filename";
+    HINT = "Hint";
+    INTERFACE = "Interface";
+    KEY = "Key";
+    LANGUAGE = "Language";
+    LINKEDID = "Linkedid";
+    LIST_ITEMS = "ListItems";
+    LOAD_TYPE = "LoadType";
+    MAILBOX = "Mailbox";
+    MEMBER_NAME = "MemberName";
+    MEMBERSHIP = "Membership";
+    MESSAGE = "Message";
+    MODULE = "Module";
+    NEW = "New";
+    OLD = "Old";
+    OUTPUT = "Output";
+    PARKEE_CHANNEL = "ParkeeChannel";
+    PARKEE_UNIQUEID = "ParkeeUniqueid";
+    PARKING_DURATION = "ParkingDuration";
+    PARKING_SPACE = "ParkingSpace";
+    PARKING_TIMEOUT = "ParkingTimeout";
+    PARKINGLOT = "Parkinglot";
+    PAUSED = "Paused";
+    PAUSED_REASON = "PausedReason";
+    PEER = "Peer";
+    PEER_STATUS = "PeerStatus";
+    PENALTY = "Penalty";
+    POSITION = "Position";
+    PRESENTITY = "Presentity";
+    PRIORITY = "Priority";
+    PRIVILEGE = "Privilege";
+    QUEUE = "Queue";
+    REASON = "Reason";
+    RESPONSE = "Response";
+    SECRET = "Secret";
+    STATE = "State";
+    STATE_INTERFACE = "StateInterface";
+    STATUS = "Status";
+    SYSTEM_NAME = "SystemName";
+    TIMEOUT = "Timeout";
+    UNIQUEID = "Uniqueid";
+    URI = "URI";
+    USERNAME = "Username";
+    VALUE = "Value";
+    VARIABLE = "Variable";
+    WAITING = "Waiting";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_key_once() {
+        assert!(ALL.contains(&UNIQUEID));
+        for (n, key) in ALL.iter().enumerate() {
+            let duplicate = ALL[..n]
+                .iter()
+                .any(|earlier| earlier.eq_ignore_ascii_case(key));
+            assert!(!duplicate, "{} listed twice", key);
+        }
+    }
+}
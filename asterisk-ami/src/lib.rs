@@ -1,22 +1,116 @@
+use cache::ResponseCache;
+use capabilities::Capabilities;
+use health::HealthState;
+use interceptor::{Interceptor, Interceptors};
+use limits::Limits;
 use log::{info, trace, warn};
+use memory::ResponseCharge;
 use response::{Response, ResponseBuilder};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use stats::{ActionStats, ConnectionStats, Counters, LatencyStats};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use subscription::Subscribers;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::net::{TcpStream, ToSocketAddrs};
 use tokio::sync::broadcast::Sender;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::{broadcast, mpsc, oneshot};
+use wire::{Direction, WireLine};
 
+pub mod agents;
+pub mod aggregator;
+pub mod aoc;
+#[cfg(feature = "sqlite")]
+pub mod archive;
+pub mod async_agi;
+pub mod bridges;
+mod cache;
+pub mod call_counts;
+pub mod call_events;
+pub mod capabilities;
+pub mod channels;
+pub mod coalesce;
+pub mod compat;
+pub mod confbridge;
+pub mod config;
+pub mod credentials;
+pub mod devices;
+pub mod dialer;
+mod error;
+pub mod events;
+pub mod fax;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "filter")]
+pub mod filter;
+#[cfg(feature = "testing")]
+pub mod fixtures;
+pub mod health;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod iax;
+pub mod interceptor;
+pub mod keys;
+mod limits;
+pub mod memory;
+pub mod metrics;
+pub mod originate;
+pub mod parking;
+pub mod pcapng;
+pub mod peers;
+pub mod pjsip;
+pub mod presence;
+#[cfg(feature = "python")]
+mod python;
+pub mod queues;
+pub mod recording;
+pub mod redaction;
 mod response;
+pub mod scheduler;
+pub mod schema;
+#[cfg(feature = "tower")]
+mod service;
+pub mod sip;
+pub mod spool;
+pub mod stats;
+pub mod subscription;
+#[cfg(unix)]
+pub mod syslog;
+pub mod system;
+mod telemetry;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod tracking;
+pub mod transfers;
+pub mod voicemail;
+pub mod wire;
+
+pub use error::Error;
 
 /// A tag is a single line of communication on the AMI
 ///
 /// It is similar to an entry in a map. It has a `key` and a `value`.
-#[derive(Debug, Clone, PartialOrd, PartialEq)]
+///
+/// The `Debug` output hides the values of credentials, see the `redaction` module.
+#[derive(Clone, PartialOrd, PartialEq)]
 pub struct Tag {
     pub key: String,
     pub value: String,
 }
 
+impl std::fmt::Debug for Tag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tag")
+            .field("key", &self.key)
+            .field("value", &redaction::value(&self.key, &self.value))
+            .finish()
+    }
+}
+
 impl Tag {
     pub fn of(key: String, value: String) -> Self {
         Self { key, value }
@@ -33,6 +127,9 @@ impl Tag {
 /// A `Packet` is a sequence of `Tag`s being transmitted over the AMI, terminated by an empty line
 pub type Packet = Vec<Tag>;
 
+/// How many events are buffered for subscribers that have not read them yet
+const EVENT_CAPACITY: usize = 32;
+
 /// A `Responder` is used to send back the result of a `Command`
 pub type Responder<T> = oneshot::Sender<T>;
 
@@ -41,12 +138,43 @@ pub type Responder<T> = oneshot::Sender<T>;
 #[derive(Debug)]
 struct Command {
     packet: Packet,
-    resp: Responder<Vec<Packet>>,
+    resp: Responder<Result<Vec<Packet>, Error>>,
+    /// The command fails with `Error::DeadlineExceeded` instead of being sent after this
+    not_after: Option<Instant>,
+}
+
+/// How a command waits for room in the command queue
+#[derive(Debug, Clone, Copy)]
+enum Enqueue {
+    /// As long as it takes
+    Wait,
+    /// Not at all
+    Try,
+    /// Until the deadline, which also applies to waiting for the response
+    Until(Instant),
+    /// As long as it takes, but the command is dropped if it is dequeued after the deadline
+    NotAfter(Instant),
 }
 
+/// A connection to an Asterisk server
+///
+/// Cloning the connection is cheap, all clones share the same server connection.
+#[derive(Clone)]
 pub struct AmiConnection {
     cmd_tx: mpsc::Sender<Command>,
     events_tx: broadcast::Sender<Option<Packet>>,
+    wire_tx: broadcast::Sender<WireLine>,
+    next_action_id: Arc<AtomicU64>,
+    action_id_prefix: Arc<RwLock<String>>,
+    capabilities: Arc<RwLock<Option<Arc<Capabilities>>>>,
+    latencies: Arc<Mutex<LatencyStats>>,
+    counters: Arc<Counters>,
+    health: Arc<HealthState>,
+    interceptors: Arc<Interceptors>,
+    limits: Arc<RwLock<Limits>>,
+    cache: Arc<Mutex<ResponseCache>>,
+    subscribers: Arc<Subscribers>,
+    ami_version: Option<Arc<str>>,
 }
 
 impl AmiConnection {
@@ -58,29 +186,97 @@ impl AmiConnection {
     pub async fn connect<A: ToSocketAddrs + std::fmt::Debug>(
         server: A,
     ) -> Result<AmiConnection, std::io::Error> {
-        let reader = Self::connect_to_server(server).await?;
+        let span = telemetry::connection_span(&format!("{:?}", server));
+        trace!("Connecting to {:?}", server);
+        let stream = TcpStream::connect(server).await?;
+        Self::start(stream, span).await
+    }
+
+    /// Establishes a connection over an already connected stream
+    ///
+    /// This allows talking to the AMI over other transports than plain TCP, e.g. TLS or an
+    /// in-memory stream in tests. The server's greeting is read before returning.
+    pub async fn from_stream<S>(
+        stream: S,
+    ) -> Result<AmiConnection, std::io::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::start(stream, telemetry::connection_span("stream")).await
+    }
+
+    async fn start<S>(
+        stream: S,
+        span: telemetry::Span,
+    ) -> Result<AmiConnection, std::io::Error>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let mut reader = BufReader::new(stream);
+        let ami_version = Self::read_greeting(&mut reader).await?;
+        metrics::connection_established();
 
         let (cmd_tx, cmd_rx) = mpsc::channel::<Command>(32);
-        let (events_tx, _) = broadcast::channel::<Option<Packet>>(32);
+        let (events_tx, _) =
+            broadcast::channel::<Option<Packet>>(EVENT_CAPACITY);
 
-        let events_tx2 = events_tx.clone();
+        let (wire_tx, _) = broadcast::channel::<WireLine>(256);
 
-        tokio::spawn(async move {
-            Self::handle_server_connection(reader, cmd_rx, events_tx2).await;
-        });
+        let wire_tx2 = wire_tx.clone();
+        let counters = Arc::new(Counters::new());
+        let counters2 = counters.clone();
+        let health = Arc::new(HealthState::new());
+        let health2 = health.clone();
+        let interceptors = Arc::new(Interceptors::default());
+        let interceptors2 = interceptors.clone();
+        let subscribers = Arc::new(Subscribers::new(events_tx.clone()));
+        let subscribers2 = subscribers.clone();
+
+        tokio::spawn(telemetry::instrument(span, async move {
+            Self::handle_server_connection(
+                reader,
+                cmd_rx,
+                subscribers2,
+                wire_tx2,
+                counters2,
+                health2,
+                interceptors2,
+            )
+            .await;
+        }));
 
-        Ok(AmiConnection { cmd_tx, events_tx })
+        Ok(AmiConnection {
+            cmd_tx,
+            events_tx,
+            wire_tx,
+            next_action_id: Arc::new(AtomicU64::new(1)),
+            action_id_prefix: Arc::new(RwLock::new(String::new())),
+            capabilities: Arc::new(RwLock::new(None)),
+            latencies: Arc::new(Mutex::new(LatencyStats::default())),
+            counters,
+            health,
+            interceptors,
+            limits: Arc::new(RwLock::new(Limits::default())),
+            cache: Arc::new(Mutex::new(ResponseCache::default())),
+            subscribers,
+            ami_version: ami_version.map(Arc::from),
+        })
     }
 
-    async fn handle_server_connection(
-        mut server_connection: BufReader<TcpStream>,
+    async fn handle_server_connection<S: AsyncRead + AsyncWrite + Unpin>(
+        mut server_connection: BufReader<S>,
         mut command_channel_rx: Receiver<Command>,
-        event_channel_tx: Sender<Option<Packet>>,
+        subscribers: Arc<Subscribers>,
+        wire_tx: Sender<WireLine>,
+        counters: Arc<Counters>,
+        health: Arc<HealthState>,
+        interceptors: Arc<Interceptors>,
     ) {
         let mut current_command: Option<Command> = None;
         let mut response_builder = ResponseBuilder::new();
         let mut line = String::new();
         let mut maybe_response: Option<Response> = None;
+        let mut response_memory = ResponseCharge::default();
         loop {
             if current_command.is_none() {
                 tokio::select! {
@@ -88,13 +284,17 @@ impl AmiConnection {
                         match bytes_read {
                             Err(e) => {
                                 warn!("Error reading from server connection: {:?}", e);
+                                telemetry::disconnected("read error");
                                 break;
                             }
                             Ok(0) => {
                                 trace!("Server connection closed");
+                                telemetry::disconnected("closed by server");
                                 break;
                             }
-                            Ok(_) => {
+                            Ok(n) => {
+                                Counters::add(&counters.bytes_read, n as u64);
+                                wire::publish(&wire_tx, Direction::Inbound, line.trim_end_matches(['\r', '\n']));
                                 maybe_response = response_builder.add_line(line.trim());
                             }
                         }
@@ -102,10 +302,22 @@ impl AmiConnection {
 
                     cmd = command_channel_rx.recv() => {
                         if let Some(c) = cmd {
+                            if c.not_after.is_some_and(|t| Instant::now() >= t) {
+                                trace!("Dropping expired command: {:?}", c.packet);
+                                Counters::add(&counters.commands_expired, 1);
+                                let _ = c.resp.send(Err(Error::DeadlineExceeded));
+                                continue;
+                            }
                             let chunk = format!("{}\r\n\r\n", packet_to_string(&c.packet));
+                            for sent in chunk.split_terminator("\r\n") {
+                                wire::publish(&wire_tx, Direction::Outbound, sent);
+                            }
+                            response_builder.expect_action_id(find_tag(&c.packet, "ActionID").cloned());
                             current_command = Some(c);
+                            Counters::add(&counters.bytes_written, chunk.len() as u64);
                             if let Err(e) = server_connection.write_all(chunk.as_bytes()).await {
                                 warn!("Error writing to server connection: {:?}", e);
+                                telemetry::disconnected("write error");
                                 break;
                             }
                         }
@@ -117,13 +329,17 @@ impl AmiConnection {
                         match bytes_read {
                             Err(e) => {
                                 warn!("Error reading from server connection: {:?}", e);
+                                telemetry::disconnected("read error");
                                 break;
                             }
                             Ok(0) => {
                                 trace!("Server connection closed");
+                                telemetry::disconnected("closed by server");
                                 break;
                             }
-                            Ok(_) => {
+                            Ok(n) => {
+                                Counters::add(&counters.bytes_read, n as u64);
+                                wire::publish(&wire_tx, Direction::Inbound, line.trim_end_matches(['\r', '\n']));
                                 maybe_response = response_builder.add_line(line.trim());
                             }
                         }
@@ -133,71 +349,73 @@ impl AmiConnection {
 
             if let Some(resp) = maybe_response {
                 match resp {
-                    Response::Event(pkt) => {
-                        if !Self::publish_event(&event_channel_tx, Some(pkt)) {
+                    Response::Event(mut pkt) => {
+                        if let Some(event) = event_name(&pkt) {
+                            metrics::event_received(event);
+                        }
+                        Counters::add(&counters.packets_parsed, 1);
+                        health.event_received();
+                        if interceptors.incoming(&mut pkt)
+                            && !subscribers.publish(Some(pkt), &counters).await
+                        {
                             break;
                         }
                     }
-                    Response::CommandResponse(cr) => {
+                    Response::CommandResponse(mut cr) => {
+                        let fits = response_memory.finish();
+                        for pkt in cr.iter_mut() {
+                            interceptors.incoming(pkt);
+                        }
+                        Counters::add(
+                            &counters.packets_parsed,
+                            cr.len() as u64,
+                        );
                         if let Some(cmd) = current_command {
+                            Counters::add(&counters.commands_completed, 1);
                             current_command = None;
-                            if let Err(e) = cmd.resp.send(cr) {
-                                warn!(
-                                    "Cannot send command response back: {:?}",
+                            if let Err(e) = cmd.resp.send(fits.map(|_| cr)) {
+                                // the caller has given up, e.g. its deadline has passed
+                                trace!(
+                                    "Discarding response nobody waits for: {:?}",
                                     e
                                 );
-                                break;
                             }
                         }
                     }
                 }
             }
             maybe_response = None;
+            response_memory
+                .charge(&mut response_builder, || subscribers.memory_budget());
             line.clear();
         }
 
         trace!("Packet passing loop ended! Publishing 'None' event");
-        Self::publish_event(&event_channel_tx, None);
-        
+        subscribers.publish(None, &counters).await;
+
         trace!("Closing command channel");
         command_channel_rx.close();
         if let Some(cmd) = current_command {
-            info!("There was a running command on closed connection: {:?}", cmd);
-            if let Err(e) = cmd.resp.send(vec![]) {
+            Counters::add(&counters.commands_failed, 1);
+            info!(
+                "There was a running command on closed connection: {:?}",
+                cmd
+            );
+            if let Err(e) = cmd.resp.send(Ok(vec![])) {
                 warn!("Cannot terminate current command on close: {:?}", e);
             }
         }
     }
 
-    fn publish_event(
-        event_channel_tx: &Sender<Option<Packet>>,
-        pkt: Option<Packet>,
-    ) -> bool {
-        if event_channel_tx.receiver_count() > 0 {
-            if let Err(e) = event_channel_tx.send(pkt) {
-                warn!("Could not send event to subscribers: {:?}", e);
-                return false;
-            }
-        }
-        true
-    }
-
-    async fn connect_to_server<A: ToSocketAddrs + std::fmt::Debug>(
-        server: A,
-    ) -> Result<BufReader<TcpStream>, std::io::Error> {
-        trace!("Connecting to {:?}", server);
-        let mut reader = BufReader::new(TcpStream::connect(server).await?);
-        Self::read_greeting(&mut reader).await?;
-        Ok(reader)
-    }
-
-    async fn read_greeting(
-        reader: &mut BufReader<TcpStream>,
-    ) -> Result<(), std::io::Error> {
+    /// Reads a greeting like `Asterisk Call Manager/5.0.0`, returns the AMI version
+    async fn read_greeting<S: AsyncRead + Unpin>(
+        reader: &mut BufReader<S>,
+    ) -> Result<Option<String>, std::io::Error> {
         let mut greeting = String::new();
         reader.read_line(&mut greeting).await?;
 
-        Ok(())
+        let version = greeting.trim().rsplit_once('/').map(|(_, v)| v);
+        Ok(version.filter(|v| !v.is_empty()).map(str::to_string))
     }
 
     /// Send a command to the Asterisk server using AMI
@@ -209,22 +427,237 @@ impl AmiConnection {
     /// # Return value
     ///
     /// Returns `Some(packets)` on success. `None` signales an error and that the connection
-    /// should be reestablished.
-    pub async fn send(&self, pkt: Packet) -> Option<Vec<Packet>> {
+    /// should be reestablished, or that an interceptor has rejected the packet.
+    pub async fn send(&self, mut pkt: Packet) -> Option<Vec<Packet>> {
+        if let Err(e) = self.interceptors.outgoing(&mut pkt) {
+            warn!("Action rejected by interceptor: {}", e);
+            return None;
+        }
+        self.send_packet(pkt).await
+    }
+
+    /// Sends a command like `send`, but fails with `Error::QueueFull` instead of waiting if
+    /// the command queue is full
+    ///
+    /// This allows latency-critical callers to shed load while the connection is busy with
+    /// earlier commands. Fails with `Error::ConnectionClosed` where `send` returns `None`.
+    pub async fn try_send(
+        &self,
+        mut pkt: Packet,
+    ) -> Result<Vec<Packet>, Error> {
+        self.interceptors.outgoing(&mut pkt)?;
+        self.dispatch(pkt, Enqueue::Try).await
+    }
+
+    /// Sends a command like `send`, but gives up with `Error::DeadlineExceeded` if the
+    /// response has not been received by `deadline`
+    ///
+    /// The deadline covers the time spent waiting for room in the command queue as well as
+    /// for the server's response. A command still queued at the deadline is never written to
+    /// the server. One that has already been written is not taken back; its response is
+    /// discarded once it arrives.
+    pub async fn send_by(
+        &self,
+        mut pkt: Packet,
+        deadline: Instant,
+    ) -> Result<Vec<Packet>, Error> {
+        self.interceptors.outgoing(&mut pkt)?;
+        self.dispatch(pkt, Enqueue::Until(deadline)).await
+    }
+
+    /// Sends a command like `send`, but fails with `Error::DeadlineExceeded` instead of
+    /// writing it to the server if it is still queued behind other commands at `not_after`
+    ///
+    /// Unlike `send_by`, a command sent in time waits for its response as long as it takes.
+    /// This keeps a backlog from, e.g., originating calls minutes after they were requested.
+    pub async fn send_not_after(
+        &self,
+        mut pkt: Packet,
+        not_after: Instant,
+    ) -> Result<Vec<Packet>, Error> {
+        self.interceptors.outgoing(&mut pkt)?;
+        self.dispatch(pkt, Enqueue::NotAfter(not_after)).await
+    }
+
+    /// Sends a packet that has already been passed to the interceptors
+    async fn send_packet(&self, pkt: Packet) -> Option<Vec<Packet>> {
+        self.dispatch(pkt, Enqueue::Wait).await.ok()
+    }
+
+    /// Queues a packet that has already been passed to the interceptors and waits for its
+    /// response
+    async fn dispatch(
+        &self,
+        pkt: Packet,
+        enqueue: Enqueue,
+    ) -> Result<Vec<Packet>, Error> {
         let (tx, rx) = oneshot::channel();
-        self.cmd_tx
-            .send(Command {
-                packet: pkt,
-                resp: tx,
-            })
-            .await
-            .ok()?;
-        rx.await.ok()
+        let span = telemetry::command_span(&pkt);
+        let action = find_tag(&pkt, "Action").cloned();
+        let started = Instant::now();
+        let not_after = match enqueue {
+            Enqueue::Until(deadline) | Enqueue::NotAfter(deadline) => {
+                Some(deadline)
+            }
+            Enqueue::Wait | Enqueue::Try => None,
+        };
+        let cmd = Command {
+            packet: pkt,
+            resp: tx,
+            not_after,
+        };
+        let semaphores =
+            self.limits.read().unwrap().semaphores(action.as_deref());
+        let budget = self.subscribers.memory_budget();
+        let size = memory::packet_size(&cmd.packet);
+        let resp = telemetry::command(span, async move {
+            let wait = match (enqueue, not_after) {
+                (Enqueue::Try, _) => limits::Wait::Never,
+                (_, Some(deadline)) => limits::Wait::Until(
+                    tokio::time::Instant::from_std(deadline),
+                ),
+                (_, None) => limits::Wait::Forever,
+            };
+            // released once the response has been received
+            let _permits = limits::acquire(semaphores, wait).await?;
+            let _memory = match budget {
+                Some(budget) => Some(budget.reserve(size, wait).await?),
+                None => None,
+            };
+            let closed = |_| Error::ConnectionClosed;
+            match enqueue {
+                Enqueue::Wait | Enqueue::NotAfter(_) => {
+                    self.cmd_tx.send(cmd).await.map_err(closed)?
+                }
+                Enqueue::Try => {
+                    self.cmd_tx.try_send(cmd).map_err(|e| match e {
+                        TrySendError::Full(_) => Error::QueueFull,
+                        TrySendError::Closed(_) => Error::ConnectionClosed,
+                    })?
+                }
+                Enqueue::Until(deadline) => {
+                    let deadline = tokio::time::Instant::from_std(deadline);
+                    tokio::time::timeout_at(deadline, self.cmd_tx.send(cmd))
+                        .await
+                        .map_err(|_| Error::DeadlineExceeded)?
+                        .map_err(closed)?;
+                    return tokio::time::timeout_at(deadline, rx)
+                        .await
+                        .map_err(|_| Error::DeadlineExceeded)?
+                        .map_err(|_| Error::ConnectionClosed)?;
+                }
+            }
+            rx.await.map_err(|_| Error::ConnectionClosed)?
+        })
+        .await;
+        let latency = started.elapsed();
+        metrics::command_completed(action.as_deref(), latency);
+        if resp.is_ok() {
+            let action = action.as_deref().unwrap_or_default();
+            self.latencies.lock().unwrap().record(action, latency);
+        }
+        resp
+    }
+
+    /// Returns a new ActionID unique for this connection
+    pub fn next_action_id(&self) -> String {
+        let n = self.next_action_id.fetch_add(1, Ordering::Relaxed);
+        format!("{}{}", self.action_id_prefix.read().unwrap(), n)
+    }
+
+    /// Adds an `ActionID` to `pkt` if it does not contain one yet
+    pub(crate) fn assign_action_id(&self, pkt: &mut Packet) {
+        if find_tag(pkt, "ActionID").is_none() {
+            pkt.push(Tag::of("ActionID".to_string(), self.next_action_id()));
+        }
+    }
+
+    /// Puts `prefix` in front of the ActionIDs generated from now on
+    ///
+    /// When several clients share an Asterisk server, a prefix like `dialer-3-` attributes
+    /// actions and the events answering them to the client in the server's logs. It applies to
+    /// all clones of the connection.
+    pub fn set_action_id_prefix(&self, prefix: &str) {
+        *self.action_id_prefix.write().unwrap() = prefix.to_string();
+    }
+
+    /// Send a command and check the server's response
+    ///
+    /// Like `send`, but fails with `Error::ConnectionClosed` if no response could be read
+    /// and with `Error::ActionFailed` if the server answered with `Response: Error`. An
+    /// `ActionID` is added to the packet if it does not contain one yet, so that the events
+    /// belonging to the response can be told apart from unrelated events.
+    ///
+    /// Once `capabilities()` has been queried, actions not available to the logged in user
+    /// fail with `Error::UnsupportedAction` without being sent. Actions whose responses are
+    /// cached, see `cache_responses`, are answered from the cache while it holds a response,
+    /// without invoking the interceptors.
+    pub(crate) async fn send_action(
+        &self,
+        mut pkt: Packet,
+    ) -> Result<Vec<Packet>, Error> {
+        if let Some(action) = find_tag(&pkt, "Action") {
+            let capabilities = self.capabilities.read().unwrap();
+            if let Some(capabilities) = capabilities.as_ref() {
+                if !capabilities.supports(action) {
+                    return Err(Error::UnsupportedAction(action.clone()));
+                }
+            }
+        }
+        self.assign_action_id(&mut pkt);
+        if let Some(resp) = self.cache.lock().unwrap().get(&pkt) {
+            return Ok(resp);
+        }
+        let request = pkt.clone();
+        self.interceptors.outgoing(&mut pkt)?;
+        let resp = self.dispatch(pkt, Enqueue::Wait).await?;
+        let first = resp.first().ok_or(Error::ConnectionClosed)?;
+        match find_tag(first, "Response") {
+            Some(r) if r.eq_ignore_ascii_case("Error") => {
+                let message =
+                    find_tag(first, "Message").cloned().unwrap_or_default();
+                Err(Error::ActionFailed(message))
+            }
+            _ => {
+                self.cache.lock().unwrap().store(&request, &resp);
+                Ok(resp)
+            }
+        }
     }
 
     pub fn events(&self) -> broadcast::Receiver<Option<Packet>> {
         self.events_tx.subscribe()
     }
+
+    /// Returns the latency statistics of the actions sent over this connection
+    pub fn stats(&self) -> Vec<ActionStats> {
+        self.latencies.lock().unwrap().snapshot()
+    }
+
+    /// Adds an interceptor to the end of the chain of this connection
+    ///
+    /// The chain is shared by all clones of the connection.
+    pub fn add_interceptor(&self, interceptor: Arc<dyn Interceptor>) {
+        self.interceptors.add(interceptor);
+    }
+
+    /// Returns the cumulative counters of this connection
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.counters.snapshot()
+    }
+
+    /// Subscribes to the raw lines sent to and received from the server
+    ///
+    /// Meant for debugging protocol issues; lines are only copied while there is at least
+    /// one subscriber. Subscribers that cannot keep up miss lines.
+    pub fn wire_tap(&self) -> broadcast::Receiver<WireLine> {
+        self.wire_tx.subscribe()
+    }
+
+    /// The version of the AMI protocol announced in the server's greeting, e.g. `5.0.0`
+    pub fn ami_version(&self) -> Option<&str> {
+        self.ami_version.as_deref()
+    }
 }
 
 /// Searches for a `Tag` within a packet
@@ -239,17 +672,155 @@ pub fn find_tag<'a>(pkt: &'a Packet, key: &str) -> Option<&'a String> {
         .map(|t| &t.value)
 }
 
-fn packet_to_string(pkt: &Packet) -> String {
+/// Finds a tag's value, treating empty values and `<unknown>` as absent
+pub(crate) fn find_value(pkt: &Packet, key: &str) -> Option<String> {
+    find_tag(pkt, key)
+        .filter(|v| !v.is_empty() && v.as_str() != "<unknown>")
+        .cloned()
+}
+
+/// Interprets a tag's value as a flag, accepting `Yes`, `Y`, `true`, `on`, and `1`
+pub(crate) fn find_flag(pkt: &Packet, key: &str) -> bool {
+    find_tag(pkt, key).is_some_and(|v| {
+        ["yes", "y", "true", "on", "1"]
+            .iter()
+            .any(|t| v.trim().eq_ignore_ascii_case(t))
+    })
+}
+
+/// Returns the name of the event if `pkt` is an event packet
+pub fn event_name(pkt: &Packet) -> Option<&String> {
+    find_tag(pkt, "Event")
+}
+
+/// Returns the `SystemName` header of a packet
+///
+/// Asterisk adds it to every event and response if `systemname` is set in `asterisk.conf`,
+/// which tells apart the nodes of a cluster.
+pub fn system_name(pkt: &Packet) -> Option<&String> {
+    find_tag(pkt, "SystemName").filter(|name| !name.is_empty())
+}
+
+/// Checks if a packet has been sent by the system called `name`, e.g. to select the events
+/// of a single node of a cluster
+pub fn is_from_system(pkt: &Packet, name: &str) -> bool {
+    system_name(pkt).is_some_and(|n| n == name)
+}
+
+/// Returns all packets of a list response that are events called `name`
+pub(crate) fn list_items<'a>(
+    resp: &'a [Packet],
+    name: &'a str,
+) -> impl Iterator<Item = &'a Packet> {
+    resp.iter().filter(move |pkt| {
+        event_name(pkt).is_some_and(|n| n.eq_ignore_ascii_case(name))
+    })
+}
+
+pub(crate) fn packet_to_string(pkt: &Packet) -> String {
     pkt.iter()
         .map(|Tag { key, value }| format!("{}: {}", key, value))
         .collect::<Vec<String>>()
         .join("\r\n")
 }
 
+/// Builds a packet from a list of key/value pairs
+#[cfg(test)]
+pub(crate) fn packet_from(tags: &[(&str, &str)]) -> Packet {
+    tags.iter().map(|(k, v)| Tag::from(k, v)).collect()
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn prefixes_action_ids() {
+        let (connection, _server) =
+            crate::testing::duplex_connection().await.unwrap();
+        assert_eq!(connection.next_action_id(), "1");
+        connection.clone().set_action_id_prefix("dialer-3-");
+        assert_eq!(connection.next_action_id(), "dialer-3-2");
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn fails_fast_if_queue_is_full() {
+        use super::*;
+        use std::time::Duration;
+
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let ping = || packet_from(&[("Action", "Ping")]);
+        // one action is written to the server, the others fill the queue
+        for _ in 0..33 {
+            let connection = connection.clone();
+            tokio::spawn(async move { connection.send(ping()).await });
+        }
+        server.read_packet().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(connection.try_send(ping()).await, Err(Error::QueueFull));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn gives_up_at_deadline() {
+        use super::*;
+        use std::time::Duration;
+
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let ping = || packet_from(&[("Action", "Ping")]);
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let (resp, _) = tokio::join!(
+            connection.send_by(ping(), deadline),
+            server.read_packet()
+        );
+        assert_eq!(resp, Err(Error::DeadlineExceeded));
+
+        // the late response is discarded without affecting the connection
+        let pong = packet_from(&[("Response", "Success"), ("Ping", "Pong")]);
+        server.send_packet(&pong).await.unwrap();
+        let (resp, _) = tokio::join!(connection.send(ping()), async {
+            server.read_packet().await.unwrap();
+            server.send_packet(&pong).await.unwrap();
+        });
+        assert_eq!(resp, Some(vec![pong]));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn drops_expired_commands() {
+        use super::*;
+        use std::time::Duration;
+
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let action = |name| packet_from(&[("Action", name)]);
+        let busy = tokio::spawn({
+            let connection = connection.clone();
+            async move { connection.send(action("Ping")).await }
+        });
+        server.read_packet().await.unwrap();
+        let not_after = Instant::now() + Duration::from_millis(10);
+        let (stale, _) = tokio::join!(
+            connection.send_not_after(action("Originate"), not_after),
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let pong = packet_from(&[("Response", "Success")]);
+                server.send_packet(&pong).await.unwrap();
+            }
+        );
+        assert_eq!(stale, Err(Error::DeadlineExceeded));
+        assert!(busy.await.unwrap().is_some());
+        assert_eq!(connection.connection_stats().commands_expired, 1);
+
+        tokio::spawn(async move { connection.send(action("Logoff")).await });
+        let next = server.read_packet().await.unwrap();
+        assert_eq!(find_tag(&next, "Action").unwrap(), "Logoff");
+    }
 }
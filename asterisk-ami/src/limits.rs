@@ -0,0 +1,125 @@
+//! Limits on the number of commands in flight
+//!
+//! The connection sends one command at a time, in the order they have been queued. A burst of
+//! slow actions like `Command` therefore delays every action queued behind it. Limiting how
+//! many actions of such a class may be queued or in flight at a time keeps room for
+//! interactive call control in between.
+
+use crate::{AmiConnection, Error};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// The limits of a connection, see `AmiConnection::limit_in_flight`
+#[derive(Debug, Default)]
+pub(crate) struct Limits {
+    total: Option<Arc<Semaphore>>,
+    /// Keyed by the lower case action name, actions of a class share a semaphore
+    classes: HashMap<String, Arc<Semaphore>>,
+}
+
+impl Limits {
+    /// The semaphores a command has to acquire a permit of before being queued
+    pub(crate) fn semaphores(
+        &self,
+        action: Option<&str>,
+    ) -> Vec<Arc<Semaphore>> {
+        let class =
+            action.and_then(|a| self.classes.get(&a.to_ascii_lowercase()));
+        class.into_iter().chain(&self.total).cloned().collect()
+    }
+}
+
+/// How long to wait for a permit
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Wait {
+    Forever,
+    Never,
+    Until(Instant),
+}
+
+/// Acquires a permit of each semaphore, they are released when dropped
+pub(crate) async fn acquire(
+    semaphores: Vec<Arc<Semaphore>>,
+    wait: Wait,
+) -> Result<Vec<OwnedSemaphorePermit>, Error> {
+    let mut permits = Vec::with_capacity(semaphores.len());
+    for semaphore in semaphores {
+        let permit = match wait {
+            Wait::Forever => semaphore.acquire_owned().await.ok(),
+            Wait::Never => match semaphore.try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Err(Error::QueueFull),
+            },
+            Wait::Until(deadline) => {
+                tokio::time::timeout_at(deadline, semaphore.acquire_owned())
+                    .await
+                    .map_err(|_| Error::DeadlineExceeded)?
+                    .ok()
+            }
+        };
+        // the semaphores are never closed
+        permits.push(permit.ok_or(Error::ConnectionClosed)?);
+    }
+    Ok(permits)
+}
+
+impl AmiConnection {
+    /// Limits the number of commands queued or waiting for their response, `None` removes
+    /// the limit
+    ///
+    /// Commands beyond the limit wait in `send` until an earlier one has been answered;
+    /// `try_send` fails with `Error::QueueFull` and `send_by` respects its deadline while
+    /// waiting. The limit applies to all clones of the connection.
+    pub fn limit_in_flight(&self, limit: Option<usize>) {
+        self.limits.write().unwrap().total =
+            limit.map(|limit| Arc::new(Semaphore::new(limit)));
+    }
+
+    /// Limits the number of commands of a class of actions queued or waiting for their
+    /// response
+    ///
+    /// All `actions` share the limit, e.g. `connection.limit_class(&["Command"], 1)` keeps a
+    /// burst of CLI commands from delaying the call control actions queued after them. An
+    /// action belongs to the class configured last.
+    pub fn limit_class(&self, actions: &[&str], limit: usize) {
+        let semaphore = Arc::new(Semaphore::new(limit));
+        let mut limits = self.limits.write().unwrap();
+        for action in actions {
+            limits
+                .classes
+                .insert(action.to_ascii_lowercase(), semaphore.clone());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use crate::testing::duplex_connection;
+    use crate::{find_tag, packet_from, Error};
+
+    #[tokio::test]
+    async fn interleaves_limited_actions() {
+        let (connection, mut server) = duplex_connection().await.unwrap();
+        connection.limit_class(&["Command"], 1);
+        let action = |name| packet_from(&[("Action", name)]);
+        for name in ["Command", "Command", "Ping"] {
+            let connection = connection.clone();
+            tokio::spawn(async move { connection.send(action(name)).await });
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            connection.try_send(action("Command")).await,
+            Err(Error::QueueFull)
+        );
+        let success = packet_from(&[("Response", "Success")]);
+        let mut sent = vec![];
+        for _ in 0..3 {
+            let pkt = server.read_packet().await.unwrap();
+            sent.push(find_tag(&pkt, "Action").unwrap().clone());
+            server.send_packet(&success).await.unwrap();
+        }
+        assert_eq!(sent, ["Command", "Ping", "Command"]);
+    }
+}
@@ -0,0 +1,369 @@
+//! A cap on the memory used by the protocol data buffered for connections
+//!
+//! Without a budget, the memory a connection buffers is only bounded by the number of
+//! commands and events queued, not by their size: a PBX answering `CoreShowChannels` with
+//! millions of channels, or a subscription that stopped reading while large events keep
+//! coming, grows the process until it fails. A `MemoryBudget` is charged for
+//!
+//! - the actions queued or waiting for their response,
+//! - the packets collected for a list response,
+//! - the events queued for subscriptions created using `AmiConnection::subscribe`,
+//!
+//! and its `OverflowPolicy` decides what happens once the limit has been reached. A budget can
+//! be shared by several connections to cap their memory as a whole. The events buffered for
+//! the receivers of `AmiConnection::events` are not charged; that buffer is bounded to a fixed
+//! number of events and overwrites the oldest ones.
+
+use crate::limits::Wait;
+use crate::response::ResponseBuilder;
+use crate::{AmiConnection, Error, Packet};
+use log::warn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// What happens to data exceeding a `MemoryBudget`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Actions and list responses that do not fit fail with `Error::MemoryLimitExceeded`,
+    /// subscriptions falling behind are ended
+    Error,
+    /// Actions that do not fit fail with `Error::MemoryLimitExceeded`, list responses are cut
+    /// short, and subscriptions falling behind miss events
+    Drop,
+    /// Actions wait until they fit, and the connection stops reading from the server until
+    /// subscriptions falling behind have caught up; list responses that do not fit fail like
+    /// with `Error`, as there is nothing to wait for
+    ///
+    /// A subscription that is never read stalls its connection.
+    Backpressure,
+}
+
+struct Budget {
+    limit: usize,
+    policy: OverflowPolicy,
+    used: AtomicUsize,
+    released: Notify,
+}
+
+/// A limit on the bytes buffered, shared by all clones
+#[derive(Clone)]
+pub struct MemoryBudget {
+    budget: Arc<Budget>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes
+    pub fn new(limit: usize, policy: OverflowPolicy) -> Self {
+        MemoryBudget {
+            budget: Arc::new(Budget {
+                limit,
+                policy,
+                used: AtomicUsize::new(0),
+                released: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.budget.limit
+    }
+
+    pub fn policy(&self) -> OverflowPolicy {
+        self.budget.policy
+    }
+
+    /// The bytes currently charged to the budget
+    pub fn used(&self) -> usize {
+        self.budget.used.load(Ordering::Acquire)
+    }
+
+    /// Charges `bytes` if they fit, they are released when the reservation is dropped
+    pub(crate) fn try_reserve(&self, bytes: usize) -> Option<Reservation> {
+        let used = &self.budget.used;
+        let mut current = used.load(Ordering::Acquire);
+        loop {
+            let charged = current
+                .checked_add(bytes)
+                .filter(|charged| *charged <= self.budget.limit)?;
+            match used.compare_exchange_weak(
+                current,
+                charged,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    return Some(Reservation {
+                        budget: self.clone(),
+                        bytes,
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Charges `bytes`, waiting for them to fit as long as the policy and `wait` allow
+    pub(crate) async fn reserve(
+        &self,
+        bytes: usize,
+        wait: Wait,
+    ) -> Result<Reservation, Error> {
+        loop {
+            let released = self.budget.released.notified();
+            tokio::pin!(released);
+            // registered before checking, so no release in between is missed
+            released.as_mut().enable();
+            if let Some(reservation) = self.try_reserve(bytes) {
+                return Ok(reservation);
+            }
+            if self.policy() != OverflowPolicy::Backpressure
+                || bytes > self.limit()
+            {
+                return Err(Error::MemoryLimitExceeded);
+            }
+            match wait {
+                Wait::Forever => released.await,
+                Wait::Never => return Err(Error::MemoryLimitExceeded),
+                Wait::Until(deadline) => {
+                    tokio::time::timeout_at(deadline, released)
+                        .await
+                        .map_err(|_| Error::DeadlineExceeded)?;
+                }
+            }
+        }
+    }
+
+    /// Charges `bytes`, waiting for them to fit while `wait` returns `true`
+    ///
+    /// Once it returns `false`, the bytes are charged even if they exceed the limit.
+    pub(crate) async fn reserve_while<F: FnMut() -> bool>(
+        &self,
+        bytes: usize,
+        mut wait: F,
+    ) -> Reservation {
+        loop {
+            let released = self.budget.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if let Some(reservation) = self.try_reserve(bytes) {
+                return reservation;
+            }
+            if !wait() {
+                self.budget.used.fetch_add(bytes, Ordering::AcqRel);
+                return Reservation {
+                    budget: self.clone(),
+                    bytes,
+                };
+            }
+            released.await;
+        }
+    }
+}
+
+impl std::fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.limit())
+            .field("policy", &self.policy())
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+/// Bytes charged to a budget until dropped
+pub(crate) struct Reservation {
+    budget: MemoryBudget,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Charges `bytes` more if they fit
+    fn try_grow(&mut self, bytes: usize) -> bool {
+        match self.budget.try_reserve(bytes) {
+            Some(mut more) => {
+                self.bytes += std::mem::take(&mut more.bytes);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            self.budget
+                .budget
+                .used
+                .fetch_sub(self.bytes, Ordering::AcqRel);
+            self.budget.budget.released.notify_waiters();
+        }
+    }
+}
+
+/// The size of a packet in wire format
+pub(crate) fn packet_size(pkt: &Packet) -> usize {
+    let tags = pkt
+        .iter()
+        .map(|tag| tag.key.len() + tag.value.len() + 4)
+        .sum::<usize>();
+    tags + 2
+}
+
+/// The memory charged for the list response being read
+#[derive(Default)]
+pub(crate) struct ResponseCharge {
+    reservation: Option<Reservation>,
+    /// The policy of the budget that has been exceeded
+    exceeded: Option<OverflowPolicy>,
+}
+
+impl ResponseCharge {
+    /// Charges the packets collected by `builder` since the last call
+    ///
+    /// If they do not fit, the rest of the response is discarded.
+    pub(crate) fn charge(
+        &mut self,
+        builder: &mut ResponseBuilder,
+        budget: impl FnOnce() -> Option<MemoryBudget>,
+    ) {
+        let charged = self.reservation.as_ref().map_or(0, |r| r.bytes);
+        let bytes = builder.response_bytes();
+        if bytes <= charged || self.exceeded.is_some() {
+            return;
+        }
+        let budget = match budget() {
+            Some(budget) => budget,
+            None => return,
+        };
+        let fits = match self.reservation.as_mut() {
+            Some(reservation) => reservation.try_grow(bytes - charged),
+            None => {
+                self.reservation = budget.try_reserve(bytes);
+                self.reservation.is_some()
+            }
+        };
+        if !fits {
+            warn!("Response exceeds the memory budget, discarding the rest");
+            builder.stop_collecting();
+            self.exceeded = Some(budget.policy());
+        }
+    }
+
+    /// Releases the memory of the response that has been completed
+    ///
+    /// Fails with `Error::MemoryLimitExceeded` if it has been cut short and the policy does
+    /// not allow handing it out.
+    pub(crate) fn finish(&mut self) -> Result<(), Error> {
+        self.reservation = None;
+        match self.exceeded.take() {
+            None | Some(OverflowPolicy::Drop) => Ok(()),
+            Some(_) => Err(Error::MemoryLimitExceeded),
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Charges the data buffered for this connection to `budget`, `None` removes the limit
+    ///
+    /// Pass clones of the same budget to several connections to cap their memory as a whole.
+    /// Data buffered before the budget has been set is not charged. The budget applies to all
+    /// clones of the connection.
+    pub fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        self.subscribers.set_memory_budget(budget);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn charges_and_releases_bytes() {
+        let budget = MemoryBudget::new(100, OverflowPolicy::Error);
+        let first = budget.try_reserve(60).unwrap();
+        assert_eq!(budget.used(), 60);
+        assert!(budget.try_reserve(41).is_none());
+        assert_eq!(
+            budget.reserve(41, Wait::Forever).await.err(),
+            Some(Error::MemoryLimitExceeded)
+        );
+        drop(first);
+        assert_eq!(budget.used(), 0);
+
+        let budget = MemoryBudget::new(100, OverflowPolicy::Backpressure);
+        let first = budget.try_reserve(60).unwrap();
+        let waiting = tokio::spawn({
+            let budget = budget.clone();
+            async move { budget.reserve(50, Wait::Forever).await.is_ok() }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert!(waiting.await.unwrap());
+        assert_eq!(budget.used(), 0);
+        assert!(budget.reserve(101, Wait::Forever).await.is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn fails_responses_exceeding_budget() {
+        use crate::{find_tag, packet_from, Tag};
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let budget = MemoryBudget::new(300, OverflowPolicy::Error);
+        connection.set_memory_budget(Some(budget.clone()));
+        let action = vec![Tag::from("Action", "CoreShowChannels")];
+        let (result, _) = tokio::join!(connection.send_action(action), async {
+            let request = server.read_packet().await.unwrap();
+            let id = find_tag(&request, "ActionID").unwrap().clone();
+            let mut response = vec![packet_from(&[
+                ("Response", "Success"),
+                ("ActionID", &id),
+                ("EventList", "start"),
+            ])];
+            for n in 0..10 {
+                response.push(packet_from(&[
+                    ("Event", "CoreShowChannel"),
+                    ("ActionID", &id),
+                    ("Channel", &format!("PJSIP/100-{:08}", n)),
+                ]));
+            }
+            response.push(packet_from(&[
+                ("Event", "CoreShowChannelsComplete"),
+                ("ActionID", &id),
+                ("EventList", "Complete"),
+            ]));
+            for pkt in &response {
+                server.send_packet(pkt).await.unwrap();
+            }
+        });
+        assert_eq!(result, Err(Error::MemoryLimitExceeded));
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn holds_back_events_for_subscription() {
+        use crate::packet_from as event;
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let budget = MemoryBudget::new(100, OverflowPolicy::Backpressure);
+        connection.set_memory_budget(Some(budget.clone()));
+        let mut subscription = connection.subscribe(|pkt| Some(pkt.clone()));
+        let newchannel = |n: usize| {
+            event(&[("Event", "Newchannel"), ("Channel", &format!("a{}", n))])
+        };
+        for n in 0..5 {
+            server.send_packet(&newchannel(n)).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(budget.used() <= 100);
+        for n in 0..5 {
+            assert_eq!(subscription.next_event().await, Some(newchannel(n)));
+        }
+        assert_eq!(connection.connection_stats().events_dropped, 0);
+    }
+}
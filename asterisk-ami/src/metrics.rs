@@ -0,0 +1,374 @@
+//! Metrics about the AMI integration itself
+//!
+//! With the `prometheus` feature enabled, `register_prometheus` adds the following metrics to a
+//! registry:
+//!
+//! * `ami_events_total` - events received, labeled with the `event` name
+//! * `ami_commands_total` - commands sent, labeled with the `action`
+//! * `ami_command_duration_seconds` - histogram of the command latencies by `action`
+//! * `ami_connections_total` - connections established, reconnects show up as increases
+//! * `ami_parse_errors_total` - lines received that could not be parsed
+//! * `ami_lagged_events_total` - events internal subscribers (e.g. trackers) missed because
+//!   they could not keep up
+//!
+//! With the `opentelemetry` feature enabled, `register_opentelemetry` creates the same
+//! instruments on an OpenTelemetry meter, using dots instead of underscores (e.g.
+//! `ami.events`). Commands are traced using the global tracer provider.
+//!
+//! With the `metrics` feature enabled, the same metrics are recorded through the `metrics`
+//! facade, under the names used for Prometheus, to whatever recorder the application has
+//! installed. Nothing has to be registered; `describe_metrics` adds their descriptions.
+//!
+//! The metrics are shared by all connections of the process.
+
+use std::time::Duration;
+
+#[cfg(any(feature = "prometheus", feature = "opentelemetry"))]
+use std::sync::OnceLock;
+
+#[cfg(feature = "prometheus")]
+struct Prometheus {
+    events: ::prometheus::IntCounterVec,
+    commands: ::prometheus::IntCounterVec,
+    command_duration: ::prometheus::HistogramVec,
+    connections: ::prometheus::IntCounter,
+    parse_errors: ::prometheus::IntCounter,
+    lagged_events: ::prometheus::IntCounter,
+}
+
+#[cfg(feature = "prometheus")]
+static PROMETHEUS: OnceLock<Prometheus> = OnceLock::new();
+
+/// Registers the metrics of this crate with a Prometheus registry
+///
+/// Metrics are only collected once this has been called. Calling it again registers the same
+/// metrics with another registry.
+#[cfg(feature = "prometheus")]
+pub fn register_prometheus(
+    registry: &::prometheus::Registry,
+) -> Result<(), ::prometheus::Error> {
+    use ::prometheus::{HistogramOpts, IntCounter, IntCounterVec, Opts};
+
+    let metrics = match PROMETHEUS.get() {
+        Some(metrics) => metrics,
+        None => {
+            let metrics = Prometheus {
+                events: IntCounterVec::new(
+                    Opts::new("ami_events_total", "Events received"),
+                    &["event"],
+                )?,
+                commands: IntCounterVec::new(
+                    Opts::new("ami_commands_total", "Commands sent"),
+                    &["action"],
+                )?,
+                command_duration: ::prometheus::HistogramVec::new(
+                    HistogramOpts::new(
+                        "ami_command_duration_seconds",
+                        "Time until the response to a command has been received",
+                    ),
+                    &["action"],
+                )?,
+                connections: IntCounter::new(
+                    "ami_connections_total",
+                    "Connections established to the Asterisk server",
+                )?,
+                parse_errors: IntCounter::new(
+                    "ami_parse_errors_total",
+                    "Lines received that could not be parsed",
+                )?,
+                lagged_events: IntCounter::new(
+                    "ami_lagged_events_total",
+                    "Events missed by subscribers that could not keep up",
+                )?,
+            };
+            // a concurrent registration may have won, use whatever has been stored
+            let _ = PROMETHEUS.set(metrics);
+            PROMETHEUS.get().unwrap()
+        }
+    };
+    registry.register(Box::new(metrics.events.clone()))?;
+    registry.register(Box::new(metrics.commands.clone()))?;
+    registry.register(Box::new(metrics.command_duration.clone()))?;
+    registry.register(Box::new(metrics.connections.clone()))?;
+    registry.register(Box::new(metrics.parse_errors.clone()))?;
+    registry.register(Box::new(metrics.lagged_events.clone()))?;
+    Ok(())
+}
+
+#[cfg(feature = "opentelemetry")]
+struct OpenTelemetry {
+    events: opentelemetry::metrics::Counter<u64>,
+    commands: opentelemetry::metrics::Counter<u64>,
+    command_duration: opentelemetry::metrics::Histogram<f64>,
+    connections: opentelemetry::metrics::Counter<u64>,
+    parse_errors: opentelemetry::metrics::Counter<u64>,
+    lagged_events: opentelemetry::metrics::Counter<u64>,
+}
+
+#[cfg(feature = "opentelemetry")]
+static OPENTELEMETRY: OnceLock<OpenTelemetry> = OnceLock::new();
+
+/// Creates the instruments of this crate on an OpenTelemetry meter
+///
+/// Metrics are only recorded once this has been called, only the first call has an effect.
+#[cfg(feature = "opentelemetry")]
+pub fn register_opentelemetry(meter: &opentelemetry::metrics::Meter) {
+    OPENTELEMETRY.get_or_init(|| OpenTelemetry {
+        events: meter
+            .u64_counter("ami.events")
+            .with_description("Events received")
+            .build(),
+        commands: meter
+            .u64_counter("ami.commands")
+            .with_description("Commands sent")
+            .build(),
+        command_duration: meter
+            .f64_histogram("ami.command.duration")
+            .with_unit("s")
+            .with_description(
+                "Time until the response to a command has been received",
+            )
+            .build(),
+        connections: meter
+            .u64_counter("ami.connections")
+            .with_description("Connections established to the Asterisk server")
+            .build(),
+        parse_errors: meter
+            .u64_counter("ami.parse_errors")
+            .with_description("Lines received that could not be parsed")
+            .build(),
+        lagged_events: meter
+            .u64_counter("ami.lagged_events")
+            .with_description(
+                "Events missed by subscribers that could not keep up",
+            )
+            .build(),
+    });
+}
+
+/// Describes the metrics of this crate to the installed `metrics` recorder
+///
+/// Recorders only keep descriptions given after they have been installed, so call this once
+/// the application has done so.
+#[cfg(feature = "metrics")]
+pub fn describe_metrics() {
+    use ::metrics::{describe_counter, describe_histogram, Unit};
+
+    describe_counter!("ami_events_total", "Events received");
+    describe_counter!("ami_commands_total", "Commands sent");
+    describe_histogram!(
+        "ami_command_duration_seconds",
+        Unit::Seconds,
+        "Time until the response to a command has been received"
+    );
+    describe_counter!(
+        "ami_connections_total",
+        "Connections established to the Asterisk server"
+    );
+    describe_counter!(
+        "ami_parse_errors_total",
+        "Lines received that could not be parsed"
+    );
+    describe_counter!(
+        "ami_lagged_events_total",
+        "Events missed by subscribers that could not keep up"
+    );
+}
+
+pub(crate) fn connection_established() {
+    #[cfg(feature = "opentelemetry")]
+    if let Some(metrics) = OPENTELEMETRY.get() {
+        metrics.connections.add(1, &[]);
+    }
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = PROMETHEUS.get() {
+        metrics.connections.inc();
+    }
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("ami_connections_total").increment(1);
+}
+
+pub(crate) fn event_received(event: &str) {
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = PROMETHEUS.get() {
+        metrics.events.with_label_values(&[event]).inc();
+    }
+    #[cfg(feature = "opentelemetry")]
+    if let Some(metrics) = OPENTELEMETRY.get() {
+        let event = opentelemetry::KeyValue::new("event", event.to_string());
+        metrics.events.add(1, &[event]);
+    }
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("ami_events_total", "event" => event.to_string())
+        .increment(1);
+    #[cfg(not(any(
+        feature = "prometheus",
+        feature = "opentelemetry",
+        feature = "metrics"
+    )))]
+    let _ = event;
+}
+
+pub(crate) fn command_completed(action: Option<&str>, latency: Duration) {
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = PROMETHEUS.get() {
+        let action = action.unwrap_or_default();
+        metrics.commands.with_label_values(&[action]).inc();
+        metrics
+            .command_duration
+            .with_label_values(&[action])
+            .observe(latency.as_secs_f64());
+    }
+    #[cfg(feature = "opentelemetry")]
+    if let Some(metrics) = OPENTELEMETRY.get() {
+        let action = [opentelemetry::KeyValue::new(
+            "action",
+            action.unwrap_or_default().to_string(),
+        )];
+        metrics.commands.add(1, &action);
+        metrics
+            .command_duration
+            .record(latency.as_secs_f64(), &action);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        let action = action.unwrap_or_default().to_string();
+        ::metrics::counter!("ami_commands_total", "action" => action.clone())
+            .increment(1);
+        ::metrics::histogram!("ami_command_duration_seconds", "action" => action)
+            .record(latency);
+    }
+    #[cfg(not(any(
+        feature = "prometheus",
+        feature = "opentelemetry",
+        feature = "metrics"
+    )))]
+    let _ = (action, latency);
+}
+
+pub(crate) fn parse_error() {
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = PROMETHEUS.get() {
+        metrics.parse_errors.inc();
+    }
+    #[cfg(feature = "opentelemetry")]
+    if let Some(metrics) = OPENTELEMETRY.get() {
+        metrics.parse_errors.add(1, &[]);
+    }
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("ami_parse_errors_total").increment(1);
+}
+
+pub(crate) fn events_lagged(count: u64) {
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics) = PROMETHEUS.get() {
+        metrics.lagged_events.inc_by(count);
+    }
+    #[cfg(feature = "opentelemetry")]
+    if let Some(metrics) = OPENTELEMETRY.get() {
+        metrics.lagged_events.add(count, &[]);
+    }
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("ami_lagged_events_total").increment(count);
+    #[cfg(not(any(
+        feature = "prometheus",
+        feature = "opentelemetry",
+        feature = "metrics"
+    )))]
+    let _ = count;
+}
+
+#[cfg(all(test, feature = "prometheus"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers_with_prometheus() {
+        let registry = ::prometheus::Registry::new();
+        register_prometheus(&registry).unwrap();
+        event_received("Newchannel");
+        command_completed(Some("Ping"), Duration::from_millis(3));
+        let names = registry
+            .gather()
+            .iter()
+            .map(|family| family.get_name().to_string())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"ami_events_total".to_string()));
+        assert!(names.contains(&"ami_command_duration_seconds".to_string()));
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod metrics_tests {
+    use super::*;
+    use ::metrics::{
+        Counter, Gauge, Histogram, Key, KeyName, Metadata, Recorder,
+        SharedString, Unit,
+    };
+    use std::sync::Mutex;
+
+    /// Keeps the names of the metrics registered, with their label values
+    #[derive(Default)]
+    struct Keys(Mutex<Vec<String>>);
+
+    impl Keys {
+        fn register(&self, key: &Key) {
+            let labels = key.labels().map(|l| l.value()).collect::<Vec<_>>();
+            let name = format!("{}{:?}", key.name(), labels);
+            self.0.lock().unwrap().push(name);
+        }
+    }
+
+    impl Recorder for Keys {
+        fn describe_counter(
+            &self,
+            _: KeyName,
+            _: Option<Unit>,
+            _: SharedString,
+        ) {
+        }
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {
+        }
+        fn describe_histogram(
+            &self,
+            _: KeyName,
+            _: Option<Unit>,
+            _: SharedString,
+        ) {
+        }
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            self.register(key);
+            Counter::noop()
+        }
+
+        fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::noop()
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            self.register(key);
+            Histogram::noop()
+        }
+    }
+
+    #[test]
+    fn records_through_facade() {
+        let keys = Keys::default();
+        ::metrics::with_local_recorder(&keys, || {
+            event_received("Newchannel");
+            command_completed(Some("Ping"), Duration::from_millis(3));
+            events_lagged(2);
+        });
+        let keys = keys.0.into_inner().unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                "ami_events_total[\"Newchannel\"]",
+                "ami_commands_total[\"Ping\"]",
+                "ami_command_duration_seconds[\"Ping\"]",
+                "ami_lagged_events_total[]",
+            ]
+        );
+    }
+}
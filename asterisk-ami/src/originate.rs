@@ -0,0 +1,539 @@
+use crate::{
+    event_name, find_tag, find_value, AmiConnection, Error, Packet, Tag,
+};
+use log::{trace, warn};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+
+/// Where an originated channel is connected to once it has been answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Destination {
+    Extension {
+        context: String,
+        exten: String,
+        priority: u32,
+    },
+    Application {
+        application: String,
+        data: String,
+    },
+}
+
+/// The parameters of an `Originate` action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginateRequest {
+    channel: String,
+    destination: Destination,
+    caller_id: Option<String>,
+    timeout: Option<Duration>,
+    account: Option<String>,
+    variables: Vec<(String, String)>,
+}
+
+impl OriginateRequest {
+    /// Calls `channel`, e.g. `PJSIP/100`, and connects it to a dialplan extension
+    pub fn to_extension(
+        channel: &str,
+        context: &str,
+        exten: &str,
+        priority: u32,
+    ) -> Self {
+        Self::new(
+            channel,
+            Destination::Extension {
+                context: context.to_string(),
+                exten: exten.to_string(),
+                priority,
+            },
+        )
+    }
+
+    /// Calls `channel` and executes a dialplan application once it has been answered
+    pub fn to_application(
+        channel: &str,
+        application: &str,
+        data: &str,
+    ) -> Self {
+        Self::new(
+            channel,
+            Destination::Application {
+                application: application.to_string(),
+                data: data.to_string(),
+            },
+        )
+    }
+
+    fn new(channel: &str, destination: Destination) -> Self {
+        OriginateRequest {
+            channel: channel.to_string(),
+            destination,
+            caller_id: None,
+            timeout: None,
+            account: None,
+            variables: vec![],
+        }
+    }
+
+    /// Sets the caller id presented to the called channel, e.g. `"Reception" <100>`
+    pub fn caller_id(mut self, caller_id: &str) -> Self {
+        self.caller_id = Some(caller_id.to_string());
+        self
+    }
+
+    /// Sets how long the channel may ring before the call is considered unanswered
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the account code used for the CDRs of the call
+    pub fn account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+    /// Sets a channel variable on the originated channel
+    pub fn variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    fn to_packet(&self, action_id: &str, channel_id: &str) -> Packet {
+        let mut pkt = vec![
+            Tag::from("Action", "Originate"),
+            Tag::from("ActionID", action_id),
+            Tag::from("Channel", &self.channel),
+            Tag::from("ChannelId", channel_id),
+            Tag::from("Async", "true"),
+        ];
+        match &self.destination {
+            Destination::Extension {
+                context,
+                exten,
+                priority,
+            } => {
+                pkt.push(Tag::from("Context", context));
+                pkt.push(Tag::from("Exten", exten));
+                pkt.push(Tag::of("Priority".to_string(), priority.to_string()));
+            }
+            Destination::Application { application, data } => {
+                pkt.push(Tag::from("Application", application));
+                pkt.push(Tag::from("Data", data));
+            }
+        }
+        if let Some(caller_id) = &self.caller_id {
+            pkt.push(Tag::from("CallerID", caller_id));
+        }
+        if let Some(timeout) = self.timeout {
+            pkt.push(Tag::of(
+                "Timeout".to_string(),
+                timeout.as_millis().to_string(),
+            ));
+        }
+        if let Some(account) = &self.account {
+            pkt.push(Tag::from("Account", account));
+        }
+        for (name, value) in &self.variables {
+            pkt.push(Tag::of(
+                "Variable".to_string(),
+                format!("{}={}", name, value),
+            ));
+        }
+        pkt
+    }
+}
+
+/// Why an originated call has not been answered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OriginateFailure {
+    /// The channel could not be created, e.g. because the endpoint is not registered
+    Unavailable,
+    /// The called party hung up or rejected the call
+    HungUp,
+    /// The call has not been answered before the timeout
+    NoAnswer,
+    Busy,
+    Congestion,
+    /// Another reason code of the `OriginateResponse` event
+    Other(u32),
+}
+
+impl OriginateFailure {
+    /// Converts the `Reason` code of an `OriginateResponse` event
+    pub fn from_code(code: &str) -> Self {
+        match code.trim().parse().unwrap_or(0) {
+            0 => OriginateFailure::Unavailable,
+            1 => OriginateFailure::HungUp,
+            3 => OriginateFailure::NoAnswer,
+            5 => OriginateFailure::Busy,
+            8 => OriginateFailure::Congestion,
+            n => OriginateFailure::Other(n),
+        }
+    }
+}
+
+/// The progress of an originated call
+///
+/// Progress only ever moves forward: `Queued`, `Ringing`, `EarlyMedia`, and finally either
+/// `Answered` or `Failed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallProgress {
+    /// The server has accepted the originate, the channel is being created
+    Queued,
+    Ringing,
+    /// The called party sends audio before answering, e.g. announcements of the provider
+    EarlyMedia,
+    Answered,
+    Failed(OriginateFailure),
+}
+
+impl CallProgress {
+    /// Checks if the call has been answered or failed
+    pub fn is_final(&self) -> bool {
+        matches!(self, CallProgress::Answered | CallProgress::Failed(_))
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            CallProgress::Queued => 0,
+            CallProgress::Ringing => 1,
+            CallProgress::EarlyMedia => 2,
+            CallProgress::Answered | CallProgress::Failed(_) => 3,
+        }
+    }
+}
+
+/// The observed state of an originated call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallState {
+    pub progress: CallProgress,
+    /// Name of the originated channel once it has been created
+    pub channel: Option<String>,
+    /// `Uniqueid` of the originated channel
+    pub unique_id: String,
+}
+
+/// The ids tying events to an originate
+#[derive(Debug, Clone)]
+struct CallIds {
+    action_id: String,
+    unique_id: String,
+}
+
+impl CallIds {
+    /// Updates `state` from an event, returns `true` if the state has been changed and
+    /// whether the originate has been completed
+    fn apply(&self, state: &mut CallState, pkt: &Packet) -> (bool, bool) {
+        let event = match event_name(pkt) {
+            Some(event) => event.to_ascii_lowercase(),
+            None => return (false, false),
+        };
+        let is_ours =
+            |key| find_tag(pkt, key).is_some_and(|id| *id == self.unique_id);
+        let next = match event.as_str() {
+            "originateresponse" => {
+                if find_tag(pkt, "ActionID") != Some(&self.action_id) {
+                    return (false, false);
+                }
+                let success = find_tag(pkt, "Response")
+                    .is_some_and(|r| r.eq_ignore_ascii_case("Success"));
+                if state.channel.is_none() {
+                    state.channel = find_value(pkt, "Channel");
+                }
+                let progress = if success {
+                    CallProgress::Answered
+                } else {
+                    CallProgress::Failed(
+                        find_tag(pkt, "Reason")
+                            .map_or(OriginateFailure::Unavailable, |r| {
+                                OriginateFailure::from_code(r)
+                            }),
+                    )
+                };
+                // the final result of the server wins over what has been derived so far
+                let changed = state.progress != progress;
+                state.progress = progress;
+                return (changed, true);
+            }
+            "newchannel" | "newstate" if is_ours("Uniqueid") => {
+                let changed = state.channel.is_none();
+                if changed {
+                    state.channel = find_value(pkt, "Channel");
+                }
+                match find_tag(pkt, "ChannelState").map(|s| s.trim()) {
+                    Some("5") => Some(CallProgress::Ringing),
+                    Some("6") => Some(CallProgress::Answered),
+                    _ => return (changed, false),
+                }
+            }
+            "dialstate" if is_ours("DestUniqueid") => {
+                match find_tag(pkt, "DialStatus")
+                    .map(|s| s.to_ascii_uppercase())
+                    .as_deref()
+                {
+                    Some("RINGING") => Some(CallProgress::Ringing),
+                    Some("PROGRESS") => Some(CallProgress::EarlyMedia),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        match next {
+            Some(next) if next.rank() > state.progress.rank() => {
+                state.progress = next;
+                (true, false)
+            }
+            _ => (false, false),
+        }
+    }
+}
+
+/// A call started by `AmiConnection::originate`
+///
+/// The progress is derived from the `Newchannel`, `Newstate`, `DialState`, and
+/// `OriginateResponse` events of the call. It is tracked until the server has sent the
+/// `OriginateResponse` or the `Call` has been dropped.
+pub struct Call {
+    action_id: String,
+    state_rx: watch::Receiver<CallState>,
+    connection: AmiConnection,
+}
+
+impl Call {
+    /// The ActionID of the `Originate` action
+    pub fn action_id(&self) -> &str {
+        &self.action_id
+    }
+
+    /// Returns the current state of the call
+    pub fn state(&self) -> CallState {
+        self.state_rx.borrow().clone()
+    }
+
+    /// Returns the current progress of the call
+    pub fn progress(&self) -> CallProgress {
+        self.state_rx.borrow().progress
+    }
+
+    /// Subscribes to the state of the call
+    pub fn watch(&self) -> watch::Receiver<CallState> {
+        self.state_rx.clone()
+    }
+
+    /// Hangs up the originated channel unless the call has been answered or failed already
+    ///
+    /// Returns whether the channel has been hung up; the call then fails once the server
+    /// reports the result of the originate. A call answered while the `Hangup` is on its way is
+    /// hung up anyway. Before the channel name is known the channel is addressed by its
+    /// `Uniqueid`.
+    pub async fn cancel(&self) -> Result<bool, Error> {
+        let state = self.state();
+        if state.progress.is_final() {
+            return Ok(false);
+        }
+        let channel = state.channel.unwrap_or(state.unique_id);
+        let hangup = vec![
+            Tag::from("Action", "Hangup"),
+            Tag::from("Channel", &channel),
+        ];
+        match self.connection.send_action(hangup).await {
+            Ok(_) => Ok(true),
+            // the channel is gone already, or has never been created
+            Err(Error::ActionFailed(_)) if self.progress().is_final() => {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Waits until the call has been answered or failed
+    ///
+    /// Fails with `Error::ConnectionClosed` if the connection is lost before the server
+    /// reported the result.
+    pub async fn wait(&mut self) -> Result<CallProgress, Error> {
+        loop {
+            let progress = self.state_rx.borrow_and_update().progress;
+            if progress.is_final() {
+                return Ok(progress);
+            }
+            if self.state_rx.changed().await.is_err() {
+                let progress = self.state_rx.borrow().progress;
+                return match progress {
+                    CallProgress::Answered => Ok(progress),
+                    _ => Err(Error::ConnectionClosed),
+                };
+            }
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Originates a call without waiting for it to be answered
+    ///
+    /// Returns as soon as the server has accepted the request; the returned `Call` reports the
+    /// progress of the call. Requires Asterisk 12 or later, as the originated channel is
+    /// identified using `ChannelId`.
+    pub async fn originate(
+        &self,
+        request: &OriginateRequest,
+    ) -> Result<Call, Error> {
+        let action_id = self.next_action_id();
+        let ids = CallIds {
+            unique_id: format!(
+                "originate-{}-{}",
+                std::process::id(),
+                action_id
+            ),
+            action_id: action_id.clone(),
+        };
+        let (state_tx, state_rx) = watch::channel(CallState {
+            progress: CallProgress::Queued,
+            channel: None,
+            unique_id: ids.unique_id.clone(),
+        });
+
+        // subscribe before sending, the first events may arrive before the response
+        let mut events = self.events();
+        self.send_action(request.to_packet(&action_id, &ids.unique_id))
+            .await?;
+
+        tokio::spawn(async move {
+            loop {
+                let pkt = match events.recv().await {
+                    Ok(Some(pkt)) => pkt,
+                    Ok(None) | Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!(
+                            "Originate {} missed {} events",
+                            ids.action_id, n
+                        );
+                        crate::metrics::events_lagged(n);
+                        continue;
+                    }
+                };
+                let mut state = state_tx.borrow().clone();
+                let (changed, done) = ids.apply(&mut state, &pkt);
+                if changed {
+                    state_tx.send_replace(state);
+                }
+                if done || state_tx.is_closed() {
+                    break;
+                }
+            }
+            trace!("Stopped tracking originate {}", ids.action_id);
+        });
+
+        Ok(Call {
+            action_id,
+            state_rx,
+            connection: self.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn derives_progress_from_events() {
+        let ids = CallIds {
+            action_id: "7".to_string(),
+            unique_id: "originate-7".to_string(),
+        };
+        let mut state = CallState {
+            progress: CallProgress::Queued,
+            channel: None,
+            unique_id: ids.unique_id.clone(),
+        };
+        ids.apply(
+            &mut state,
+            &event(&[
+                ("Event", "Newchannel"),
+                ("Channel", "PJSIP/100-00000001"),
+                ("ChannelState", "0"),
+                ("Uniqueid", "originate-7"),
+            ]),
+        );
+        assert_eq!(state.channel.as_deref(), Some("PJSIP/100-00000001"));
+        for (status, progress) in [
+            ("PROGRESS", CallProgress::EarlyMedia),
+            ("RINGING", CallProgress::EarlyMedia),
+        ] {
+            ids.apply(
+                &mut state,
+                &event(&[
+                    ("Event", "DialState"),
+                    ("DestUniqueid", "originate-7"),
+                    ("DialStatus", status),
+                ]),
+            );
+            assert_eq!(state.progress, progress);
+        }
+        let (_, done) = ids.apply(
+            &mut state,
+            &event(&[
+                ("Event", "OriginateResponse"),
+                ("ActionID", "7"),
+                ("Response", "Failure"),
+                ("Reason", "5"),
+            ]),
+        );
+        assert!(done);
+        assert_eq!(
+            state.progress,
+            CallProgress::Failed(OriginateFailure::Busy)
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn cancels_unanswered_call() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let request =
+            OriginateRequest::to_extension("PJSIP/100", "default", "200", 1);
+        let respond = |action: &Packet| {
+            event(&[
+                ("Response", "Success"),
+                ("ActionID", find_tag(action, "ActionID").unwrap()),
+            ])
+        };
+        let (call, _) = tokio::join!(connection.originate(&request), async {
+            let originate = server.read_packet().await.unwrap();
+            server.send_packet(&respond(&originate)).await.unwrap();
+        });
+        let call = call.unwrap();
+        let mut states = call.watch();
+        let unique_id = call.state().unique_id;
+        let newchannel = event(&[
+            ("Event", "Newchannel"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("ChannelState", "0"),
+            ("Uniqueid", &unique_id),
+        ]);
+        server.send_packet(&newchannel).await.unwrap();
+        states.changed().await.unwrap();
+
+        let (cancelled, hangup) = tokio::join!(call.cancel(), async {
+            let hangup = server.read_packet().await.unwrap();
+            server.send_packet(&respond(&hangup)).await.unwrap();
+            hangup
+        });
+        assert_eq!(cancelled, Ok(true));
+        assert_eq!(find_tag(&hangup, "Action").unwrap(), "Hangup");
+        assert_eq!(find_tag(&hangup, "Channel").unwrap(), "PJSIP/100-00000001");
+
+        let response = event(&[
+            ("Event", "OriginateResponse"),
+            ("ActionID", call.action_id()),
+            ("Response", "Failure"),
+            ("Reason", "1"),
+        ]);
+        server.send_packet(&response).await.unwrap();
+        states.changed().await.unwrap();
+        assert_eq!(call.cancel().await, Ok(false));
+    }
+}
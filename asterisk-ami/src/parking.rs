@@ -0,0 +1,324 @@
+use crate::tracking::{publish, reconcile, spawn_tracker};
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::AbortHandle;
+
+/// A call occupying a parking space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParkedCall {
+    pub parking_lot: String,
+    pub space: String,
+    /// The parked channel
+    pub channel: String,
+    pub unique_id: Option<String>,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    /// The dial string of the channel that parked the call
+    pub parker: Option<String>,
+    pub parked_at: Instant,
+    /// The moment the call is forced out of the parking space, `None` if it never times out
+    pub timeout_at: Option<Instant>,
+}
+
+impl ParkedCall {
+    /// Time the call has been parked for
+    pub fn duration(&self) -> Duration {
+        self.parked_at.elapsed()
+    }
+
+    /// Time remaining until the call times out
+    pub fn timeout_remaining(&self) -> Option<Duration> {
+        let timeout_at = self.timeout_at?;
+        Some(timeout_at.saturating_duration_since(Instant::now()))
+    }
+
+    fn key(&self) -> (String, String) {
+        (self.parking_lot.clone(), self.space.clone())
+    }
+
+    /// Parses parking events and `ParkedCalls` list items of Asterisk 12 and later as well as
+    /// the legacy format using `Channel` and `Exten`
+    fn from_packet(pkt: &Packet, now: Instant) -> Option<Self> {
+        let seconds = |key| {
+            find_tag(pkt, key)
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs)
+        };
+        let parked_for = seconds("ParkingDuration")
+            .or_else(|| seconds("Duration"))
+            .unwrap_or_default();
+        let timeout = seconds("ParkingTimeout").or_else(|| seconds("Timeout"));
+        Some(ParkedCall {
+            parking_lot: find_value(pkt, "Parkinglot")
+                .unwrap_or_else(|| "default".to_string()),
+            space: find_value(pkt, "ParkingSpace")
+                .or_else(|| find_value(pkt, "Exten"))?,
+            channel: find_value(pkt, "ParkeeChannel")
+                .or_else(|| find_value(pkt, "Channel"))?,
+            unique_id: find_value(pkt, "ParkeeUniqueid")
+                .or_else(|| find_value(pkt, "Uniqueid")),
+            caller_id_num: find_value(pkt, "ParkeeCallerIDNum")
+                .or_else(|| find_value(pkt, "CallerIDNum")),
+            caller_id_name: find_value(pkt, "ParkeeCallerIDName")
+                .or_else(|| find_value(pkt, "CallerIDName")),
+            parker: find_value(pkt, "ParkerDialString")
+                .or_else(|| find_value(pkt, "From")),
+            parked_at: now.checked_sub(parked_for).unwrap_or(now),
+            timeout_at: timeout
+                .filter(|t| !t.is_zero())
+                .map(|timeout| now + timeout),
+        })
+    }
+}
+
+/// Why a call has left its parking space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnparkReason {
+    /// The call has been picked up by the given channel
+    Retrieved(Option<String>),
+    TimedOut,
+    /// The parked caller hung up
+    GaveUp,
+    /// The call left while the tracker was not following the server, e.g. during a reconnect
+    Unknown,
+}
+
+/// A change of the parking lots observed by a `ParkingTracker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParkingChange {
+    Parked(ParkedCall),
+    /// The parked channel has been replaced, e.g. by a transfer
+    Swapped(ParkedCall),
+    Unparked {
+        call: ParkedCall,
+        reason: UnparkReason,
+    },
+}
+
+type ParkingLots = HashMap<(String, String), ParkedCall>;
+
+impl AmiConnection {
+    /// Lists the parked calls of a parking lot or, if `parking_lot` is `None`, of all lots
+    pub async fn parked_calls(
+        &self,
+        parking_lot: Option<&str>,
+    ) -> Result<Vec<ParkedCall>, Error> {
+        let mut pkt = vec![Tag::from("Action", "ParkedCalls")];
+        if let Some(parking_lot) = parking_lot {
+            pkt.push(Tag::from("ParkingLot", parking_lot));
+        }
+        let resp = self.send_action(pkt).await?;
+        let now = Instant::now();
+        Ok(list_items(&resp, "ParkedCall")
+            .filter_map(|pkt| ParkedCall::from_packet(pkt, now))
+            .collect())
+    }
+}
+
+/// Keeps track of the occupied spaces of all parking lots
+///
+/// The tracker is seeded using `ParkedCalls` and afterwards kept up to date from the
+/// `ParkedCall`, `ParkedCallSwap`, `UnParkedCall`, `ParkedCallTimeOut`, and
+/// `ParkedCallGiveUp` events.
+pub struct ParkingTracker {
+    calls: Arc<RwLock<ParkingLots>>,
+    changes_tx: broadcast::Sender<ParkingChange>,
+    task: Mutex<AbortHandle>,
+}
+
+impl ParkingTracker {
+    /// Starts tracking the parking lots of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<ParkingTracker, Error> {
+        let events = connection.events();
+        let calls = Arc::new(RwLock::new(seed(connection).await?));
+        let (changes_tx, _) = broadcast::channel::<ParkingChange>(32);
+
+        let task = spawn_tracker(
+            "Parking tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&calls),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(ParkingTracker {
+            calls,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// Calls that left their space in the meantime are reported as `Unparked` with the reason
+    /// `Unknown`, newly parked calls as `Parked`, and spaces now holding another channel as
+    /// `Swapped`. Afterwards the events of `connection` are followed instead of the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let fresh = seed(connection).await?;
+        let changes =
+            reconcile(&mut self.calls.write().unwrap(), fresh, |old, call| {
+                if old.channel == call.channel {
+                    call.parked_at = old.parked_at;
+                    call.timeout_at = old.timeout_at;
+                }
+            });
+        let unparked =
+            changes
+                .removed
+                .into_iter()
+                .map(|call| ParkingChange::Unparked {
+                    call,
+                    reason: UnparkReason::Unknown,
+                });
+        let changes = unparked
+            .chain(changes.added.into_iter().map(ParkingChange::Parked))
+            .chain(changes.updated.into_iter().map(ParkingChange::Swapped));
+        for change in changes {
+            publish(&self.changes_tx, change);
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Parking tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.calls),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of all parked calls
+    pub fn snapshot(&self) -> Vec<ParkedCall> {
+        self.calls.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the call parked in `space` of `parking_lot`
+    pub fn get(&self, parking_lot: &str, space: &str) -> Option<ParkedCall> {
+        let calls = self.calls.read().unwrap();
+        calls
+            .get(&(parking_lot.to_string(), space.to_string()))
+            .cloned()
+    }
+
+    /// Returns the calls parked in a single parking lot
+    pub fn lot(&self, parking_lot: &str) -> Vec<ParkedCall> {
+        let calls = self.calls.read().unwrap();
+        calls
+            .values()
+            .filter(|c| c.parking_lot == parking_lot)
+            .cloned()
+            .collect()
+    }
+
+    /// Subscribes to changes of the parking lots
+    pub fn changes(&self) -> broadcast::Receiver<ParkingChange> {
+        self.changes_tx.subscribe()
+    }
+}
+
+impl Drop for ParkingTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists the calls currently parked on the server, keyed by lot and space
+async fn seed(connection: &AmiConnection) -> Result<ParkingLots, Error> {
+    Ok(connection
+        .parked_calls(None)
+        .await?
+        .into_iter()
+        .map(|call| (call.key(), call))
+        .collect())
+}
+
+fn apply_event(calls: &mut ParkingLots, pkt: &Packet) -> Option<ParkingChange> {
+    let event = event_name(pkt)?.to_ascii_lowercase();
+    let reason = match event.as_str() {
+        "parkedcall" | "parkedcallswap" => {
+            let call = ParkedCall::from_packet(pkt, Instant::now())?;
+            let previous = calls.insert(call.key(), call.clone());
+            return Some(match previous {
+                Some(_) if event == "parkedcallswap" => {
+                    ParkingChange::Swapped(call)
+                }
+                _ => ParkingChange::Parked(call),
+            });
+        }
+        "unparkedcall" => UnparkReason::Retrieved(
+            find_value(pkt, "RetrieverChannel")
+                .or_else(|| find_value(pkt, "From")),
+        ),
+        "parkedcalltimeout" => UnparkReason::TimedOut,
+        "parkedcallgiveup" => UnparkReason::GaveUp,
+        _ => return None,
+    };
+    let key = ParkedCall::from_packet(pkt, Instant::now())?.key();
+    let call = calls.remove(&key)?;
+    Some(ParkingChange::Unparked { call, reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_parked_calls() {
+        let mut calls = ParkingLots::new();
+        let parked = apply_event(
+            &mut calls,
+            &event(&[
+                ("Event", "ParkedCall"),
+                ("ParkeeChannel", "PJSIP/trunk-00000002"),
+                ("ParkeeUniqueid", "1621.2"),
+                ("ParkerDialString", "PJSIP/100"),
+                ("Parkinglot", "default"),
+                ("ParkingSpace", "701"),
+                ("ParkingTimeout", "45"),
+                ("ParkingDuration", "0"),
+            ]),
+        );
+        let call = match parked {
+            Some(ParkingChange::Parked(call)) => call,
+            other => panic!("unexpected change {:?}", other),
+        };
+        assert_eq!(call.parker.as_deref(), Some("PJSIP/100"));
+        assert!(call.timeout_remaining().unwrap() > Duration::from_secs(40));
+
+        let unparked = apply_event(
+            &mut calls,
+            &event(&[
+                ("Event", "UnParkedCall"),
+                ("ParkeeChannel", "PJSIP/trunk-00000002"),
+                ("Parkinglot", "default"),
+                ("ParkingSpace", "701"),
+                ("RetrieverChannel", "PJSIP/200-00000005"),
+            ]),
+        );
+        assert!(matches!(
+            unparked,
+            Some(ParkingChange::Unparked {
+                reason: UnparkReason::Retrieved(Some(_)),
+                ..
+            })
+        ));
+        assert!(calls.is_empty());
+    }
+}
@@ -0,0 +1,431 @@
+//! Capturing the AMI traffic of a connection to a pcapng file
+//!
+//! The capture can be opened in Wireshark, whose AMI dissector decodes it, without running
+//! tcpdump on the PBX. It's built from the wire tap, so the packets in it are reconstructed
+//! rather than captured: every AMI packet becomes one TCP segment of a stream between
+//! `192.0.2.1` and `192.0.2.2:5038`, with made-up addresses but consistent sequence numbers, and
+//! credentials are redacted. See `Capture` for what the capture may be missing.
+
+use crate::wire::{Direction, WireLine};
+use crate::AmiConnection;
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const CLIENT_ADDR: [u8; 4] = [192, 0, 2, 1];
+const SERVER_ADDR: [u8; 4] = [192, 0, 2, 2];
+const CLIENT_PORT: u16 = 50380;
+/// The port Wireshark's AMI dissector is registered for
+const SERVER_PORT: u16 = 5038;
+/// IPv4 packets without a link-layer header
+const LINKTYPE_RAW: u16 = 101;
+/// What fits into an IPv4 packet after the IP and TCP headers
+const MAX_SEGMENT: usize = 65535 - 40;
+
+/// The ones' complement sum used by the IP and TCP checksums
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for pair in part.chunks(2) {
+            let word = match pair {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                [high] => u16::from_be_bytes([*high, 0]),
+                _ => 0,
+            };
+            sum += u32::from(word);
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Writes AMI traffic as the TCP segments of a single connection to a pcapng stream
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    /// The next sequence numbers of the client and the server
+    client_seq: u32,
+    server_seq: u32,
+    /// The comment attached to the next packet
+    comment: Option<String>,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Starts a section of a pcapng file, with one interface carrying the connection
+    ///
+    /// Sections can be appended to an existing file, Wireshark shows them one after another.
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        // section header block, without options and of unspecified length
+        writer.write_all(&0x0a0d_0d0a_u32.to_le_bytes())?;
+        writer.write_all(&28_u32.to_le_bytes())?;
+        writer.write_all(&0x1a2b_3c4d_u32.to_le_bytes())?;
+        writer.write_all(&1_u16.to_le_bytes())?;
+        writer.write_all(&0_u16.to_le_bytes())?;
+        writer.write_all(&(-1_i64).to_le_bytes())?;
+        writer.write_all(&28_u32.to_le_bytes())?;
+        // interface description block, with microsecond timestamps
+        writer.write_all(&1_u32.to_le_bytes())?;
+        writer.write_all(&20_u32.to_le_bytes())?;
+        writer.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        writer.write_all(&0_u16.to_le_bytes())?;
+        writer.write_all(&0_u32.to_le_bytes())?;
+        writer.write_all(&20_u32.to_le_bytes())?;
+        Ok(PcapngWriter {
+            writer,
+            client_seq: 1,
+            server_seq: 1,
+            comment: None,
+        })
+    }
+
+    /// Writes data sent in `direction` at the time `at`, split into segments as needed
+    pub fn write(
+        &mut self,
+        direction: Direction,
+        at: SystemTime,
+        data: &[u8],
+    ) -> std::io::Result<()> {
+        for payload in data.chunks(MAX_SEGMENT) {
+            let segment = self.segment(direction, payload);
+            self.write_packet(at, &segment)?;
+        }
+        Ok(())
+    }
+
+    /// Attaches a comment to the next packet written, shown by Wireshark as `pkt_comment`
+    ///
+    /// Comments given before that packet are joined by line breaks.
+    pub fn annotate(&mut self, comment: &str) {
+        match self.comment.as_mut() {
+            Some(pending) => {
+                pending.push('\n');
+                pending.push_str(comment);
+            }
+            None => self.comment = Some(comment.to_string()),
+        }
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Returns the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Builds the IPv4 packet carrying a TCP segment, advancing the sequence numbers
+    fn segment(&mut self, direction: Direction, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, src_port, dst_port, seq, ack) = match direction {
+            Direction::Outbound => (
+                CLIENT_ADDR,
+                SERVER_ADDR,
+                CLIENT_PORT,
+                SERVER_PORT,
+                &mut self.client_seq,
+                self.server_seq,
+            ),
+            Direction::Inbound => (
+                SERVER_ADDR,
+                CLIENT_ADDR,
+                SERVER_PORT,
+                CLIENT_PORT,
+                &mut self.server_seq,
+                self.client_seq,
+            ),
+        };
+        let tcp_len = (20 + payload.len()) as u16;
+
+        let mut tcp = Vec::with_capacity(tcp_len as usize);
+        tcp.extend_from_slice(&src_port.to_be_bytes());
+        tcp.extend_from_slice(&dst_port.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        // a header of five words, with PSH and ACK set
+        tcp.extend_from_slice(&[5 << 4, 0x18]);
+        tcp.extend_from_slice(&u16::MAX.to_be_bytes());
+        tcp.extend_from_slice(&[0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+        let pseudo_header =
+            [&src[..], &dst[..], &[0, 6], &tcp_len.to_be_bytes()[..]].concat();
+        let tcp_checksum = checksum(&[&pseudo_header, &tcp]);
+        tcp[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+        *seq = seq.wrapping_add(payload.len() as u32);
+
+        let mut ip = Vec::with_capacity(20 + tcp.len());
+        ip.extend_from_slice(&[0x45, 0]);
+        ip.extend_from_slice(&(20 + tcp_len).to_be_bytes());
+        // no identification, don't fragment, TTL 64, TCP
+        ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        ip.extend_from_slice(&src);
+        ip.extend_from_slice(&dst);
+        let ip_checksum = checksum(&[&ip]);
+        ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+        ip.extend_from_slice(&tcp);
+        ip
+    }
+
+    /// Writes an enhanced packet block
+    fn write_packet(
+        &mut self,
+        at: SystemTime,
+        packet: &[u8],
+    ) -> std::io::Result<()> {
+        let micros = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let padding = (4 - packet.len() % 4) % 4;
+        // an opt_comment option followed by opt_endofopt
+        let comment = self.comment.take().map(String::into_bytes);
+        let options_len = comment
+            .as_ref()
+            .map_or(0, |c| 4 + c.len() + (4 - c.len() % 4) % 4 + 4);
+        let block_len = (32 + packet.len() + padding + options_len) as u32;
+        self.writer.write_all(&6_u32.to_le_bytes())?;
+        self.writer.write_all(&block_len.to_le_bytes())?;
+        self.writer.write_all(&0_u32.to_le_bytes())?;
+        self.writer
+            .write_all(&((micros >> 32) as u32).to_le_bytes())?;
+        self.writer.write_all(&(micros as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer
+            .write_all(&(packet.len() as u32).to_le_bytes())?;
+        self.writer.write_all(packet)?;
+        self.writer.write_all(&[0; 3][..padding])?;
+        if let Some(comment) = comment {
+            let padding = (4 - comment.len() % 4) % 4;
+            self.writer.write_all(&1_u16.to_le_bytes())?;
+            self.writer
+                .write_all(&(comment.len() as u16).to_le_bytes())?;
+            self.writer.write_all(&comment)?;
+            self.writer.write_all(&[0; 3][..padding])?;
+            self.writer.write_all(&[0; 4])?;
+        }
+        self.writer.write_all(&block_len.to_le_bytes())
+    }
+}
+
+/// The lines of an AMI packet sent in one direction, not yet written
+#[derive(Default)]
+struct Pending {
+    data: Vec<u8>,
+    first_line_at: Option<SystemTime>,
+}
+
+impl Pending {
+    /// Adds a line, returns whether it ended a packet
+    fn push(&mut self, line: &WireLine) -> bool {
+        self.first_line_at.get_or_insert(line.at);
+        self.data.extend_from_slice(line.line.as_bytes());
+        self.data.extend_from_slice(b"\r\n");
+        line.line.is_empty()
+    }
+
+    fn write<W: Write>(
+        &mut self,
+        direction: Direction,
+        writer: &mut PcapngWriter<W>,
+    ) -> std::io::Result<()> {
+        if let Some(at) = self.first_line_at.take() {
+            writer.write(direction, at, &self.data)?;
+            self.data.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Captures the traffic of a connection to a pcapng file
+///
+/// Both directions are captured, timestamped with the time the first line of each packet was
+/// sent or received. The capture is not a byte-exact copy of the traffic:
+///
+/// - it starts after the server's greeting, which is never part of it,
+/// - credentials like the `Secret` of `Login` are redacted,
+/// - like for the `Recorder`, lines are missed if the disk cannot keep up. The stream stays
+///   consistent for Wireshark, so a warning is logged and the packet following the gap
+///   carries a comment with the number of lines missed.
+pub struct Capture {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl Capture {
+    /// Starts capturing to `path`, replacing the file if it exists
+    pub fn start<P: AsRef<Path>>(
+        connection: &AmiConnection,
+        path: P,
+    ) -> std::io::Result<Capture> {
+        Self::tap(connection, File::create(path)?)
+    }
+
+    /// Starts capturing to a new section at the end of `path`, creating the file if needed
+    pub fn append<P: AsRef<Path>>(
+        connection: &AmiConnection,
+        path: P,
+    ) -> std::io::Result<Capture> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Self::tap(connection, file)
+    }
+
+    fn tap(connection: &AmiConnection, file: File) -> std::io::Result<Capture> {
+        let mut writer = PcapngWriter::new(BufWriter::new(file))?;
+        let mut wire = connection.wire_tap();
+        let mut events = connection.events();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            let mut inbound = Pending::default();
+            let mut outbound = Pending::default();
+            loop {
+                let line: WireLine = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    event = events.recv() => match event {
+                        Ok(None) | Err(RecvError::Closed) => break,
+                        _ => continue,
+                    },
+                    line = wire.recv() => match line {
+                        Ok(line) => line,
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("Capture missed {} lines", n);
+                            writer.annotate(&format!(
+                                "{} lines of the AMI traffic missed before this packet",
+                                n
+                            ));
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                let pending = match line.direction {
+                    Direction::Inbound => &mut inbound,
+                    Direction::Outbound => &mut outbound,
+                };
+                if pending.push(&line) {
+                    pending.write(line.direction, &mut writer)?;
+                }
+            }
+            inbound.write(Direction::Inbound, &mut writer)?;
+            outbound.write(Direction::Outbound, &mut writer)?;
+            writer.flush()
+        });
+
+        Ok(Capture { stop_tx, task })
+    }
+
+    /// Stops capturing and writes the remaining lines to the file
+    pub async fn stop(self) -> std::io::Result<()> {
+        let _ = self.stop_tx.send(());
+        self.task.await.map_err(std::io::Error::other)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Splits a pcapng stream into its blocks' types and bodies
+    fn blocks(mut data: &[u8]) -> Vec<(u32, &[u8])> {
+        let word = |bytes: &[u8]| {
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        let mut blocks = vec![];
+        while !data.is_empty() {
+            let len = word(&data[4..]) as usize;
+            assert_eq!(word(&data[len - 4..]) as usize, len);
+            blocks.push((word(data), &data[8..len - 4]));
+            data = &data[len..];
+        }
+        blocks
+    }
+
+    #[test]
+    fn writes_tcp_segments() {
+        let at = SystemTime::UNIX_EPOCH + Duration::from_micros(1_500_000);
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        let ping = b"Action: Ping\r\nActionID: 1\r\n\r\n";
+        let pong = b"Response: Success\r\nActionID: 1\r\nPing: Pong\r\n\r\n";
+        writer.write(Direction::Outbound, at, ping).unwrap();
+        writer.write(Direction::Inbound, at, pong).unwrap();
+        let data = writer.into_inner();
+
+        let blocks = blocks(&data);
+        let types = blocks.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        assert_eq!(types, vec![0x0a0d0d0a, 1, 6, 6]);
+        let response = blocks[3].1;
+        assert_eq!(&response[4..12], &[0, 0, 0, 0, 0x60, 0xe3, 0x16, 0]);
+        let packet = &response[20..20 + 40 + pong.len()];
+        assert_eq!(checksum(&[&packet[..20]]), 0);
+        assert_eq!(&packet[12..16], &SERVER_ADDR);
+        assert_eq!(&packet[20..22], &SERVER_PORT.to_be_bytes());
+        // the server's first segment acknowledges the client's
+        let ack = u32::from_be_bytes([
+            packet[28], packet[29], packet[30], packet[31],
+        ]);
+        assert_eq!(ack, 1 + ping.len() as u32);
+        assert_eq!(&packet[40..], &pong[..]);
+    }
+
+    #[test]
+    fn attaches_comments() {
+        let mut writer = PcapngWriter::new(vec![]).unwrap();
+        writer.annotate("3 lines missed");
+        let ping = b"Action: Ping\r\n\r\n";
+        writer
+            .write(Direction::Outbound, SystemTime::UNIX_EPOCH, ping)
+            .unwrap();
+        writer
+            .write(Direction::Outbound, SystemTime::UNIX_EPOCH, ping)
+            .unwrap();
+        let data = writer.into_inner();
+
+        let blocks = blocks(&data);
+        let options = &blocks[2].1[20 + 40 + ping.len()..];
+        assert_eq!(&options[..4], &[1, 0, 14, 0]);
+        assert_eq!(&options[4..18], b"3 lines missed");
+        assert_eq!(&options[18..], &[0; 6]);
+        assert_eq!(blocks[3].1.len(), 20 + 40 + ping.len());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn captures_both_directions() {
+        use crate::{find_tag, packet_from};
+        let path = std::env::temp_dir().join(format!(
+            "asterisk-ami-capture-{}.pcapng",
+            std::process::id()
+        ));
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let capture = Capture::start(&connection, &path).unwrap();
+        let (result, _) = tokio::join!(connection.ping(), async {
+            let ping = server.read_packet().await.unwrap();
+            let pong = packet_from(&[
+                ("Response", "Success"),
+                ("ActionID", find_tag(&ping, "ActionID").unwrap()),
+                ("Ping", "Pong"),
+            ]);
+            server.send_packet(&pong).await.unwrap();
+        });
+        result.unwrap();
+        capture.stop().await.unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 4);
+        let payload =
+            |block: &[u8]| String::from_utf8_lossy(&block[60..]).into_owned();
+        assert!(payload(blocks[2].1).starts_with("Action: Ping\r\n"));
+        assert!(payload(blocks[3].1).contains("Ping: Pong\r\n\r\n"));
+    }
+}
@@ -0,0 +1,559 @@
+use crate::devices::DeviceState;
+use crate::tracking::{derive_watch, publish, reconcile, spawn_tracker};
+use crate::{event_name, find_tag, find_value, AmiConnection, Error, Packet};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+/// What kind of entity a `PeerStatus` describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerKind {
+    /// A peer or endpoint as reported by `PeerStatus` events
+    Peer,
+    /// A PJSIP contact as reported by `ContactStatus` events
+    Contact,
+    /// An outbound registration as reported by `Registry` events
+    Registration,
+}
+
+/// The reachability or registration status of a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reachability {
+    Registered,
+    Unregistered,
+    Reachable,
+    Unreachable,
+    Lagged,
+    Rejected,
+    Unknown,
+}
+
+impl Reachability {
+    /// Parses the status sent in `PeerStatus`, `ContactStatus`, and `Registry` events
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "registered" => Reachability::Registered,
+            "unregistered" => Reachability::Unregistered,
+            "reachable" | "created" | "updated" => Reachability::Reachable,
+            "unreachable" | "unavailable" => Reachability::Unreachable,
+            "lagged" => Reachability::Lagged,
+            "rejected" | "failed" => Reachability::Rejected,
+            _ => Reachability::Unknown,
+        }
+    }
+}
+
+/// The state of an outbound registration to a trunk or provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationState {
+    Registered,
+    Unregistered,
+    /// A request has been sent and the answer is still outstanding
+    Pending,
+    Rejected,
+    Failed,
+    Timeout,
+    Stopped,
+    Unknown,
+}
+
+impl RegistrationState {
+    /// Parses the registration state of chan_sip and PJSIP registration listings
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "registered" => RegistrationState::Registered,
+            "unregistered" => RegistrationState::Unregistered,
+            "request sent" | "auth. sent" | "unregistering" => {
+                RegistrationState::Pending
+            }
+            "rejected" | "no authentication" => RegistrationState::Rejected,
+            "failed" => RegistrationState::Failed,
+            "timeout" => RegistrationState::Timeout,
+            "stopped" | "shutdown" => RegistrationState::Stopped,
+            _ => RegistrationState::Unknown,
+        }
+    }
+}
+
+/// The last known status of a peer, contact, or registration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub kind: PeerKind,
+    /// Peer name, contact URI, or `username@domain` of a registration
+    pub name: String,
+    pub channel_type: Option<String>,
+    /// Name of the endpoint a contact belongs to
+    pub endpoint: Option<String>,
+    pub status: Reachability,
+    /// The status as sent by Asterisk
+    pub status_text: String,
+    /// Round trip time of the last qualify
+    pub latency: Option<Duration>,
+    /// `true` if the contact has been removed
+    pub removed: bool,
+}
+
+impl PeerStatus {
+    fn from_event(pkt: &Packet) -> Option<Self> {
+        let event = event_name(pkt)?.to_ascii_lowercase();
+        let (kind, name, status_text) = match event.as_str() {
+            "peerstatus" => (
+                PeerKind::Peer,
+                find_tag(pkt, "Peer")?.clone(),
+                find_tag(pkt, "PeerStatus")?,
+            ),
+            "contactstatus" => (
+                PeerKind::Contact,
+                find_tag(pkt, "URI")?.clone(),
+                find_tag(pkt, "ContactStatus")?,
+            ),
+            "registry" => (
+                PeerKind::Registration,
+                format!(
+                    "{}@{}",
+                    find_tag(pkt, "Username")?,
+                    find_tag(pkt, "Domain")?
+                ),
+                find_tag(pkt, "Status")?,
+            ),
+            _ => return None,
+        };
+        let latency = match kind {
+            PeerKind::Peer => find_tag(pkt, "Time")
+                .and_then(|t| t.trim().parse().ok())
+                .map(Duration::from_millis),
+            PeerKind::Contact => find_tag(pkt, "RoundtripUsec")
+                .and_then(|t| t.trim().parse().ok())
+                .filter(|&usec| usec > 0)
+                .map(Duration::from_micros),
+            PeerKind::Registration => None,
+        };
+        Some(PeerStatus {
+            kind,
+            name,
+            channel_type: find_value(pkt, "ChannelType"),
+            endpoint: find_value(pkt, "EndpointName")
+                .or_else(|| find_value(pkt, "AOR")),
+            status: Reachability::parse(status_text),
+            status_text: status_text.clone(),
+            latency,
+            removed: status_text.eq_ignore_ascii_case("Removed"),
+        })
+    }
+}
+
+type Peers = HashMap<(PeerKind, String), PeerStatus>;
+
+/// Keeps track of the reachability and registration status of all endpoints and trunks
+///
+/// The tracker is seeded using `SIPpeers`, `SIPshowregistry`, `PJSIPShowEndpoints`,
+/// `PJSIPShowContacts`, and `PJSIPShowRegistrationsOutbound`, skipping the listings of channel
+/// drivers that are not loaded, and afterwards kept up to date from the `PeerStatus`,
+/// `ContactStatus`, and `Registry` events.
+pub struct PeerTracker {
+    peers: Arc<RwLock<Peers>>,
+    changes_tx: broadcast::Sender<PeerStatus>,
+    task: Mutex<AbortHandle>,
+}
+
+impl PeerTracker {
+    /// Starts tracking the peers of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<PeerTracker, Error> {
+        let events = connection.events();
+        let peers = Arc::new(RwLock::new(seed(connection).await?));
+        let (changes_tx, _) = broadcast::channel::<PeerStatus>(32);
+
+        let task = spawn_tracker(
+            "Peer tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&peers),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(PeerTracker {
+            peers,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// The tracker is seeded again and the differences are published as changes: entities that
+    /// are gone are sent with `removed` set, new and changed ones with their fresh status.
+    /// Afterwards the events of `connection` are followed instead of the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let fresh = seed(connection).await?;
+        let changes =
+            reconcile(&mut self.peers.write().unwrap(), fresh, |_, _| {});
+        let removed = changes.removed.into_iter().map(|status| PeerStatus {
+            removed: true,
+            ..status
+        });
+        let changes = removed.chain(changes.added).chain(changes.updated);
+        for change in changes {
+            publish(&self.changes_tx, change);
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Peer tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.peers),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of the status of all known peers, contacts, and registrations
+    pub fn snapshot(&self) -> Vec<PeerStatus> {
+        self.peers.read().unwrap().values().cloned().collect()
+    }
+
+    /// Returns the last known status of a single entity
+    pub fn get(&self, kind: PeerKind, name: &str) -> Option<PeerStatus> {
+        let peers = self.peers.read().unwrap();
+        peers.get(&(kind, name.to_string())).cloned()
+    }
+
+    /// Subscribes to status changes
+    pub fn changes(&self) -> broadcast::Receiver<PeerStatus> {
+        self.changes_tx.subscribe()
+    }
+
+    /// Watches the number of outbound registrations, e.g. to trunks, that are registered
+    pub fn watch_registered_trunks(&self) -> watch::Receiver<usize> {
+        derive_watch("Registration watch", &self.peers, self.changes(), |p| {
+            p.values()
+                .filter(|s| s.kind == PeerKind::Registration)
+                .filter(|s| s.status == Reachability::Registered)
+                .count()
+        })
+    }
+}
+
+impl Drop for PeerTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists the peers, contacts, and registrations currently known to the server
+async fn seed(connection: &AmiConnection) -> Result<Peers, Error> {
+    let mut peers = Peers::new();
+    let mut insert = |status: PeerStatus| {
+        peers.insert((status.kind, status.name.clone()), status);
+    };
+    for peer in optional(connection.sip_peers().await)? {
+        insert(PeerStatus {
+            kind: PeerKind::Peer,
+            name: format!("SIP/{}", peer.name),
+            channel_type: Some("SIP".to_string()),
+            endpoint: None,
+            status: peer.status,
+            status_text: peer.status_text.unwrap_or_default(),
+            latency: peer.rtt,
+            removed: false,
+        });
+    }
+    for registration in optional(connection.sip_show_registry().await)? {
+        let state_text = registration.state_text.unwrap_or_default();
+        insert(PeerStatus {
+            kind: PeerKind::Registration,
+            name: format!(
+                "{}@{}",
+                registration.username.unwrap_or_default(),
+                registration.domain.unwrap_or(registration.host)
+            ),
+            channel_type: Some("SIP".to_string()),
+            endpoint: None,
+            status: Reachability::parse(&state_text),
+            status_text: state_text,
+            latency: None,
+            removed: false,
+        });
+    }
+    for endpoint in optional(connection.pjsip_show_endpoints().await)? {
+        let status = match endpoint.device_state {
+            DeviceState::Unknown => Reachability::Unknown,
+            DeviceState::Invalid | DeviceState::Unavailable => {
+                Reachability::Unreachable
+            }
+            _ => Reachability::Reachable,
+        };
+        insert(PeerStatus {
+            kind: PeerKind::Peer,
+            name: format!("PJSIP/{}", endpoint.endpoint),
+            channel_type: Some("PJSIP".to_string()),
+            endpoint: None,
+            status,
+            status_text: endpoint.device_state_text.unwrap_or_default(),
+            latency: None,
+            removed: false,
+        });
+    }
+    for contact in optional(connection.pjsip_show_contacts().await)? {
+        let uri = match contact.uri {
+            Some(uri) => uri,
+            None => continue,
+        };
+        insert(PeerStatus {
+            kind: PeerKind::Contact,
+            name: uri,
+            channel_type: Some("PJSIP".to_string()),
+            endpoint: contact.endpoint.or(contact.aor),
+            status: contact.status,
+            status_text: contact.status_text.unwrap_or_default(),
+            latency: contact.rtt,
+            removed: false,
+        });
+    }
+    let registrations =
+        optional(connection.pjsip_show_registrations_outbound().await)?;
+    for registration in registrations {
+        // `Registry` events name them by the client and server URI
+        let status_text = registration.status_text.unwrap_or_default();
+        insert(PeerStatus {
+            kind: PeerKind::Registration,
+            name: format!(
+                "{}@{}",
+                registration.client_uri.unwrap_or_default(),
+                registration.server_uri.unwrap_or_default()
+            ),
+            channel_type: Some("PJSIP".to_string()),
+            endpoint: None,
+            status: Reachability::parse(&status_text),
+            status_text,
+            latency: None,
+            removed: false,
+        });
+    }
+    Ok(peers)
+}
+
+/// Treats the listing of a channel driver that is not loaded or not available as empty
+fn optional<T>(listing: Result<Vec<T>, Error>) -> Result<Vec<T>, Error> {
+    match listing {
+        Err(Error::ActionFailed(_)) | Err(Error::UnsupportedAction(_)) => {
+            Ok(vec![])
+        }
+        listing => listing,
+    }
+}
+
+fn apply_event(peers: &mut Peers, pkt: &Packet) -> Option<PeerStatus> {
+    let status = PeerStatus::from_event(pkt)?;
+    let key = (status.kind, status.name.clone());
+    if status.removed {
+        peers.remove(&key);
+    } else {
+        peers.insert(key, status.clone());
+    }
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_peer_and_contact_status() {
+        let mut peers = Peers::new();
+        apply_event(
+            &mut peers,
+            &event(&[
+                ("Event", "PeerStatus"),
+                ("ChannelType", "SIP"),
+                ("Peer", "SIP/trunk"),
+                ("PeerStatus", "Reachable"),
+                ("Time", "23"),
+            ]),
+        );
+        let contact = apply_event(
+            &mut peers,
+            &event(&[
+                ("Event", "ContactStatus"),
+                ("URI", "sip:100@10.0.0.5:5060"),
+                ("ContactStatus", "Unreachable"),
+                ("AOR", "100"),
+                ("EndpointName", "100"),
+                ("RoundtripUsec", "0"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(contact.status, Reachability::Unreachable);
+        assert_eq!(contact.latency, None);
+
+        let peer = &peers[&(PeerKind::Peer, "SIP/trunk".to_string())];
+        assert_eq!(peer.latency, Some(Duration::from_millis(23)));
+
+        apply_event(
+            &mut peers,
+            &event(&[
+                ("Event", "ContactStatus"),
+                ("URI", "sip:100@10.0.0.5:5060"),
+                ("ContactStatus", "Removed"),
+            ]),
+        );
+        assert_eq!(peers.len(), 1);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn watches_registered_trunks() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (tracker, _) = tokio::join!(
+            PeerTracker::start(&connection),
+            answer_seed(&mut server, &[])
+        );
+        let tracker = tracker.unwrap();
+        let mut registered = tracker.watch_registered_trunks();
+        assert_eq!(*registered.borrow(), 0);
+
+        let registry = |status| {
+            event(&[
+                ("Event", "Registry"),
+                ("ChannelType", "PJSIP"),
+                ("Username", "provider"),
+                ("Domain", "sip.example.com"),
+                ("Status", status),
+            ])
+        };
+        server.send_packet(&registry("Registered")).await.unwrap();
+        registered.changed().await.unwrap();
+        assert_eq!(*registered.borrow_and_update(), 1);
+
+        // unrelated changes do not wake up the receiver
+        let peer = event(&[
+            ("Event", "PeerStatus"),
+            ("Peer", "PJSIP/100"),
+            ("PeerStatus", "Reachable"),
+        ]);
+        server.send_packet(&peer).await.unwrap();
+        server.send_packet(&registry("Rejected")).await.unwrap();
+        registered.changed().await.unwrap();
+        assert_eq!(*registered.borrow_and_update(), 0);
+        assert_eq!(tracker.snapshot().len(), 2);
+    }
+
+    /// Answers the listings seeding a tracker, actions missing in `listings` fail
+    #[cfg(feature = "testing")]
+    async fn answer_seed(
+        server: &mut crate::testing::ServerSide,
+        listings: &[(&str, Vec<Packet>)],
+    ) {
+        use crate::Tag;
+        for _ in 0..5 {
+            let action = server.read_packet().await.unwrap();
+            let name = find_tag(&action, "Action").unwrap().clone();
+            let action_id = find_tag(&action, "ActionID").unwrap().clone();
+            let reply = |tags: &[(&str, &str)]| {
+                let mut pkt = event(tags);
+                pkt.push(Tag::from("ActionID", &action_id));
+                pkt
+            };
+            let items = match listings.iter().find(|(a, _)| *a == name) {
+                Some((_, items)) => items,
+                None => {
+                    let error = reply(&[
+                        ("Response", "Error"),
+                        ("Message", "Invalid/unknown command"),
+                    ]);
+                    server.send_packet(&error).await.unwrap();
+                    continue;
+                }
+            };
+            let start =
+                reply(&[("Response", "Success"), ("EventList", "start")]);
+            server.send_packet(&start).await.unwrap();
+            for item in items {
+                let mut item = item.clone();
+                item.push(Tag::from("ActionID", &action_id));
+                server.send_packet(&item).await.unwrap();
+            }
+            let complete =
+                reply(&[("Event", "ListComplete"), ("EventList", "Complete")]);
+            server.send_packet(&complete).await.unwrap();
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn resyncs_on_new_connection() {
+        let peer = |name: &str, status: &str| {
+            event(&[
+                ("Event", "PeerEntry"),
+                ("ObjectName", name),
+                ("Status", status),
+            ])
+        };
+        let (old, mut old_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let endpoint = event(&[
+            ("Event", "EndpointList"),
+            ("ObjectName", "100"),
+            ("DeviceState", "Unavailable"),
+        ]);
+        let seed = [
+            (
+                "SIPpeers",
+                vec![peer("trunk", "OK (23 ms)"), peer("gone", "OK (5 ms)")],
+            ),
+            ("PJSIPShowEndpoints", vec![endpoint.clone()]),
+        ];
+        let (tracker, _) = tokio::join!(
+            PeerTracker::start(&old),
+            answer_seed(&mut old_server, &seed)
+        );
+        let tracker = tracker.unwrap();
+        let trunk = tracker.get(PeerKind::Peer, "SIP/trunk").unwrap();
+        assert_eq!(trunk.latency, Some(Duration::from_millis(23)));
+        let endpoint_status = tracker.get(PeerKind::Peer, "PJSIP/100").unwrap();
+        assert_eq!(endpoint_status.status, Reachability::Unreachable);
+        assert_eq!(endpoint_status.status_text, "Unavailable");
+        let mut changes = tracker.changes();
+
+        let (new, mut new_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let contact = event(&[
+            ("Event", "ContactList"),
+            ("ObjectName", "100;@abc"),
+            ("Uri", "sip:100@10.0.0.5:5060"),
+            ("Endpoint", "100"),
+            ("Status", "Reachable"),
+        ]);
+        let seed = [
+            ("SIPpeers", vec![peer("trunk", "OK (23 ms)")]),
+            ("PJSIPShowEndpoints", vec![endpoint]),
+            ("PJSIPShowContacts", vec![contact]),
+        ];
+        let (resynced, _) = tokio::join!(
+            tracker.resync(&new),
+            answer_seed(&mut new_server, &seed)
+        );
+        resynced.unwrap();
+        let removed = changes.recv().await.unwrap();
+        assert_eq!(removed.name, "SIP/gone");
+        assert!(removed.removed);
+        let added = changes.recv().await.unwrap();
+        assert_eq!(added.name, "sip:100@10.0.0.5:5060");
+        assert_eq!(added.endpoint.as_deref(), Some("100"));
+        assert!(changes.try_recv().is_err());
+        assert_eq!(tracker.snapshot().len(), 3);
+    }
+}
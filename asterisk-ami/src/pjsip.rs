@@ -0,0 +1,269 @@
+use crate::devices::DeviceState;
+use crate::peers::{Reachability, RegistrationState};
+use crate::{
+    find_tag, find_value, list_items, AmiConnection, Error, Packet, Tag,
+};
+use std::time::Duration;
+
+/// A PJSIP endpoint as listed by `PJSIPShowEndpoints`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PjsipEndpoint {
+    pub endpoint: String,
+    pub transport: Option<String>,
+    pub aors: Vec<String>,
+    pub auths: Vec<String>,
+    pub outbound_auths: Vec<String>,
+    pub contacts: Vec<String>,
+    pub device_state: DeviceState,
+    /// The device state as sent by Asterisk, e.g. `Not in use`
+    pub device_state_text: Option<String>,
+    pub active_channels: u32,
+}
+
+impl PjsipEndpoint {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(PjsipEndpoint {
+            endpoint: find_tag(pkt, "ObjectName")?.clone(),
+            transport: find_value(pkt, "Transport"),
+            aors: find_list(pkt, "Aor"),
+            auths: find_list(pkt, "Auths"),
+            outbound_auths: find_list(pkt, "OutboundAuths"),
+            contacts: find_list(pkt, "Contacts"),
+            device_state: find_tag(pkt, "DeviceState")
+                .map_or(DeviceState::Unknown, |s| DeviceState::parse(s)),
+            device_state_text: find_value(pkt, "DeviceState"),
+            active_channels: find_tag(pkt, "ActiveChannels")
+                .and_then(|c| c.trim().parse().ok())
+                .unwrap_or(0),
+        })
+    }
+}
+
+/// A PJSIP contact as listed by `PJSIPShowContacts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PjsipContact {
+    /// The contact's id, e.g. `100;@5f2b...`
+    pub id: String,
+    pub uri: Option<String>,
+    pub aor: Option<String>,
+    pub endpoint: Option<String>,
+    pub status: Reachability,
+    /// The status as sent by Asterisk, e.g. `Reachable` or `NonQualified`
+    pub status_text: Option<String>,
+    pub user_agent: Option<String>,
+    /// Round trip time of the last qualify
+    pub rtt: Option<Duration>,
+    pub via_address: Option<String>,
+}
+
+impl PjsipContact {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let status_text = find_value(pkt, "Status");
+        Some(PjsipContact {
+            id: find_tag(pkt, "ObjectName")?.clone(),
+            uri: find_value(pkt, "Uri"),
+            aor: find_value(pkt, "Aor").or_else(|| {
+                find_tag(pkt, "ObjectName")?
+                    .split(';')
+                    .next()
+                    .map(String::from)
+            }),
+            endpoint: find_value(pkt, "Endpoint"),
+            status: status_text
+                .as_deref()
+                .map_or(Reachability::Unknown, Reachability::parse),
+            status_text,
+            user_agent: find_value(pkt, "UserAgent"),
+            rtt: find_tag(pkt, "RoundtripUsec")
+                .and_then(|t| t.trim().parse().ok())
+                .filter(|&usec| usec > 0)
+                .map(Duration::from_micros),
+            via_address: find_value(pkt, "ViaAddr"),
+        })
+    }
+}
+
+/// An outbound registration as listed by `PJSIPShowRegistrationsOutbound`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PjsipOutboundRegistration {
+    pub name: String,
+    pub server_uri: Option<String>,
+    pub client_uri: Option<String>,
+    pub transport: Option<String>,
+    pub outbound_auth: Option<String>,
+    pub state: RegistrationState,
+    /// The state as sent by Asterisk
+    pub status_text: Option<String>,
+    /// Time until the next registration attempt
+    pub next_registration: Option<Duration>,
+    pub expiration: Option<Duration>,
+    pub retry_interval: Option<Duration>,
+}
+
+impl PjsipOutboundRegistration {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let seconds = |key| {
+            find_tag(pkt, key)
+                .and_then(|v| v.trim().parse().ok())
+                .map(Duration::from_secs)
+        };
+        let status_text = find_value(pkt, "Status");
+        Some(PjsipOutboundRegistration {
+            name: find_tag(pkt, "ObjectName")?.clone(),
+            server_uri: find_value(pkt, "ServerUri"),
+            client_uri: find_value(pkt, "ClientUri"),
+            transport: find_value(pkt, "Transport"),
+            outbound_auth: find_value(pkt, "OutboundAuth"),
+            state: status_text
+                .as_deref()
+                .map_or(RegistrationState::Unknown, RegistrationState::parse),
+            status_text,
+            next_registration: seconds("NextReg"),
+            expiration: seconds("Expiration"),
+            retry_interval: seconds("RetryInterval"),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Lists all PJSIP endpoints
+    pub async fn pjsip_show_endpoints(
+        &self,
+    ) -> Result<Vec<PjsipEndpoint>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "PJSIPShowEndpoints")])
+            .await?;
+        Ok(list_items(&resp, "EndpointList")
+            .filter_map(PjsipEndpoint::from_list_item)
+            .collect())
+    }
+
+    /// Lists all PJSIP contacts with their qualify status
+    pub async fn pjsip_show_contacts(
+        &self,
+    ) -> Result<Vec<PjsipContact>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "PJSIPShowContacts")])
+            .await?;
+        Ok(list_items(&resp, "ContactList")
+            .filter_map(PjsipContact::from_list_item)
+            .collect())
+    }
+
+    /// Lists all outbound registrations with their current state
+    pub async fn pjsip_show_registrations_outbound(
+        &self,
+    ) -> Result<Vec<PjsipOutboundRegistration>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from(
+                "Action",
+                "PJSIPShowRegistrationsOutbound",
+            )])
+            .await?;
+        Ok(list_items(&resp, "OutboundRegistrationDetail")
+            .filter_map(PjsipOutboundRegistration::from_list_item)
+            .collect())
+    }
+
+    /// Qualifies all contacts of an endpoint
+    ///
+    /// The results are reported asynchronously by `ContactStatus` events, which are also
+    /// picked up by the `PeerTracker`.
+    pub async fn pjsip_qualify(&self, endpoint: &str) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "PJSIPQualify"),
+            Tag::from("Endpoint", endpoint),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+/// Splits a comma separated list, dropping empty entries
+pub(crate) fn find_list(pkt: &Packet, key: &str) -> Vec<String> {
+    find_tag(pkt, key)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|e| !e.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_endpoint_list_item() {
+        let endpoint = PjsipEndpoint::from_list_item(&packet_from(&[
+            ("Event", "EndpointList"),
+            ("ObjectType", "endpoint"),
+            ("ObjectName", "100"),
+            ("Transport", "transport-udp"),
+            ("Aor", "100"),
+            ("Auths", "100-auth"),
+            ("OutboundAuths", ""),
+            ("Contacts", "100/sip:100@192.0.2.5:5060,"),
+            ("DeviceState", "Not in use"),
+            ("ActiveChannels", "0"),
+        ]))
+        .unwrap();
+        assert_eq!(endpoint.contacts, vec!["100/sip:100@192.0.2.5:5060"]);
+        assert!(endpoint.outbound_auths.is_empty());
+        assert_eq!(endpoint.device_state, DeviceState::NotInUse);
+        assert_eq!(endpoint.device_state_text.as_deref(), Some("Not in use"));
+    }
+
+    #[test]
+    fn parses_contact_list_item() {
+        let contact = PjsipContact::from_list_item(&packet_from(&[
+            ("Event", "ContactList"),
+            ("ObjectType", "contact"),
+            ("ObjectName", "100;@a1b2c3"),
+            ("Uri", "sip:100@192.0.2.5:5060"),
+            ("UserAgent", "Yealink SIP-T46S"),
+            ("Endpoint", "100"),
+            ("Status", "Reachable"),
+            ("RoundtripUsec", "12345"),
+        ]))
+        .unwrap();
+        assert_eq!(contact.aor.as_deref(), Some("100"));
+        assert_eq!(contact.status, Reachability::Reachable);
+        assert_eq!(contact.rtt, Some(Duration::from_micros(12345)));
+    }
+
+    #[test]
+    fn parses_outbound_registration_detail() {
+        let registration =
+            PjsipOutboundRegistration::from_list_item(&packet_from(&[
+                ("Event", "OutboundRegistrationDetail"),
+                ("ObjectType", "registration"),
+                ("ObjectName", "provider"),
+                ("ServerUri", "sip:sip.example.com"),
+                ("ClientUri", "sip:4930123@sip.example.com"),
+                ("Transport", "transport-udp"),
+                ("OutboundAuth", "provider-auth"),
+                ("RetryInterval", "60"),
+                ("Expiration", "3600"),
+                ("Status", "Rejected"),
+                ("NextReg", "45"),
+            ]))
+            .unwrap();
+        assert_eq!(registration.name, "provider");
+        assert_eq!(registration.state, RegistrationState::Rejected);
+        assert_eq!(registration.status_text.as_deref(), Some("Rejected"));
+        assert_eq!(
+            registration.next_registration,
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(registration.expiration, Some(Duration::from_secs(3600)));
+        assert_eq!(
+            RegistrationState::parse("Request Sent"),
+            RegistrationState::Pending
+        );
+    }
+}
@@ -0,0 +1,617 @@
+use crate::tracking::{publish, reconcile, spawn_tracker};
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+/// The state of an extension's hint as reported in the `Status` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtensionState {
+    Idle,
+    InUse,
+    Busy,
+    Unavailable,
+    Ringing,
+    RingInUse,
+    OnHold,
+    /// The hint has been removed from the dialplan
+    Removed,
+    /// The extension does not exist or has no hint
+    NotFound,
+    Unknown,
+}
+
+impl ExtensionState {
+    /// Converts the numeric value of an extension's `Status` header
+    pub fn from_code(code: &str) -> Self {
+        match code.trim().parse::<i32>() {
+            Ok(-2) => ExtensionState::Removed,
+            Ok(-1) => ExtensionState::NotFound,
+            Ok(0) => ExtensionState::Idle,
+            Ok(1) => ExtensionState::InUse,
+            Ok(2) => ExtensionState::Busy,
+            Ok(4) => ExtensionState::Unavailable,
+            Ok(8) => ExtensionState::Ringing,
+            Ok(9) => ExtensionState::RingInUse,
+            Ok(16) | Ok(17) => ExtensionState::OnHold,
+            _ => ExtensionState::Unknown,
+        }
+    }
+}
+
+/// A presence state as used by `PresenceState` and the presence events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceState {
+    NotSet,
+    Unavailable,
+    Available,
+    Away,
+    ExtendedAway,
+    Chat,
+    DoNotDisturb,
+    Unknown,
+}
+
+impl PresenceState {
+    /// Parses a presence state as sent by Asterisk, e.g. `available` or `dnd`
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "not_set" => PresenceState::NotSet,
+            "unavailable" => PresenceState::Unavailable,
+            "available" => PresenceState::Available,
+            "away" => PresenceState::Away,
+            "xa" => PresenceState::ExtendedAway,
+            "chat" => PresenceState::Chat,
+            "dnd" => PresenceState::DoNotDisturb,
+            _ => PresenceState::Unknown,
+        }
+    }
+}
+
+/// The presence of a presentity with its optional subtype and message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Presence {
+    pub state: PresenceState,
+    pub subtype: Option<String>,
+    pub message: Option<String>,
+}
+
+impl Presence {
+    fn from_packet(pkt: &Packet, state_key: &str) -> Option<Self> {
+        Some(Presence {
+            state: PresenceState::parse(find_tag(pkt, state_key)?),
+            subtype: find_value(pkt, "Subtype"),
+            message: find_value(pkt, "Message"),
+        })
+    }
+}
+
+/// A `PresenceStateChange` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PresenceStateChange {
+    /// The presence provider that changed, e.g. `CustomPresence:100`
+    pub presentity: String,
+    pub presence: Presence,
+}
+
+impl PresenceStateChange {
+    /// Parses a `PresenceStateChange` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        if !event_name(pkt)?.eq_ignore_ascii_case("PresenceStateChange") {
+            return None;
+        }
+        Some(PresenceStateChange {
+            presentity: find_tag(pkt, "Presentity")?.clone(),
+            presence: Presence::from_packet(pkt, "Status")?,
+        })
+    }
+}
+
+/// The parsed hint of an extension, e.g. `PJSIP/100&Custom:DND100,CustomPresence:100`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hint {
+    /// The devices whose states are combined into the extension state
+    pub devices: Vec<String>,
+    /// The presence provider the extension's presence state is taken from
+    pub presence_provider: Option<String>,
+}
+
+impl Hint {
+    /// Parses the text of a hint
+    pub fn parse(hint: &str) -> Self {
+        let mut parts = hint.splitn(2, ',');
+        let devices = parts
+            .next()
+            .unwrap_or_default()
+            .split('&')
+            .map(str::trim)
+            .filter(|d| !d.is_empty())
+            .map(String::from)
+            .collect();
+        let presence_provider = parts
+            .next()
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(String::from);
+        Hint {
+            devices,
+            presence_provider,
+        }
+    }
+}
+
+/// The state of an extension as reported by `ExtensionState` and `ExtensionStateList`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionStatus {
+    pub exten: String,
+    pub context: String,
+    pub hint: Option<Hint>,
+    pub state: ExtensionState,
+    pub status_text: Option<String>,
+}
+
+impl ExtensionStatus {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        Some(ExtensionStatus {
+            exten: find_tag(pkt, "Exten")?.clone(),
+            context: find_tag(pkt, "Context")?.clone(),
+            hint: find_value(pkt, "Hint").map(|h| Hint::parse(&h)),
+            state: find_tag(pkt, "Status")
+                .map_or(ExtensionState::Unknown, |s| {
+                    ExtensionState::from_code(s)
+                }),
+            status_text: find_value(pkt, "StatusText"),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Queries the state of a single extension
+    pub async fn extension_state(
+        &self,
+        exten: &str,
+        context: &str,
+    ) -> Result<ExtensionStatus, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "ExtensionState"),
+                Tag::from("Exten", exten),
+                Tag::from("Context", context),
+            ])
+            .await?;
+        resp.first()
+            .and_then(ExtensionStatus::from_packet)
+            .ok_or(Error::UnexpectedResponse)
+    }
+
+    /// Queries the presence state of a presence provider, e.g. `CustomPresence:100`
+    pub async fn presence_state(
+        &self,
+        provider: &str,
+    ) -> Result<Presence, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "PresenceState"),
+                Tag::from("Provider", provider),
+            ])
+            .await?;
+        resp.first()
+            .and_then(|pkt| Presence::from_packet(pkt, "State"))
+            .ok_or(Error::UnexpectedResponse)
+    }
+
+    /// Lists the states of all extensions having a hint
+    pub async fn extension_state_list(
+        &self,
+    ) -> Result<Vec<ExtensionStatus>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "ExtensionStateList")])
+            .await?;
+        Ok(list_items(&resp, "ExtensionStatus")
+            .filter_map(ExtensionStatus::from_packet)
+            .collect())
+    }
+}
+
+/// An extension with a hint known to a `PresenceTracker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub exten: String,
+    pub context: String,
+    pub hint: Option<String>,
+    pub state: ExtensionState,
+    pub presence: Option<Presence>,
+}
+
+impl Extension {
+    fn new(exten: &str, context: &str) -> Self {
+        Extension {
+            exten: exten.to_string(),
+            context: context.to_string(),
+            hint: None,
+            state: ExtensionState::Unknown,
+            presence: None,
+        }
+    }
+
+    /// Returns the parsed hint of the extension
+    pub fn parsed_hint(&self) -> Option<Hint> {
+        self.hint.as_deref().map(Hint::parse)
+    }
+
+    /// Returns the `exten@context` the extension is identified by
+    pub fn key(&self) -> String {
+        extension_key(&self.exten, &self.context)
+    }
+}
+
+fn extension_key(exten: &str, context: &str) -> String {
+    format!("{}@{}", exten, context)
+}
+
+#[derive(Default)]
+struct Extensions {
+    extensions: HashMap<String, Extension>,
+    watchers: HashMap<String, watch::Sender<Option<Extension>>>,
+}
+
+/// Keeps a BLF-style map of the state of all extensions having a hint
+///
+/// The tracker is seeded using `ExtensionStateList` and afterwards kept up to date from the
+/// `ExtensionStatus` and `PresenceStatus` events.
+pub struct PresenceTracker {
+    extensions: Arc<RwLock<Extensions>>,
+    changes_tx: broadcast::Sender<Extension>,
+    task: Mutex<AbortHandle>,
+}
+
+impl PresenceTracker {
+    /// Starts tracking the extensions of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<PresenceTracker, Error> {
+        let events = connection.events();
+        let extensions = Arc::new(RwLock::new(Extensions {
+            extensions: seed(connection).await?,
+            ..Default::default()
+        }));
+        let (changes_tx, _) = broadcast::channel::<Extension>(32);
+
+        let task = spawn_tracker(
+            "Presence tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&extensions),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(PresenceTracker {
+            extensions,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// The tracker is seeded again and the extensions that differ are published as changes,
+    /// those whose hint has been removed in the meantime with the state `Removed`. As the seed
+    /// does not include presence states, the last known presence of an extension is kept.
+    /// Afterwards the events of `connection` are followed instead of the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let fresh = seed(connection).await?;
+        {
+            let mut extensions = self.extensions.write().unwrap();
+            let changes = reconcile(
+                &mut extensions.extensions,
+                fresh,
+                |old, extension| {
+                    if extension.presence.is_none() {
+                        extension.presence = old.presence.clone();
+                    }
+                },
+            );
+            let removed = changes.removed.into_iter().map(|mut extension| {
+                extension.state = ExtensionState::Removed;
+                extension
+            });
+            for extension in removed.chain(changes.added).chain(changes.updated)
+            {
+                if let Some(watcher) = extensions.watchers.get(&extension.key())
+                {
+                    watcher.send_replace(Some(extension.clone()));
+                }
+                publish(&self.changes_tx, extension);
+            }
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Presence tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.extensions),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of all currently known extensions
+    pub fn snapshot(&self) -> Vec<Extension> {
+        let extensions = self.extensions.read().unwrap();
+        extensions.extensions.values().cloned().collect()
+    }
+
+    /// Returns the current state of an extension
+    pub fn get(&self, exten: &str, context: &str) -> Option<Extension> {
+        let extensions = self.extensions.read().unwrap();
+        extensions
+            .extensions
+            .get(&extension_key(exten, context))
+            .cloned()
+    }
+
+    /// Watches the state of a single extension
+    ///
+    /// The value is `None` as long as the extension is not known to the tracker.
+    pub fn watch(
+        &self,
+        exten: &str,
+        context: &str,
+    ) -> watch::Receiver<Option<Extension>> {
+        let key = extension_key(exten, context);
+        let mut extensions = self.extensions.write().unwrap();
+        let current = extensions.extensions.get(&key).cloned();
+        extensions
+            .watchers
+            .entry(key)
+            .or_insert_with(|| watch::channel(current).0)
+            .subscribe()
+    }
+
+    /// Subscribes to changes of all tracked extensions
+    pub fn changes(&self) -> broadcast::Receiver<Extension> {
+        self.changes_tx.subscribe()
+    }
+}
+
+impl Drop for PresenceTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists the states of all extensions having a hint
+async fn seed(
+    connection: &AmiConnection,
+) -> Result<HashMap<String, Extension>, Error> {
+    let resp = connection
+        .send_action(vec![Tag::from("Action", "ExtensionStateList")])
+        .await?;
+    let mut extensions = Extensions::default();
+    for pkt in list_items(&resp, "ExtensionStatus") {
+        apply_event(&mut extensions, pkt);
+    }
+    Ok(extensions.extensions)
+}
+
+fn apply_event(extensions: &mut Extensions, pkt: &Packet) -> Option<Extension> {
+    let event = event_name(pkt)?.to_ascii_lowercase();
+    if event != "extensionstatus" && event != "presencestatus" {
+        return None;
+    }
+    let exten = find_tag(pkt, "Exten")?;
+    let context = find_tag(pkt, "Context")?;
+    let key = extension_key(exten, context);
+
+    let extension = extensions
+        .extensions
+        .entry(key.clone())
+        .or_insert_with(|| Extension::new(exten, context));
+    if let Some(hint) = find_value(pkt, "Hint") {
+        extension.hint = Some(hint);
+    }
+    if event == "extensionstatus" {
+        extension.state = find_tag(pkt, "Status")
+            .map_or(ExtensionState::Unknown, |s| ExtensionState::from_code(s));
+    } else {
+        extension.presence = Presence::from_packet(pkt, "Status");
+    }
+    let extension = extension.clone();
+
+    if extension.state == ExtensionState::Removed {
+        extensions.extensions.remove(&key);
+    }
+    if let Some(watcher) = extensions.watchers.get(&key) {
+        watcher.send_replace(Some(extension.clone()));
+    }
+    Some(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_extension_and_presence_state() {
+        let mut extensions = Extensions::default();
+        let (tx, rx) = watch::channel(None);
+        extensions.watchers.insert("100@default".to_string(), tx);
+
+        apply_event(
+            &mut extensions,
+            &event(&[
+                ("Event", "ExtensionStatus"),
+                ("Exten", "100"),
+                ("Context", "default"),
+                ("Hint", "PJSIP/100"),
+                ("Status", "8"),
+                ("StatusText", "Ringing"),
+            ]),
+        );
+        apply_event(
+            &mut extensions,
+            &event(&[
+                ("Event", "PresenceStatus"),
+                ("Exten", "100"),
+                ("Context", "default"),
+                ("Status", "dnd"),
+            ]),
+        );
+
+        let extension = rx.borrow().clone().unwrap();
+        assert_eq!(extension.parsed_hint().unwrap().devices, vec!["PJSIP/100"]);
+        assert_eq!(extension.state, ExtensionState::Ringing);
+        assert_eq!(
+            extension.presence.unwrap().state,
+            PresenceState::DoNotDisturb
+        );
+        assert_eq!(extension.hint.as_deref(), Some("PJSIP/100"));
+    }
+
+    #[test]
+    fn parses_presence_state_change() {
+        let change = PresenceStateChange::from_event(&event(&[
+            ("Event", "PresenceStateChange"),
+            ("Presentity", "CustomPresence:100"),
+            ("Status", "away"),
+            ("Subtype", "lunch"),
+            ("Message", "Back at 2"),
+        ]))
+        .unwrap();
+        assert_eq!(change.presentity, "CustomPresence:100");
+        assert_eq!(
+            change.presence,
+            Presence {
+                state: PresenceState::Away,
+                subtype: Some("lunch".to_string()),
+                message: Some("Back at 2".to_string()),
+            }
+        );
+
+        let other = event(&[
+            ("Event", "PresenceStatus"),
+            ("Presentity", "CustomPresence:100"),
+            ("Status", "away"),
+        ]);
+        assert_eq!(PresenceStateChange::from_event(&other), None);
+    }
+
+    /// Answers the `ExtensionStateList` seeding a tracker with `(exten, status)` items
+    #[cfg(feature = "testing")]
+    async fn list_extensions(
+        server: &mut crate::testing::ServerSide,
+        extensions: &[(&str, &str)],
+    ) {
+        let action = server.read_packet().await.unwrap();
+        let action_id = find_tag(&action, "ActionID").unwrap().clone();
+        let reply = |tags: &[(&str, &str)]| {
+            let mut pkt = event(tags);
+            pkt.push(Tag::from("ActionID", &action_id));
+            pkt
+        };
+        let start = reply(&[("Response", "Success"), ("EventList", "start")]);
+        server.send_packet(&start).await.unwrap();
+        for (exten, status) in extensions {
+            let hint = format!("PJSIP/{}", exten);
+            let item = reply(&[
+                ("Event", "ExtensionStatus"),
+                ("Exten", exten),
+                ("Context", "default"),
+                ("Hint", &hint),
+                ("Status", status),
+            ]);
+            server.send_packet(&item).await.unwrap();
+        }
+        let complete = reply(&[
+            ("Event", "ExtensionStateListComplete"),
+            ("EventList", "Complete"),
+        ]);
+        server.send_packet(&complete).await.unwrap();
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn resyncs_on_new_connection() {
+        let (old, mut old_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (tracker, _) = tokio::join!(
+            PresenceTracker::start(&old),
+            list_extensions(&mut old_server, &[("100", "0"), ("101", "0")])
+        );
+        let tracker = tracker.unwrap();
+        let mut changes = tracker.changes();
+        let watch = tracker.watch("101", "default");
+        let dnd = event(&[
+            ("Event", "PresenceStatus"),
+            ("Exten", "100"),
+            ("Context", "default"),
+            ("Status", "dnd"),
+        ]);
+        old_server.send_packet(&dnd).await.unwrap();
+        changes.recv().await.unwrap();
+
+        let (new, mut new_server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (resynced, _) = tokio::join!(
+            tracker.resync(&new),
+            list_extensions(&mut new_server, &[("100", "1"), ("102", "0")])
+        );
+        resynced.unwrap();
+        let removed = changes.recv().await.unwrap();
+        assert_eq!(removed.exten, "101");
+        assert_eq!(removed.state, ExtensionState::Removed);
+        assert_eq!(
+            watch.borrow().as_ref().unwrap().state,
+            ExtensionState::Removed
+        );
+        assert_eq!(changes.recv().await.unwrap().exten, "102");
+        // the presence state is kept as the seed does not include it
+        let updated = changes.recv().await.unwrap();
+        assert_eq!(updated.state, ExtensionState::InUse);
+        assert_eq!(
+            updated.presence.unwrap().state,
+            PresenceState::DoNotDisturb
+        );
+        assert!(tracker.get("101", "default").is_none());
+
+        // only the events of the new connection are followed
+        let status = |exten| {
+            event(&[
+                ("Event", "ExtensionStatus"),
+                ("Exten", exten),
+                ("Context", "default"),
+                ("Status", "8"),
+            ])
+        };
+        old_server.send_packet(&status("100")).await.unwrap();
+        new_server.send_packet(&status("102")).await.unwrap();
+        assert_eq!(changes.recv().await.unwrap().exten, "102");
+        assert_eq!(
+            tracker.get("100", "default").unwrap().state,
+            ExtensionState::InUse
+        );
+    }
+
+    #[test]
+    fn parses_hints() {
+        let hint = Hint::parse("PJSIP/100&Custom:DND100,CustomPresence:100");
+        assert_eq!(hint.devices, vec!["PJSIP/100", "Custom:DND100"]);
+        assert_eq!(
+            hint.presence_provider.as_deref(),
+            Some("CustomPresence:100")
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Python bindings, enabled by the `python` feature
+//!
+//! The module offers a blocking API to Python scripts, the connection is driven by a tokio
+//! runtime of its own. Build it as an extension module using
+//! [maturin](https://www.maturin.rs/):
+//!
+//! ```text
+//! maturin build --release --features python,pyo3/extension-module
+//! ```
+//!
+//! Packets are lists of `(key, value)` tuples, as keys like `Variable` may be repeated.
+//! Actions can also be given as a dict:
+//!
+//! ```python
+//! import asterisk_ami
+//!
+//! conn = asterisk_ami.connect("127.0.0.1:5038")
+//! events = conn.events()
+//! conn.login("admin", "secret")
+//! print(conn.send({"Action": "CoreStatus"}))
+//! for event in events:
+//!     print(dict(event))
+//! ```
+
+use crate::{find_tag, AmiConnection, Packet, Tag};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+
+create_exception!(asterisk_ami, AmiError, PyException);
+
+/// How long to wait for an event before checking for signals like `KeyboardInterrupt`
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+type PyPacket = Vec<(String, String)>;
+
+fn to_py(pkt: Packet) -> PyPacket {
+    pkt.into_iter().map(|tag| (tag.key, tag.value)).collect()
+}
+
+fn from_py(action: &Bound<'_, PyAny>) -> PyResult<Packet> {
+    let pairs = match action.cast::<PyDict>() {
+        Ok(dict) => dict.items().into_any(),
+        Err(_) => action.clone(),
+    };
+    pairs
+        .try_iter()?
+        .map(|pair| {
+            let (key, value): (Bound<'_, PyAny>, Bound<'_, PyAny>) =
+                pair?.extract()?;
+            Ok(Tag::of(key.str()?.to_string(), value.str()?.to_string()))
+        })
+        .collect()
+}
+
+fn closed() -> PyErr {
+    AmiError::new_err(crate::Error::ConnectionClosed.to_string())
+}
+
+/// A connection to an Asterisk server
+#[pyclass(module = "asterisk_ami")]
+struct Connection {
+    runtime: Arc<Runtime>,
+    connection: AmiConnection,
+}
+
+/// Connects to `address`, given as `host:port`
+#[pyfunction]
+fn connect(py: Python<'_>, address: String) -> PyResult<Connection> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()?;
+    let connection = py
+        .detach(|| runtime.block_on(AmiConnection::connect(address)))
+        .map_err(|e| AmiError::new_err(e.to_string()))?;
+    Ok(Connection {
+        runtime: Arc::new(runtime),
+        connection,
+    })
+}
+
+#[pymethods]
+impl Connection {
+    /// Logs in, raises `AmiError` with the server's message if the login is rejected
+    fn login(
+        &self,
+        py: Python<'_>,
+        username: String,
+        secret: String,
+    ) -> PyResult<()> {
+        let login = vec![
+            Tag::from("Action", "Login"),
+            Tag::from("Username", &username),
+            Tag::from("Secret", &secret),
+        ];
+        let resp = py
+            .detach(|| self.runtime.block_on(self.connection.send(login)))
+            .ok_or_else(closed)?;
+        let first = resp.first().ok_or_else(closed)?;
+        match find_tag(first, "Response") {
+            Some(r) if r.eq_ignore_ascii_case("Success") => Ok(()),
+            _ => Err(AmiError::new_err(
+                find_tag(first, "Message")
+                    .cloned()
+                    .unwrap_or_else(|| "login failed".to_string()),
+            )),
+        }
+    }
+
+    /// Sends an action, returns the packets of the response
+    ///
+    /// An `ActionID` is added if the action does not contain one.
+    fn send(
+        &self,
+        py: Python<'_>,
+        action: &Bound<'_, PyAny>,
+    ) -> PyResult<Vec<PyPacket>> {
+        let mut action = from_py(action)?;
+        self.connection.assign_action_id(&mut action);
+        let resp = py
+            .detach(|| self.runtime.block_on(self.connection.send(action)))
+            .ok_or_else(closed)?;
+        Ok(resp.into_iter().map(to_py).collect())
+    }
+
+    /// Subscribes to the events received from now on
+    fn events(&self) -> Events {
+        Events {
+            runtime: self.runtime.clone(),
+            events: self.connection.events(),
+        }
+    }
+}
+
+/// The events received by a connection, iterating ends when the connection is closed
+#[pyclass(module = "asterisk_ami")]
+struct Events {
+    runtime: Arc<Runtime>,
+    events: broadcast::Receiver<Option<Packet>>,
+}
+
+#[pymethods]
+impl Events {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyPacket>> {
+        self.next(py, None)
+    }
+
+    /// Waits for the next event, returns `None` after `timeout` seconds or once the connection
+    /// has been closed
+    ///
+    /// Events the subscriber fell too far behind on are skipped.
+    #[pyo3(signature = (timeout=None))]
+    fn next(
+        &mut self,
+        py: Python<'_>,
+        timeout: Option<f64>,
+    ) -> PyResult<Option<PyPacket>> {
+        let deadline =
+            timeout.map(|t| Instant::now() + Duration::from_secs_f64(t));
+        loop {
+            let wait = match deadline {
+                Some(deadline) => SIGNAL_CHECK_INTERVAL
+                    .min(deadline.saturating_duration_since(Instant::now())),
+                None => SIGNAL_CHECK_INTERVAL,
+            };
+            let (runtime, events) = (&self.runtime, &mut self.events);
+            let received = py.detach(|| {
+                runtime.block_on(async {
+                    tokio::time::timeout(wait, events.recv()).await
+                })
+            });
+            match received {
+                Ok(Ok(Some(event))) => return Ok(Some(to_py(event))),
+                Ok(Ok(None)) | Ok(Err(RecvError::Closed)) => return Ok(None),
+                Ok(Err(RecvError::Lagged(_))) => {}
+                Err(_) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                    return Ok(None)
+                }
+                Err(_) => py.check_signals()?,
+            }
+        }
+    }
+}
+
+#[pymodule]
+fn asterisk_ami(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(connect, m)?)?;
+    m.add_class::<Connection>()?;
+    m.add_class::<Events>()?;
+    m.add("AmiError", m.py().get_type::<AmiError>())?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::MockAmiServer;
+
+    #[test]
+    fn adds_action_ids() {
+        let runtime = Runtime::new().unwrap();
+        let server = runtime.block_on(MockAmiServer::start()).unwrap();
+        server.respond(
+            "Ping",
+            vec![vec![
+                Tag::from("Response", "Success"),
+                Tag::from("Ping", "Pong"),
+            ]],
+        );
+        Python::initialize();
+        Python::attach(|py| {
+            let connection = connect(py, server.addr().to_string()).unwrap();
+            let action = PyDict::new(py);
+            action.set_item("Action", "Ping").unwrap();
+            let resp = connection.send(py, action.as_any()).unwrap();
+            assert!(resp[0].contains(&("Ping".to_string(), "Pong".to_string())));
+        });
+        let received = server.received();
+        assert!(find_tag(&received[0], "ActionID").is_some());
+    }
+}
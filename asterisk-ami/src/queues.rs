@@ -0,0 +1,759 @@
+use crate::tracking::{derive_watch, publish, reconcile, spawn_tracker};
+use crate::{
+    event_name, find_flag, find_tag, find_value, list_items, AmiConnection,
+    Error, Packet, Tag,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+/// The device state of a queue member
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberStatus {
+    Unknown,
+    NotInUse,
+    InUse,
+    Busy,
+    Invalid,
+    Unavailable,
+    Ringing,
+    RingInUse,
+    OnHold,
+    /// Another status code
+    Other(u32),
+}
+
+impl MemberStatus {
+    /// Converts the numeric `Status` of queue member events
+    pub fn from_code(code: &str) -> Self {
+        match code.trim().parse().unwrap_or(0) {
+            0 => MemberStatus::Unknown,
+            1 => MemberStatus::NotInUse,
+            2 => MemberStatus::InUse,
+            3 => MemberStatus::Busy,
+            4 => MemberStatus::Invalid,
+            5 => MemberStatus::Unavailable,
+            6 => MemberStatus::Ringing,
+            7 => MemberStatus::RingInUse,
+            8 => MemberStatus::OnHold,
+            n => MemberStatus::Other(n),
+        }
+    }
+}
+
+/// An agent or device answering the calls of a queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueMember {
+    /// The interface calls are sent to, e.g. `PJSIP/100`
+    pub interface: String,
+    pub name: Option<String>,
+    pub status: MemberStatus,
+    pub paused: bool,
+    pub paused_reason: Option<String>,
+    pub penalty: u32,
+    pub calls_taken: u32,
+    /// Whether the member is currently talking to a caller of any queue
+    pub in_call: bool,
+}
+
+impl QueueMember {
+    /// Parses `QueueMember` list items and the member events of Asterisk 12 and later
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        let number = |key| {
+            find_tag(pkt, key)
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Some(QueueMember {
+            interface: find_value(pkt, "Interface")
+                .or_else(|| find_value(pkt, "Location"))?,
+            name: find_value(pkt, "MemberName")
+                .or_else(|| find_value(pkt, "Name")),
+            status: find_tag(pkt, "Status")
+                .map(|s| MemberStatus::from_code(s))
+                .unwrap_or(MemberStatus::Unknown),
+            paused: find_flag(pkt, "Paused"),
+            paused_reason: find_value(pkt, "PausedReason")
+                .or_else(|| find_value(pkt, "Reason")),
+            penalty: number("Penalty"),
+            calls_taken: number("CallsTaken"),
+            in_call: find_flag(pkt, "InCall"),
+        })
+    }
+}
+
+/// A caller waiting in a queue
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueCaller {
+    pub channel: String,
+    pub unique_id: Option<String>,
+    /// The position in the queue, starting at 1
+    pub position: u32,
+    pub caller_id_num: Option<String>,
+    pub caller_id_name: Option<String>,
+    pub joined_at: Instant,
+}
+
+impl QueueCaller {
+    /// Time the caller has been waiting for
+    pub fn wait_time(&self) -> Duration {
+        self.joined_at.elapsed()
+    }
+
+    /// Parses `QueueEntry` list items and `QueueCallerJoin` events
+    fn from_packet(pkt: &Packet, now: Instant) -> Option<Self> {
+        let waiting = find_tag(pkt, "Wait")
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+        Some(QueueCaller {
+            channel: find_value(pkt, "Channel")?,
+            unique_id: find_value(pkt, "Uniqueid"),
+            position: find_tag(pkt, "Position")
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0),
+            caller_id_num: find_value(pkt, "CallerIDNum"),
+            caller_id_name: find_value(pkt, "CallerIDName"),
+            joined_at: now.checked_sub(waiting).unwrap_or(now),
+        })
+    }
+}
+
+/// A call queue with its members and waiting callers
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Queue {
+    pub name: String,
+    pub strategy: Option<String>,
+    pub calls_completed: u32,
+    pub calls_abandoned: u32,
+    /// The average hold time as calculated by Asterisk
+    pub hold_time: Duration,
+    /// The average talk time as calculated by Asterisk
+    pub talk_time: Duration,
+    pub members: Vec<QueueMember>,
+    /// The waiting callers ordered by their position
+    pub callers: Vec<QueueCaller>,
+}
+
+impl Queue {
+    /// Returns the member with the given interface
+    pub fn member(&self, interface: &str) -> Option<&QueueMember> {
+        self.members.iter().find(|m| m.interface == interface)
+    }
+
+    /// Returns the members that are neither paused nor unavailable
+    pub fn available_members(&self) -> impl Iterator<Item = &QueueMember> {
+        self.members.iter().filter(|m| {
+            !m.paused
+                && !matches!(
+                    m.status,
+                    MemberStatus::Invalid | MemberStatus::Unavailable
+                )
+        })
+    }
+
+    /// Parses a `QueueParams` list item
+    fn from_params(pkt: &Packet) -> Option<Self> {
+        let number = |key| {
+            find_tag(pkt, key)
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0)
+        };
+        Some(Queue {
+            name: find_value(pkt, "Queue")?,
+            strategy: find_value(pkt, "Strategy"),
+            calls_completed: number("Completed"),
+            calls_abandoned: number("Abandoned"),
+            hold_time: Duration::from_secs(number("Holdtime").into()),
+            talk_time: Duration::from_secs(number("TalkTime").into()),
+            members: vec![],
+            callers: vec![],
+        })
+    }
+
+    fn sort_callers(&mut self) {
+        self.callers.sort_by_key(|c| c.position);
+    }
+}
+
+/// A change of the queues observed by a `QueueTracker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueChange {
+    CallerJoined {
+        queue: String,
+        caller: QueueCaller,
+    },
+    /// The caller hung up before being connected to a member
+    CallerAbandoned {
+        queue: String,
+        caller: QueueCaller,
+    },
+    /// The caller has left the queue, either connected to a member or after abandoning
+    CallerLeft {
+        queue: String,
+        caller: QueueCaller,
+    },
+    MemberAdded {
+        queue: String,
+        member: QueueMember,
+    },
+    /// The status, pause state, penalty, or call count of a member has changed
+    MemberChanged {
+        queue: String,
+        member: QueueMember,
+    },
+    MemberRemoved {
+        queue: String,
+        member: QueueMember,
+    },
+}
+
+type Queues = HashMap<String, Queue>;
+
+/// The number of data fields of a queue_log line
+const QUEUE_LOG_FIELDS: usize = 5;
+
+/// An entry written to the queue_log using `AmiConnection::queue_log`
+///
+/// Asterisk writes it in the standard layout `time|callid|queue|agent|event|data1|…|data5`,
+/// where `callid` is the `Uniqueid` of the caller's channel, `agent` the member's interface,
+/// and the data fields those added using `field`:
+///
+/// ```ignore
+/// let entry = QueueLogEntry::new("support", "DISPOSITION")
+///     .call("1700000000.42")
+///     .agent("PJSIP/100")
+///     .field("SOLVED")
+///     .field("ticket-4711");
+/// connection.queue_log(&entry).await?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueLogEntry {
+    queue: String,
+    event: String,
+    call: Option<String>,
+    agent: Option<String>,
+    fields: Vec<String>,
+}
+
+impl QueueLogEntry {
+    /// Creates an entry for `queue`, `event` is usually a custom one like `DISPOSITION`
+    pub fn new(queue: &str, event: &str) -> Self {
+        QueueLogEntry {
+            queue: queue.to_string(),
+            event: event.to_string(),
+            call: None,
+            agent: None,
+            fields: vec![],
+        }
+    }
+
+    /// Sets the `Uniqueid` of the caller's channel, `NONE` is logged without one
+    pub fn call(mut self, unique_id: &str) -> Self {
+        self.call = Some(unique_id.to_string());
+        self
+    }
+
+    /// Sets the interface of the member the entry is about, `NONE` is logged without one
+    pub fn agent(mut self, interface: &str) -> Self {
+        self.agent = Some(interface.to_string());
+        self
+    }
+
+    /// Appends a data field, up to five are supported
+    pub fn field(mut self, value: &str) -> Self {
+        self.fields.push(value.to_string());
+        self
+    }
+
+    fn to_packet(&self) -> Result<Packet, Error> {
+        if self.fields.len() > QUEUE_LOG_FIELDS {
+            return Err(Error::InvalidArgument(format!(
+                "queue_log entries have at most {} data fields",
+                QUEUE_LOG_FIELDS
+            )));
+        }
+        let values = std::iter::once(&self.queue)
+            .chain(Some(&self.event))
+            .chain(&self.call)
+            .chain(&self.agent)
+            .chain(&self.fields);
+        for value in values {
+            if value.contains(&['|', '\r', '\n'][..]) {
+                return Err(Error::InvalidArgument(format!(
+                    "not a valid queue_log field: {:?}",
+                    value
+                )));
+            }
+        }
+        let mut pkt = vec![
+            Tag::from("Action", "QueueLog"),
+            Tag::from("Queue", &self.queue),
+            Tag::from("Event", &self.event),
+        ];
+        if let Some(call) = &self.call {
+            pkt.push(Tag::from("Uniqueid", call));
+        }
+        if let Some(agent) = &self.agent {
+            pkt.push(Tag::from("Interface", agent));
+        }
+        if !self.fields.is_empty() {
+            pkt.push(Tag::of("Message".to_string(), self.fields.join("|")));
+        }
+        Ok(pkt)
+    }
+}
+
+impl AmiConnection {
+    /// Returns the state of a queue or, if `queue` is `None`, of all queues
+    pub async fn queue_status(
+        &self,
+        queue: Option<&str>,
+    ) -> Result<Vec<Queue>, Error> {
+        let mut pkt = vec![Tag::from("Action", "QueueStatus")];
+        if let Some(queue) = queue {
+            pkt.push(Tag::from("Queue", queue));
+        }
+        let resp = self.send_action(pkt).await?;
+        let now = Instant::now();
+        let mut queues = list_items(&resp, "QueueParams")
+            .filter_map(Queue::from_params)
+            .map(|q| (q.name.clone(), q))
+            .collect::<Queues>();
+        for pkt in list_items(&resp, "QueueMember") {
+            let queue = find_tag(pkt, "Queue").and_then(|q| queues.get_mut(q));
+            if let (Some(queue), Some(member)) =
+                (queue, QueueMember::from_packet(pkt))
+            {
+                queue.members.push(member);
+            }
+        }
+        for pkt in list_items(&resp, "QueueEntry") {
+            let queue = find_tag(pkt, "Queue").and_then(|q| queues.get_mut(q));
+            if let (Some(queue), Some(caller)) =
+                (queue, QueueCaller::from_packet(pkt, now))
+            {
+                queue.callers.push(caller);
+            }
+        }
+        let mut queues = queues.into_values().collect::<Vec<_>>();
+        queues.iter_mut().for_each(Queue::sort_callers);
+        queues.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(queues)
+    }
+
+    /// Writes a custom entry to the queue_log, e.g. a disposition code set by a CRM
+    pub async fn queue_log(&self, entry: &QueueLogEntry) -> Result<(), Error> {
+        self.send_action(entry.to_packet()?).await?;
+        Ok(())
+    }
+}
+
+/// Keeps track of all queues, their members, and waiting callers
+///
+/// The tracker is seeded using `QueueStatus` and afterwards kept up to date from the
+/// `QueueCallerJoin`, `QueueCallerAbandon`, `QueueCallerLeave`, `QueueMember*`, and
+/// `AgentComplete` events of Asterisk 12 and later. Queues created after the tracker has been
+/// started are added once the first caller or member event for them is seen, their
+/// parameters stay unknown.
+pub struct QueueTracker {
+    queues: Arc<RwLock<Queues>>,
+    changes_tx: broadcast::Sender<QueueChange>,
+    task: Mutex<AbortHandle>,
+}
+
+impl QueueTracker {
+    /// Starts tracking the queues of the server `connection` is connected to
+    pub async fn start(
+        connection: &AmiConnection,
+    ) -> Result<QueueTracker, Error> {
+        let events = connection.events();
+        let queues = Arc::new(RwLock::new(seed(connection).await?));
+        let (changes_tx, _) = broadcast::channel::<QueueChange>(32);
+
+        let task = spawn_tracker(
+            "Queue tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&queues),
+            changes_tx.clone(),
+            apply_event,
+        );
+
+        Ok(QueueTracker {
+            queues,
+            changes_tx,
+            task: Mutex::new(task),
+        })
+    }
+
+    /// Continues tracking using a new connection to the server, e.g. after a reconnect
+    ///
+    /// The tracker is seeded again and the differences of each queue are published: members
+    /// as `MemberAdded`, `MemberChanged`, or `MemberRemoved`, callers as `CallerJoined` or
+    /// `CallerLeft`. Queues that no longer exist lose all their members and callers.
+    /// Afterwards the events of `connection` are followed instead of the old ones.
+    pub async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), Error> {
+        let events = connection.events();
+        let mut fresh = seed(connection).await?;
+        let changes = {
+            let mut queues = self.queues.write().unwrap();
+            let mut changes = vec![];
+            for (name, queue) in queues.drain() {
+                let mut update = fresh.remove(&name).unwrap_or_default();
+                changes.extend(reconcile_queue(&name, queue, &mut update));
+                if !update.name.is_empty() {
+                    fresh.insert(name, update);
+                }
+            }
+            for (name, queue) in &mut fresh {
+                changes.extend(reconcile_queue(name, Queue::default(), queue));
+            }
+            *queues = fresh;
+            changes
+        };
+        for change in changes {
+            publish(&self.changes_tx, change);
+        }
+
+        let mut task = self.task.lock().unwrap();
+        task.abort();
+        *task = spawn_tracker(
+            "Queue tracker",
+            events,
+            connection.cache.clone(),
+            Arc::downgrade(&self.queues),
+            self.changes_tx.clone(),
+            apply_event,
+        );
+        Ok(())
+    }
+
+    /// Returns a copy of all queues ordered by their name
+    pub fn snapshot(&self) -> Vec<Queue> {
+        let mut queues = self
+            .queues
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        queues.sort_by(|a, b| a.name.cmp(&b.name));
+        queues
+    }
+
+    /// Returns a single queue
+    pub fn get(&self, queue: &str) -> Option<Queue> {
+        self.queues.read().unwrap().get(queue).cloned()
+    }
+
+    /// Subscribes to changes of the queues
+    pub fn changes(&self) -> broadcast::Receiver<QueueChange> {
+        self.changes_tx.subscribe()
+    }
+
+    /// Watches the number of callers waiting in a queue, `0` while the queue is unknown
+    pub fn watch_waiting_callers(&self, queue: &str) -> watch::Receiver<usize> {
+        let queue = queue.to_string();
+        derive_watch("Queue caller watch", &self.queues, self.changes(), {
+            move |queues| queues.get(&queue).map_or(0, |q| q.callers.len())
+        })
+    }
+}
+
+impl Drop for QueueTracker {
+    fn drop(&mut self) {
+        self.task.lock().unwrap().abort();
+    }
+}
+
+/// Lists the queues currently configured on the server, keyed by their name
+async fn seed(connection: &AmiConnection) -> Result<Queues, Error> {
+    Ok(connection
+        .queue_status(None)
+        .await?
+        .into_iter()
+        .map(|q| (q.name.clone(), q))
+        .collect())
+}
+
+/// Lists the changes between the known state of a queue and its fresh seed
+///
+/// Callers that are still waiting keep the moment they have joined the queue.
+fn reconcile_queue(
+    name: &str,
+    known: Queue,
+    fresh: &mut Queue,
+) -> Vec<QueueChange> {
+    let by_interface = |members: Vec<QueueMember>| {
+        members
+            .into_iter()
+            .map(|m| (m.interface.clone(), m))
+            .collect::<HashMap<_, _>>()
+    };
+    let by_channel = |callers: Vec<QueueCaller>| {
+        callers
+            .into_iter()
+            .map(|c| (c.channel.clone(), c))
+            .collect::<HashMap<_, _>>()
+    };
+    let queue = || name.to_string();
+
+    let mut members = by_interface(known.members);
+    let members =
+        reconcile(&mut members, by_interface(fresh.members.clone()), |_, _| {});
+    let mut callers = by_channel(known.callers);
+    let fresh_callers = by_channel(std::mem::take(&mut fresh.callers));
+    let changed_callers =
+        reconcile(&mut callers, fresh_callers, |old, caller| {
+            caller.joined_at = old.joined_at
+        });
+    fresh.callers = callers.into_values().collect();
+    fresh.sort_callers();
+
+    let mut changes = vec![];
+    changes.extend(members.removed.into_iter().map(|member| {
+        QueueChange::MemberRemoved {
+            queue: queue(),
+            member,
+        }
+    }));
+    changes.extend(members.added.into_iter().map(|member| {
+        QueueChange::MemberAdded {
+            queue: queue(),
+            member,
+        }
+    }));
+    changes.extend(members.updated.into_iter().map(|member| {
+        QueueChange::MemberChanged {
+            queue: queue(),
+            member,
+        }
+    }));
+    changes.extend(changed_callers.removed.into_iter().map(|caller| {
+        QueueChange::CallerLeft {
+            queue: queue(),
+            caller,
+        }
+    }));
+    changes.extend(changed_callers.added.into_iter().map(|caller| {
+        QueueChange::CallerJoined {
+            queue: queue(),
+            caller,
+        }
+    }));
+    changes
+}
+
+fn apply_event(queues: &mut Queues, pkt: &Packet) -> Option<QueueChange> {
+    let event = event_name(pkt)?.to_ascii_lowercase();
+    let name = find_value(pkt, "Queue")?;
+    let queue = queues.entry(name.clone()).or_insert_with(|| Queue {
+        name: name.clone(),
+        ..Queue::default()
+    });
+    match event.as_str() {
+        "queuecallerjoin" => {
+            let caller = QueueCaller::from_packet(pkt, Instant::now())?;
+            queue.callers.push(caller.clone());
+            queue.sort_callers();
+            Some(QueueChange::CallerJoined {
+                queue: name,
+                caller,
+            })
+        }
+        "queuecallerabandon" => {
+            let channel = find_tag(pkt, "Channel")?;
+            queue.calls_abandoned += 1;
+            let caller =
+                queue.callers.iter().find(|c| &c.channel == channel)?;
+            Some(QueueChange::CallerAbandoned {
+                queue: name,
+                caller: caller.clone(),
+            })
+        }
+        "queuecallerleave" => {
+            let channel = find_tag(pkt, "Channel")?;
+            let index =
+                queue.callers.iter().position(|c| &c.channel == channel)?;
+            let caller = queue.callers.remove(index);
+            for behind in &mut queue.callers[index..] {
+                behind.position = behind.position.saturating_sub(1);
+            }
+            Some(QueueChange::CallerLeft {
+                queue: name,
+                caller,
+            })
+        }
+        "agentcomplete" => {
+            queue.calls_completed += 1;
+            None
+        }
+        "queuememberadded" => {
+            let member = QueueMember::from_packet(pkt)?;
+            queue.members.retain(|m| m.interface != member.interface);
+            queue.members.push(member.clone());
+            Some(QueueChange::MemberAdded {
+                queue: name,
+                member,
+            })
+        }
+        "queuememberremoved" => {
+            let interface = QueueMember::from_packet(pkt)?.interface;
+            let index = queue
+                .members
+                .iter()
+                .position(|m| m.interface == interface)?;
+            Some(QueueChange::MemberRemoved {
+                queue: name,
+                member: queue.members.remove(index),
+            })
+        }
+        "queuememberstatus"
+        | "queuememberpause"
+        | "queuememberpaused"
+        | "queuememberpenalty"
+        | "queuememberringinuse" => {
+            let update = QueueMember::from_packet(pkt)?;
+            let member = queue
+                .members
+                .iter_mut()
+                .find(|m| m.interface == update.interface)?;
+            if *member == update {
+                return None;
+            }
+            *member = update.clone();
+            Some(QueueChange::MemberChanged {
+                queue: name,
+                member: update,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn tracks_callers_and_members() {
+        let mut queues = Queues::new();
+        for (channel, position) in
+            [("PJSIP/trunk-01", "1"), ("PJSIP/trunk-02", "2")]
+        {
+            apply_event(
+                &mut queues,
+                &event(&[
+                    ("Event", "QueueCallerJoin"),
+                    ("Queue", "support"),
+                    ("Channel", channel),
+                    ("Position", position),
+                ]),
+            );
+        }
+        let left = apply_event(
+            &mut queues,
+            &event(&[
+                ("Event", "QueueCallerLeave"),
+                ("Queue", "support"),
+                ("Channel", "PJSIP/trunk-01"),
+                ("Position", "1"),
+            ]),
+        );
+        assert!(matches!(left, Some(QueueChange::CallerLeft { .. })));
+        assert_eq!(queues["support"].callers[0].position, 1);
+
+        let member = [
+            ("Queue", "support"),
+            ("MemberName", "Alice"),
+            ("Interface", "PJSIP/100"),
+            ("Status", "1"),
+            ("Paused", "0"),
+        ];
+        let added =
+            event(&[&[("Event", "QueueMemberAdded")], &member[..]].concat());
+        apply_event(&mut queues, &added);
+        let mut paused =
+            event(&[&[("Event", "QueueMemberPause")], &member[..]].concat());
+        paused.iter_mut().find(|t| t.key == "Paused").unwrap().value =
+            "1".into();
+        let changed = apply_event(&mut queues, &paused);
+        assert!(matches!(
+            changed,
+            Some(QueueChange::MemberChanged { member, .. }) if member.paused
+        ));
+        assert_eq!(queues["support"].available_members().count(), 0);
+    }
+
+    #[test]
+    fn builds_queue_log_action() {
+        let entry = QueueLogEntry::new("support", "DISPOSITION")
+            .call("1700000000.42")
+            .field("SOLVED")
+            .field("ticket-4711");
+        assert_eq!(
+            entry.to_packet().unwrap(),
+            event(&[
+                ("Action", "QueueLog"),
+                ("Queue", "support"),
+                ("Event", "DISPOSITION"),
+                ("Uniqueid", "1700000000.42"),
+                ("Message", "SOLVED|ticket-4711"),
+            ])
+        );
+        let entry = entry.field("a|b");
+        assert!(matches!(entry.to_packet(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn reconciles_queue_with_fresh_seed() {
+        let now = Instant::now();
+        let member = |interface: &str| {
+            QueueMember::from_packet(&event(&[("Interface", interface)]))
+                .unwrap()
+        };
+        let caller = |channel: &str, position: &str, wait: &str| {
+            let pkt = event(&[
+                ("Channel", channel),
+                ("Position", position),
+                ("Wait", wait),
+            ]);
+            QueueCaller::from_packet(&pkt, now).unwrap()
+        };
+        let known = Queue {
+            name: "support".to_string(),
+            members: vec![member("PJSIP/100"), member("PJSIP/101")],
+            callers: vec![caller("PJSIP/trunk-01", "1", "30")],
+            ..Queue::default()
+        };
+        let joined_at = known.callers[0].joined_at;
+        let mut fresh = Queue {
+            members: vec![member("PJSIP/101"), member("PJSIP/102")],
+            callers: vec![
+                caller("PJSIP/trunk-02", "2", "5"),
+                caller("PJSIP/trunk-01", "1", "31"),
+            ],
+            ..known.clone()
+        };
+        let changes = reconcile_queue("support", known, &mut fresh);
+        assert!(matches!(
+            &changes[..],
+            [
+                QueueChange::MemberRemoved { member: removed, .. },
+                QueueChange::MemberAdded { member: added, .. },
+                QueueChange::CallerJoined { caller: joined, .. },
+            ] if removed.interface == "PJSIP/100"
+                && added.interface == "PJSIP/102"
+                && joined.channel == "PJSIP/trunk-02"
+        ));
+        assert_eq!(fresh.callers[0].channel, "PJSIP/trunk-01");
+        assert_eq!(fresh.callers[0].joined_at, joined_at);
+    }
+}
@@ -0,0 +1,394 @@
+//! Recording AMI sessions and replaying them offline
+//!
+//! A recording is a text file containing the inbound packets in wire format, each preceded by
+//! a line `@<milliseconds>` giving the time since the recording started:
+//!
+//! ```text
+//! @1520
+//! Event: Newchannel
+//! Channel: PJSIP/100-00000001
+//!
+//! ```
+
+use crate::wire::{Direction, WireLine};
+use crate::{event_name, packet_to_string, AmiConnection, Packet, Tag};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// The number of packets waiting to be written before tapped lines are dropped
+const WRITE_QUEUE: usize = 1024;
+
+/// A packet of a recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedPacket {
+    /// Time since the recording started
+    pub offset: Duration,
+    pub packet: Packet,
+}
+
+/// Writes a single packet of a recording
+pub fn write_packet<W: Write>(
+    writer: &mut W,
+    offset: Duration,
+    pkt: &Packet,
+) -> std::io::Result<()> {
+    writeln!(writer, "@{}", offset.as_millis())?;
+    for tag in pkt {
+        writeln!(writer, "{}: {}", tag.key, tag.value)?;
+    }
+    writeln!(writer)
+}
+
+/// Reads all packets of a recording
+pub fn read_recording<R: BufRead>(
+    reader: R,
+) -> std::io::Result<Vec<RecordedPacket>> {
+    let mut packets = vec![];
+    let mut offset = Duration::ZERO;
+    let mut pkt = Packet::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if let Some(millis) = line.strip_prefix('@') {
+            offset =
+                millis.parse().map(Duration::from_millis).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Invalid offset {:?}", line),
+                    )
+                })?;
+        } else if line.is_empty() {
+            if !pkt.is_empty() {
+                packets.push(RecordedPacket {
+                    offset,
+                    packet: std::mem::take(&mut pkt),
+                });
+            }
+        } else if let Some((key, value)) = line.split_once(':') {
+            pkt.push(Tag::from(key, value.trim()));
+        }
+    }
+    if !pkt.is_empty() {
+        packets.push(RecordedPacket {
+            offset,
+            packet: pkt,
+        });
+    }
+    Ok(packets)
+}
+
+/// Records the packets received by a connection to a file
+///
+/// Responses are recorded as well as events. The recording is based on the wire tap, so
+/// credentials are redacted and packets may be missing if the disk cannot keep up.
+pub struct Recorder {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl Recorder {
+    /// Starts recording to `path`, replacing the file if it exists
+    pub fn start<P: AsRef<Path>>(
+        connection: &AmiConnection,
+        path: P,
+    ) -> std::io::Result<Recorder> {
+        Ok(Self::record(connection, File::create(path)?))
+    }
+
+    /// Starts recording to the end of `path`, creating the file if needed
+    ///
+    /// The offsets of the appended packets start at zero again, when replayed they follow the
+    /// existing packets without delay.
+    pub fn append<P: AsRef<Path>>(
+        connection: &AmiConnection,
+        path: P,
+    ) -> std::io::Result<Recorder> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::record(connection, file))
+    }
+
+    fn record(connection: &AmiConnection, file: File) -> Recorder {
+        let mut wire = connection.wire_tap();
+        let mut events = connection.events();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let (packets_tx, packets_rx) = mpsc::channel(WRITE_QUEUE);
+        let writer = tokio::task::spawn_blocking(move || {
+            write_packets(file, packets_rx)
+        });
+
+        let task = tokio::spawn(async move {
+            let mut collector = Collector::new(SystemTime::now());
+            loop {
+                let line: WireLine = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    event = events.recv() => match event {
+                        Ok(None) | Err(RecvError::Closed) => break,
+                        _ => continue,
+                    },
+                    line = wire.recv() => match line {
+                        Ok(line) => line,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                };
+                if let Some(recorded) = collector.push(line) {
+                    if packets_tx.send(recorded).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            // the lines tapped before the connection has been closed or the recorder stopped
+            loop {
+                let line = match wire.try_recv() {
+                    Ok(line) => line,
+                    Err(TryRecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                };
+                if let Some(recorded) = collector.push(line) {
+                    if packets_tx.send(recorded).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            drop(packets_tx);
+            writer.await.map_err(std::io::Error::other)?
+        });
+
+        Recorder { stop_tx, task }
+    }
+
+    /// Stops recording and writes the remaining packets to the file
+    pub async fn stop(self) -> std::io::Result<()> {
+        let _ = self.stop_tx.send(());
+        self.task.await.map_err(std::io::Error::other)?
+    }
+}
+
+/// Assembles the inbound packets of a recording from the tapped lines
+struct Collector {
+    started: SystemTime,
+    first_line_at: SystemTime,
+    pkt: Packet,
+}
+
+impl Collector {
+    fn new(started: SystemTime) -> Self {
+        Collector {
+            started,
+            first_line_at: started,
+            pkt: Packet::new(),
+        }
+    }
+
+    /// Adds a line, returns the packet it completes
+    fn push(&mut self, line: WireLine) -> Option<RecordedPacket> {
+        if line.direction != Direction::Inbound {
+            return None;
+        }
+        if line.line.is_empty() {
+            if self.pkt.is_empty() {
+                return None;
+            }
+            return Some(RecordedPacket {
+                offset: self
+                    .first_line_at
+                    .duration_since(self.started)
+                    .unwrap_or_default(),
+                packet: std::mem::take(&mut self.pkt),
+            });
+        }
+        if let Some((key, value)) = line.line.split_once(':') {
+            if self.pkt.is_empty() {
+                self.first_line_at = line.at;
+            }
+            self.pkt.push(Tag::from(key, value.trim()));
+        }
+        None
+    }
+}
+
+/// Writes the packets of a recording to `file` until the sender is dropped, blocking
+fn write_packets(
+    file: File,
+    mut packets: mpsc::Receiver<RecordedPacket>,
+) -> std::io::Result<()> {
+    let mut file = BufWriter::new(file);
+    while let Some(recorded) = packets.blocking_recv() {
+        write_packet(&mut file, recorded.offset, &recorded.packet)?;
+    }
+    file.flush()
+}
+
+/// How fast a recording is replayed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// With the delays between the packets as recorded
+    Original,
+    /// Faster by the given factor, e.g. `10.0`
+    Accelerated(f64),
+    /// Without any delay
+    Immediate,
+}
+
+impl Timing {
+    fn scale(&self, offset: Duration) -> Duration {
+        match self {
+            Timing::Original => offset,
+            Timing::Accelerated(factor) if *factor > 0.0 => {
+                offset.div_f64(*factor)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Replays the events of a recording into a new connection
+///
+/// The returned connection behaves like one connected to a server: the recorded events are
+/// delivered to its subscribers, trackers can be started on it, and the connection is closed
+/// once the last event has been replayed. Recorded responses are skipped; actions sent during
+/// the replay are answered with an empty `Response: Success`.
+///
+/// Events due immediately may be delivered before the caller had a chance to subscribe, use
+/// `replay_subscribed` to receive all of them.
+pub async fn replay(
+    packets: Vec<RecordedPacket>,
+    timing: Timing,
+) -> std::io::Result<AmiConnection> {
+    Ok(replay_subscribed(packets, timing).await?.0)
+}
+
+/// Replays the events of a recording like `replay`, subscribing to the events before the
+/// first one is replayed
+pub async fn replay_subscribed(
+    packets: Vec<RecordedPacket>,
+    timing: Timing,
+) -> std::io::Result<(AmiConnection, broadcast::Receiver<Option<Packet>>)> {
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let (reader, mut writer) = tokio::io::split(server);
+    writer.write_all(b"Asterisk Call Manager/5.0.0\r\n").await?;
+    let connection = AmiConnection::from_stream(client).await?;
+    let events = connection.events();
+
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        let mut action_id = None;
+        let started = Instant::now();
+        let mut events = packets
+            .into_iter()
+            .filter(|recorded| event_name(&recorded.packet).is_some());
+        let mut next = events.next();
+        while let Some(recorded) = &next {
+            let deadline = started + timing.scale(recorded.offset);
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => {
+                    if writer.write_all(to_wire(&recorded.packet).as_bytes()).await.is_err() {
+                        break;
+                    }
+                    next = events.next();
+                }
+                read = reader.read_line(&mut line) => {
+                    match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                    let trimmed = line.trim();
+                    if trimmed.is_empty() {
+                        let mut response = vec![Tag::from("Response", "Success")];
+                        if let Some(id) = action_id.take() {
+                            response.push(Tag::of("ActionID".to_string(), id));
+                        }
+                        if writer.write_all(to_wire(&response).as_bytes()).await.is_err() {
+                            break;
+                        }
+                    } else if let Some((key, value)) = trimmed.split_once(':') {
+                        if key.eq_ignore_ascii_case("ActionID") {
+                            action_id = Some(value.trim().to_string());
+                        }
+                    }
+                    line.clear();
+                }
+            }
+        }
+    });
+
+    Ok((connection, events))
+}
+
+/// Replays the events of a recording file, see `replay`
+pub async fn replay_file<P: AsRef<Path>>(
+    path: P,
+    timing: Timing,
+) -> std::io::Result<AmiConnection> {
+    let file = std::io::BufReader::new(File::open(path)?);
+    replay(read_recording(file)?, timing).await
+}
+
+fn to_wire(pkt: &Packet) -> String {
+    format!("{}\r\n\r\n", packet_to_string(pkt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{find_tag, packet_from};
+
+    #[tokio::test]
+    async fn replays_recorded_events() {
+        let mut file = vec![];
+        write_packet(
+            &mut file,
+            Duration::ZERO,
+            &packet_from(&[("Response", "Success"), ("Ping", "Pong")]),
+        )
+        .unwrap();
+        for (offset, event) in [(10, "FullyBooted"), (2000, "Shutdown")] {
+            let event = packet_from(&[("Event", event)]);
+            write_packet(&mut file, Duration::from_millis(offset), &event)
+                .unwrap();
+        }
+        let packets = read_recording(file.as_slice()).unwrap();
+        assert_eq!(packets[2].offset, Duration::from_millis(2000));
+
+        let connection =
+            replay(packets, Timing::Accelerated(100.0)).await.unwrap();
+        let mut events = connection.events();
+        assert!(connection.ping().await.is_ok());
+        let mut replayed = vec![];
+        while let Some(event) = events.recv().await.unwrap() {
+            replayed.push(find_tag(&event, "Event").unwrap().clone());
+        }
+        assert_eq!(replayed, vec!["FullyBooted", "Shutdown"]);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn records_packets_up_to_the_close() {
+        let path = std::env::temp_dir()
+            .join(format!("asterisk-ami-record-{}", std::process::id()));
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let recorder = Recorder::start(&connection, &path).unwrap();
+        for n in 0..50 {
+            let n = n.to_string();
+            let event = packet_from(&[("Event", "Test"), ("N", &n)]);
+            server.send_packet(&event).await.unwrap();
+        }
+        drop(server);
+        recorder.task.await.unwrap().unwrap();
+
+        let file = std::io::BufReader::new(File::open(&path).unwrap());
+        let packets = read_recording(file).unwrap();
+        assert_eq!(packets.len(), 50);
+        assert_eq!(find_tag(&packets[49].packet, "N").unwrap(), "49");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! Redaction of credentials in `Debug` output and the wire tap
+//!
+//! Values of tags whose key contains `secret`, `password`, or `passwd` (e.g. `Secret`,
+//! `AuthSecret`, `MD5Secret`) are replaced by `********`. Redaction is enabled by default.
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) const REDACTED: &str = "********";
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables the redaction of credentials for the whole process
+///
+/// Only disable it while debugging authentication problems, as credentials end up in logs
+/// afterwards.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Checks if the values of tags with the given key are redacted
+pub fn is_sensitive(key: &str) -> bool {
+    let key = key.trim().to_ascii_lowercase();
+    ["secret", "password", "passwd"]
+        .iter()
+        .any(|word| key.contains(word))
+}
+
+/// Returns the value to show for a tag
+pub(crate) fn value<'a>(key: &str, value: &'a str) -> &'a str {
+    if ENABLED.load(Ordering::Relaxed) && is_sensitive(key) {
+        REDACTED
+    } else {
+        value
+    }
+}
+
+/// Redacts a raw `Key: value` line
+pub(crate) fn line(line: &str) -> Cow<'_, str> {
+    match line.split_once(':') {
+        Some((key, raw)) if value(key, raw) == REDACTED => {
+            Cow::Owned(format!("{}: {}", key, REDACTED))
+        }
+        _ => Cow::Borrowed(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Tag;
+
+    #[test]
+    fn redacts_credentials() {
+        let login = vec![
+            Tag::from("Action", "Login"),
+            Tag::from("Username", "admin"),
+            Tag::from("Secret", "s3cr3t"),
+        ];
+        let debug = format!("{:?}", login);
+        assert!(debug.contains("admin"));
+        assert!(!debug.contains("s3cr3t"));
+        assert_eq!(line("AuthSecret: s3cr3t"), "AuthSecret: ********");
+        assert_eq!(line("Channel: PJSIP/100"), "Channel: PJSIP/100");
+    }
+}
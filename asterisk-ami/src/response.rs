@@ -10,6 +10,12 @@ pub struct ResponseBuilder {
     response: Vec<Packet>,
     in_packet: Packet,
     in_response_sequence: bool,
+    in_command_output: bool,
+    action_id: Option<String>,
+    /// The size of the packets collected for the response, see `crate::memory::packet_size`
+    response_bytes: usize,
+    /// Cleared once further packets of the response are to be discarded
+    collecting: bool,
 }
 
 impl ResponseBuilder {
@@ -18,9 +24,33 @@ impl ResponseBuilder {
             response: vec![],
             in_packet: vec![],
             in_response_sequence: false,
+            in_command_output: false,
+            action_id: None,
+            response_bytes: 0,
+            collecting: true,
         }
     }
 
+    /// Sets the ActionID of the command whose response is expected next
+    ///
+    /// While a response sequence is read, events carrying a different ActionID (or none at
+    /// all) are passed on as standalone events instead of being added to the response.
+    pub fn expect_action_id(&mut self, action_id: Option<String>) {
+        self.action_id = action_id;
+    }
+
+    /// The size of the packets collected for the response being read
+    pub fn response_bytes(&self) -> usize {
+        self.response_bytes
+    }
+
+    /// Discards the remaining packets of the response being read
+    ///
+    /// The response completes as usual, with the packets collected so far.
+    pub fn stop_collecting(&mut self) {
+        self.collecting = false;
+    }
+
     /// processes a single line received from the Asterisk server
     ///
     /// # Arguments
@@ -30,16 +60,24 @@ impl ResponseBuilder {
     /// Returns `None` if neither a response nor an event is complete, `Some(...)` if a response
     /// is complete.
     pub fn add_line(&mut self, line: &str) -> Option<Response> {
+        if self.in_command_output {
+            self.add_output_line(line);
+            return None;
+        }
         if line.is_empty() {
-            if !self.in_response_sequence
-                && !self.in_packet.is_empty()
+            if !self.in_packet.is_empty()
                 && self.in_packet[0].key.eq_ignore_ascii_case("Event")
+                && (!self.in_response_sequence || !self.belongs_to_response())
             {
                 let data = self.in_packet.clone();
                 self.in_packet.clear();
                 return Some(Response::Event(data));
             } else {
-                self.response.push(self.in_packet.clone());
+                if self.collecting || self.response.is_empty() {
+                    self.response_bytes +=
+                        crate::memory::packet_size(&self.in_packet);
+                    self.response.push(self.in_packet.clone());
+                }
                 let event_list =
                     find_tag(&self.in_packet, "EventList").cloned();
                 self.in_packet.clear();
@@ -51,19 +89,62 @@ impl ResponseBuilder {
                     }
                 }
                 if !self.in_response_sequence {
-                    let data = self.response.clone();
-                    self.response.clear();
+                    let data = std::mem::take(&mut self.response);
+                    self.response_bytes = 0;
+                    self.collecting = true;
                     return Some(Response::CommandResponse(data));
                 }
             }
         } else {
-            if let Some(tag) = line_to_tag(line) {
-                self.in_packet.push(tag);
+            match line_to_tag(line) {
+                Some(tag) => {
+                    self.in_command_output = self.in_packet.is_empty()
+                        && tag.key.eq_ignore_ascii_case("Response")
+                        && tag.value.eq_ignore_ascii_case("Follows");
+                    self.in_packet.push(tag);
+                }
+                None => {
+                    crate::telemetry::malformed_line(line);
+                    crate::metrics::parse_error();
+                }
             }
         }
 
         None
     }
+
+    /// Collects the raw output of a `Response: Follows` as `Output` headers
+    ///
+    /// Older Asterisk versions answer `Command` this way. The output is terminated by
+    /// `--END COMMAND--` instead of an empty line and may itself contain empty lines or colons.
+    fn add_output_line(&mut self, line: &str) {
+        if let Some(rest) = line.strip_suffix("--END COMMAND--") {
+            if !rest.trim().is_empty() {
+                self.in_packet.push(Tag::from("Output", rest.trim()));
+            }
+            self.in_command_output = false;
+            return;
+        }
+        let has_output = self.in_packet.iter().any(|t| t.key == "Output");
+        match line_to_tag(line) {
+            Some(tag)
+                if !has_output
+                    && (tag.key.eq_ignore_ascii_case("ActionID")
+                        || tag.key.eq_ignore_ascii_case("Privilege")) =>
+            {
+                self.in_packet.push(tag)
+            }
+            _ => self.in_packet.push(Tag::from("Output", line)),
+        }
+    }
+
+    fn belongs_to_response(&self) -> bool {
+        match &self.action_id {
+            Some(expected) => find_tag(&self.in_packet, "ActionID")
+                .is_some_and(|id| id == expected),
+            None => true,
+        }
+    }
 }
 
 fn line_to_tag(line: &str) -> Option<Tag> {
@@ -73,3 +154,66 @@ fn line_to_tag(line: &str) -> Option<Tag> {
         Tag::from(key, value)
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(builder: &mut ResponseBuilder, lines: &[&str]) -> Vec<Response> {
+        lines.iter().filter_map(|l| builder.add_line(l)).collect()
+    }
+
+    #[test]
+    fn passes_unrelated_events_during_list_response() {
+        let mut builder = ResponseBuilder::new();
+        builder.expect_action_id(Some("7".to_string()));
+        let responses = feed(
+            &mut builder,
+            &[
+                "Response: Success",
+                "ActionID: 7",
+                "EventList: start",
+                "",
+                "Event: Newchannel",
+                "Channel: PJSIP/100-00000001",
+                "",
+                "Event: Status",
+                "ActionID: 7",
+                "",
+                "Event: StatusComplete",
+                "ActionID: 7",
+                "EventList: Complete",
+                "",
+            ],
+        );
+        assert_eq!(responses.len(), 2);
+        assert!(
+            matches!(&responses[0], Response::Event(e) if e[0].value == "Newchannel")
+        );
+        assert!(
+            matches!(&responses[1], Response::CommandResponse(r) if r.len() == 3)
+        );
+    }
+
+    #[test]
+    fn discards_rest_of_response() {
+        let mut builder = ResponseBuilder::new();
+        let mut lines = vec!["Response: Success", "EventList: start", ""];
+        for _ in 0..3 {
+            lines.extend(&["Event: CoreShowChannel", "Channel: a", ""]);
+        }
+        let (head, tail) = lines.split_at(6);
+        assert!(feed(&mut builder, head).is_empty());
+        assert_eq!(builder.response_bytes(), 77);
+        builder.stop_collecting();
+        let mut responses = feed(&mut builder, tail);
+        responses.extend(feed(
+            &mut builder,
+            &["Event: CoreShowChannelsComplete", "EventList: Complete", ""],
+        ));
+        assert!(
+            matches!(&responses[..], [Response::CommandResponse(r)] if r.len() == 2)
+        );
+        assert_eq!(builder.response_bytes(), 0);
+    }
+}
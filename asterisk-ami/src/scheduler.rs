@@ -0,0 +1,364 @@
+use crate::{AmiConnection, Error, Packet};
+use log::{debug, warn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// The fields of a cron expression, each as a bit set of the values it matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether both the day of month and the day of week are restricted, in which case a day
+    /// matching either of them is enough, as with the classic cron
+    either_day: bool,
+}
+
+/// Parses a field of a cron expression like `*`, `5`, `1-5`, `*/15`, or `0,30`
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok()?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None if step > 1 => (range.parse().ok()?, max),
+                None => {
+                    let value = range.parse().ok()?;
+                    (value, value)
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+/// Converts days since the epoch to year, month, and day of the Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+        - day_of_era / 146_096)
+        / 365;
+    let day_of_year =
+        day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Option<Cron> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return None;
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // both 0 and 7 stand for Sunday
+        if weekdays & 1 << 7 != 0 {
+            weekdays |= 1;
+        }
+        Some(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn matches_day(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // the epoch has been a Thursday
+        let weekday = (days + 4).rem_euclid(7);
+        let day_matches = self.days & 1 << day != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        self.months & 1 << month != 0
+            && if self.either_day {
+                day_matches || weekday_matches
+            } else {
+                day_matches && weekday_matches
+            }
+    }
+
+    /// Finds the first matching minute after `after`, given in seconds since the epoch (UTC)
+    fn next_after(&self, after: u64) -> Option<u64> {
+        let mut time = (after / 60 + 1) * 60;
+        // a schedule like February 30th never matches, give up after a few years
+        let give_up = time + 5 * 366 * 86400;
+        while time < give_up {
+            let days = (time / 86400) as i64;
+            if !self.matches_day(days) {
+                time = (time / 86400 + 1) * 86400;
+            } else if self.hours & 1 << (time % 86400 / 3600) == 0 {
+                time = (time / 3600 + 1) * 3600;
+            } else if self.minutes & 1 << (time % 3600 / 60) == 0 {
+                time += 60;
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// When a scheduled action is run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule(Timing);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Timing {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Runs the action when the scheduler starts and then every `interval`
+    pub fn every(interval: Duration) -> Schedule {
+        Schedule(Timing::Every(interval.max(Duration::from_millis(1))))
+    }
+
+    /// Runs the action at the times given by the cron expression, e.g. `0 3 * * *` for
+    /// every night at 3 o'clock
+    ///
+    /// The five fields are minute, hour, day of month, month, and day of week, each may be
+    /// `*`, a value, a range like `1-5`, a step like `*/15`, or a list of those. Names of
+    /// months and days are not supported. The times are in UTC.
+    pub fn cron(expression: &str) -> Result<Schedule, Error> {
+        Cron::parse(expression)
+            .map(|cron| Schedule(Timing::Cron(cron)))
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!(
+                    "invalid cron expression: {}",
+                    expression
+                ))
+            })
+    }
+
+    /// The time of the next run after `now`, `None` if there is none
+    fn next_run(&self, now: Instant, first: bool) -> Option<Instant> {
+        match &self.0 {
+            Timing::Every(_) if first => Some(now),
+            Timing::Every(interval) => Some(now + *interval),
+            Timing::Cron(cron) => {
+                let wall = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                let next = cron.next_after(wall.as_secs())?;
+                Some(now + (Duration::from_secs(next) - wall))
+            }
+        }
+    }
+}
+
+/// A run of a scheduled action
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    /// The name the job has been added with
+    pub job: String,
+    pub started: SystemTime,
+    /// The response of the server, all packets of it for list actions
+    pub response: Result<Vec<Packet>, Error>,
+}
+
+struct Job {
+    name: String,
+    schedule: Schedule,
+    action: Packet,
+    next_run: Option<Instant>,
+    running: bool,
+}
+
+/// Runs actions on a fixed interval or a cron schedule, e.g. `QueueStatus` snapshots or a
+/// nightly `Reload`
+///
+/// A run is skipped if the previous run of the same job has not been answered yet.
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+}
+
+impl Scheduler {
+    /// Creates a scheduler without any jobs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job sending `action` on `schedule`
+    pub fn job(
+        mut self,
+        name: &str,
+        schedule: Schedule,
+        action: Packet,
+    ) -> Self {
+        self.jobs.push(Job {
+            name: name.to_string(),
+            schedule,
+            action,
+            next_run: None,
+            running: false,
+        });
+        self
+    }
+
+    /// Starts running the jobs over `connection`, passing the result of each run to `sink`
+    ///
+    /// To process the results elsewhere, `sink` may forward them to a channel.
+    pub fn start<F>(
+        self,
+        connection: &AmiConnection,
+        mut sink: F,
+    ) -> RunningScheduler
+    where
+        F: FnMut(JobResult) + Send + 'static,
+    {
+        let connection = connection.clone();
+        let mut jobs = self.jobs;
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let (done_tx, mut done_rx) =
+            mpsc::unbounded_channel::<(usize, JobResult)>();
+        let task = tokio::spawn(async move {
+            let now = Instant::now();
+            for job in &mut jobs {
+                job.next_run = job.schedule.next_run(now, true);
+                if job.next_run.is_none() {
+                    warn!("Scheduled job {} never runs", job.name);
+                }
+            }
+            loop {
+                let next = jobs.iter().filter_map(|j| j.next_run).min();
+                let wake_at = next.unwrap_or_else(Instant::now);
+                tokio::select! {
+                    _ = &mut stop_rx => break,
+                    Some((n, result)) = done_rx.recv() => {
+                        jobs[n].running = false;
+                        sink(result);
+                    }
+                    _ = tokio::time::sleep_until(wake_at), if next.is_some() => {
+                        run_due(&mut jobs, &connection, &done_tx);
+                    }
+                }
+            }
+        });
+        RunningScheduler { stop_tx, task }
+    }
+}
+
+/// Starts the runs of the jobs that are due, reporting their results to `done_tx`
+fn run_due(
+    jobs: &mut [Job],
+    connection: &AmiConnection,
+    done_tx: &mpsc::UnboundedSender<(usize, JobResult)>,
+) {
+    let now = Instant::now();
+    for (n, job) in jobs.iter_mut().enumerate() {
+        if job.next_run.is_none_or(|at| at > now) {
+            continue;
+        }
+        job.next_run = job.schedule.next_run(now, false);
+        if job.running {
+            debug!("Skipping scheduled job {}, still running", job.name);
+            continue;
+        }
+        job.running = true;
+        let connection = connection.clone();
+        let action = job.action.clone();
+        let job = job.name.clone();
+        let done_tx = done_tx.clone();
+        tokio::spawn(async move {
+            let started = SystemTime::now();
+            let response = connection.send_action(action).await;
+            let result = JobResult {
+                job,
+                started,
+                response,
+            };
+            let _ = done_tx.send((n, result));
+        });
+    }
+}
+
+/// A `Scheduler` running its jobs
+pub struct RunningScheduler {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl RunningScheduler {
+    /// Stops scheduling runs, results of runs still in progress are dropped
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(());
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_next_cron_time() {
+        // Tuesday, 2023-11-14 22:13:20 UTC
+        let now = 1_700_000_000;
+        let next =
+            |expression| Cron::parse(expression).unwrap().next_after(now);
+        assert_eq!(next("0 3 * * *"), Some(1_700_017_200));
+        assert_eq!(next("*/15 * * * *"), Some(1_700_000_100));
+        assert_eq!(next("0 9 * * 1"), Some(1_700_470_800));
+        assert_eq!(next("0 0 1 1 *"), Some(1_704_067_200));
+        assert_eq!(next("0 0 30 2 *"), None);
+        assert!(Cron::parse("60 * * * *").is_none());
+        assert!(Cron::parse("* * *").is_none());
+        assert!(Schedule::cron("*/0 * * * *").is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn runs_jobs_on_interval() {
+        use crate::{find_tag, packet_from, Tag};
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (results_tx, mut results_rx) = mpsc::unbounded_channel();
+        let scheduler = Scheduler::new()
+            .job(
+                "ping",
+                Schedule::every(Duration::from_millis(10)),
+                vec![Tag::from("Action", "Ping")],
+            )
+            .start(&connection, move |result| {
+                let _ = results_tx.send(result);
+            });
+        for _ in 0..2 {
+            let ping = server.read_packet().await.unwrap();
+            let pong = packet_from(&[
+                ("Response", "Success"),
+                ("ActionID", find_tag(&ping, "ActionID").unwrap()),
+                ("Ping", "Pong"),
+            ]);
+            server.send_packet(&pong).await.unwrap();
+            let result = results_rx.recv().await.unwrap();
+            assert_eq!(result.job, "ping");
+            let response = result.response.unwrap();
+            assert_eq!(find_tag(&response[0], "Ping").unwrap(), "Pong");
+        }
+        scheduler.stop().await;
+    }
+}
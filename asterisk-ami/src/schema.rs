@@ -0,0 +1,435 @@
+//! Checking outgoing actions against the actions Asterisk knows
+//!
+//! Asterisk answers a malformed action with an `Response: Error` that does not always say
+//! what is wrong, and some mistakes, like a `Timeout` of `30s`, are not reported at all. The
+//! `SchemaValidator` rejects such actions before they are sent, naming the header at fault.
+//! The bundled schema covers the actions of the core and the common modules; actions it does
+//! not know are passed on unless the validator is strict.
+
+use crate::interceptor::Interceptor;
+use crate::{find_tag, Error, Packet};
+
+/// The format of the value of a header
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Integer,
+    /// As understood by `ast_true` and `ast_false`
+    Boolean,
+    /// A channel to be created, like `PJSIP/100` or `Local/200@default`
+    Dialstring,
+    OneOf(&'static [&'static str]),
+}
+
+impl Format {
+    fn check(&self, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let valid = match self {
+            Format::Integer => value.parse::<i64>().is_ok(),
+            Format::Boolean => [
+                "yes", "no", "true", "false", "y", "n", "t", "f", "1", "0",
+                "on", "off",
+            ]
+            .iter()
+            .any(|b| value.eq_ignore_ascii_case(b)),
+            Format::Dialstring => {
+                value.split_once('/').is_some_and(|(tech, resource)| {
+                    !tech.is_empty() && !resource.is_empty()
+                })
+            }
+            Format::OneOf(values) => {
+                values.iter().any(|v| value.eq_ignore_ascii_case(v))
+            }
+        };
+        if valid {
+            return Ok(());
+        }
+        Err(match self {
+            Format::Integer => "an integer".to_string(),
+            Format::Boolean => "yes or no".to_string(),
+            Format::Dialstring => "a channel like PJSIP/100".to_string(),
+            Format::OneOf(values) => format!("one of {}", values.join(", ")),
+        })
+    }
+}
+
+/// What an action requires
+struct ActionSchema {
+    name: &'static str,
+    /// The first major version of Asterisk providing the action
+    since: u32,
+    /// The major version the action has been removed in
+    removed: Option<u32>,
+    required: &'static [&'static str],
+    /// At least one of these groups of headers has to be present completely
+    one_of: &'static [&'static [&'static str]],
+    formats: &'static [(&'static str, Format)],
+}
+
+const fn action(name: &'static str) -> ActionSchema {
+    ActionSchema {
+        name,
+        since: 0,
+        removed: None,
+        required: &[],
+        one_of: &[],
+        formats: &[],
+    }
+}
+
+const SCHEMA: &[ActionSchema] = &[
+    ActionSchema {
+        required: &["Channel", "Timeout"],
+        formats: &[("Timeout", Format::Integer)],
+        ..action("AbsoluteTimeout")
+    },
+    ActionSchema {
+        required: &["Channel", "Exten"],
+        ..action("Atxfer")
+    },
+    ActionSchema {
+        since: 12,
+        required: &["Channel", "Exten"],
+        ..action("BlindTransfer")
+    },
+    ActionSchema {
+        required: &["Channel1", "Channel2"],
+        formats: &[(
+            "Tone",
+            Format::OneOf(&["no", "Channel1", "Channel2", "Both"]),
+        )],
+        ..action("Bridge")
+    },
+    ActionSchema {
+        since: 12,
+        required: &["BridgeUniqueid"],
+        ..action("BridgeInfo")
+    },
+    ActionSchema {
+        since: 12,
+        ..action("BridgeList")
+    },
+    ActionSchema {
+        required: &["Command"],
+        ..action("Command")
+    },
+    ActionSchema {
+        required: &["Conference"],
+        ..action("ConfbridgeList")
+    },
+    ActionSchema {
+        required: &["Family", "Key"],
+        ..action("DBDel")
+    },
+    ActionSchema {
+        required: &["Family", "Key"],
+        ..action("DBGet")
+    },
+    ActionSchema {
+        required: &["Family", "Key"],
+        ..action("DBPut")
+    },
+    ActionSchema {
+        since: 13,
+        ..action("DeviceStateList")
+    },
+    ActionSchema {
+        required: &["EventMask"],
+        ..action("Events")
+    },
+    ActionSchema {
+        required: &["Exten", "Context"],
+        ..action("ExtensionState")
+    },
+    ActionSchema {
+        required: &["Filename"],
+        ..action("GetConfig")
+    },
+    ActionSchema {
+        required: &["Variable"],
+        ..action("Getvar")
+    },
+    ActionSchema {
+        required: &["Channel"],
+        formats: &[("Cause", Format::Integer)],
+        ..action("Hangup")
+    },
+    ActionSchema {
+        removed: Some(21),
+        ..action("IAXpeers")
+    },
+    ActionSchema {
+        required: &["Username"],
+        ..action("Login")
+    },
+    ActionSchema {
+        required: &["Channel"],
+        ..action("MixMonitor")
+    },
+    ActionSchema {
+        required: &["Module"],
+        ..action("ModuleCheck")
+    },
+    ActionSchema {
+        required: &["LoadType"],
+        formats: &[("LoadType", Format::OneOf(&["load", "unload", "reload"]))],
+        ..action("ModuleLoad")
+    },
+    ActionSchema {
+        required: &["Channel"],
+        one_of: &[&["Application"], &["Exten", "Context", "Priority"]],
+        formats: &[
+            ("Channel", Format::Dialstring),
+            ("Timeout", Format::Integer),
+            ("Async", Format::Boolean),
+            ("EarlyMedia", Format::Boolean),
+        ],
+        ..action("Originate")
+    },
+    ActionSchema {
+        required: &["Channel"],
+        formats: &[("Timeout", Format::Integer)],
+        ..action("Park")
+    },
+    ActionSchema {
+        since: 12,
+        required: &["Endpoint"],
+        ..action("PJSIPShowEndpoint")
+    },
+    ActionSchema {
+        since: 12,
+        ..action("PJSIPShowEndpoints")
+    },
+    ActionSchema {
+        since: 13,
+        required: &["Endpoint"],
+        ..action("PJSIPQualify")
+    },
+    ActionSchema {
+        required: &["Channel", "Digit"],
+        formats: &[("Duration", Format::Integer), ("Receive", Format::Boolean)],
+        ..action("PlayDTMF")
+    },
+    ActionSchema {
+        required: &["Queue", "Interface"],
+        formats: &[("Penalty", Format::Integer), ("Paused", Format::Boolean)],
+        ..action("QueueAdd")
+    },
+    ActionSchema {
+        required: &["Interface", "Paused"],
+        formats: &[("Paused", Format::Boolean)],
+        ..action("QueuePause")
+    },
+    ActionSchema {
+        required: &["Interface", "Penalty"],
+        formats: &[("Penalty", Format::Integer)],
+        ..action("QueuePenalty")
+    },
+    ActionSchema {
+        required: &["Queue", "Interface"],
+        ..action("QueueRemove")
+    },
+    ActionSchema {
+        required: &["Channel", "Exten", "Context", "Priority"],
+        ..action("Redirect")
+    },
+    ActionSchema {
+        required: &["Variable"],
+        ..action("Setvar")
+    },
+    ActionSchema {
+        removed: Some(21),
+        ..action("SIPpeers")
+    },
+    ActionSchema {
+        removed: Some(21),
+        required: &["Peer"],
+        ..action("SIPshowpeer")
+    },
+    ActionSchema {
+        required: &["Channel"],
+        ..action("StopMixMonitor")
+    },
+    ActionSchema {
+        required: &["SrcFilename", "DstFilename"],
+        formats: &[("Reload", Format::Boolean)],
+        ..action("UpdateConfig")
+    },
+];
+
+/// Extracts the major version from a version string like `18.20.0` or `certified/18.9-cert4`
+pub(crate) fn major_version(version: &str) -> Option<u32> {
+    let version = version.rsplit('/').next()?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// An `Interceptor` rejecting actions that do not match the bundled schema
+///
+/// Rejected actions fail with `Error::InvalidArgument` describing the problem, e.g.
+/// `Originate: Timeout must be an integer, got "30s"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaValidator {
+    version: u32,
+    strict: bool,
+}
+
+impl SchemaValidator {
+    /// Validates actions for the given major version of Asterisk, e.g. `20`
+    pub fn new(major_version: u32) -> Self {
+        SchemaValidator {
+            version: major_version,
+            strict: false,
+        }
+    }
+
+    /// Validates actions for the version reported by the server, e.g. the `asterisk_version`
+    /// of `CoreSettings`
+    ///
+    /// Returns `None` for versions without a major version number, like those of builds from
+    /// the development branch.
+    pub fn for_version(version: &str) -> Option<Self> {
+        major_version(version).map(Self::new)
+    }
+
+    /// Rejects actions the schema does not know as well
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Checks an action against the schema
+    pub fn validate(&self, action: &Packet) -> Result<(), Error> {
+        let name = match find_tag(action, "Action") {
+            Some(name) => name,
+            None => return Err(invalid("packet without an Action header")),
+        };
+        let schema = SCHEMA.iter().find(|s| s.name.eq_ignore_ascii_case(name));
+        let schema = match schema {
+            Some(schema) => schema,
+            None if self.strict => {
+                return Err(invalid(&format!("unknown action {}", name)))
+            }
+            None => return Ok(()),
+        };
+        if self.version < schema.since {
+            return Err(invalid(&format!(
+                "{} requires Asterisk {} or later",
+                schema.name, schema.since
+            )));
+        }
+        if schema
+            .removed
+            .is_some_and(|removed| self.version >= removed)
+        {
+            return Err(invalid(&format!(
+                "{} has been removed in Asterisk {}",
+                schema.name,
+                schema.removed.unwrap_or_default()
+            )));
+        }
+        let has =
+            |key: &str| find_tag(action, key).is_some_and(|v| !v.is_empty());
+        if let Some(missing) = schema.required.iter().find(|key| !has(key)) {
+            return Err(invalid(&format!(
+                "{}: missing header {}",
+                schema.name, missing
+            )));
+        }
+        let complete = |group: &&[&str]| group.iter().all(|key| has(key));
+        if !schema.one_of.is_empty() && !schema.one_of.iter().any(complete) {
+            let groups = schema
+                .one_of
+                .iter()
+                .map(|group| group.join(", "))
+                .collect::<Vec<_>>();
+            return Err(invalid(&format!(
+                "{}: requires either {}",
+                schema.name,
+                groups.join(" or ")
+            )));
+        }
+        for (key, format) in schema.formats {
+            if let Some(value) = find_tag(action, key) {
+                if let Err(expected) = format.check(value) {
+                    return Err(invalid(&format!(
+                        "{}: {} must be {}, got {:?}",
+                        schema.name, key, expected, value
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidArgument(message.to_string())
+}
+
+impl Interceptor for SchemaValidator {
+    fn outgoing(&self, action: &mut Packet) -> Result<(), Error> {
+        self.validate(action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn rejects_malformed_actions() {
+        let validator = SchemaValidator::for_version("18.20.0").unwrap();
+        let originate = |headers: &[(&str, &str)]| {
+            let mut pkt = packet_from(&[("Action", "Originate")]);
+            pkt.extend(packet_from(headers));
+            validator.validate(&pkt)
+        };
+        assert_eq!(
+            originate(&[("Channel", "PJSIP/100"), ("Application", "Echo")]),
+            Ok(())
+        );
+        assert_eq!(
+            originate(&[("Application", "Echo")]),
+            Err(invalid("Originate: missing header Channel"))
+        );
+        assert_eq!(
+            originate(&[("Channel", "PJSIP/100"), ("Exten", "200")]),
+            Err(invalid(
+                "Originate: requires either Application or Exten, Context, Priority"
+            ))
+        );
+        assert_eq!(
+            originate(&[
+                ("Channel", "PJSIP/100"),
+                ("Application", "Echo"),
+                ("Timeout", "30s"),
+            ]),
+            Err(invalid(
+                "Originate: Timeout must be an integer, got \"30s\""
+            ))
+        );
+        assert_eq!(
+            originate(&[("Channel", "100"), ("Application", "Echo")]),
+            Err(invalid(
+                "Originate: Channel must be a channel like PJSIP/100, got \"100\""
+            ))
+        );
+
+        let sip_peers = packet_from(&[("Action", "SIPpeers")]);
+        assert!(validator.validate(&sip_peers).is_ok());
+        assert_eq!(
+            SchemaValidator::new(21).validate(&sip_peers),
+            Err(invalid("SIPpeers has been removed in Asterisk 21"))
+        );
+        let bridges = packet_from(&[("Action", "BridgeList")]);
+        assert_eq!(
+            SchemaValidator::new(11).validate(&bridges),
+            Err(invalid("BridgeList requires Asterisk 12 or later"))
+        );
+        let custom = packet_from(&[("Action", "MyModuleAction")]);
+        assert!(validator.validate(&custom).is_ok());
+        assert!(validator.clone().strict().validate(&custom).is_err());
+        assert_eq!(major_version("certified/18.9-cert4"), Some(18));
+        assert_eq!(major_version("GIT-master-abc123"), None);
+    }
+}
@@ -0,0 +1,37 @@
+//! `tower::Service` implementation, enabled by the `tower` feature
+
+use crate::{AmiConnection, Error, Packet};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Sends actions like `send`, but reports a `Response: Error` as `Error::ActionFailed`
+///
+/// Like all action helpers, an `ActionID` is added if the packet does not contain one. This
+/// allows composing timeouts, retries, rate limits, and the like from the tower middleware
+/// ecosystem around a connection:
+///
+/// ```ignore
+/// let service = ServiceBuilder::new()
+///     .timeout(Duration::from_secs(5))
+///     .service(connection.clone());
+/// ```
+impl tower_service::Service<Packet> for AmiConnection {
+    type Response = Vec<Packet>;
+    type Error = Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Vec<Packet>, Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if self.cmd_tx.is_closed() {
+            Poll::Ready(Err(Error::ConnectionClosed))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn call(&mut self, pkt: Packet) -> Self::Future {
+        let connection = self.clone();
+        Box::pin(async move { connection.send_action(pkt).await })
+    }
+}
@@ -0,0 +1,244 @@
+use crate::peers::{Reachability, RegistrationState};
+use crate::{
+    find_flag, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+use std::time::Duration;
+
+/// A chan_sip peer as listed by `SIPpeers`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SipPeer {
+    pub name: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub dynamic: bool,
+    pub status: Reachability,
+    /// The status as sent by Asterisk, e.g. `OK (23 ms)`
+    pub status_text: Option<String>,
+    /// Round trip time of the last qualify
+    pub rtt: Option<Duration>,
+    pub description: Option<String>,
+}
+
+impl SipPeer {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let status_text = find_value(pkt, "Status");
+        let (status, rtt) = parse_qualify_status(status_text.as_deref());
+        Some(SipPeer {
+            name: find_tag(pkt, "ObjectName")?.clone(),
+            host: find_host(pkt, "IPaddress"),
+            port: find_port(pkt, "IPport"),
+            dynamic: find_flag(pkt, "Dynamic"),
+            status,
+            status_text,
+            rtt,
+            description: find_value(pkt, "Description"),
+        })
+    }
+}
+
+/// The details of a chan_sip peer as returned by `SIPshowpeer`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SipPeerDetails {
+    pub peer: SipPeer,
+    pub context: Option<String>,
+    pub caller_id: Option<String>,
+    pub user_agent: Option<String>,
+    pub reg_contact: Option<String>,
+    /// All headers of the response for details not covered by the typed fields
+    pub raw: Packet,
+}
+
+impl SipPeerDetails {
+    fn from_packet(pkt: &Packet) -> Option<Self> {
+        let status_text = find_value(pkt, "Status");
+        let (status, rtt) = parse_qualify_status(status_text.as_deref());
+        Some(SipPeerDetails {
+            peer: SipPeer {
+                name: find_tag(pkt, "ObjectName")?.clone(),
+                host: find_host(pkt, "Address-IP"),
+                port: find_port(pkt, "Address-Port"),
+                dynamic: find_flag(pkt, "Dynamic"),
+                status,
+                status_text,
+                rtt,
+                description: find_value(pkt, "Description"),
+            },
+            context: find_value(pkt, "Context"),
+            caller_id: find_value(pkt, "Callerid"),
+            user_agent: find_value(pkt, "SIP-Useragent"),
+            reg_contact: find_value(pkt, "Reg-Contact"),
+            raw: pkt.clone(),
+        })
+    }
+}
+
+/// A chan_sip outbound registration as listed by `SIPshowregistry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SipRegistration {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub domain: Option<String>,
+    pub state: RegistrationState,
+    /// The state as sent by Asterisk, e.g. `Registered` or `Request Sent`
+    pub state_text: Option<String>,
+    /// The registration refresh interval
+    pub refresh: Option<Duration>,
+    /// Time of the last successful registration as a unix timestamp
+    pub registration_time: Option<u64>,
+}
+
+impl SipRegistration {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let state_text = find_value(pkt, "State");
+        Some(SipRegistration {
+            host: find_tag(pkt, "Host")?.clone(),
+            port: find_port(pkt, "Port"),
+            username: find_value(pkt, "Username"),
+            domain: find_value(pkt, "Domain"),
+            state: state_text
+                .as_deref()
+                .map_or(RegistrationState::Unknown, RegistrationState::parse),
+            state_text,
+            refresh: find_tag(pkt, "Refresh")
+                .and_then(|r| r.trim().parse().ok())
+                .map(Duration::from_secs),
+            registration_time: find_tag(pkt, "RegistrationTime")
+                .and_then(|t| t.trim().parse().ok())
+                .filter(|&t| t != 0),
+        })
+    }
+}
+
+impl AmiConnection {
+    /// Lists all chan_sip peers
+    pub async fn sip_peers(&self) -> Result<Vec<SipPeer>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "SIPpeers")])
+            .await?;
+        Ok(list_items(&resp, "PeerEntry")
+            .filter_map(SipPeer::from_list_item)
+            .collect())
+    }
+
+    /// Shows the details of a single chan_sip peer
+    pub async fn sip_show_peer(
+        &self,
+        peer: &str,
+    ) -> Result<SipPeerDetails, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "SIPshowpeer"),
+                Tag::from("Peer", peer),
+            ])
+            .await?;
+        resp.first()
+            .and_then(SipPeerDetails::from_packet)
+            .ok_or(Error::UnexpectedResponse)
+    }
+
+    /// Lists all chan_sip outbound registrations
+    pub async fn sip_show_registry(
+        &self,
+    ) -> Result<Vec<SipRegistration>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "SIPshowregistry")])
+            .await?;
+        Ok(list_items(&resp, "RegistryEntry")
+            .filter_map(SipRegistration::from_list_item)
+            .collect())
+    }
+}
+
+/// Finds a host address, treating `(null)` and `-none-` as absent
+pub(crate) fn find_host(pkt: &Packet, key: &str) -> Option<String> {
+    find_value(pkt, key).filter(|h| h != "(null)" && h != "-none-")
+}
+
+/// Finds a port, treating `0` as absent
+pub(crate) fn find_port(pkt: &Packet, key: &str) -> Option<u16> {
+    find_tag(pkt, key)
+        .and_then(|p| p.trim().parse().ok())
+        .filter(|&p| p != 0)
+}
+
+/// Parses a qualify status like `OK (23 ms)`, `LAGGED (2100 ms)`, or `UNREACHABLE`
+pub(crate) fn parse_qualify_status(
+    status: Option<&str>,
+) -> (Reachability, Option<Duration>) {
+    let status = match status {
+        Some(status) => status.trim(),
+        None => return (Reachability::Unknown, None),
+    };
+    let word = status.split_whitespace().next().unwrap_or_default();
+    let reachability = match word.to_ascii_uppercase().as_str() {
+        "OK" | "REACHABLE" => Reachability::Reachable,
+        "LAGGED" => Reachability::Lagged,
+        "UNREACHABLE" => Reachability::Unreachable,
+        _ => Reachability::Unknown,
+    };
+    let rtt = status
+        .split_once('(')
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .and_then(|ms| ms.parse().ok())
+        .map(Duration::from_millis);
+    (reachability, rtt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_peer_entry() {
+        let peer = SipPeer::from_list_item(&packet_from(&[
+            ("Event", "PeerEntry"),
+            ("Channeltype", "SIP"),
+            ("ObjectName", "trunk"),
+            ("IPaddress", "192.0.2.10"),
+            ("IPport", "5060"),
+            ("Dynamic", "no"),
+            ("Status", "OK (23 ms)"),
+        ]))
+        .unwrap();
+        assert_eq!(peer.port, Some(5060));
+        assert_eq!(peer.status, Reachability::Reachable);
+        assert_eq!(peer.rtt, Some(Duration::from_millis(23)));
+    }
+
+    #[test]
+    fn parses_unmonitored_peer() {
+        let (status, rtt) = parse_qualify_status(Some("Unmonitored"));
+        assert_eq!(status, Reachability::Unknown);
+        assert_eq!(rtt, None);
+    }
+
+    #[test]
+    fn parses_registry_entry() {
+        let registration = SipRegistration::from_list_item(&packet_from(&[
+            ("Event", "RegistryEntry"),
+            ("Host", "sip.example.com"),
+            ("Port", "5060"),
+            ("Username", "trunk"),
+            ("Domain", "sip.example.com"),
+            ("Refresh", "105"),
+            ("State", "Registered"),
+            ("RegistrationTime", "1700000000"),
+        ]))
+        .unwrap();
+        assert_eq!(registration.host, "sip.example.com");
+        assert_eq!(registration.port, Some(5060));
+        assert_eq!(registration.username.as_deref(), Some("trunk"));
+        assert_eq!(registration.state, RegistrationState::Registered);
+        assert_eq!(registration.refresh, Some(Duration::from_secs(105)));
+
+        let without_host = packet_from(&[
+            ("Event", "RegistryEntry"),
+            ("Username", "trunk"),
+            ("State", "Registered"),
+        ]);
+        assert_eq!(SipRegistration::from_list_item(&without_host), None);
+    }
+}
@@ -0,0 +1,371 @@
+use crate::recording::{read_recording, write_packet};
+use crate::{AmiConnection, Packet};
+use log::{trace, warn};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+/// The number of events buffered in memory before they are spilled to disk
+const MEMORY_EVENTS: usize = 32;
+/// The number of segment files the spool is split into, the oldest is evicted when full
+const SEGMENTS: u64 = 8;
+
+/// A segment file of the spool
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    events: u64,
+}
+
+/// The segment being written to
+struct Writer {
+    segment: Segment,
+    file: BufWriter<File>,
+}
+
+/// The events spilled to disk, oldest segment first
+struct Backlog {
+    dir: PathBuf,
+    segment_bytes: u64,
+    max_bytes: u64,
+    next_segment: u64,
+    segments: VecDeque<Segment>,
+    writer: Option<Writer>,
+    /// The segment being handed out, removed after its last event has been taken
+    loaded: Option<Segment>,
+    /// The events of the loaded segment that have not been handed out yet
+    pending: VecDeque<Packet>,
+    evicted: Arc<AtomicU64>,
+}
+
+impl Backlog {
+    /// Opens the spool directory, picking up the segments left by an earlier spool
+    fn open(dir: &Path, max_bytes: u64) -> std::io::Result<Backlog> {
+        std::fs::create_dir_all(dir)?;
+        let mut segments = vec![];
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let number = path
+                .file_name()
+                .and_then(|n| n.to_str()?.strip_suffix(".spool"))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(number) = number {
+                segments.push((number, path));
+            }
+        }
+        segments.sort();
+        let segments = segments
+            .into_iter()
+            .map(|(number, path)| {
+                let segment = Segment {
+                    bytes: std::fs::metadata(&path)?.len(),
+                    events: read_segment(&path)?.len() as u64,
+                    path,
+                };
+                Ok((number, segment))
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Ok(Backlog {
+            dir: dir.to_path_buf(),
+            segment_bytes: (max_bytes / SEGMENTS).max(1),
+            max_bytes,
+            next_segment: segments.last().map_or(0, |(n, _)| n + 1),
+            segments: segments.into_iter().map(|(_, s)| s).collect(),
+            writer: None,
+            loaded: None,
+            pending: VecDeque::new(),
+            evicted: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.loaded.is_none()
+            && self.segments.is_empty()
+            && self.writer.is_none()
+    }
+
+    /// Appends an event to the newest segment, evicting the oldest ones if the spool is full
+    fn spill(&mut self, pkt: &Packet) -> std::io::Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let path =
+                    self.dir.join(format!("{:020}.spool", self.next_segment));
+                self.next_segment += 1;
+                self.writer.insert(Writer {
+                    file: BufWriter::new(File::create(&path)?),
+                    segment: Segment {
+                        path,
+                        bytes: 0,
+                        events: 0,
+                    },
+                })
+            }
+        };
+        let mut encoded = vec![];
+        write_packet(&mut encoded, Duration::ZERO, pkt)?;
+        writer.file.write_all(&encoded)?;
+        writer.segment.bytes += encoded.len() as u64;
+        writer.segment.events += 1;
+        if writer.segment.bytes >= self.segment_bytes {
+            self.rotate()?;
+        }
+
+        let mut bytes = self.segments.iter().map(|s| s.bytes).sum::<u64>();
+        while bytes > self.max_bytes {
+            let oldest = match self.segments.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            warn!(
+                "Event spool full, evicting {} events of {}",
+                oldest.events,
+                oldest.path.display()
+            );
+            std::fs::remove_file(&oldest.path)?;
+            self.evicted.fetch_add(oldest.events, Ordering::Relaxed);
+            bytes -= oldest.bytes;
+        }
+        Ok(())
+    }
+
+    /// Closes the segment being written to, so it can be evicted or handed out
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.file.flush()?;
+            self.segments.push_back(writer.segment);
+        }
+        Ok(())
+    }
+
+    /// Checks if there is an event to hand out, loading the next segment if needed
+    ///
+    /// Segments without events, e.g. left by a spool that stopped before flushing them, are
+    /// removed on the way.
+    fn has_next(&mut self) -> std::io::Result<bool> {
+        while self.loaded.is_none() {
+            if self.segments.is_empty() {
+                self.rotate()?;
+            }
+            let segment = match self.segments.pop_front() {
+                Some(segment) => segment,
+                None => break,
+            };
+            trace!("Loading spooled events of {}", segment.path.display());
+            self.pending = read_segment(&segment.path)?;
+            if self.pending.is_empty() {
+                std::fs::remove_file(&segment.path)?;
+            } else {
+                self.loaded = Some(segment);
+            }
+        }
+        Ok(self.loaded.is_some())
+    }
+
+    /// Takes the oldest event, removing its segment once it has been handed out completely
+    fn next(&mut self) -> std::io::Result<Option<Packet>> {
+        let pkt = self.pending.pop_front();
+        if self.pending.is_empty() {
+            if let Some(segment) = self.loaded.take() {
+                std::fs::remove_file(&segment.path)?;
+            }
+        }
+        Ok(pkt)
+    }
+}
+
+/// Reads the events of a segment file
+fn read_segment(path: &Path) -> std::io::Result<VecDeque<Packet>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(read_recording(file)?
+        .into_iter()
+        .map(|recorded| recorded.packet)
+        .collect())
+}
+
+/// A subscription to the events of a connection that spills to disk instead of losing events
+///
+/// Events are buffered in memory as long as the subscriber keeps up. Once it falls behind,
+/// they are appended to segment files in a spool directory and handed out from there, oldest
+/// first, when the subscriber catches up again, so a temporarily slow consumer like a database
+/// writer does not miss any. The spool is bounded by `max_bytes`; when it is full, the oldest
+/// segment is evicted. Segments left in the directory by an earlier spool, e.g. when the
+/// subscriber has been dropped, are handed out first; events of a segment that was only
+/// partially handed out will be repeated.
+pub struct Spool {
+    events_rx: mpsc::Receiver<Packet>,
+    evicted: Arc<AtomicU64>,
+}
+
+impl Spool {
+    /// Starts spooling the events of `connection` to the directory `dir`
+    pub fn start<P: AsRef<Path>>(
+        connection: &AmiConnection,
+        dir: P,
+        max_bytes: u64,
+    ) -> std::io::Result<Spool> {
+        let backlog = Backlog::open(dir.as_ref(), max_bytes)?;
+        let evicted = backlog.evicted.clone();
+        let (events_tx, events_rx) = mpsc::channel(MEMORY_EVENTS);
+        let events = connection.events();
+        tokio::spawn(async move {
+            if let Err(e) = spool(events, events_tx, backlog).await {
+                warn!("Event spool failed: {:?}", e);
+            }
+        });
+        Ok(Spool { events_rx, evicted })
+    }
+
+    /// Waits for the next event
+    ///
+    /// Returns `None` once the connection has been closed and all spooled events have been
+    /// handed out, or if writing to the spool directory failed.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        self.events_rx.recv().await
+    }
+
+    /// The number of events that have been lost because the spool was full
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+}
+
+async fn spool(
+    mut events: tokio::sync::broadcast::Receiver<Option<Packet>>,
+    events_tx: mpsc::Sender<Packet>,
+    mut backlog: Backlog,
+) -> std::io::Result<()> {
+    let mut open = true;
+    loop {
+        let spooled = backlog.has_next()?;
+        if !open && !spooled {
+            break;
+        }
+        tokio::select! {
+            event = events.recv(), if open => match event {
+                Ok(Some(pkt)) if backlog.is_empty() => {
+                    match events_tx.try_send(pkt) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full(pkt)) => backlog.spill(&pkt)?,
+                        Err(TrySendError::Closed(_)) => break,
+                    }
+                }
+                Ok(Some(pkt)) => backlog.spill(&pkt)?,
+                Ok(None) | Err(RecvError::Closed) => open = false,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Event spool missed {} events", n);
+                    crate::metrics::events_lagged(n);
+                }
+            },
+            permit = events_tx.reserve(), if spooled => match permit {
+                Ok(permit) => {
+                    if let Some(pkt) = backlog.next()? {
+                        permit.send(pkt);
+                    }
+                }
+                Err(_) => break,
+            },
+        }
+    }
+    backlog.rotate()?;
+    trace!("Event spool stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "asterisk-ami-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn evicts_oldest_segments() {
+        let dir = spool_dir("evict");
+        let mut backlog = Backlog::open(&dir, 800).unwrap();
+        for n in 0..100 {
+            let n = n.to_string();
+            backlog
+                .spill(&event(&[("Event", "Test"), ("N", &n)]))
+                .unwrap();
+        }
+        assert!(backlog.evicted.load(Ordering::Relaxed) > 0);
+        assert!(backlog.has_next().unwrap());
+        let oldest = backlog.next().unwrap().unwrap();
+        let first_kept = backlog.evicted.load(Ordering::Relaxed).to_string();
+        assert_eq!(oldest, event(&[("Event", "Test"), ("N", &first_kept)]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_empty_segments_and_counts_left_events() {
+        let dir = spool_dir("reopen");
+        // segments created right before a spool stopped, never flushed
+        let empty = |n: u64| dir.join(format!("{:020}.spool", n));
+        std::fs::create_dir_all(&dir).unwrap();
+        File::create(empty(0)).unwrap();
+        let mut backlog = Backlog::open(&dir, 1 << 20).unwrap();
+        for n in 0..3 {
+            let n = n.to_string();
+            backlog
+                .spill(&event(&[("Event", "Test"), ("N", &n)]))
+                .unwrap();
+        }
+        backlog.rotate().unwrap();
+        File::create(empty(2)).unwrap();
+        drop(backlog);
+
+        let mut backlog = Backlog::open(&dir, 1 << 20).unwrap();
+        let events = backlog.segments.iter().map(|s| s.events);
+        assert_eq!(events.collect::<Vec<_>>(), vec![0, 3, 0]);
+        assert!(backlog.has_next().unwrap());
+        let first = backlog.next().unwrap().unwrap();
+        assert_eq!(first, event(&[("Event", "Test"), ("N", "0")]));
+        backlog.next().unwrap();
+        backlog.next().unwrap();
+        assert!(!backlog.has_next().unwrap());
+        assert!(backlog.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn hands_out_spilled_events_in_order() {
+        let dir = spool_dir("order");
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut spool = Spool::start(&connection, &dir, 1 << 20).unwrap();
+        let count = MEMORY_EVENTS * 3;
+        for n in 0..count {
+            let n = n.to_string();
+            let pkt = event(&[("Event", "Test"), ("N", &n)]);
+            server.send_packet(&pkt).await.unwrap();
+            // give the spool a chance to keep up with the events channel
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        drop(server);
+        for n in 0..count {
+            let pkt = spool.next_event().await.unwrap();
+            assert_eq!(crate::find_tag(&pkt, "N"), Some(&n.to_string()));
+        }
+        assert_eq!(spool.next_event().await, None);
+        assert_eq!(spool.evicted(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
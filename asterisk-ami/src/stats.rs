@@ -0,0 +1,170 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// How many of the most recent latencies of an action are kept to calculate percentiles
+const SAMPLES: usize = 1000;
+
+/// Latency statistics of a single action, see `AmiConnection::stats`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionStats {
+    pub action: String,
+    /// Number of commands sent since the connection has been established
+    pub count: u64,
+    /// Median latency of the most recent commands
+    pub p50: Duration,
+    /// 95th percentile of the latency of the most recent commands
+    pub p95: Duration,
+    /// Highest latency seen since the connection has been established
+    pub max: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    count: u64,
+    max: Duration,
+    recent: VecDeque<Duration>,
+}
+
+/// Collects the latencies of the commands sent over a connection
+#[derive(Debug, Default)]
+pub(crate) struct LatencyStats {
+    actions: HashMap<String, Samples>,
+}
+
+impl LatencyStats {
+    pub(crate) fn record(&mut self, action: &str, latency: Duration) {
+        let samples = match self.actions.get_mut(action) {
+            Some(samples) => samples,
+            None => self.actions.entry(action.to_string()).or_default(),
+        };
+        samples.count += 1;
+        samples.max = samples.max.max(latency);
+        if samples.recent.len() == SAMPLES {
+            samples.recent.pop_front();
+        }
+        samples.recent.push_back(latency);
+    }
+
+    /// Returns the statistics of all actions, sorted by action name
+    pub(crate) fn snapshot(&self) -> Vec<ActionStats> {
+        let mut stats = self
+            .actions
+            .iter()
+            .map(|(action, samples)| {
+                let mut sorted =
+                    samples.recent.iter().copied().collect::<Vec<_>>();
+                sorted.sort_unstable();
+                ActionStats {
+                    action: action.clone(),
+                    count: samples.count,
+                    p50: percentile(&sorted, 50),
+                    p95: percentile(&sorted, 95),
+                    max: samples.max,
+                }
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| a.action.cmp(&b.action));
+        stats
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+/// Cumulative counters of a connection, see `AmiConnection::connection_stats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Packets received, including those being part of a response
+    pub packets_parsed: u64,
+    /// Events passed on to subscribers
+    pub events_published: u64,
+    /// Events at least one subscriber has missed because it could not keep up
+    pub events_dropped: u64,
+    /// Commands a response has been received for, including error responses
+    pub commands_completed: u64,
+    /// Commands that have not been answered because the connection has been lost
+    pub commands_failed: u64,
+    /// Commands dropped without being sent because their deadline had passed while queued
+    pub commands_expired: u64,
+    /// Time since the connection has been established
+    pub uptime: Duration,
+}
+
+/// The counters shared between a connection and the task handling it
+#[derive(Debug)]
+pub(crate) struct Counters {
+    pub(crate) bytes_read: AtomicU64,
+    pub(crate) bytes_written: AtomicU64,
+    pub(crate) packets_parsed: AtomicU64,
+    pub(crate) events_published: AtomicU64,
+    pub(crate) events_dropped: AtomicU64,
+    pub(crate) commands_completed: AtomicU64,
+    pub(crate) commands_failed: AtomicU64,
+    pub(crate) commands_expired: AtomicU64,
+    connected_at: Instant,
+}
+
+impl Counters {
+    pub(crate) fn new() -> Self {
+        Counters {
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            packets_parsed: AtomicU64::new(0),
+            events_published: AtomicU64::new(0),
+            events_dropped: AtomicU64::new(0),
+            commands_completed: AtomicU64::new(0),
+            commands_failed: AtomicU64::new(0),
+            commands_expired: AtomicU64::new(0),
+            connected_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, value: u64) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        ConnectionStats {
+            bytes_read: get(&self.bytes_read),
+            bytes_written: get(&self.bytes_written),
+            packets_parsed: get(&self.packets_parsed),
+            events_published: get(&self.events_published),
+            events_dropped: get(&self.events_dropped),
+            commands_completed: get(&self.commands_completed),
+            commands_failed: get(&self.commands_failed),
+            commands_expired: get(&self.commands_expired),
+            uptime: self.connected_at.elapsed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculates_percentiles() {
+        let mut stats = LatencyStats::default();
+        for ms in 1..=100 {
+            stats.record("Ping", Duration::from_millis(ms));
+        }
+        stats.record("Status", Duration::from_millis(7));
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot[0].action, "Ping");
+        assert_eq!(snapshot[0].count, 100);
+        assert_eq!(snapshot[0].p50, Duration::from_millis(50));
+        assert_eq!(snapshot[0].p95, Duration::from_millis(95));
+        assert_eq!(snapshot[0].max, Duration::from_millis(100));
+        assert_eq!(snapshot[1].p95, Duration::from_millis(7));
+    }
+}
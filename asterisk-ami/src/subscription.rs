@@ -0,0 +1,298 @@
+//! Subscriptions transforming the events on the connection's dispatch path
+//!
+//! Subscribers of `AmiConnection::events` receive a clone of every event, with all its
+//! headers. A subscription created using `AmiConnection::subscribe` instead runs its transform
+//! before the event is queued for it, so only what the subscriber keeps is buffered: a
+//! projection to the few headers a dashboard shows, or a copy with personal data like the
+//! caller's number scrubbed before it reaches code that logs or stores it.
+
+use crate::memory::{packet_size, MemoryBudget, OverflowPolicy, Reservation};
+use crate::stats::Counters;
+use crate::{AmiConnection, Packet, Tag, EVENT_CAPACITY};
+use log::{trace, warn};
+use std::sync::{Mutex, RwLock};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc};
+
+type Transform = Box<dyn FnMut(&Packet) -> Option<Packet> + Send>;
+
+struct Entry {
+    transform: Transform,
+    /// The events with the memory charged for them, released once they have been received
+    events_tx: mpsc::Sender<(Packet, Option<Reservation>)>,
+}
+
+impl Entry {
+    /// Queues the transformed event, returns whether the subscription is still open
+    async fn deliver(
+        &mut self,
+        pkt: &Packet,
+        budget: Option<&MemoryBudget>,
+        counters: &Counters,
+    ) -> bool {
+        if self.events_tx.is_closed() {
+            return false;
+        }
+        let transformed = match (self.transform)(pkt) {
+            Some(transformed) => transformed,
+            None => return true,
+        };
+        let size = packet_size(&transformed);
+        let reservation = match budget {
+            None => None,
+            Some(budget) if budget.policy() == OverflowPolicy::Backpressure => {
+                // only a subscription with events queued can catch up
+                let events_tx = &self.events_tx;
+                let queued = || events_tx.capacity() < events_tx.max_capacity();
+                let reservation = budget.reserve_while(size, queued).await;
+                return self
+                    .events_tx
+                    .send((transformed, Some(reservation)))
+                    .await
+                    .is_ok();
+            }
+            Some(budget) => match budget.try_reserve(size) {
+                Some(reservation) => Some(reservation),
+                None if budget.policy() == OverflowPolicy::Error => {
+                    warn!("Subscription exceeds the memory budget, ending it");
+                    return false;
+                }
+                None => {
+                    warn!("Subscription exceeds the memory budget, dropping event");
+                    Counters::add(&counters.events_dropped, 1);
+                    crate::metrics::events_lagged(1);
+                    return true;
+                }
+            },
+        };
+        match self.events_tx.try_send((transformed, reservation)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Subscription is full, dropping event");
+                Counters::add(&counters.events_dropped, 1);
+                crate::metrics::events_lagged(1);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// The subscribers of the events of a connection, shared with the task handling it
+pub(crate) struct Subscribers {
+    events_tx: broadcast::Sender<Option<Packet>>,
+    /// The transforming subscriptions, `None` once the connection has been closed
+    entries: Mutex<Option<Vec<Entry>>>,
+    /// Charged for the events queued for subscriptions, and by the connection for its
+    /// commands and responses
+    memory: RwLock<Option<MemoryBudget>>,
+}
+
+impl Subscribers {
+    pub(crate) fn new(events_tx: broadcast::Sender<Option<Packet>>) -> Self {
+        Subscribers {
+            events_tx,
+            entries: Mutex::new(Some(vec![])),
+            memory: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn memory_budget(&self) -> Option<MemoryBudget> {
+        self.memory.read().unwrap().clone()
+    }
+
+    pub(crate) fn set_memory_budget(&self, budget: Option<MemoryBudget>) {
+        *self.memory.write().unwrap() = budget;
+    }
+
+    /// Hands out an event to all subscribers, `None` once the connection has been closed
+    ///
+    /// Returns `false` if the event could not be broadcast. Waits for subscriptions to catch
+    /// up if their memory budget asks for backpressure.
+    pub(crate) async fn publish(
+        &self,
+        pkt: Option<Packet>,
+        counters: &Counters,
+    ) -> bool {
+        match &pkt {
+            Some(pkt) => self.dispatch(pkt, counters).await,
+            None => self.close(),
+        }
+        if self.events_tx.receiver_count() > 0 {
+            // a full channel drops its oldest event for the slowest subscriber
+            if self.events_tx.len() >= EVENT_CAPACITY {
+                Counters::add(&counters.events_dropped, 1);
+            }
+            if pkt.is_some() {
+                Counters::add(&counters.events_published, 1);
+            }
+            if let Err(e) = self.events_tx.send(pkt) {
+                warn!("Could not send event to subscribers: {:?}", e);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Passes an event through the transform of every subscription
+    async fn dispatch(&self, pkt: &Packet, counters: &Counters) {
+        let entries = match self.entries.lock().unwrap().as_mut() {
+            Some(entries) if !entries.is_empty() => std::mem::take(entries),
+            _ => return,
+        };
+        let budget = self.memory_budget();
+        let mut open = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            if entry.deliver(pkt, budget.as_ref(), counters).await {
+                open.push(entry);
+            }
+        }
+        // subscriptions may have been added while delivering
+        if let Some(entries) = self.entries.lock().unwrap().as_mut() {
+            open.append(entries);
+            *entries = open;
+        }
+    }
+
+    /// Ends all subscriptions, the connection has been closed
+    fn close(&self) {
+        if let Some(entries) = self.entries.lock().unwrap().take() {
+            trace!("Closing {} subscriptions", entries.len());
+        }
+    }
+}
+
+/// The events of a connection as produced by the transform of a subscription
+pub struct Subscription {
+    events_rx: mpsc::Receiver<(Packet, Option<Reservation>)>,
+}
+
+impl Subscription {
+    /// Waits for the next transformed event
+    ///
+    /// Returns `None` once the connection has been closed.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        self.events_rx.recv().await.map(|(pkt, _)| pkt)
+    }
+}
+
+impl AmiConnection {
+    /// Subscribes to the events as returned by `transform`
+    ///
+    /// `transform` is called for every event on the connection's hot path, like an
+    /// `Interceptor`, so it should not block. Events it returns `None` for are skipped. If the
+    /// subscriber falls behind, new events are dropped for it and counted as
+    /// `events_dropped`, unless the memory budget of the connection asks for backpressure,
+    /// see `set_memory_budget`. Helpers like `project` and `scrub` build common transforms.
+    pub fn subscribe<F>(&self, transform: F) -> Subscription
+    where
+        F: FnMut(&Packet) -> Option<Packet> + Send + 'static,
+    {
+        let (events_tx, events_rx) = mpsc::channel(EVENT_CAPACITY);
+        if let Some(entries) = self.subscribers.entries.lock().unwrap().as_mut()
+        {
+            entries.push(Entry {
+                transform: Box::new(transform),
+                events_tx,
+            });
+        }
+        Subscription { events_rx }
+    }
+}
+
+/// A transform keeping only the headers with the given keys, e.g. `["Event", "Channel"]`
+pub fn project(
+    keys: &[&str],
+) -> impl FnMut(&Packet) -> Option<Packet> + Send + 'static {
+    let keys = keys
+        .iter()
+        .map(|k| k.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    move |pkt| {
+        Some(
+            pkt.iter()
+                .filter(|tag| keys.contains(&tag.key.to_ascii_lowercase()))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// A transform replacing the values of the headers with the given keys by `********`, e.g.
+/// `["CallerIDNum", "CallerIDName", "ConnectedLineNum", "ConnectedLineName"]`
+pub fn scrub(
+    keys: &[&str],
+) -> impl FnMut(&Packet) -> Option<Packet> + Send + 'static {
+    let keys = keys
+        .iter()
+        .map(|k| k.to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    move |pkt| {
+        Some(
+            pkt.iter()
+                .map(|tag| {
+                    if keys.contains(&tag.key.to_ascii_lowercase()) {
+                        Tag::from(&tag.key, crate::redaction::REDACTED)
+                    } else {
+                        tag.clone()
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn projects_and_scrubs_headers() {
+        let newchannel = event(&[
+            ("Event", "Newchannel"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("CallerIDNum", "+4917012345678"),
+            ("Uniqueid", "1.1"),
+        ]);
+        assert_eq!(
+            project(&["event", "Channel"])(&newchannel),
+            Some(event(&[
+                ("Event", "Newchannel"),
+                ("Channel", "PJSIP/100-00000001"),
+            ]))
+        );
+        let scrubbed = scrub(&["CallerIDNum"])(&newchannel).unwrap();
+        assert_eq!(
+            crate::find_tag(&scrubbed, "CallerIDNum").unwrap(),
+            "********"
+        );
+        assert_eq!(scrubbed.len(), newchannel.len());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn transforms_events_for_subscriber() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut hangups = connection.subscribe(|pkt| {
+            crate::event_name(pkt)
+                .filter(|e| *e == "Hangup")
+                .and_then(|_| project(&["Channel"])(pkt))
+        });
+        server
+            .send_packet(&event(&[("Event", "Newchannel"), ("Channel", "a")]))
+            .await
+            .unwrap();
+        server
+            .send_packet(&event(&[("Event", "Hangup"), ("Channel", "b")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            hangups.next_event().await,
+            Some(event(&[("Channel", "b")]))
+        );
+        drop(server);
+        assert_eq!(hangups.next_event().await, None);
+    }
+}
@@ -0,0 +1,278 @@
+//! Forwarding events to syslog or systemd-journald
+//!
+//! The events are sent to the local socket of the logging daemon, so they end up in the same
+//! log aggregation as the other logs of the machine. The headers of an event are passed as
+//! structured fields: as RFC 5424 structured data with the SD-ID `ami@32473` for syslog, and
+//! as fields prefixed with `AMI_`, like `AMI_CHANNEL`, for journald.
+
+use crate::{event_name, find_tag, AmiConnection, Packet};
+use log::warn;
+use std::path::{Path, PathBuf};
+use tokio::net::UnixDatagram;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// The socket of the local syslog daemon
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+/// The socket of systemd-journald for the native protocol
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// The program name the events are logged with
+const IDENTIFIER: &str = "asterisk-ami";
+/// The syslog facility `local0`
+const FACILITY: u8 = 16;
+
+/// The protocol spoken with the logging daemon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// RFC 5424 messages with structured data
+    Syslog,
+    /// The native protocol of systemd-journald
+    Journald,
+}
+
+/// The syslog severity of an event: `warning` for security events, `info` for all others
+fn severity(pkt: &Packet) -> u8 {
+    let security = find_tag(pkt, "Privilege").is_some_and(|p| {
+        p.split(',')
+            .any(|c| c.trim().eq_ignore_ascii_case("security"))
+    });
+    if security {
+        4
+    } else {
+        6
+    }
+}
+
+/// The human readable message: the event name followed by the channel, if there is one
+fn message(pkt: &Packet) -> String {
+    let event = event_name(pkt).map(String::as_str).unwrap_or("Packet");
+    match find_tag(pkt, "Channel") {
+        Some(channel) => format!("{} {}", event, channel),
+        None => event.to_string(),
+    }
+}
+
+/// Encodes an event as RFC 5424 message, leaving the timestamp and hostname to the daemon
+fn encode_syslog(pkt: &Packet) -> Vec<u8> {
+    let mut data = String::from("[ami@32473");
+    for tag in pkt {
+        // SD-NAMEs are limited to 32 printable characters other than '=', ' ', ']', and '"'
+        let name = tag
+            .key
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !"=]\"".contains(*c))
+            .take(32)
+            .collect::<String>();
+        if name.is_empty() {
+            continue;
+        }
+        let mut value = String::with_capacity(tag.value.len());
+        for c in tag.value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                value.push('\\');
+            }
+            value.push(c);
+        }
+        data.push_str(&format!(" {}=\"{}\"", name, value));
+    }
+    data.push(']');
+    format!(
+        "<{}>1 - - {} {} - {} {}",
+        FACILITY * 8 + severity(pkt),
+        IDENTIFIER,
+        std::process::id(),
+        data,
+        message(pkt)
+    )
+    .into_bytes()
+}
+
+/// Appends a field in the native journald format, length-prefixed if it spans lines
+fn journal_field(out: &mut Vec<u8>, name: &str, value: &str) {
+    out.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        out.push(b'\n');
+        out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        out.push(b'=');
+    }
+    out.extend_from_slice(value.as_bytes());
+    out.push(b'\n');
+}
+
+/// Encodes an event for journald, every header becomes a field like `AMI_UNIQUEID`
+fn encode_journald(pkt: &Packet) -> Vec<u8> {
+    let mut out = vec![];
+    journal_field(&mut out, "MESSAGE", &message(pkt));
+    journal_field(&mut out, "PRIORITY", &severity(pkt).to_string());
+    journal_field(&mut out, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    for tag in pkt {
+        // field names consist of upper case letters, digits, and underscores
+        let name = tag
+            .key
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                _ => '_',
+            })
+            .collect::<String>();
+        journal_field(&mut out, &format!("AMI_{}", name), &tag.value);
+    }
+    out
+}
+
+/// Forwards the events of a connection to syslog or journald
+pub struct LogSink {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl LogSink {
+    /// Starts forwarding the events `select` returns `true` for to the local syslog daemon
+    pub fn syslog<F>(
+        connection: &AmiConnection,
+        select: F,
+    ) -> std::io::Result<LogSink>
+    where
+        F: Fn(&Packet) -> bool + Send + 'static,
+    {
+        Self::start(connection, Format::Syslog, SYSLOG_SOCKET, select)
+    }
+
+    /// Starts forwarding the events `select` returns `true` for to systemd-journald
+    pub fn journald<F>(
+        connection: &AmiConnection,
+        select: F,
+    ) -> std::io::Result<LogSink>
+    where
+        F: Fn(&Packet) -> bool + Send + 'static,
+    {
+        Self::start(connection, Format::Journald, JOURNALD_SOCKET, select)
+    }
+
+    /// Starts forwarding the selected events to the datagram socket at `socket`
+    ///
+    /// Events the daemon does not accept, e.g. because it is restarting, are dropped with a
+    /// warning; only failing to connect to the socket is reported as an error.
+    pub fn start<P, F>(
+        connection: &AmiConnection,
+        format: Format,
+        socket: P,
+        select: F,
+    ) -> std::io::Result<LogSink>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Packet) -> bool + Send + 'static,
+    {
+        let path: PathBuf = socket.as_ref().to_path_buf();
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&path)?;
+        let mut events = connection.events();
+        let (stop_tx, mut stop_rx) = oneshot::channel();
+        let task = tokio::spawn(async move {
+            loop {
+                let pkt = tokio::select! {
+                    _ = &mut stop_rx => break,
+                    event = events.recv() => match event {
+                        Ok(Some(pkt)) => pkt,
+                        Ok(None) | Err(RecvError::Closed) => break,
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("Log sink missed {} events", n);
+                            crate::metrics::events_lagged(n);
+                            continue;
+                        }
+                    },
+                };
+                if !select(&pkt) {
+                    continue;
+                }
+                let datagram = match format {
+                    Format::Syslog => encode_syslog(&pkt),
+                    Format::Journald => encode_journald(&pkt),
+                };
+                if let Err(e) = socket.send(&datagram).await {
+                    warn!("Cannot log event to {}: {}", path.display(), e);
+                }
+            }
+        });
+        Ok(LogSink { stop_tx, task })
+    }
+
+    /// Stops forwarding events
+    pub async fn stop(self) {
+        let _ = self.stop_tx.send(());
+        let _ = self.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from as event;
+
+    #[test]
+    fn encodes_structured_data() {
+        let pkt = event(&[
+            ("Event", "InvalidPassword"),
+            ("Privilege", "security,all"),
+            ("AccountID", "100"),
+            ("Note", "wrong \"secret\" [sic]"),
+        ]);
+        let message = String::from_utf8(encode_syslog(&pkt)).unwrap();
+        let expected = format!(
+            "<132>1 - - asterisk-ami {} - [ami@32473 Event=\"InvalidPassword\" \
+             Privilege=\"security,all\" AccountID=\"100\" \
+             Note=\"wrong \\\"secret\\\" [sic\\]\"] InvalidPassword",
+            std::process::id()
+        );
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn encodes_journal_fields() {
+        let pkt = event(&[
+            ("Event", "Hangup"),
+            ("Channel", "PJSIP/100-00000001"),
+            ("Cause-txt", "Normal\nClearing"),
+        ]);
+        let mut expected = b"MESSAGE=Hangup PJSIP/100-00000001\n\
+            PRIORITY=6\n\
+            SYSLOG_IDENTIFIER=asterisk-ami\n\
+            AMI_EVENT=Hangup\n\
+            AMI_CHANNEL=PJSIP/100-00000001\n\
+            AMI_CAUSE_TXT\n"
+            .to_vec();
+        expected.extend_from_slice(&15u64.to_le_bytes());
+        expected.extend_from_slice(b"Normal\nClearing\n");
+        assert_eq!(encode_journald(&pkt), expected);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn forwards_selected_events() {
+        let path = std::env::temp_dir()
+            .join(format!("asterisk-ami-log-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let daemon = UnixDatagram::bind(&path).unwrap();
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let sink =
+            LogSink::start(&connection, Format::Journald, &path, |pkt| {
+                event_name(pkt).is_some_and(|e| e == "Hangup")
+            })
+            .unwrap();
+
+        let newchannel = event(&[("Event", "Newchannel")]);
+        server.send_packet(&newchannel).await.unwrap();
+        let hangup = event(&[("Event", "Hangup")]);
+        server.send_packet(&hangup).await.unwrap();
+        let mut datagram = [0; 1024];
+        let len = daemon.recv(&mut datagram).await.unwrap();
+        assert!(datagram[..len].starts_with(b"MESSAGE=Hangup\n"));
+
+        sink.stop().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{
+    find_flag, find_tag, find_value, is_from_system, AmiConnection, Error,
+    Packet, Tag,
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// The status of the Asterisk core as returned by `CoreStatus`
+///
+/// Dates and times are given in the server's local time zone, as sent by Asterisk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreStatus {
+    pub startup_date: Option<String>,
+    pub startup_time: Option<String>,
+    pub reload_date: Option<String>,
+    pub reload_time: Option<String>,
+    pub current_calls: u32,
+}
+
+impl CoreStatus {
+    fn from_packet(pkt: &Packet) -> Self {
+        CoreStatus {
+            startup_date: find_value(pkt, "CoreStartupDate"),
+            startup_time: find_value(pkt, "CoreStartupTime"),
+            reload_date: find_value(pkt, "CoreReloadDate"),
+            reload_time: find_value(pkt, "CoreReloadTime"),
+            current_calls: find_tag(pkt, "CoreCurrentCalls")
+                .and_then(|c| c.trim().parse().ok())
+                .unwrap_or(0),
+        }
+    }
+}
+
+/// The settings of the Asterisk core as returned by `CoreSettings`
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreSettings {
+    pub ami_version: Option<String>,
+    pub asterisk_version: Option<String>,
+    pub system_name: Option<String>,
+    /// Maximum number of simultaneous calls, `0` if unlimited
+    pub max_calls: u32,
+    /// Maximum load average before new calls are rejected, `0.0` if unlimited
+    pub max_load_avg: f64,
+    pub run_user: Option<String>,
+    pub run_group: Option<String>,
+    pub max_file_handles: Option<u64>,
+    pub realtime_enabled: bool,
+    pub cdr_enabled: bool,
+    pub http_enabled: bool,
+}
+
+impl CoreSettings {
+    fn from_packet(pkt: &Packet) -> Self {
+        CoreSettings {
+            ami_version: find_value(pkt, "AMIversion"),
+            asterisk_version: find_value(pkt, "AsteriskVersion"),
+            system_name: find_value(pkt, "SystemName"),
+            max_calls: find_tag(pkt, "CoreMaxCalls")
+                .and_then(|c| c.trim().parse().ok())
+                .unwrap_or(0),
+            max_load_avg: find_tag(pkt, "CoreMaxLoadAvg")
+                .and_then(|l| l.trim().parse().ok())
+                .unwrap_or(0.0),
+            run_user: find_value(pkt, "CoreRunUser"),
+            run_group: find_value(pkt, "CoreRunGroup"),
+            max_file_handles: find_tag(pkt, "CoreMaxFilehandles")
+                .and_then(|h| h.trim().parse().ok()),
+            realtime_enabled: find_flag(pkt, "CoreRealTimeEnabled"),
+            cdr_enabled: find_flag(pkt, "CoreCDRenabled"),
+            http_enabled: find_flag(pkt, "CoreHTTPenabled"),
+        }
+    }
+}
+
+/// The operation performed by `module_load`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleLoadType {
+    Load,
+    Unload,
+    Reload,
+}
+
+impl ModuleLoadType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ModuleLoadType::Load => "load",
+            ModuleLoadType::Unload => "unload",
+            ModuleLoadType::Reload => "reload",
+        }
+    }
+}
+
+/// Whether a module is loaded, as returned by `ModuleCheck`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleStatus {
+    /// The module is loaded, with the version reported by older Asterisk versions
+    Loaded {
+        version: Option<String>,
+    },
+    NotLoaded,
+}
+
+/// The events of a connection sent by a single system, see `AmiConnection::events_from_system`
+pub struct SystemEvents {
+    events: broadcast::Receiver<Option<Packet>>,
+    name: String,
+}
+
+impl SystemEvents {
+    /// Waits for the next event with the `SystemName` of the subscription
+    ///
+    /// Returns `None` once the connection has been closed. Events missed because the
+    /// subscriber fell behind are skipped.
+    pub async fn next_event(&mut self) -> Option<Packet> {
+        loop {
+            match self.events.recv().await {
+                Ok(Some(pkt)) if is_from_system(&pkt, &self.name) => {
+                    return Some(pkt)
+                }
+                Ok(Some(_)) => {}
+                Err(RecvError::Lagged(n)) => crate::metrics::events_lagged(n),
+                Ok(None) | Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl AmiConnection {
+    /// Subscribes to the events with the `SystemName` `name`
+    ///
+    /// This selects the events of a single node when several servers of a cluster share a
+    /// connection, e.g. through an AMI proxy. Events without a `SystemName` are skipped.
+    pub fn events_from_system(&self, name: &str) -> SystemEvents {
+        SystemEvents {
+            events: self.events(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Queries the status of the Asterisk core
+    pub async fn core_status(&self) -> Result<CoreStatus, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "CoreStatus")])
+            .await?;
+        resp.first()
+            .map(CoreStatus::from_packet)
+            .ok_or(Error::UnexpectedResponse)
+    }
+
+    /// Queries the settings of the Asterisk core
+    pub async fn core_settings(&self) -> Result<CoreSettings, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "CoreSettings")])
+            .await?;
+        resp.first()
+            .map(CoreSettings::from_packet)
+            .ok_or(Error::UnexpectedResponse)
+    }
+
+    /// Checks if a module (e.g. `res_pjsip.so`) is loaded
+    pub async fn module_check(
+        &self,
+        module: &str,
+    ) -> Result<ModuleStatus, Error> {
+        match self
+            .send_action(vec![
+                Tag::from("Action", "ModuleCheck"),
+                Tag::from("Module", module),
+            ])
+            .await
+        {
+            Ok(resp) => Ok(ModuleStatus::Loaded {
+                version: resp.first().and_then(|p| find_value(p, "Version")),
+            }),
+            Err(Error::ActionFailed(message))
+                if message.to_ascii_lowercase().contains("not loaded") =>
+            {
+                Ok(ModuleStatus::NotLoaded)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Loads, unloads, or reloads a module
+    ///
+    /// For `ModuleLoadType::Reload` the `module` may be `None` to reload all modules, or a
+    /// subsystem like `cdr`, `dnsmgr`, `extconfig`, `enum`, `acl`, `manager`, `http`,
+    /// `logger`, `features`, `dsp`, `udptl`, `indications`, `cel`, or `plc`.
+    pub async fn module_load(
+        &self,
+        module: Option<&str>,
+        load_type: ModuleLoadType,
+    ) -> Result<(), Error> {
+        let mut pkt = vec![
+            Tag::from("Action", "ModuleLoad"),
+            Tag::from("LoadType", load_type.as_str()),
+        ];
+        if let Some(module) = module {
+            pkt.push(Tag::from("Module", module));
+        }
+        self.send_action(pkt).await?;
+        Ok(())
+    }
+
+    /// Reloads a module, e.g. `res_pjsip.so` or `pbx_config.so`, or all modules if `None`
+    pub async fn reload(&self, module: Option<&str>) -> Result<(), Error> {
+        let mut pkt = vec![Tag::from("Action", "Reload")];
+        if let Some(module) = module {
+            pkt.push(Tag::from("Module", module));
+        }
+        self.send_action(pkt).await?;
+        Ok(())
+    }
+
+    /// Queries the time since the server started and since its last reload
+    ///
+    /// This uses the CLI command `core show uptime seconds` and thus requires the `command`
+    /// privilege.
+    pub async fn core_uptime(
+        &self,
+    ) -> Result<(Duration, Option<Duration>), Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "Command"),
+                Tag::from("Command", "core show uptime seconds"),
+            ])
+            .await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        let uptime = find_seconds(first, "System uptime")
+            .ok_or(Error::UnexpectedResponse)?;
+        Ok((uptime, find_seconds(first, "Last reload")))
+    }
+}
+
+/// Finds a `<name>: <seconds>` line of a command's output
+///
+/// Before Asterisk 14 the output lines are sent as plain lines, later versions prefix each of
+/// them with `Output:`.
+fn find_seconds(pkt: &Packet, name: &str) -> Option<Duration> {
+    let value = find_tag(pkt, name).cloned().or_else(|| {
+        pkt.iter()
+            .filter(|t| t.key.eq_ignore_ascii_case("Output"))
+            .find_map(|t| {
+                let (key, value) = t.value.split_once(':')?;
+                Some(value.trim().to_string())
+                    .filter(|_| key.trim().eq_ignore_ascii_case(name))
+            })
+    })?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn finds_uptime_in_command_output() {
+        let pkt = packet_from(&[
+            ("Response", "Success"),
+            ("Output", "System uptime: 3600"),
+            ("Output", "Last reload: 60"),
+        ]);
+        assert_eq!(
+            find_seconds(&pkt, "System uptime"),
+            Some(Duration::from_secs(3600))
+        );
+        let legacy =
+            packet_from(&[("Response", "Follows"), ("System uptime", "120")]);
+        assert_eq!(
+            find_seconds(&legacy, "System uptime"),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn selects_events_by_system_name() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let mut events = connection.events_from_system("pbx-b");
+        for name in ["pbx-a", "", "pbx-b"].iter() {
+            let pkt =
+                packet_from(&[("Event", "FullyBooted"), ("SystemName", name)]);
+            server.send_packet(&pkt).await.unwrap();
+        }
+        let pkt = events.next_event().await.unwrap();
+        assert_eq!(crate::system_name(&pkt).map(String::as_str), Some("pbx-b"));
+        drop(server);
+        assert_eq!(events.next_event().await, None);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn checks_modules() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let responses = [
+            &[("Response", "Success"), ("Version", "1.2.3")][..],
+            &[("Response", "Error"), ("Message", "Module not loaded")],
+            &[("Response", "Error"), ("Message", "Permission denied")],
+        ];
+        let (statuses, _) = tokio::join!(
+            async {
+                vec![
+                    connection.module_check("res_pjsip.so").await,
+                    connection.module_check("chan_sip.so").await,
+                    connection.module_check("app_queue.so").await,
+                ]
+            },
+            async {
+                for response in responses {
+                    let action = server.read_packet().await.unwrap();
+                    let mut pkt = packet_from(response);
+                    let action_id = find_tag(&action, "ActionID").unwrap();
+                    pkt.push(Tag::from("ActionID", action_id));
+                    server.send_packet(&pkt).await.unwrap();
+                }
+            }
+        );
+        let mut statuses = statuses.into_iter();
+        assert_eq!(
+            statuses.next().unwrap().unwrap(),
+            ModuleStatus::Loaded {
+                version: Some("1.2.3".to_string())
+            }
+        );
+        assert_eq!(statuses.next().unwrap().unwrap(), ModuleStatus::NotLoaded);
+        assert!(matches!(
+            statuses.next().unwrap(),
+            Err(Error::ActionFailed(m)) if m == "Permission denied"
+        ));
+    }
+}
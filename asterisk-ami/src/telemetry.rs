@@ -0,0 +1,135 @@
+//! Optional instrumentation of the connection using `tracing` and OpenTelemetry
+//!
+//! Without the `tracing` and `opentelemetry` features all functions are no-ops, so callers do not need to care
+//! whether the feature is enabled.
+
+use crate::{find_tag, Error, Packet};
+use std::future::Future;
+
+#[cfg(feature = "tracing")]
+pub(crate) type Span = tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+/// Creates the span the task handling a server connection runs in
+pub(crate) fn connection_span(server: &str) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::info_span!("ami_connection", server);
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = server;
+        Span
+    }
+}
+
+/// Runs `fut` within `span`
+pub(crate) async fn instrument<F: Future>(span: Span, fut: F) -> F::Output {
+    #[cfg(feature = "tracing")]
+    return tracing::Instrument::instrument(fut, span).await;
+    #[cfg(not(feature = "tracing"))]
+    {
+        let _ = span;
+        fut.await
+    }
+}
+
+/// The spans a single command is traced in
+pub(crate) struct CommandSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "opentelemetry")]
+    otel: opentelemetry::global::BoxedSpan,
+}
+
+/// Creates the span of a command, carrying its action and ActionID
+pub(crate) fn command_span(pkt: &Packet) -> CommandSpan {
+    let action = find_tag(pkt, "Action").map(String::as_str);
+    let action_id = find_tag(pkt, "ActionID").map(String::as_str);
+    #[cfg(not(any(feature = "tracing", feature = "opentelemetry")))]
+    let _ = (action, action_id);
+    CommandSpan {
+        #[cfg(feature = "tracing")]
+        span: tracing::info_span!(
+            "ami_command",
+            action,
+            action_id,
+            latency_ms = tracing::field::Empty,
+        ),
+        #[cfg(feature = "opentelemetry")]
+        otel: {
+            use opentelemetry::trace::{SpanKind, Tracer};
+            use opentelemetry::KeyValue;
+
+            let mut attributes = vec![];
+            if let Some(action) = action {
+                attributes
+                    .push(KeyValue::new("ami.action", action.to_string()));
+            }
+            if let Some(action_id) = action_id {
+                attributes.push(KeyValue::new(
+                    "ami.action_id",
+                    action_id.to_string(),
+                ));
+            }
+            let tracer = opentelemetry::global::tracer("asterisk-ami");
+            tracer
+                .span_builder(format!("AMI {}", action.unwrap_or("command")))
+                .with_kind(SpanKind::Client)
+                .with_attributes(attributes)
+                .start(&tracer)
+        },
+    }
+}
+
+/// Runs a command within its span and records the latency once `fut` has completed
+pub(crate) async fn command<F, T>(span: CommandSpan, fut: F) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    #[cfg(feature = "tracing")]
+    let started = std::time::Instant::now();
+    #[cfg(feature = "tracing")]
+    let result = instrument(span.span.clone(), fut).await;
+    #[cfg(not(feature = "tracing"))]
+    let result = fut.await;
+
+    #[cfg(feature = "tracing")]
+    {
+        let latency = started.elapsed().as_millis() as u64;
+        span.span.record("latency_ms", latency);
+        if let Err(e) = &result {
+            span.span
+                .in_scope(|| tracing::warn!("No response to command: {}", e));
+        }
+    }
+    #[cfg(feature = "opentelemetry")]
+    {
+        use opentelemetry::trace::{Span as _, Status};
+
+        let mut otel = span.otel;
+        if let Err(e) = &result {
+            otel.set_status(Status::error(e.to_string()));
+        }
+        otel.end();
+    }
+    #[cfg(not(any(feature = "tracing", feature = "opentelemetry")))]
+    let _ = span;
+    result
+}
+
+/// Reports the loss of the server connection
+pub(crate) fn disconnected(reason: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(reason, "AMI connection lost");
+    #[cfg(not(feature = "tracing"))]
+    let _ = reason;
+}
+
+/// Reports a line received from the server that could not be parsed
+pub(crate) fn malformed_line(line: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(line, "Ignoring malformed AMI line");
+    #[cfg(not(feature = "tracing"))]
+    let _ = line;
+}
@@ -0,0 +1,693 @@
+//! Helpers for testing code using this crate without a real Asterisk server
+//!
+//! Enabled by the `testing` feature, which is meant to be used from `dev-dependencies`.
+
+use crate::{find_tag, packet_to_string, AmiConnection, Error, Packet, Tag};
+use log::{trace, warn};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+/// Produces the response packets to an action
+pub type ActionHandler = Box<dyn Fn(&Packet) -> Vec<Packet> + Send + Sync>;
+
+struct MockState {
+    credentials: Option<(String, String)>,
+    handlers: HashMap<String, ActionHandler>,
+    scenarios: HashMap<String, Vec<Step>>,
+    received: Vec<Packet>,
+    events_tx: broadcast::Sender<Packet>,
+    faults: VecDeque<Fault>,
+}
+
+/// A failure injected into the data sent to the client
+///
+/// Faults are queued using `MockAmiServer::inject` or `ServerSide::inject` and each of them
+/// applies to the next packet written, be it a response or an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Closes the connection after writing the given number of bytes of the packet
+    Disconnect { after_bytes: usize },
+    /// Waits before writing the packet, leaving the client's reads stalled
+    Stall(Duration),
+    /// Sends every line of the packet twice
+    DuplicateLines,
+    /// Writes the given bytes before the packet
+    Garbage(Vec<u8>),
+}
+
+/// Writes a packet, distorted by `fault`
+///
+/// Fails with `ConnectionAborted` after a `Fault::Disconnect`, the caller is expected to close
+/// the connection.
+async fn write_packet<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    pkt: &Packet,
+    fault: Option<Fault>,
+) -> std::io::Result<()> {
+    let wire = to_wire(pkt);
+    match fault {
+        None => writer.write_all(wire.as_bytes()).await,
+        Some(Fault::Disconnect { after_bytes }) => {
+            let bytes = wire.as_bytes();
+            writer
+                .write_all(&bytes[..after_bytes.min(bytes.len())])
+                .await?;
+            writer.shutdown().await?;
+            Err(std::io::ErrorKind::ConnectionAborted.into())
+        }
+        Some(Fault::Stall(duration)) => {
+            tokio::time::sleep(duration).await;
+            writer.write_all(wire.as_bytes()).await
+        }
+        Some(Fault::DuplicateLines) => {
+            let doubled = wire
+                .split_inclusive("\r\n")
+                .filter(|line| *line != "\r\n")
+                .map(|line| line.repeat(2))
+                .collect::<String>();
+            writer
+                .write_all(format!("{}\r\n", doubled).as_bytes())
+                .await
+        }
+        Some(Fault::Garbage(bytes)) => {
+            writer.write_all(&bytes).await?;
+            writer.write_all(wire.as_bytes()).await
+        }
+    }
+}
+
+/// A single step of a scenario
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Respond(Packet),
+    Emit { delay: Duration, event: Packet },
+}
+
+/// Declarative definitions of how the mock server reacts to actions
+///
+/// A scenario consists of blocks starting with `on <Action>:`. The indented steps of a block
+/// are `respond:`, adding a packet to the response, and `emit:`, sending an event. Steps may
+/// be delayed using `after <duration>` (e.g. `200ms`, `2s`), delays add up within a block.
+/// The tags of a packet follow on lines indented further. `${Header}` is replaced by the value
+/// of a header of the action, e.g. `${ActionID}` or `${Channel}`. Lines starting with `#` are
+/// comments.
+///
+/// ```text
+/// on Originate:
+///   respond:
+///     Response: Success
+///     Message: Originate successfully queued
+///   after 200ms emit:
+///     Event: OriginateResponse
+///     ActionID: ${ActionID}
+///     Response: Success
+///     Channel: ${Channel}-00000001
+///   after 2s emit:
+///     Event: Hangup
+///     Channel: ${Channel}-00000001
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scenario {
+    actions: Vec<(String, Vec<Step>)>,
+}
+
+impl Scenario {
+    /// Parses a scenario definition
+    pub fn parse(definition: &str) -> Result<Scenario, Error> {
+        let invalid = |n: usize, msg: &str| {
+            Error::InvalidArgument(format!("line {}: {}", n + 1, msg))
+        };
+        let mut scenario = Scenario::default();
+        let mut delay = Duration::ZERO;
+        for (n, line) in definition.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indent = line.len() - line.trim_start().len();
+            if indent == 0 {
+                let action = trimmed
+                    .strip_prefix("on ")
+                    .and_then(|a| a.strip_suffix(':'))
+                    .ok_or_else(|| invalid(n, "expected `on <Action>:`"))?;
+                scenario.actions.push((action.trim().to_string(), vec![]));
+                delay = Duration::ZERO;
+                continue;
+            }
+            let steps = match scenario.actions.last_mut() {
+                Some((_, steps)) => steps,
+                None => {
+                    return Err(invalid(n, "step outside of an `on` block"))
+                }
+            };
+            let step = trimmed.strip_suffix(':').map(str::trim).filter(|s| {
+                ["respond", "emit"].contains(s) || s.starts_with("after ")
+            });
+            let step = match step {
+                Some(step) => step,
+                None => {
+                    // a tag of the packet of the last step
+                    let (key, value) = trimmed
+                        .split_once(':')
+                        .ok_or_else(|| invalid(n, "expected `Key: value`"))?;
+                    let tag = Tag::from(key.trim(), value.trim());
+                    match steps.last_mut() {
+                        Some(Step::Respond(pkt)) => pkt.push(tag),
+                        Some(Step::Emit { event, .. }) => event.push(tag),
+                        None => {
+                            return Err(invalid(n, "tag outside of a step"))
+                        }
+                    }
+                    continue;
+                }
+            };
+            let step = match step.strip_prefix("after ") {
+                Some(rest) => {
+                    let (duration, step) =
+                        rest.trim().split_once(' ').ok_or_else(|| {
+                            invalid(n, "expected `after <duration> <step>:`")
+                        })?;
+                    delay += parse_duration(duration)
+                        .ok_or_else(|| invalid(n, "invalid duration"))?;
+                    step.trim()
+                }
+                None => step,
+            };
+            steps.push(match step {
+                "respond" => Step::Respond(vec![]),
+                "emit" => Step::Emit {
+                    delay,
+                    event: vec![],
+                },
+                _ => return Err(invalid(n, "expected `respond` or `emit`")),
+            });
+        }
+        Ok(scenario)
+    }
+}
+
+/// Parses durations like `200ms`, `2s`, `1.5s`, or `1m`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let (number, unit) = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .map(|pos| value.split_at(pos))?;
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Replaces `${Header}` by the value of the header of `action`
+fn substitute(pkt: &Packet, action: &Packet) -> Packet {
+    pkt.iter()
+        .map(|tag| {
+            let mut value = tag.value.clone();
+            // inserted values are never expanded again, they may contain `${...}` themselves
+            let mut cursor = 0;
+            while let Some(start) = value[cursor..].find("${") {
+                let start = cursor + start;
+                let end = match value[start..].find('}') {
+                    Some(end) => start + end,
+                    None => break,
+                };
+                let header = &value[start + 2..end];
+                let replacement =
+                    find_tag(action, header).cloned().unwrap_or_default();
+                value.replace_range(start..=end, &replacement);
+                cursor = start + replacement.len();
+            }
+            Tag::of(tag.key.clone(), value)
+        })
+        .collect()
+}
+
+/// An AMI server listening on a local port that answers with canned responses
+///
+/// The server sends a greeting, accepts `Login` (checking the credentials if set using
+/// `credentials`), answers `Ping`, and answers all other actions with the responses
+/// programmed using `respond` or `on_action`. Unknown actions are answered with an error like
+/// Asterisk does. The `ActionID` of an action is copied into every packet of its response.
+///
+/// ```ignore
+/// let server = MockAmiServer::start().await?;
+/// server.respond("CoreStatus", vec![vec![Tag::from("Response", "Success")]]);
+/// let connection = AmiConnection::connect(server.addr()).await?;
+/// ```
+pub struct MockAmiServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<MockState>>,
+    events_tx: broadcast::Sender<Packet>,
+}
+
+impl MockAmiServer {
+    /// Starts a server on a random port of the loopback interface
+    pub async fn start() -> std::io::Result<MockAmiServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (events_tx, _) = broadcast::channel(256);
+        let state = Arc::new(Mutex::new(MockState {
+            credentials: None,
+            handlers: HashMap::new(),
+            scenarios: HashMap::new(),
+            received: vec![],
+            events_tx: events_tx.clone(),
+            faults: VecDeque::new(),
+        }));
+
+        let accept_state = state.clone();
+        let accept_events = events_tx.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Mock AMI server cannot accept: {:?}", e);
+                        break;
+                    }
+                };
+                let state = accept_state.clone();
+                let events = accept_events.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, state, events).await {
+                        trace!("Mock AMI client disconnected: {:?}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(MockAmiServer {
+            addr,
+            state,
+            events_tx,
+        })
+    }
+
+    /// The address to connect to
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Only accepts logins with the given credentials, by default any login is accepted
+    pub fn credentials(&self, username: &str, secret: &str) {
+        self.state.lock().unwrap().credentials =
+            Some((username.to_string(), secret.to_string()));
+    }
+
+    /// Answers every `action` with the given packets
+    pub fn respond(&self, action: &str, response: Vec<Packet>) {
+        self.on_action(action, Box::new(move |_| response.clone()));
+    }
+
+    /// Answers every `action` with the packets produced by `handler`
+    pub fn on_action(&self, action: &str, handler: ActionHandler) {
+        self.state
+            .lock()
+            .unwrap()
+            .handlers
+            .insert(action.to_ascii_lowercase(), handler);
+    }
+
+    /// Reacts to actions as defined by a `Scenario`
+    ///
+    /// Scenarios take precedence over handlers registered for the same action.
+    pub fn load_scenario(&self, scenario: &Scenario) {
+        let mut state = self.state.lock().unwrap();
+        for (action, steps) in &scenario.actions {
+            state
+                .scenarios
+                .insert(action.to_ascii_lowercase(), steps.clone());
+        }
+    }
+
+    /// Sends an event to all connected clients
+    pub fn emit(&self, event: Packet) {
+        let _ = self.events_tx.send(event);
+    }
+
+    /// Distorts the next packet sent to any client
+    ///
+    /// Several faults are applied to consecutive packets in the order they were injected.
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// Returns all actions received so far, including logins
+    pub fn received(&self) -> Vec<Packet> {
+        self.state.lock().unwrap().received.clone()
+    }
+}
+
+/// The server side of a connection created by `duplex_connection`
+///
+/// Unlike `MockAmiServer` nothing happens automatically; tests read the actions sent by the
+/// client and write whatever bytes they want, e.g. lines split over several writes.
+pub struct ServerSide {
+    stream: BufReader<DuplexStream>,
+    faults: VecDeque<Fault>,
+}
+
+impl ServerSide {
+    /// Writes raw bytes to the client
+    pub async fn write_raw(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.stream.get_mut().write_all(bytes).await
+    }
+
+    /// Writes a packet including the terminating empty line
+    ///
+    /// Applies the next fault injected using `inject`, if any.
+    pub async fn send_packet(&mut self, pkt: &Packet) -> std::io::Result<()> {
+        let fault = self.faults.pop_front();
+        write_packet(self.stream.get_mut(), pkt, fault).await
+    }
+
+    /// Distorts the next packet written using `send_packet`
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push_back(fault);
+    }
+
+    /// Reads a single line sent by the client, without its line break
+    pub async fn read_line(&mut self) -> std::io::Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Reads the next packet sent by the client
+    pub async fn read_packet(&mut self) -> std::io::Result<Packet> {
+        let mut pkt = Packet::new();
+        loop {
+            let line = self.read_line().await?;
+            if line.is_empty() {
+                if !pkt.is_empty() {
+                    return Ok(pkt);
+                }
+            } else if let Some((key, value)) = line.split_once(':') {
+                pkt.push(Tag::from(key.trim(), value.trim()));
+            }
+        }
+    }
+}
+
+/// Creates a connection talking to an in-memory stream instead of a server
+///
+/// The greeting has already been sent, tests continue with the returned server side.
+pub async fn duplex_connection() -> std::io::Result<(AmiConnection, ServerSide)>
+{
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    let mut server = ServerSide {
+        stream: BufReader::new(server),
+        faults: VecDeque::new(),
+    };
+    server.write_raw(b"Asterisk Call Manager/5.0.0\r\n").await?;
+    let connection = AmiConnection::from_stream(client).await?;
+    Ok((connection, server))
+}
+
+/// Builds the response to a single action
+fn respond_to(state: &Mutex<MockState>, action: &Packet) -> Vec<Packet> {
+    let mut state = state.lock().unwrap();
+    state.received.push(action.clone());
+    let name = find_tag(action, "Action")
+        .map(|a| a.to_ascii_lowercase())
+        .unwrap_or_default();
+    let scripted = state.scenarios.get(&name).map(|steps| {
+        let mut response = vec![];
+        for step in steps {
+            match step {
+                Step::Respond(pkt) => response.push(substitute(pkt, action)),
+                Step::Emit { delay, event } => {
+                    let event = substitute(event, action);
+                    let events_tx = state.events_tx.clone();
+                    let delay = *delay;
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = events_tx.send(event);
+                    });
+                }
+            }
+        }
+        response
+    });
+    let handled =
+        scripted.or_else(|| state.handlers.get(&name).map(|h| h(action)));
+    let mut response = match handled {
+        Some(response) => response,
+        None => match name.as_str() {
+            "login" => login(&state.credentials, action),
+            "ping" => vec![vec![
+                Tag::from("Response", "Success"),
+                Tag::from("Ping", "Pong"),
+            ]],
+            "logoff" => vec![vec![
+                Tag::from("Response", "Goodbye"),
+                Tag::from("Message", "Thanks for all the fish."),
+            ]],
+            _ => vec![vec![
+                Tag::from("Response", "Error"),
+                Tag::from("Message", "Invalid/unknown command"),
+            ]],
+        },
+    };
+    if let Some(action_id) = find_tag(action, "ActionID") {
+        for pkt in response.iter_mut() {
+            pkt.retain(|tag| !tag.key.eq_ignore_ascii_case("ActionID"));
+            pkt.insert(1.min(pkt.len()), Tag::from("ActionID", action_id));
+        }
+    }
+    response
+}
+
+fn login(
+    credentials: &Option<(String, String)>,
+    action: &Packet,
+) -> Vec<Packet> {
+    let accepted = credentials.as_ref().is_none_or(|(username, secret)| {
+        find_tag(action, "Username") == Some(username)
+            && find_tag(action, "Secret") == Some(secret)
+    });
+    vec![if accepted {
+        vec![
+            Tag::from("Response", "Success"),
+            Tag::from("Message", "Authentication accepted"),
+        ]
+    } else {
+        vec![
+            Tag::from("Response", "Error"),
+            Tag::from("Message", "Authentication failed"),
+        ]
+    }]
+}
+
+fn next_fault(state: &Mutex<MockState>) -> Option<Fault> {
+    state.lock().unwrap().faults.pop_front()
+}
+
+fn to_wire(pkt: &Packet) -> String {
+    format!("{}\r\n\r\n", packet_to_string(pkt))
+}
+
+async fn serve(
+    stream: TcpStream,
+    state: Arc<Mutex<MockState>>,
+    mut events: broadcast::Receiver<Packet>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    writer.write_all(b"Asterisk Call Manager/5.0.0\r\n").await?;
+
+    let mut action = Packet::new();
+    let mut line = String::new();
+    loop {
+        tokio::select! {
+            read = reader.read_line(&mut line) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+                let trimmed = line.trim();
+                if trimmed.is_empty() {
+                    if !action.is_empty() {
+                        for pkt in respond_to(&state, &action) {
+                            let fault = next_fault(&state);
+                            write_packet(&mut writer, &pkt, fault).await?;
+                        }
+                        action.clear();
+                    }
+                } else if let Some((key, value)) = trimmed.split_once(':') {
+                    action.push(Tag::from(key.trim(), value.trim()));
+                }
+                line.clear();
+            }
+            event = events.recv() => {
+                match event {
+                    Ok(event) => {
+                        let fault = next_fault(&state);
+                        write_packet(&mut writer, &event, fault).await?
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn serves_canned_responses_and_events() {
+        let server = MockAmiServer::start().await.unwrap();
+        server.credentials("admin", "secret");
+        server.respond(
+            "CoreStatus",
+            vec![vec![
+                Tag::from("Response", "Success"),
+                Tag::from("CoreCurrentCalls", "3"),
+            ]],
+        );
+
+        let connection = AmiConnection::connect(server.addr()).await.unwrap();
+        let mut events = connection.events();
+        let login = connection
+            .send(vec![
+                Tag::from("Action", "Login"),
+                Tag::from("Username", "admin"),
+                Tag::from("Secret", "secret"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(find_tag(&login[0], "Response").unwrap(), "Success");
+        assert_eq!(connection.core_status().await.unwrap().current_calls, 3);
+        assert!(matches!(
+            connection.mailbox_count("100@default").await,
+            Err(Error::ActionFailed(_))
+        ));
+
+        server.emit(vec![Tag::from("Event", "FullyBooted")]);
+        let event = events.recv().await.unwrap().unwrap();
+        assert_eq!(find_tag(&event, "Event").unwrap(), "FullyBooted");
+        assert_eq!(server.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn reassembles_partial_lines() {
+        let (connection, mut server) = duplex_connection().await.unwrap();
+        let mut events = connection.events();
+        let client = connection.clone();
+        let ping = tokio::spawn(async move { client.ping().await });
+
+        let action = server.read_packet().await.unwrap();
+        assert_eq!(find_tag(&action, "Action").unwrap(), "Ping");
+        let action_id = find_tag(&action, "ActionID").unwrap().clone();
+        server.write_raw(b"Event: FullyBo").await.unwrap();
+        server
+            .write_raw(b"oted\r\n\r\nResponse: Succ")
+            .await
+            .unwrap();
+        let rest = format!("ess\r\nActionID: {}\r\n\r\n", action_id);
+        server.write_raw(rest.as_bytes()).await.unwrap();
+
+        assert!(ping.await.unwrap().is_ok());
+        let event = events.recv().await.unwrap().unwrap();
+        assert_eq!(find_tag(&event, "Event").unwrap(), "FullyBooted");
+    }
+
+    #[tokio::test]
+    async fn plays_scenarios() {
+        let scenario = Scenario::parse(
+            "# a call being answered and hung up
+on Originate:
+  respond:
+    Response: Success
+    Message: Originate successfully queued
+  after 20ms emit:
+    Event: OriginateResponse
+    ActionID: ${ActionID}
+    Response: Success
+    Channel: ${Channel}-00000001
+  after 10ms emit:
+    Event: Hangup
+    Channel: ${Channel}-00000001
+",
+        )
+        .unwrap();
+        let server = MockAmiServer::start().await.unwrap();
+        server.load_scenario(&scenario);
+
+        let connection = AmiConnection::connect(server.addr()).await.unwrap();
+        let mut events = connection.events();
+        let resp = connection
+            .send(vec![
+                Tag::from("Action", "Originate"),
+                Tag::from("ActionID", "42"),
+                Tag::from("Channel", "PJSIP/100"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(find_tag(&resp[0], "ActionID").unwrap(), "42");
+
+        let answered = events.recv().await.unwrap().unwrap();
+        assert_eq!(find_tag(&answered, "ActionID").unwrap(), "42");
+        assert_eq!(
+            find_tag(&answered, "Channel").unwrap(),
+            "PJSIP/100-00000001"
+        );
+        let hangup = events.recv().await.unwrap().unwrap();
+        assert_eq!(find_tag(&hangup, "Event").unwrap(), "Hangup");
+    }
+
+    #[tokio::test]
+    async fn injects_faults() {
+        let server = MockAmiServer::start().await.unwrap();
+        let connection = AmiConnection::connect(server.addr()).await.unwrap();
+        server.inject(Fault::Garbage(b"\x07#%nonsense\r\n".to_vec()));
+        server.inject(Fault::DuplicateLines);
+        assert!(connection.ping().await.is_ok());
+        assert!(connection.ping().await.is_ok());
+
+        server.inject(Fault::Stall(Duration::from_millis(20)));
+        assert!(connection.ping().await.unwrap() >= Duration::from_millis(20));
+
+        server.inject(Fault::Disconnect { after_bytes: 10 });
+        assert!(matches!(
+            connection.ping().await,
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn does_not_expand_substituted_values() {
+        let action = crate::packet_from(&[
+            ("Action", "Originate"),
+            ("Data", "${Data}"),
+            ("Exten", "${EXTEN}"),
+        ]);
+        let pkt = crate::packet_from(&[
+            ("Event", "Test"),
+            ("Info", "${Data}-${Exten}"),
+        ]);
+        let substituted = substitute(&pkt, &action);
+        assert_eq!(find_tag(&substituted, "Info").unwrap(), "${Data}-${EXTEN}");
+    }
+
+    #[test]
+    fn rejects_invalid_scenarios() {
+        assert!(Scenario::parse("  respond:").is_err());
+        assert!(Scenario::parse("on Ping:\n  after soon emit:").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::cache::ResponseCache;
+use crate::Packet;
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::task::AbortHandle;
+
+/// Spawns the task keeping the state of a tracker up to date
+///
+/// Every event received is passed to `apply` together with the tracker's state, changes
+/// returned by it are published on `changes_tx` and drop the cached responses the event makes
+/// outdated from `cache`. The task ends when the connection is closed,
+/// the tracker owning the state has been dropped, or it is aborted using the returned handle.
+pub(crate) fn spawn_tracker<S, C, F>(
+    name: &'static str,
+    mut events: broadcast::Receiver<Option<Packet>>,
+    cache: Arc<Mutex<ResponseCache>>,
+    state: Weak<RwLock<S>>,
+    changes_tx: broadcast::Sender<C>,
+    apply: F,
+) -> AbortHandle
+where
+    S: Send + Sync + 'static,
+    C: Send + 'static,
+    F: Fn(&mut S, &Packet) -> Option<C> + Send + 'static,
+{
+    let task = tokio::spawn(async move {
+        loop {
+            let pkt = match events.recv().await {
+                Ok(Some(pkt)) => pkt,
+                Ok(None) | Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(n)) => {
+                    warn!("{} missed {} events", name, n);
+                    crate::metrics::events_lagged(n);
+                    continue;
+                }
+            };
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => break,
+            };
+            let change = apply(&mut state.write().unwrap(), &pkt);
+            if let Some(change) = change {
+                cache.lock().unwrap().invalidate(&pkt);
+                publish(&changes_tx, change);
+            }
+        }
+        trace!("{} stopped", name);
+    });
+    task.abort_handle()
+}
+
+/// Publishes a change to the subscribers of a tracker, if there are any
+pub(crate) fn publish<C>(changes_tx: &broadcast::Sender<C>, change: C) {
+    if changes_tx.receiver_count() > 0 {
+        let _ = changes_tx.send(change);
+    }
+}
+
+/// What differs between the state of a tracker and a fresh seed
+pub(crate) struct Reconciliation<V> {
+    pub(crate) removed: Vec<V>,
+    pub(crate) added: Vec<V>,
+    pub(crate) updated: Vec<V>,
+}
+
+/// Replaces the state of a tracker with a fresh seed, e.g. after a reconnect
+///
+/// `keep` copies what the seed cannot know from an entity already tracked to its fresh
+/// counterpart, like the `Instant` it has been first seen at, before they are compared.
+pub(crate) fn reconcile<K, V, F>(
+    current: &mut HashMap<K, V>,
+    fresh: HashMap<K, V>,
+    keep: F,
+) -> Reconciliation<V>
+where
+    K: Eq + Hash,
+    V: Clone + PartialEq,
+    F: Fn(&V, &mut V),
+{
+    let mut next = HashMap::with_capacity(fresh.len());
+    let (mut added, mut updated) = (vec![], vec![]);
+    for (key, mut value) in fresh {
+        match current.remove(&key) {
+            Some(old) => {
+                keep(&old, &mut value);
+                if value != old {
+                    updated.push(value.clone());
+                }
+            }
+            None => added.push(value.clone()),
+        }
+        next.insert(key, value);
+    }
+    let removed = std::mem::replace(current, next).into_values().collect();
+    Reconciliation {
+        removed,
+        added,
+        updated,
+    }
+}
+
+/// Publishes a value derived from the state of a tracker on a watch channel
+///
+/// The value is recalculated whenever the tracker reports a change and only sent if it
+/// differs from the previous one. The task ends when the tracker has been dropped, the
+/// connection is closed, or all receivers are gone.
+pub(crate) fn derive_watch<S, C, T, F>(
+    name: &'static str,
+    state: &Arc<RwLock<S>>,
+    mut changes: broadcast::Receiver<C>,
+    derive: F,
+) -> watch::Receiver<T>
+where
+    S: Send + Sync + 'static,
+    C: Clone + Send + 'static,
+    T: PartialEq + Send + Sync + 'static,
+    F: Fn(&S) -> T + Send + 'static,
+{
+    let (value_tx, value_rx) = watch::channel(derive(&state.read().unwrap()));
+    let state = Arc::downgrade(state);
+    let current = move || Some(derive(&state.upgrade()?.read().unwrap()));
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                change = changes.recv() => match change {
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                },
+                _ = value_tx.closed() => break,
+            }
+            let value = match current() {
+                Some(value) => value,
+                None => break,
+            };
+            value_tx.send_if_modified(|previous| {
+                let modified = *previous != value;
+                if modified {
+                    *previous = value;
+                }
+                modified
+            });
+        }
+        trace!("{} stopped", name);
+    });
+    value_rx
+}
@@ -0,0 +1,242 @@
+use crate::{event_name, find_flag, find_tag, find_value, Packet};
+
+/// Whether a transfer has been performed blind or attended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferKind {
+    Blind,
+    Attended,
+}
+
+/// The outcome of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferResult {
+    Success,
+    Fail,
+    Invalid,
+    NotPermitted,
+    Unknown,
+}
+
+impl TransferResult {
+    fn parse(value: Option<&String>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            Some("success") => TransferResult::Success,
+            Some("fail") => TransferResult::Fail,
+            Some("invalid") => TransferResult::Invalid,
+            Some("not permitted") => TransferResult::NotPermitted,
+            _ => TransferResult::Unknown,
+        }
+    }
+}
+
+/// Where the transferee ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferDestination {
+    /// A blind transfer to a dialplan extension
+    Extension {
+        context: String,
+        exten: String,
+    },
+    /// The transferee has been moved into a bridge
+    Bridge(String),
+    /// The transferee has been handed over to a dialplan application
+    App(String),
+    /// The transferee has been linked to the target through a pair of local channels
+    Link {
+        local_one: Option<String>,
+        local_two: Option<String>,
+    },
+    /// A three-way call in the given bridge
+    Threeway(String),
+    Unknown,
+}
+
+/// A channel taking part in a transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferParty {
+    pub channel: String,
+    pub unique_id: Option<String>,
+}
+
+impl TransferParty {
+    fn find(pkt: &Packet, prefixes: &[&str]) -> Option<Self> {
+        prefixes.iter().find_map(|prefix| {
+            Some(TransferParty {
+                channel: find_value(pkt, &format!("{}Channel", prefix))?,
+                unique_id: find_value(pkt, &format!("{}Uniqueid", prefix)),
+            })
+        })
+    }
+}
+
+/// A `BlindTransfer` or `AttendedTransfer` event, or the legacy `Transfer` event
+///
+/// The headers naming the parties differ between the events and Asterisk versions; they are
+/// mapped to the transferer (who initiated the transfer), the transferee (who is transferred),
+/// and the target (who the transferee is connected to).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    pub kind: TransferKind,
+    pub result: TransferResult,
+    pub transferer: Option<TransferParty>,
+    pub transferee: Option<TransferParty>,
+    pub target: Option<TransferParty>,
+    pub destination: TransferDestination,
+    /// `true` if the transfer has been initiated by a channel driver (e.g. a SIP REFER)
+    pub is_external: bool,
+}
+
+impl Transfer {
+    /// Parses a transfer event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        match event_name(pkt)?.to_ascii_lowercase().as_str() {
+            "blindtransfer" => Some(Self::blind(pkt)),
+            "attendedtransfer" => Some(Self::attended(pkt)),
+            "transfer" => Self::legacy(pkt),
+            _ => None,
+        }
+    }
+
+    fn blind(pkt: &Packet) -> Self {
+        let destination =
+            match (find_value(pkt, "Context"), find_value(pkt, "Extension")) {
+                (Some(context), Some(exten)) => {
+                    TransferDestination::Extension { context, exten }
+                }
+                _ => TransferDestination::Unknown,
+            };
+        Transfer {
+            kind: TransferKind::Blind,
+            result: TransferResult::parse(find_tag(pkt, "Result")),
+            transferer: TransferParty::find(pkt, &["Transferer"]),
+            transferee: TransferParty::find(pkt, &["Transferee"]),
+            target: None,
+            destination,
+            is_external: find_flag(pkt, "IsExternal"),
+        }
+    }
+
+    fn attended(pkt: &Packet) -> Self {
+        let destination = match find_tag(pkt, "DestType")
+            .map(|t| t.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("bridge") => find_value(pkt, "DestBridgeUniqueid").map_or(
+                TransferDestination::Unknown,
+                TransferDestination::Bridge,
+            ),
+            Some("app") => find_value(pkt, "DestApp")
+                .map_or(TransferDestination::Unknown, TransferDestination::App),
+            Some("link") => TransferDestination::Link {
+                local_one: find_value(pkt, "LocalOneChannel"),
+                local_two: find_value(pkt, "LocalTwoChannel"),
+            },
+            Some("threeway") => find_value(pkt, "DestBridgeUniqueid").map_or(
+                TransferDestination::Unknown,
+                TransferDestination::Threeway,
+            ),
+            _ => TransferDestination::Unknown,
+        };
+        Transfer {
+            kind: TransferKind::Attended,
+            result: TransferResult::parse(find_tag(pkt, "Result")),
+            transferer: TransferParty::find(
+                pkt,
+                &["OrigTransferer", "Transferer"],
+            ),
+            transferee: TransferParty::find(
+                pkt,
+                &["Transferee", "OrigBridgeChannel"],
+            ),
+            target: TransferParty::find(
+                pkt,
+                &["TransferTarget", "SecondTransferer", "Target"],
+            ),
+            destination,
+            is_external: find_flag(pkt, "IsExternal"),
+        }
+    }
+
+    fn legacy(pkt: &Packet) -> Option<Self> {
+        let kind = match find_tag(pkt, "TransferType")?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "blind" => TransferKind::Blind,
+            _ => TransferKind::Attended,
+        };
+        let destination = match (
+            find_value(pkt, "TransferContext"),
+            find_value(pkt, "TransferExten"),
+        ) {
+            (Some(context), Some(exten)) if kind == TransferKind::Blind => {
+                TransferDestination::Extension { context, exten }
+            }
+            _ => TransferDestination::Unknown,
+        };
+        Some(Transfer {
+            kind,
+            result: TransferResult::Success,
+            transferer: TransferParty::find(pkt, &[""]),
+            transferee: None,
+            target: TransferParty::find(pkt, &["Target"]),
+            destination,
+            is_external: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_blind_transfer() {
+        let transfer = Transfer::from_event(&packet_from(&[
+            ("Event", "BlindTransfer"),
+            ("Result", "Success"),
+            ("TransfererChannel", "PJSIP/100-00000001"),
+            ("TransfererUniqueid", "1621.1"),
+            ("TransfereeChannel", "PJSIP/trunk-00000002"),
+            ("TransfereeUniqueid", "1621.2"),
+            ("IsExternal", "Yes"),
+            ("Context", "internal"),
+            ("Extension", "200"),
+        ]))
+        .unwrap();
+        assert_eq!(transfer.kind, TransferKind::Blind);
+        assert!(transfer.is_external);
+        assert_eq!(
+            transfer.transferee.unwrap().channel,
+            "PJSIP/trunk-00000002"
+        );
+        assert_eq!(
+            transfer.destination,
+            TransferDestination::Extension {
+                context: "internal".to_string(),
+                exten: "200".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn parses_attended_transfer() {
+        let transfer = Transfer::from_event(&packet_from(&[
+            ("Event", "AttendedTransfer"),
+            ("Result", "Success"),
+            ("OrigTransfererChannel", "PJSIP/100-00000001"),
+            ("SecondTransfererChannel", "PJSIP/100-00000003"),
+            ("TransfereeChannel", "PJSIP/trunk-00000002"),
+            ("TransferTargetChannel", "PJSIP/200-00000004"),
+            ("DestType", "Bridge"),
+            ("DestBridgeUniqueid", "b-2"),
+        ]))
+        .unwrap();
+        assert_eq!(transfer.target.unwrap().channel, "PJSIP/200-00000004");
+        assert_eq!(
+            transfer.destination,
+            TransferDestination::Bridge("b-2".to_string())
+        );
+    }
+}
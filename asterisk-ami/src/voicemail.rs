@@ -0,0 +1,318 @@
+use crate::{
+    event_name, find_tag, find_value, list_items, AmiConnection, Error, Packet,
+    Tag,
+};
+
+/// A voicemail mailbox as listed by `VoicemailUsersList`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoicemailUser {
+    pub mailbox: String,
+    pub context: String,
+    pub full_name: Option<String>,
+    pub email: Option<String>,
+    pub language: Option<String>,
+    pub max_messages: Option<u32>,
+    pub new_messages: u32,
+    pub old_messages: u32,
+}
+
+impl VoicemailUser {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        let count =
+            |key| find_tag(pkt, key).and_then(|v| v.trim().parse().ok());
+        Some(VoicemailUser {
+            mailbox: find_tag(pkt, "VoiceMailbox")?.clone(),
+            context: find_tag(pkt, "VMContext")?.clone(),
+            full_name: find_value(pkt, "Fullname"),
+            email: find_value(pkt, "Email"),
+            language: find_value(pkt, "Language"),
+            max_messages: count("MaxMessageCount"),
+            new_messages: count("NewMessageCount").unwrap_or(0),
+            old_messages: count("OldMessageCount").unwrap_or(0),
+        })
+    }
+}
+
+/// Message counts of a mailbox as returned by `MailboxCount`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MailboxCount {
+    pub urgent: u32,
+    pub new: u32,
+    pub old: u32,
+}
+
+/// A `MessageWaiting` event signaling a change of a mailbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageWaiting {
+    /// The mailbox as `mailbox@context`
+    pub mailbox: String,
+    pub waiting: bool,
+    pub new: u32,
+    pub old: u32,
+}
+
+impl MessageWaiting {
+    /// Parses a `MessageWaiting` event
+    pub fn from_event(pkt: &Packet) -> Option<Self> {
+        if !event_name(pkt)?.eq_ignore_ascii_case("MessageWaiting") {
+            return None;
+        }
+        let new = parse_count(pkt, "New");
+        Some(MessageWaiting {
+            mailbox: find_tag(pkt, "Mailbox")?.clone(),
+            waiting: find_tag(pkt, "Waiting").map_or(new > 0, |w| {
+                let w = w.trim();
+                w.eq_ignore_ascii_case("yes") || w.parse().unwrap_or(0) > 0
+            }),
+            new,
+            old: parse_count(pkt, "Old"),
+        })
+    }
+}
+
+/// The external MWI state of a mailbox as returned by `MWIGet`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MwiState {
+    pub mailbox: String,
+    pub new: u32,
+    pub old: u32,
+}
+
+impl MwiState {
+    fn from_list_item(pkt: &Packet) -> Option<Self> {
+        Some(MwiState {
+            mailbox: find_tag(pkt, "Mailbox")?.clone(),
+            new: parse_count(pkt, "NewMessages"),
+            old: parse_count(pkt, "OldMessages"),
+        })
+    }
+}
+
+fn parse_count(pkt: &Packet, key: &str) -> u32 {
+    find_tag(pkt, key)
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+impl AmiConnection {
+    /// Lists all voicemail mailboxes
+    pub async fn voicemail_users_list(
+        &self,
+    ) -> Result<Vec<VoicemailUser>, Error> {
+        let resp = self
+            .send_action(vec![Tag::from("Action", "VoicemailUsersList")])
+            .await?;
+        Ok(list_items(&resp, "VoicemailUserEntry")
+            .filter_map(VoicemailUser::from_list_item)
+            .collect())
+    }
+
+    /// Checks whether a mailbox (`mailbox@context`) has messages waiting
+    pub async fn mailbox_status(&self, mailbox: &str) -> Result<bool, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "MailboxStatus"),
+                Tag::from("Mailbox", mailbox),
+            ])
+            .await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        let waiting =
+            find_tag(first, "Waiting").ok_or(Error::UnexpectedResponse)?;
+        Ok(waiting.trim() != "0")
+    }
+
+    /// Counts the messages in a mailbox (`mailbox@context`)
+    pub async fn mailbox_count(
+        &self,
+        mailbox: &str,
+    ) -> Result<MailboxCount, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "MailboxCount"),
+                Tag::from("Mailbox", mailbox),
+            ])
+            .await?;
+        let first = resp.first().ok_or(Error::UnexpectedResponse)?;
+        Ok(MailboxCount {
+            urgent: parse_count(first, "UrgMessages"),
+            new: parse_count(first, "NewMessages"),
+            old: parse_count(first, "OldMessages"),
+        })
+    }
+
+    /// Gets the external MWI state of mailboxes
+    ///
+    /// `mailbox` is either a single `mailbox@context` or a regular expression enclosed in
+    /// slashes, e.g. `/^100@/`.
+    pub async fn mwi_get(&self, mailbox: &str) -> Result<Vec<MwiState>, Error> {
+        let resp = self
+            .send_action(vec![
+                Tag::from("Action", "MWIGet"),
+                Tag::from("Mailbox", mailbox),
+            ])
+            .await?;
+        Ok(list_items(&resp, "MWIGet")
+            .filter_map(MwiState::from_list_item)
+            .collect())
+    }
+
+    /// Sets the external MWI state of a mailbox (`mailbox@context`)
+    pub async fn mwi_update(
+        &self,
+        mailbox: &str,
+        new: u32,
+        old: u32,
+    ) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "MWIUpdate"),
+            Tag::from("Mailbox", mailbox),
+            Tag::of("NewMessages".to_string(), new.to_string()),
+            Tag::of("OldMessages".to_string(), old.to_string()),
+        ])
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the external MWI state of mailboxes
+    ///
+    /// Like with `mwi_get` the `mailbox` may be a regular expression enclosed in slashes.
+    pub async fn mwi_delete(&self, mailbox: &str) -> Result<(), Error> {
+        self.send_action(vec![
+            Tag::from("Action", "MWIDelete"),
+            Tag::from("Mailbox", mailbox),
+        ])
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet_from;
+
+    #[test]
+    fn parses_message_waiting() {
+        let mwi = MessageWaiting::from_event(&packet_from(&[
+            ("Event", "MessageWaiting"),
+            ("Mailbox", "100@default"),
+            ("Waiting", "1"),
+            ("New", "2"),
+            ("Old", "5"),
+        ]))
+        .unwrap();
+        assert!(mwi.waiting);
+        assert_eq!((mwi.new, mwi.old), (2, 5));
+    }
+
+    #[test]
+    fn parses_voicemail_user_entry() {
+        let user = VoicemailUser::from_list_item(&packet_from(&[
+            ("Event", "VoicemailUserEntry"),
+            ("VMContext", "default"),
+            ("VoiceMailbox", "100"),
+            ("Fullname", "Alice"),
+            ("Email", ""),
+            ("Language", "en"),
+            ("MaxMessageCount", "100"),
+            ("NewMessageCount", "3"),
+            ("OldMessageCount", "7"),
+        ]))
+        .unwrap();
+        assert_eq!(user.mailbox, "100");
+        assert_eq!(user.context, "default");
+        assert_eq!(user.full_name.as_deref(), Some("Alice"));
+        assert_eq!(user.email, None);
+        assert_eq!(user.max_messages, Some(100));
+        assert_eq!((user.new_messages, user.old_messages), (3, 7));
+    }
+
+    #[test]
+    fn parses_mwi_state() {
+        let state = MwiState::from_list_item(&packet_from(&[
+            ("Event", "MWIGet"),
+            ("Mailbox", "100@default"),
+            ("OldMessages", "4"),
+            ("NewMessages", "1"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            state,
+            MwiState {
+                mailbox: "100@default".to_string(),
+                new: 1,
+                old: 4,
+            }
+        );
+        let without_mailbox = packet_from(&[("Event", "MWIGet")]);
+        assert_eq!(MwiState::from_list_item(&without_mailbox), None);
+    }
+
+    /// Answers the next action with a single response made of `tags`
+    #[cfg(feature = "testing")]
+    async fn answer(
+        server: &mut crate::testing::ServerSide,
+        tags: &[(&str, &str)],
+    ) {
+        let action = server.read_packet().await.unwrap();
+        let mut pkt = packet_from(tags);
+        pkt.push(Tag::from(
+            "ActionID",
+            find_tag(&action, "ActionID").unwrap(),
+        ));
+        server.send_packet(&pkt).await.unwrap();
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn queries_mailbox_status() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        for (waiting, expected) in [("1", true), ("0", false)] {
+            let response = [
+                ("Response", "Success"),
+                ("Mailbox", "100@default"),
+                ("Waiting", waiting),
+            ];
+            let (status, _) = tokio::join!(
+                connection.mailbox_status("100@default"),
+                answer(&mut server, &response)
+            );
+            assert_eq!(status.unwrap(), expected);
+        }
+
+        let (status, _) = tokio::join!(
+            connection.mailbox_status("100@default"),
+            answer(&mut server, &[("Response", "Success")])
+        );
+        assert!(matches!(status, Err(Error::UnexpectedResponse)));
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn counts_mailbox_messages() {
+        let (connection, mut server) =
+            crate::testing::duplex_connection().await.unwrap();
+        let (count, _) = tokio::join!(
+            connection.mailbox_count("100@default"),
+            answer(
+                &mut server,
+                &[
+                    ("Response", "Success"),
+                    ("Mailbox", "100@default"),
+                    ("UrgMessages", "1"),
+                    ("NewMessages", "2"),
+                ]
+            )
+        );
+        // counts missing in the response are taken as 0
+        assert_eq!(
+            count.unwrap(),
+            MailboxCount {
+                urgent: 1,
+                new: 2,
+                old: 0,
+            }
+        );
+    }
+}
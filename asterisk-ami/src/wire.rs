@@ -0,0 +1,40 @@
+use crate::redaction;
+use std::time::SystemTime;
+use tokio::sync::broadcast;
+
+/// The direction a line has been transferred in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Received from the server
+    Inbound,
+    /// Sent to the server
+    Outbound,
+}
+
+/// A raw line as sent over the wire, see `AmiConnection::wire_tap`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireLine {
+    pub direction: Direction,
+    /// The line without its terminating line break; empty lines end a packet
+    ///
+    /// Credentials are redacted unless redaction has been disabled.
+    pub line: String,
+    pub at: SystemTime,
+}
+
+/// Publishes lines to the subscribers of the wire tap
+///
+/// Nothing is copied as long as nobody is subscribed. Credentials are redacted.
+pub(crate) fn publish(
+    wire_tx: &broadcast::Sender<WireLine>,
+    direction: Direction,
+    line: &str,
+) {
+    if wire_tx.receiver_count() > 0 {
+        let _ = wire_tx.send(WireLine {
+            direction,
+            line: redaction::line(line).into_owned(),
+            at: SystemTime::now(),
+        });
+    }
+}
@@ -0,0 +1,98 @@
+use crate::output::Output;
+use crate::server::{is_error, Server};
+use asterisk_ami::Packet;
+use log::info;
+use serde_json::json;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Sends `action` `count` times using `concurrency` parallel senders and reports the
+/// throughput and latencies
+pub async fn run(
+    server: &Server,
+    action: Packet,
+    count: usize,
+    concurrency: usize,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let connection = server.connect().await?;
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+
+    let remaining = Arc::new(AtomicUsize::new(count));
+    let started = Instant::now();
+    let senders = (0..concurrency.max(1)).map(|_| {
+        let connection = connection.clone();
+        let remaining = remaining.clone();
+        let action = action.clone();
+        tokio::spawn(async move {
+            let mut latencies = vec![];
+            let mut errors = 0;
+            while remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    n.checked_sub(1)
+                })
+                .is_ok()
+            {
+                let sent = Instant::now();
+                match connection.send(action.clone()).await {
+                    Some(resp) => {
+                        latencies.push(sent.elapsed());
+                        errors += is_error(&resp) as usize;
+                    }
+                    None => return Err("Connection closed"),
+                }
+            }
+            Ok((latencies, errors))
+        })
+    });
+    let mut latencies = vec![];
+    let mut errors = 0;
+    for sender in senders.collect::<Vec<_>>() {
+        let (sent, failed) = sender.await??;
+        latencies.extend(sent);
+        errors += failed;
+    }
+    let elapsed = started.elapsed();
+    latencies.sort();
+
+    let percentile = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().max(1) - 1))
+            .copied()
+            .unwrap_or_default()
+    };
+    let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+    let max = latencies.last().copied().unwrap_or_default();
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    match output {
+        Output::Log | Output::Color => info!(
+            "{} actions ({} failed) in {:.2?}: {:.1}/s, p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+            latencies.len(),
+            errors,
+            elapsed,
+            throughput,
+            percentile(50),
+            percentile(90),
+            percentile(99),
+            max
+        ),
+        Output::Json => println!(
+            "{}",
+            json!({
+                "actions": latencies.len(),
+                "errors": errors,
+                "seconds": elapsed.as_secs_f64(),
+                "per_second": throughput,
+                "p50_ms": ms(percentile(50)),
+                "p90_ms": ms(percentile(90)),
+                "p99_ms": ms(percentile(99)),
+                "max_ms": ms(max),
+            })
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use crate::output::Output;
+use crate::server::Server;
+use asterisk_ami::{event_name, find_tag, Packet};
+use log::warn;
+use std::error::Error;
+use tokio::sync::broadcast::error::RecvError;
+
+/// The columns of a record: title, width, and the headers of `Cdr` and `CEL` events to fill
+/// it from
+const COLUMNS: &[(&str, usize, &str, &str)] = &[
+    ("END", 19, "EndTime", "EventTime"),
+    ("SOURCE", 16, "Source", "CallerIDnum"),
+    ("DESTINATION", 16, "Destination", "Exten"),
+    ("CONTEXT", 16, "DestinationContext", "Context"),
+    ("DISPOSITION", 11, "Disposition", "EventName"),
+    ("DURATION", 8, "Duration", ""),
+    ("BILLSEC", 7, "BillableSeconds", ""),
+    ("CHANNEL", 0, "Channel", "Channel"),
+];
+
+/// Checks if an event is the record of a finished call
+///
+/// A `CEL` event is only a record of the whole call if it is the `LINKEDID_END` event
+/// written once the last channel of the call has hung up.
+fn is_record(event: &Packet) -> bool {
+    match event_name(event).map(String::as_str) {
+        Some("Cdr") => true,
+        Some("CEL") => find_tag(event, "EventName")
+            .is_some_and(|name| name == "LINKEDID_END"),
+        _ => false,
+    }
+}
+
+fn row<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    let cells = COLUMNS.iter().zip(values).map(|((_, width, _, _), value)| {
+        format!("{:width$}", value, width = width)
+    });
+    cells.collect::<Vec<_>>().join(" ").trim_end().to_string()
+}
+
+/// Prints the records of finished calls as they are written, until the connection is closed
+///
+/// The server needs `cdr_manager` or `cel_manager` enabled for the records to be sent.
+pub async fn run(
+    server: &Server,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let connection = server.connect().await?;
+    let mut events = connection.events();
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+    if output != Output::Json {
+        println!("{}", row(COLUMNS.iter().map(|(title, _, _, _)| *title)));
+    }
+    loop {
+        let event = match events.recv().await {
+            Ok(Some(event)) if is_record(&event) => event,
+            Ok(Some(_)) => continue,
+            Err(RecvError::Lagged(n)) => {
+                warn!("Missed {} events", n);
+                continue;
+            }
+            Ok(None) | Err(RecvError::Closed) => {
+                return Err("Connection closed".into())
+            }
+        };
+        if output == Output::Json {
+            output.event(&event);
+            continue;
+        }
+        let cel = event_name(&event).is_some_and(|name| name == "CEL");
+        println!(
+            "{}",
+            row(COLUMNS.iter().map(|(_, _, cdr_key, cel_key)| {
+                let key = if cel { cel_key } else { cdr_key };
+                find_tag(&event, key).map_or("", String::as_str)
+            }))
+        );
+    }
+}
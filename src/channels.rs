@@ -0,0 +1,80 @@
+use crate::server::Server;
+use crate::tui;
+use asterisk_ami::channels::{Channel, ChannelTracker};
+use ratatui::layout::Constraint;
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Row, Table};
+use ratatui::Frame;
+use std::error::Error;
+
+/// Shows a continuously updated table of the active channels
+pub async fn run(server: &Server) -> Result<(), Box<dyn Error>> {
+    let connection = server.connect().await?;
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+    let tracker = ChannelTracker::start(&connection).await?;
+    tui::run(&connection, tracker.changes(), |frame| {
+        let mut channels = tracker.snapshot();
+        channels.sort_by_key(|c| c.created);
+        draw(frame, &channels)
+    })
+    .await?;
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, channels: &[Channel]) {
+    let caller_id =
+        |num: &Option<String>, name: &Option<String>| match (num, name) {
+            (Some(num), Some(name)) => format!("\"{}\" <{}>", name, num),
+            (Some(num), None) => num.clone(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => String::new(),
+        };
+    let rows = channels.iter().map(|c| {
+        Row::new(vec![
+            c.name.clone(),
+            format!("{:?}", c.state),
+            caller_id(&c.caller_id_num, &c.caller_id_name),
+            caller_id(&c.connected_line_num, &c.connected_line_name),
+            match (&c.exten, &c.context) {
+                (Some(exten), Some(context)) => {
+                    format!("{}@{}", exten, context)
+                }
+                _ => String::new(),
+            },
+            tui::duration(c.duration()),
+            c.bridge_id.clone().unwrap_or_default(),
+        ])
+    });
+    let widths = [
+        Constraint::Fill(3),
+        Constraint::Fill(1),
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Length(9),
+        Constraint::Fill(3),
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(
+                Row::new(vec![
+                    "Channel",
+                    "State",
+                    "Caller ID",
+                    "Connected",
+                    "Extension",
+                    "Duration",
+                    "Bridge",
+                ])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+            )
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("{} channels (q to quit)", channels.len())),
+            ),
+        frame.area(),
+    );
+}
@@ -0,0 +1,82 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// The settings of a named profile of the configuration file
+///
+/// ```toml
+/// [profiles.prod]
+/// server = "pbx.example.com:5039"
+/// user = "monitoring"
+/// pass-keyring = true
+/// tls = true
+/// ca-cert = "/etc/ssl/pbx-ca.pem"
+/// events = ["Hangup"]
+/// match = ["Channel=^PJSIP/trunk-"]
+/// filter = 'Cause != "16"'
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Profile {
+    pub server: Option<String>,
+    pub user: Option<String>,
+    pub secret: Option<String>,
+    pub pass_keyring: bool,
+    pub tls: bool,
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub insecure: bool,
+    pub events: Vec<String>,
+    #[serde(rename = "match")]
+    pub matches: Vec<String>,
+    pub filter: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// `$XDG_CONFIG_HOME/ami-cli/config.toml`, by default in `~/.config`
+fn default_path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config"))
+        })?;
+    Some(config.join("ami-cli").join("config.toml"))
+}
+
+/// Loads a profile from the configuration file
+///
+/// Without a `name` the profile `default` is used if it exists. A missing configuration file
+/// is only an error if a profile has been requested explicitly.
+pub fn load(
+    path: Option<&str>,
+    name: Option<&str>,
+) -> Result<Profile, Box<dyn Error>> {
+    let path = match path.map(PathBuf::from).or_else(default_path) {
+        Some(path) => path,
+        None if name.is_none() => return Ok(Profile::default()),
+        None => return Err("Cannot locate the configuration file".into()),
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e)
+            if e.kind() == std::io::ErrorKind::NotFound && name.is_none() =>
+        {
+            return Ok(Profile::default())
+        }
+        Err(e) => return Err(format!("{}: {}", path.display(), e).into()),
+    };
+    let mut config: Config = toml::from_str(&content)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    match name {
+        Some(name) => config.profiles.remove(name).ok_or_else(|| {
+            format!("No profile {} in {}", name, path.display()).into()
+        }),
+        None => Ok(config.profiles.remove("default").unwrap_or_default()),
+    }
+}
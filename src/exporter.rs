@@ -0,0 +1,332 @@
+use crate::server::Server;
+use asterisk_ami::channels::ChannelTracker;
+use asterisk_ami::parking::ParkingTracker;
+use asterisk_ami::peers::PeerTracker;
+use asterisk_ami::queues::QueueTracker;
+use asterisk_ami::AmiConnection;
+use log::{info, trace, warn};
+use prometheus::{
+    Encoder, GaugeVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::error::RecvError;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long a scraper may take to send its request and receive the metrics
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Requests with larger headers are rejected
+const MAX_REQUEST: u64 = 8192;
+
+struct Trackers {
+    channels: ChannelTracker,
+    queues: QueueTracker,
+    peers: PeerTracker,
+    parking: ParkingTracker,
+}
+
+impl Trackers {
+    async fn start(
+        connection: &AmiConnection,
+    ) -> Result<Trackers, asterisk_ami::Error> {
+        Ok(Trackers {
+            channels: ChannelTracker::start(connection).await?,
+            queues: QueueTracker::start(connection).await?,
+            peers: PeerTracker::start(connection).await?,
+            parking: ParkingTracker::start(connection).await?,
+        })
+    }
+
+    /// Seeds all trackers again and follows `connection` from now on
+    async fn resync(
+        &self,
+        connection: &AmiConnection,
+    ) -> Result<(), asterisk_ami::Error> {
+        self.channels.resync(connection).await?;
+        self.queues.resync(connection).await?;
+        self.peers.resync(connection).await?;
+        self.parking.resync(connection).await
+    }
+}
+
+/// The gauges describing the current state of the server, updated on every scrape
+struct Gauges {
+    up: IntGauge,
+    channels: IntGaugeVec,
+    queue_callers: IntGaugeVec,
+    queue_longest_wait: GaugeVec,
+    queue_members: IntGaugeVec,
+    queue_completed: IntGaugeVec,
+    queue_abandoned: IntGaugeVec,
+    peers: IntGaugeVec,
+    peer_latency: GaugeVec,
+    parked_calls: IntGaugeVec,
+}
+
+impl Gauges {
+    fn register(registry: &Registry) -> prometheus::Result<Gauges> {
+        let int_vec = |name: &str, help: &str, labels: &[&str]| {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok::<_, prometheus::Error>(gauge)
+        };
+        let float_vec = |name: &str, help: &str, labels: &[&str]| {
+            let gauge = GaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok::<_, prometheus::Error>(gauge)
+        };
+        let up = IntGauge::new("asterisk_up", "Whether the AMI is connected")?;
+        registry.register(Box::new(up.clone()))?;
+        Ok(Gauges {
+            up,
+            channels: int_vec(
+                "asterisk_channels",
+                "Active channels",
+                &["state"],
+            )?,
+            queue_callers: int_vec(
+                "asterisk_queue_callers",
+                "Callers waiting in a queue",
+                &["queue"],
+            )?,
+            queue_longest_wait: float_vec(
+                "asterisk_queue_longest_wait_seconds",
+                "Wait time of the caller waiting longest",
+                &["queue"],
+            )?,
+            queue_members: int_vec(
+                "asterisk_queue_members",
+                "Members of a queue",
+                &["queue", "status", "paused"],
+            )?,
+            queue_completed: int_vec(
+                "asterisk_queue_calls_completed",
+                "Calls answered by a member as reported by Asterisk",
+                &["queue"],
+            )?,
+            queue_abandoned: int_vec(
+                "asterisk_queue_calls_abandoned",
+                "Calls abandoned by the caller as reported by Asterisk",
+                &["queue"],
+            )?,
+            peers: int_vec(
+                "asterisk_peers",
+                "Peers, contacts, and registrations by status",
+                &["kind", "status"],
+            )?,
+            peer_latency: float_vec(
+                "asterisk_peer_latency_seconds",
+                "Round trip time of the last qualify",
+                &["kind", "name"],
+            )?,
+            parked_calls: int_vec(
+                "asterisk_parked_calls",
+                "Calls waiting in a parking lot",
+                &["parking_lot"],
+            )?,
+        })
+    }
+
+    fn update(&self, trackers: Option<&Trackers>) {
+        for gauge in [
+            &self.channels,
+            &self.queue_callers,
+            &self.queue_members,
+            &self.queue_completed,
+            &self.queue_abandoned,
+            &self.peers,
+            &self.parked_calls,
+        ] {
+            gauge.reset();
+        }
+        self.queue_longest_wait.reset();
+        self.peer_latency.reset();
+        self.up.set(trackers.is_some() as i64);
+        let trackers = match trackers {
+            Some(trackers) => trackers,
+            None => return,
+        };
+
+        for channel in trackers.channels.snapshot() {
+            let state = format!("{:?}", channel.state);
+            self.channels.with_label_values(&[&state]).inc();
+        }
+        for queue in trackers.queues.snapshot() {
+            let name = [queue.name.as_str()];
+            self.queue_callers
+                .with_label_values(&name)
+                .set(queue.callers.len() as i64);
+            let longest = queue.callers.iter().map(|c| c.wait_time()).max();
+            self.queue_longest_wait
+                .with_label_values(&name)
+                .set(longest.unwrap_or_default().as_secs_f64());
+            self.queue_completed
+                .with_label_values(&name)
+                .set(queue.calls_completed.into());
+            self.queue_abandoned
+                .with_label_values(&name)
+                .set(queue.calls_abandoned.into());
+            for member in &queue.members {
+                let status = format!("{:?}", member.status);
+                let paused = member.paused.to_string();
+                self.queue_members
+                    .with_label_values(&[&queue.name, &status, &paused])
+                    .inc();
+            }
+        }
+        for peer in trackers.peers.snapshot() {
+            let kind = format!("{:?}", peer.kind);
+            let status = format!("{:?}", peer.status);
+            self.peers.with_label_values(&[&kind, &status]).inc();
+            if let Some(latency) = peer.latency {
+                self.peer_latency
+                    .with_label_values(&[&kind, &peer.name])
+                    .set(latency.as_secs_f64());
+            }
+        }
+        for call in trackers.parking.snapshot() {
+            self.parked_calls
+                .with_label_values(&[&call.parking_lot])
+                .inc();
+        }
+    }
+}
+
+/// Everything a scrape reports on
+struct Metrics {
+    registry: Registry,
+    gauges: Mutex<Gauges>,
+    trackers: Mutex<Option<Arc<Trackers>>>,
+}
+
+impl Metrics {
+    /// Updates the gauges and encodes all metrics
+    fn encode(&self, encoder: &TextEncoder) -> prometheus::Result<Vec<u8>> {
+        let gauges = self.gauges.lock().unwrap();
+        gauges.update(self.trackers.lock().unwrap().as_deref());
+        let mut body = vec![];
+        encoder.encode(&self.registry.gather(), &mut body)?;
+        Ok(body)
+    }
+}
+
+/// Serves Prometheus metrics about the server on `listen`
+///
+/// The connection is re-established whenever it is lost; until then `asterisk_up` is `0`.
+/// Afterwards the trackers are seeded again, so what changed during the outage is picked up.
+pub async fn run(server: Server, listen: &str) -> Result<(), Box<dyn Error>> {
+    let registry = Registry::new();
+    asterisk_ami::metrics::register_prometheus(&registry)?;
+    let metrics = Arc::new(Metrics {
+        gauges: Mutex::new(Gauges::register(&registry)?),
+        registry,
+        trackers: Mutex::new(None),
+    });
+
+    let listener = TcpListener::bind(listen).await?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    let scraped = metrics.clone();
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Cannot accept: {}", e);
+                    continue;
+                }
+            };
+            let metrics = scraped.clone();
+            tokio::spawn(async move {
+                let served = serve(stream, &metrics);
+                match tokio::time::timeout(REQUEST_TIMEOUT, served).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => trace!("Cannot serve metrics: {}", e),
+                    Err(_) => trace!("Metrics request timed out"),
+                }
+            });
+        }
+    });
+
+    let mut known = None::<Arc<Trackers>>;
+    loop {
+        match track(&server, &mut known).await {
+            Ok(connection) => {
+                *metrics.trackers.lock().unwrap() = known.clone();
+                let mut events = connection.events();
+                while let Ok(Some(_)) | Err(RecvError::Lagged(_)) =
+                    events.recv().await
+                {}
+                warn!("Connection closed, reconnecting");
+                *metrics.trackers.lock().unwrap() = None;
+            }
+            Err(e) => {
+                warn!(
+                    "Cannot connect, retrying in {:?}: {}",
+                    RECONNECT_DELAY, e
+                )
+            }
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Connects and starts the trackers, or resynchronizes the `known` ones after a reconnect
+async fn track(
+    server: &Server,
+    known: &mut Option<Arc<Trackers>>,
+) -> Result<AmiConnection, Box<dyn Error>> {
+    let connection = server.connect().await?;
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+    match known {
+        Some(trackers) => trackers.resync(&connection).await?,
+        None => *known = Some(Arc::new(Trackers::start(&connection).await?)),
+    }
+    Ok(connection)
+}
+
+/// Answers a single HTTP request
+async fn serve(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream.take(MAX_REQUEST));
+    let mut request = String::new();
+    stream.read_line(&mut request).await?;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        match stream.read_line(&mut header).await? {
+            0 => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Request incomplete or too large",
+                ))
+            }
+            1 | 2 => break,
+            _ => {}
+        }
+    }
+
+    let encoder = TextEncoder::new();
+    let (status, content_type, body) = if request.starts_with("GET /metrics ") {
+        let body = metrics.encode(&encoder).map_err(std::io::Error::other)?;
+        ("200 OK", encoder.format_type(), body)
+    } else {
+        ("404 Not Found", "text/plain", b"Not found\n".to_vec())
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let stream = stream.get_mut().get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,60 @@
+use asterisk_ami::filter::Filter;
+use asterisk_ami::{event_name, Packet};
+use regex::Regex;
+use std::error::Error;
+
+/// Selects the events to show
+///
+/// An event is shown if its name is one of the given names (or no names are given), all
+/// header patterns match, and it is selected by the filter expression, if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    names: Vec<String>,
+    headers: Vec<(String, Regex)>,
+    expression: Option<Filter>,
+}
+
+impl EventFilter {
+    /// Creates a filter from event names, `key=regex` header patterns, and an expression
+    pub fn new<'a>(
+        names: impl IntoIterator<Item = &'a str>,
+        headers: impl IntoIterator<Item = &'a str>,
+        expression: Option<&str>,
+    ) -> Result<EventFilter, Box<dyn Error>> {
+        let headers = headers
+            .into_iter()
+            .map(|header| {
+                let (key, pattern) = header
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected key=regex: {}", header))?;
+                Ok((key.trim().to_string(), Regex::new(pattern)?))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(EventFilter {
+            names: names.into_iter().map(String::from).collect(),
+            headers,
+            expression: expression.map(Filter::parse).transpose()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.headers.is_empty()
+            && self.expression.is_none()
+    }
+
+    pub fn matches(&self, event: &Packet) -> bool {
+        let name_matches = self.names.is_empty()
+            || event_name(event).is_some_and(|name| {
+                self.names.iter().any(|n| n.eq_ignore_ascii_case(name))
+            });
+        name_matches
+            && self.expression.as_ref().is_none_or(|e| e.matches(event))
+            && self.headers.iter().all(|(key, regex)| {
+                event.iter().any(|tag| {
+                    tag.key.eq_ignore_ascii_case(key)
+                        && regex.is_match(&tag.value)
+                })
+            })
+    }
+}
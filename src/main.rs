@@ -1,17 +1,45 @@
-use asterisk_ami::{AmiConnection, Tag};
+mod bench;
+mod cdr;
+mod channels;
+mod config;
+mod exporter;
+mod filter;
+mod originate;
+mod output;
+mod queues;
+mod repl;
+mod replay;
+mod script;
+mod secret;
+mod server;
+mod tls;
+mod tui;
+mod watch;
+
+use asterisk_ami::credentials::Credentials;
+use asterisk_ami::pcapng::Capture;
+use asterisk_ami::recording::{Recorder, Timing};
+use asterisk_ami::Tag;
 use clap::{clap_app, crate_version};
+use filter::EventFilter;
+use log::{error, info, trace, warn, LevelFilter};
+use output::Output;
+use server::Server;
 use simple_logger::SimpleLogger;
 use std::error::Error;
-use std::net::SocketAddr;
-use log::{error, info, trace, warn};
-use tokio::io;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::io::IsTerminal;
+use std::time::Duration;
+use tls::TlsOptions;
+use tokio::sync::broadcast::error::RecvError;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     SimpleLogger::new()
         .with_utc_timestamps()
         .with_colors(true)
+        .with_module_level("rustyline", LevelFilter::Warn)
         .init()
         .unwrap();
 
@@ -21,60 +49,293 @@ async fn main() -> Result<(), Box<dyn Error>> {
             (version: crate_version!())
             (author: "Matthias Wimmer <m@tthias.eu>")
             (about: "Example application for the asterisk-ami crate")
+            (@arg PROFILE: -P --profile +takes_value "Use the settings of a profile of the configuration file")
+            (@arg CONFIG: --config +takes_value "The configuration file (default ~/.config/ami-cli/config.toml)")
             (@arg SERVER: -s --server +takes_value "Server to connect to")
             (@arg USER: -u --user +takes_value "Username to authenticate with")
-            (@arg PASS: -p --pass +takes_value "Password to authenticate with")
+            (@arg PASS: -p --pass +takes_value "Password to authenticate with, visible to other users in the process list")
+            (@arg PASS_FD: --("pass-fd") +takes_value "Read the password from this file descriptor")
+            (@arg PASS_KEYRING: --("pass-keyring") "Read the password from the system keyring")
+            (@arg STORE_KEYRING: --("store-keyring") "Prompt for the password and store it in the system keyring")
             (@arg EVENTS: -e --events "Show all incoming events")
+            (@arg EVENT: --event +takes_value +multiple number_of_values(1) "Only show events with this name, implies --events")
+            (@arg MATCH: --match +takes_value +multiple number_of_values(1) "Only show events with a header matching key=regex, implies --events")
+            (@arg FILTER: --filter +takes_value "Only show events selected by an expression like 'Event == \"Hangup\" && Channel =~ \"^PJSIP/\"', implies --events")
+            (@arg RECORD: --record +takes_value "Append all received packets to a file for replaying them later")
+            (@arg CAPTURE: --capture +takes_value "Append the traffic in both directions to a pcapng file for Wireshark")
+            (@arg RECONNECT: --reconnect +takes_value min_values(0) require_equals(true) "Keep reconnecting with increasing delays starting at BACKOFF (default 1s)")
+            (@arg TLS: --tls "Connect using TLS, by default to port 5039")
+            (@arg CA_CERT: --("ca-cert") +takes_value "PEM file with the CA certificates to trust, implies --tls")
+            (@arg CLIENT_CERT: --("client-cert") +takes_value "PEM file with a client certificate and its private key, implies --tls")
+            (@arg INSECURE: --insecure "Accept any server certificate, implies --tls")
+            (@arg SCRIPT: --script +takes_value "Send the actions of a file, separated by empty lines, and exit")
+            (@arg JSON: --json "Print events and responses as JSON, one object per line")
+            (@arg NO_COLOR: --("no-color") "Do not color events by their class")
+            (@subcommand action =>
+                (about: "Send a single action, exit with 1 if it fails and 2 if the server cannot be reached")
+                (@arg NAME: +required "The action to send, e.g. QueuePause")
+                (@arg HEADERS: +multiple "Headers of the action as Key=Value")
+            )
+            (@subcommand originate =>
+                (about: "Originate a call and wait until it has been answered, exits with 1 if it fails")
+                (@arg CHANNEL: -c --channel +takes_value +required "The channel to call, e.g. PJSIP/100")
+                (@arg EXTEN: --exten +takes_value conflicts_with[APP] "Connect to EXTEN@CONTEXT once answered")
+                (@arg APP: --app +takes_value "Run a dialplan application once answered")
+                (@arg DATA: --data +takes_value requires[APP] "The arguments of the application")
+                (@arg CALLERID: --callerid +takes_value "The caller id, e.g. \"Reception\" <100>")
+                (@arg TIMEOUT: --timeout +takes_value "Seconds to wait for the call to be answered")
+                (@arg VARIABLE: --variable +takes_value +multiple number_of_values(1) "Set a channel variable as NAME=VALUE")
+            )
+            (@subcommand bench =>
+                (about: "Send an action many times and report throughput and latencies")
+                (@arg NAME: default_value("Ping") "The action to send, e.g. Getvar")
+                (@arg HEADERS: +multiple "Headers of the action as Key=Value")
+                (@arg COUNT: -n --count +takes_value default_value("1000") "Number of actions to send")
+                (@arg CONCURRENCY: -c --concurrency +takes_value default_value("10") "Number of actions in flight at a time")
+            )
+            (@subcommand cdr =>
+                (about: "Print the records of finished calls from Cdr and CEL events, like tail -f")
+            )
+            (@subcommand channels =>
+                (about: "Show a live table of the active channels")
+            )
+            (@subcommand exporter =>
+                (about: "Serve Prometheus metrics about channels, queues, and peers")
+                (@arg LISTEN: -l --listen +takes_value "Address to serve the metrics on (default 0.0.0.0:9580)")
+            )
+            (@subcommand replay =>
+                (about: "Print the events of a file written using --record, honoring --event, --match, --filter, and --json")
+                (@arg FILE: +required "The recording to replay")
+                (@arg SPEED: --speed +takes_value conflicts_with[REALTIME] "Replay with the recorded delays shortened by this factor")
+                (@arg REALTIME: --realtime "Replay with the recorded delays")
+            )
+            (@subcommand queues =>
+                (about: "Show a live dashboard of the queues, their members, and waiting callers")
+            )
+            (@subcommand watch =>
+                (about: "Print the events of a channel or peer, following the other legs of its calls")
+                (@arg CHANNEL: -c --channel +takes_value required_unless[PEER] conflicts_with[PEER] "The channel, e.g. PJSIP/100 or PJSIP/100-00000001")
+                (@arg PEER: -p --peer +takes_value "The endpoint, e.g. PJSIP/100 or 100")
+            )
     )
     .get_matches();
 
-    let all_events = args.is_present("EVENTS");
+    let profile =
+        config::load(args.value_of("CONFIG"), args.value_of("PROFILE"))?;
+    let list = |arg, default: &[String]| -> Vec<String> {
+        match args.values_of(arg) {
+            Some(values) => values.map(String::from).collect(),
+            None => default.to_vec(),
+        }
+    };
+    let filter = EventFilter::new(
+        list("EVENT", &profile.events).iter().map(String::as_str),
+        list("MATCH", &profile.matches).iter().map(String::as_str),
+        args.value_of("FILTER").or(profile.filter.as_deref()),
+    )?;
+    let all_events = args.is_present("EVENTS") || !filter.is_empty();
+    let output = if args.is_present("JSON") {
+        Output::Json
+    } else if args.is_present("NO_COLOR")
+        || std::env::var_os("NO_COLOR").is_some()
+        || !std::io::stderr().is_terminal()
+    {
+        Output::Log
+    } else {
+        Output::Color
+    };
+
+    if let ("replay", Some(replay)) = args.subcommand() {
+        let timing = match replay.value_of("SPEED") {
+            Some(speed) => Timing::Accelerated(speed.parse()?),
+            None if replay.is_present("REALTIME") => Timing::Original,
+            None => Timing::Immediate,
+        };
+        let path = replay.value_of("FILE").unwrap_or_default();
+        return replay::run(path, timing, &filter, output).await;
+    }
 
     let username = args
         .value_of("USER")
         .map(String::from)
+        .or_else(|| profile.user.clone())
         .or(dotenv::var("USERNAME").ok())
-        .expect("No username given");
-    let secret = args
-        .value_of("PASS")
-        .map(String::from)
-        .or(dotenv::var("SECRET").ok())
-        .expect("No password given");
-    let server = args
-        .value_of("SERVER")
-        .map(String::from)
-        .or(dotenv::var("SERVER").ok())
-        .unwrap_or(String::from("127.0.0.1:5038"));
-    let server_address: SocketAddr = server.parse()?;
+        .ok_or("No username given")?;
+    if args.is_present("STORE_KEYRING") {
+        let secret =
+            rpassword::prompt_password(format!("Password for {}: ", username))?;
+        return secret::store(&username, &secret);
+    }
+    let secret = secret::read(&args, &profile, &username)?;
+    let tls_options = TlsOptions {
+        ca_cert: args
+            .value_of("CA_CERT")
+            .map(String::from)
+            .or_else(|| profile.ca_cert.clone()),
+        client_cert: args
+            .value_of("CLIENT_CERT")
+            .map(String::from)
+            .or_else(|| profile.client_cert.clone()),
+        insecure: args.is_present("INSECURE") || profile.insecure,
+    };
+    let tls = (args.is_present("TLS")
+        || profile.tls
+        || tls_options.ca_cert.is_some()
+        || tls_options.client_cert.is_some()
+        || tls_options.insecure)
+        .then_some(tls_options);
+    if let Some(tls) = &tls {
+        tls.connector()?;
+    }
+    let server = Server {
+        address: args
+            .value_of("SERVER")
+            .map(String::from)
+            .or_else(|| profile.server.clone())
+            .or(dotenv::var("SERVER").ok())
+            .unwrap_or(String::from(match tls {
+                Some(_) => "127.0.0.1:5039",
+                None => "127.0.0.1:5038",
+            })),
+        tls,
+        credentials: Credentials::new(&username, &secret),
+        keyring: secret::in_keyring(&args, &profile),
+    };
+
+    if let ("originate", Some(originate)) = args.subcommand() {
+        let request = originate::request(originate)?;
+        let answered = originate::run(&server, &request, output).await;
+        std::process::exit(exit_code(answered));
+    }
+
+    match args.subcommand() {
+        ("bench", Some(bench)) => {
+            let action = script::action_from_args(
+                bench.value_of("NAME").unwrap_or_default(),
+                bench.values_of("HEADERS").into_iter().flatten(),
+            )?;
+            let count = bench.value_of("COUNT").unwrap_or_default().parse()?;
+            let concurrency =
+                bench.value_of("CONCURRENCY").unwrap_or_default().parse()?;
+            return bench::run(&server, action, count, concurrency, output)
+                .await;
+        }
+        ("cdr", _) => return cdr::run(&server, output).await,
+        ("channels", _) => return channels::run(&server).await,
+        ("queues", _) => return queues::run(&server).await,
+        ("watch", Some(watch)) => {
+            let target = match watch.value_of("CHANNEL") {
+                Some(channel) => watch::Target::Channel(channel.to_string()),
+                None => watch::Target::Peer(
+                    watch.value_of("PEER").unwrap_or_default().to_string(),
+                ),
+            };
+            return watch::run(&server, target, &filter, output).await;
+        }
+        ("exporter", Some(exporter)) => {
+            let listen = exporter.value_of("LISTEN").unwrap_or("0.0.0.0:9580");
+            return exporter::run(server, listen).await;
+        }
+        _ => {}
+    }
 
-    let mut stdin_reader = BufReader::new(io::stdin());
+    let actions = match (args.subcommand(), args.value_of("SCRIPT")) {
+        (("action", Some(action)), _) => Some(vec![script::action_from_args(
+            action.value_of("NAME").unwrap_or_default(),
+            action.values_of("HEADERS").into_iter().flatten(),
+        )?]),
+        (_, Some(path)) => Some(script::parse(&std::fs::read_to_string(path)?)),
+        _ => None,
+    };
+    if let Some(actions) = actions {
+        let succeeded = script::run(&server, &actions, output).await;
+        std::process::exit(exit_code(succeeded));
+    }
+
+    let reconnect = match args.is_present("RECONNECT") {
+        false => None,
+        true => Some(
+            args.value_of("RECONNECT")
+                .map(parse_duration)
+                .unwrap_or(Ok(Duration::from_secs(1)))?,
+        ),
+    };
+    let mut backoff = reconnect.unwrap_or_default();
+
+    let mut input = repl::Input::start()?;
+    let mut recorder: Option<Recorder> = None;
+    let mut capture: Option<Capture> = None;
 
     'outer: loop {
-        let ami_connection = AmiConnection::connect(server_address).await?;
+        let ami_connection = match server.connect().await {
+            Ok(connection) => connection,
+            Err(e) if reconnect.is_some() => {
+                warn!("Cannot connect, retrying in {:?}: {}", backoff, e);
+                back_off(&mut backoff).await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some(path) = args.value_of("RECORD") {
+            if let Some(previous) = recorder.take() {
+                previous.stop().await?;
+            }
+            recorder = Some(Recorder::append(&ami_connection, path)?);
+        }
+        if let Some(path) = args.value_of("CAPTURE") {
+            if let Some(previous) = capture.take() {
+                previous.stop().await?;
+            }
+            capture = Some(Capture::append(&ami_connection, path)?);
+        }
 
         if all_events {
             let mut events = ami_connection.events();
+            let filter = filter.clone();
             tokio::spawn(async move {
                 loop {
                     match events.recv().await {
+                        Err(RecvError::Closed) => break,
                         Err(e) => warn!("Error on reading event: {:?}", e),
-                        Ok(Some(evt)) => info!("Event: {:?}", evt),
+                        Ok(Some(evt)) if filter.matches(&evt) => {
+                            output.event(&evt)
+                        }
+                        Ok(Some(_)) => {}
                         Ok(None) => {
                             trace!("Connection closed.");
-                            continue;
+                            break;
                         }
                     }
                 }
             });
         }
 
-        let login = vec![
-            Tag::from("Action", "Login"),
-            Tag::from("Username", &username),
-            Tag::from("Secret", &secret),
-        ];
-        match ami_connection.send(login).await {
-            Some(resp) => info!("Login Response: {:?}", resp),
+        match server.login(&ami_connection).await {
+            Some(true) => {
+                backoff = reconnect.unwrap_or_default();
+                if let Ok(capabilities) = ami_connection.capabilities().await {
+                    let actions =
+                        capabilities.actions().map(|a| a.name.clone());
+                    input.set_actions(actions.collect());
+                }
+            }
+            Some(false) if reconnect.is_some() => {
+                warn!("Login failed, retrying in {:?}", backoff);
+                back_off(&mut backoff).await;
+                continue;
+            }
+            Some(false) => {
+                error!("Login failed");
+                break;
+            }
+            None if reconnect.is_some() => {
+                warn!(
+                    "Connection closed while logging in, retrying in {:?}",
+                    backoff
+                );
+                back_off(&mut backoff).await;
+                continue;
+            }
             None => {
                 error!(
                     "Error on logging in ... maybe cannot connect to server?"
@@ -83,34 +344,91 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
 
-        let mut line_buffer = String::new();
+        // A single word is sent as `Action: <word>`, complete actions are entered as
+        // `Key: Value` lines terminated by an empty line
+        let mut pending = Vec::new();
+        let mut closed = ami_connection.events();
         loop {
+            let prompt = if pending.is_empty() { "ami> " } else { "...> " };
             tokio::select! {
-                bytes_read = stdin_reader.read_line(&mut line_buffer) => {
-                    if bytes_read? == 0 {
-                        trace!("Stdin closed");
-                        break 'outer;
+                event = closed.recv() => {
+                    if let Ok(None) | Err(RecvError::Closed) = event {
+                        info!("Connection closed.");
+                        break;
                     }
-
-                    let cmd = line_buffer.trim();
-                    if cmd == "" {
-                        trace!("Good Bye");
-                        break 'outer;
-                    } else {
-                        let pkt = vec![Tag::from("Action", cmd)];
-                        match ami_connection.send(pkt).await {
-                            Some(resp) => info!("Response: {:?}", resp),
-                            None => {
-                                info!("No response. Connection probably closed.");
-                                break;
-                            },
+                }
+                line = input.read_line(prompt) => {
+                    let line = match line {
+                        Some(line) => line.trim().to_string(),
+                        None => {
+                            trace!("Stdin closed");
+                            break 'outer;
+                        }
+                    };
+                    let pkt = match (line.split_once(':'), pending.is_empty()) {
+                        (None, true) if line.is_empty() => {
+                            trace!("Good Bye");
+                            break 'outer;
+                        }
+                        (None, true) => vec![Tag::from("Action", &line)],
+                        (None, false) if line.is_empty() => {
+                            std::mem::take(&mut pending)
+                        }
+                        (None, false) => {
+                            warn!("Ignoring line without colon: {}", line);
+                            continue;
                         }
+                        (Some((key, value)), _) => {
+                            pending.push(Tag::from(key.trim(), value.trim()));
+                            continue;
+                        }
+                    };
+                    match ami_connection.send(pkt).await {
+                        Some(resp) => output.response("Response", &resp),
+                        None => {
+                            info!("No response. Connection probably closed.");
+                            break;
+                        },
                     }
-                    line_buffer.clear();
                 }
             }
         }
     }
 
+    if let Some(recorder) = recorder {
+        recorder.stop().await?;
+    }
+    if let Some(capture) = capture {
+        capture.stop().await?;
+    }
     Ok(())
 }
+
+/// Waits before the next attempt to connect, doubling the delay for the one after
+async fn back_off(backoff: &mut Duration) {
+    tokio::time::sleep(*backoff).await;
+    *backoff = (*backoff * 2).min(MAX_BACKOFF);
+}
+
+/// Exits with 0 on success, 1 if the server reported a failure, and 2 on other errors
+fn exit_code(result: Result<bool, Box<dyn Error>>) -> i32 {
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            error!("{}", e);
+            2
+        }
+    }
+}
+
+/// Parses durations like `500ms`, `5s`, or `5`
+fn parse_duration(value: &str) -> Result<Duration, Box<dyn Error>> {
+    let (number, millis) = match value.strip_suffix("ms") {
+        Some(number) => (number, 1),
+        None => (value.strip_suffix('s').unwrap_or(value), 1000),
+    };
+    Ok(Duration::from_millis(
+        number.trim().parse::<u64>()? * millis,
+    ))
+}
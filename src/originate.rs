@@ -0,0 +1,65 @@
+use crate::output::Output;
+use crate::server::Server;
+use asterisk_ami::originate::{CallProgress, OriginateRequest};
+use clap::ArgMatches;
+use log::error;
+use std::error::Error;
+use std::time::Duration;
+
+/// Builds the request from the arguments of the `originate` subcommand
+pub fn request(args: &ArgMatches) -> Result<OriginateRequest, Box<dyn Error>> {
+    let channel = args.value_of("CHANNEL").unwrap_or_default();
+    let mut request = match (args.value_of("EXTEN"), args.value_of("APP")) {
+        (Some(exten), None) => {
+            let (exten, context) =
+                exten.split_once('@').unwrap_or((exten, "default"));
+            OriginateRequest::to_extension(channel, context, exten, 1)
+        }
+        (None, Some(app)) => OriginateRequest::to_application(
+            channel,
+            app,
+            args.value_of("DATA").unwrap_or_default(),
+        ),
+        _ => return Err("Either --exten or --app is required".into()),
+    };
+    if let Some(caller_id) = args.value_of("CALLERID") {
+        request = request.caller_id(caller_id);
+    }
+    if let Some(timeout) = args.value_of("TIMEOUT") {
+        request = request.timeout(Duration::from_secs(timeout.parse()?));
+    }
+    for variable in args.values_of("VARIABLE").into_iter().flatten() {
+        let (name, value) = variable
+            .split_once('=')
+            .ok_or_else(|| format!("Expected NAME=VALUE: {}", variable))?;
+        request = request.variable(name, value);
+    }
+    Ok(request)
+}
+
+/// Originates the call and prints its progress until it has been answered or failed
+///
+/// Returns whether the call has been answered.
+pub async fn run(
+    server: &Server,
+    request: &OriginateRequest,
+    output: Output,
+) -> Result<bool, Box<dyn Error>> {
+    let connection = server.connect().await?;
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        error!("Login failed");
+        return Ok(false);
+    }
+    let call = connection.originate(request).await?;
+    let mut states = call.watch();
+    loop {
+        let state = states.borrow_and_update().clone();
+        output.call_state(&state);
+        if state.progress.is_final() {
+            return Ok(state.progress == CallProgress::Answered);
+        }
+        if states.changed().await.is_err() {
+            return Err("Connection closed".into());
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use asterisk_ami::originate::CallState;
+use asterisk_ami::{find_tag, Packet, Tag};
+use log::info;
+use serde_json::{json, Map, Value};
+
+/// How received packets are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Logged using their `Debug` formatting
+    Log,
+    /// Logged like `Log`, events colored by their privilege class
+    Color,
+    /// Printed to stdout as one JSON object per line
+    Json,
+}
+
+impl Output {
+    pub fn event(self, event: &Packet) {
+        match self {
+            Output::Log => info!("Event: {:?}", event),
+            Output::Color => match color(event) {
+                Some(code) => info!("\x1b[{}mEvent: {:?}\x1b[0m", code, event),
+                None => info!("Event: {:?}", event),
+            },
+            Output::Json => println!("{}", Value::Object(to_json(event))),
+        }
+    }
+
+    /// Prints a response, `what` describes it in log output
+    pub fn response(self, what: &str, response: &[Packet]) {
+        match self {
+            Output::Log | Output::Color => info!("{}: {:?}", what, response),
+            Output::Json => {
+                let mut json =
+                    response.first().map(to_json).unwrap_or_default();
+                if response.len() > 1 {
+                    let events =
+                        response[1..].iter().map(to_json).map(Value::Object);
+                    json.insert("events".to_string(), events.collect());
+                }
+                println!("{}", Value::Object(json));
+            }
+        }
+    }
+}
+
+impl Output {
+    pub fn call_state(self, state: &CallState) {
+        match self {
+            Output::Log | Output::Color => info!(
+                "Call {}: {:?}",
+                state.channel.as_deref().unwrap_or(&state.unique_id),
+                state.progress
+            ),
+            Output::Json => println!(
+                "{}",
+                json!({
+                    "progress": format!("{:?}", state.progress),
+                    "channel": state.channel,
+                    "unique_id": state.unique_id,
+                })
+            ),
+        }
+    }
+}
+
+/// The ANSI color for an event: calls green, security red and system yellow
+fn color(event: &Packet) -> Option<&'static str> {
+    let privilege = find_tag(event, "Privilege")?;
+    let has = |class| privilege.split(',').any(|c| c.trim() == class);
+    [("security", "31"), ("system", "33"), ("call", "32")]
+        .iter()
+        .find(|(class, _)| has(*class))
+        .map(|(_, code)| *code)
+}
+
+/// Converts a packet to a JSON object, values of repeated headers are collected in an array
+fn to_json(pkt: &Packet) -> Map<String, Value> {
+    let mut json = Map::new();
+    for Tag { key, value } in pkt {
+        let value = Value::String(value.clone());
+        match json.get_mut(key) {
+            None => {
+                json.insert(key.clone(), value);
+            }
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+        }
+    }
+    json
+}
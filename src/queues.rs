@@ -0,0 +1,121 @@
+use crate::server::Server;
+use crate::tui;
+use asterisk_ami::queues::{Queue, QueueTracker};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Row, Table};
+use ratatui::Frame;
+use std::error::Error;
+
+/// Shows a live dashboard of all queues
+pub async fn run(server: &Server) -> Result<(), Box<dyn Error>> {
+    let connection = server.connect().await?;
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+    let tracker = QueueTracker::start(&connection).await?;
+    tui::run(&connection, tracker.changes(), |frame| {
+        draw(frame, &tracker.snapshot())
+    })
+    .await?;
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, queues: &[Queue]) {
+    let header = Style::default().add_modifier(Modifier::BOLD);
+    let [summary, members, callers] = Layout::vertical([
+        Constraint::Length(queues.len() as u16 + 3),
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+    ])
+    .areas(frame.area());
+
+    let rows = queues.iter().map(|q| {
+        let longest = q.callers.iter().map(|c| c.wait_time()).max();
+        Row::new(vec![
+            q.name.clone(),
+            q.callers.len().to_string(),
+            longest.map(tui::duration).unwrap_or_default(),
+            format!("{}/{}", q.available_members().count(), q.members.len()),
+            q.calls_completed.to_string(),
+            q.calls_abandoned.to_string(),
+            tui::duration(q.hold_time),
+        ])
+    });
+    let widths = [Constraint::Fill(1); 7];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(
+                Row::new(vec![
+                    "Queue",
+                    "Waiting",
+                    "Longest wait",
+                    "Available",
+                    "Completed",
+                    "Abandoned",
+                    "Avg. hold",
+                ])
+                .style(header),
+            )
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title("Queues (q to quit)"),
+            ),
+        summary,
+    );
+
+    let rows = queues.iter().flat_map(|q| {
+        q.members.iter().map(move |m| {
+            Row::new(vec![
+                q.name.clone(),
+                m.name.clone().unwrap_or_else(|| m.interface.clone()),
+                m.interface.clone(),
+                format!("{:?}", m.status),
+                match (m.paused, &m.paused_reason) {
+                    (true, Some(reason)) => format!("paused ({})", reason),
+                    (true, None) => "paused".to_string(),
+                    (false, _) => String::new(),
+                },
+                m.calls_taken.to_string(),
+            ])
+        })
+    });
+    frame.render_widget(
+        Table::new(rows, [Constraint::Fill(1); 6])
+            .header(
+                Row::new(vec![
+                    "Queue",
+                    "Member",
+                    "Interface",
+                    "Status",
+                    "Paused",
+                    "Calls",
+                ])
+                .style(header),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Members")),
+        members,
+    );
+
+    let rows = queues.iter().flat_map(|q| {
+        q.callers.iter().map(move |c| {
+            Row::new(vec![
+                q.name.clone(),
+                c.position.to_string(),
+                c.caller_id_num.clone().unwrap_or_default(),
+                c.caller_id_name.clone().unwrap_or_default(),
+                tui::duration(c.wait_time()),
+            ])
+        })
+    });
+    frame.render_widget(
+        Table::new(rows, [Constraint::Fill(1); 5])
+            .header(
+                Row::new(vec!["Queue", "Position", "Number", "Name", "Wait"])
+                    .style(header),
+            )
+            .block(Block::default().borders(Borders::ALL).title("Callers")),
+        callers,
+    );
+}
@@ -0,0 +1,126 @@
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+/// Reads the lines typed by the user with history and completion of action names
+///
+/// The editor runs on its own thread as it blocks while waiting for input.
+pub struct Input {
+    prompt_tx: std::sync::mpsc::Sender<String>,
+    lines_rx: mpsc::Receiver<Option<String>>,
+    waiting: bool,
+    actions: Arc<RwLock<Vec<String>>>,
+}
+
+impl Input {
+    pub fn start() -> rustyline::Result<Input> {
+        let actions = Arc::new(RwLock::new(vec![]));
+        let mut editor = Editor::<ActionHelper, DefaultHistory>::new()?;
+        editor.set_helper(Some(ActionHelper {
+            actions: actions.clone(),
+        }));
+        let history = history_file();
+        if let Some(history) = &history {
+            let _ = editor.load_history(history);
+        }
+
+        let (prompt_tx, prompt_rx) = std::sync::mpsc::channel::<String>();
+        let (lines_tx, lines_rx) = mpsc::channel(1);
+        std::thread::spawn(move || {
+            while let Ok(prompt) = prompt_rx.recv() {
+                let line = match editor.readline(&prompt) {
+                    Ok(line) => line,
+                    Err(ReadlineError::Interrupted | ReadlineError::Eof) => {
+                        let _ = lines_tx.blocking_send(None);
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("Cannot read input: {}", e);
+                        let _ = lines_tx.blocking_send(None);
+                        break;
+                    }
+                };
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                    if let Some(history) = &history {
+                        let _ = editor.save_history(history);
+                    }
+                }
+                if lines_tx.blocking_send(Some(line)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Input {
+            prompt_tx,
+            lines_rx,
+            waiting: false,
+            actions,
+        })
+    }
+
+    /// Reads the next line, `None` once the input has been closed
+    ///
+    /// Cancel safe: if the future is dropped, the line is returned by the next call.
+    pub async fn read_line(&mut self, prompt: &str) -> Option<String> {
+        if !self.waiting {
+            self.prompt_tx.send(prompt.to_string()).ok()?;
+            self.waiting = true;
+        }
+        let line = self.lines_rx.recv().await.flatten();
+        self.waiting = false;
+        line
+    }
+
+    /// Sets the action names offered for completion
+    pub fn set_actions(&self, mut actions: Vec<String>) {
+        actions.sort();
+        *self.actions.write().unwrap() = actions;
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".asterisk-ami-history"))
+}
+
+/// Completes action names as first word of a line or as value of an `Action:` line
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct ActionHelper {
+    actions: Arc<RwLock<Vec<String>>>,
+}
+
+impl Completer for ActionHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let word = match before.split_once(':') {
+            None => before.trim_start(),
+            Some((key, value)) if key.trim().eq_ignore_ascii_case("Action") => {
+                value.trim_start()
+            }
+            Some(_) => return Ok((pos, vec![])),
+        };
+        let prefix = word.to_ascii_lowercase();
+        let candidates = self
+            .actions
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|a| a.to_ascii_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect();
+        Ok((pos - word.len(), candidates))
+    }
+}
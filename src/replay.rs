@@ -0,0 +1,25 @@
+use crate::filter::EventFilter;
+use crate::output::Output;
+use asterisk_ami::recording::{read_recording, replay_subscribed, Timing};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Prints the events of a recording, filtered like those of a live session
+pub async fn run(
+    path: &str,
+    timing: Timing,
+    filter: &EventFilter,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let packets = read_recording(BufReader::new(File::open(path)?))?;
+    let (_connection, mut events) = replay_subscribed(packets, timing).await?;
+    loop {
+        match events.recv().await {
+            Ok(Some(event)) if filter.matches(&event) => output.event(&event),
+            Ok(Some(_)) | Err(RecvError::Lagged(_)) => {}
+            Ok(None) | Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use crate::output::Output;
+use crate::server::{is_error, Server};
+use asterisk_ami::{Packet, Tag};
+use log::{error, warn};
+use std::error::Error;
+
+/// Parses the actions of a script
+///
+/// Actions consist of `Key: Value` lines and are separated by empty lines. Lines starting
+/// with `#` are comments.
+pub fn parse(script: &str) -> Vec<Packet> {
+    let mut actions = vec![];
+    let mut action = Packet::new();
+    for line in script.lines().map(str::trim) {
+        if line.starts_with('#') {
+            continue;
+        }
+        match line.split_once(':') {
+            Some((key, value)) => {
+                action.push(Tag::from(key.trim(), value.trim()))
+            }
+            None if line.is_empty() => {
+                if !action.is_empty() {
+                    actions.push(std::mem::take(&mut action));
+                }
+            }
+            None => warn!("Ignoring line without colon: {}", line),
+        }
+    }
+    if !action.is_empty() {
+        actions.push(action);
+    }
+    actions
+}
+
+/// Builds an action from its name and `Key=Value` arguments
+pub fn action_from_args<'a>(
+    name: &str,
+    headers: impl IntoIterator<Item = &'a str>,
+) -> Result<Packet, Box<dyn Error>> {
+    let mut action = vec![Tag::from("Action", name)];
+    for header in headers {
+        let (key, value) = header
+            .split_once('=')
+            .ok_or_else(|| format!("Expected Key=Value: {}", header))?;
+        action.push(Tag::from(key.trim(), value.trim()));
+    }
+    Ok(action)
+}
+
+/// Logs in and sends all actions in order
+///
+/// Returns whether the login and all actions succeeded.
+pub async fn run(
+    server: &Server,
+    actions: &[Packet],
+    output: Output,
+) -> Result<bool, Box<dyn Error>> {
+    let connection = server.connect().await?;
+    let closed = || String::from("Connection closed");
+    if !server.login(&connection).await.ok_or_else(closed)? {
+        error!("Login failed");
+        return Ok(false);
+    }
+    let mut succeeded = true;
+    for action in actions {
+        let resp = connection.send(action.clone()).await.ok_or_else(closed)?;
+        output.response("Response", &resp);
+        succeeded &= !is_error(&resp);
+    }
+    Ok(succeeded)
+}
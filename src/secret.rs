@@ -0,0 +1,77 @@
+use crate::config::Profile;
+use clap::ArgMatches;
+use std::error::Error;
+use std::io::IsTerminal;
+
+/// The keyring service the secrets are stored under
+const KEYRING_SERVICE: &str = "asterisk-ami";
+
+/// Reads the secret to log in with
+///
+/// Tried in order: `--pass`, `--pass-fd`, `--pass-keyring`, the secret of the profile, the
+/// `SECRET` environment variable, and finally a hidden prompt if stdin is a terminal.
+pub fn read(
+    args: &ArgMatches,
+    profile: &Profile,
+    username: &str,
+) -> Result<String, Box<dyn Error>> {
+    if let Some(secret) = args.value_of("PASS") {
+        return Ok(secret.to_string());
+    }
+    if let Some(fd) = args.value_of("PASS_FD") {
+        return from_fd(fd.parse()?);
+    }
+    if in_keyring(args, profile) {
+        return from_keyring(username);
+    }
+    if let Some(secret) = &profile.secret {
+        return Ok(secret.clone());
+    }
+    if let Ok(secret) = dotenv::var("SECRET") {
+        return Ok(secret);
+    }
+    if std::io::stdin().is_terminal() {
+        return Ok(rpassword::prompt_password(format!(
+            "Password for {}: ",
+            username
+        ))?);
+    }
+    Err("No password given".into())
+}
+
+/// Checks if `read` takes the secret from the keyring
+pub fn in_keyring(args: &ArgMatches, profile: &Profile) -> bool {
+    !args.is_present("PASS")
+        && !args.is_present("PASS_FD")
+        && (args.is_present("PASS_KEYRING") || profile.pass_keyring)
+}
+
+/// Reads the secret stored in the keyring for `username`
+pub fn from_keyring(username: &str) -> Result<String, Box<dyn Error>> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, username)?;
+    Ok(entry.get_password()?)
+}
+
+/// Stores a secret in the keyring for use with `--pass-keyring`
+pub fn store(username: &str, secret: &str) -> Result<(), Box<dyn Error>> {
+    keyring::Entry::new(KEYRING_SERVICE, username)?.set_password(secret)?;
+    Ok(())
+}
+
+/// Reads the first line of an inherited file descriptor, e.g. `--pass-fd 3 3<secret.txt`
+#[cfg(unix)]
+fn from_fd(fd: i32) -> Result<String, Box<dyn Error>> {
+    use std::io::BufRead;
+    use std::os::unix::io::FromRawFd;
+
+    // the descriptor is owned by us from now on and closed once read
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
+    let mut line = String::new();
+    std::io::BufReader::new(file).read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(not(unix))]
+fn from_fd(_fd: i32) -> Result<String, Box<dyn Error>> {
+    Err("--pass-fd is only supported on Unix".into())
+}
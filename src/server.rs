@@ -0,0 +1,61 @@
+use crate::secret;
+use crate::tls::{self, TlsOptions};
+use asterisk_ami::credentials::Credentials;
+use asterisk_ami::{find_tag, AmiConnection, Error as AmiError};
+use log::{info, warn};
+use std::error::Error;
+
+/// Where and how to connect
+pub struct Server {
+    pub address: String,
+    /// Certificates and keys are read again on every connect, so renewed ones are picked up
+    pub tls: Option<TlsOptions>,
+    pub credentials: Credentials,
+    /// Whether the secret is read from the keyring again before every login
+    pub keyring: bool,
+}
+
+impl Server {
+    pub async fn connect(&self) -> Result<AmiConnection, Box<dyn Error>> {
+        match &self.tls {
+            Some(options) => {
+                tls::connect(&self.address, &options.connector()?).await
+            }
+            None => Ok(AmiConnection::connect(self.address.as_str()).await?),
+        }
+    }
+
+    /// Logs in, returns `None` if the connection has been closed
+    ///
+    /// Secrets from the keyring are read again first, so a rotated secret is used on the
+    /// next reconnect.
+    pub async fn login(&self, connection: &AmiConnection) -> Option<bool> {
+        let username = self.credentials.username();
+        if self.keyring {
+            match secret::from_keyring(&username) {
+                Ok(secret) => self.credentials.rotate(&username, &secret),
+                Err(e) => {
+                    warn!("Cannot read the password from the keyring: {}", e)
+                }
+            }
+        }
+        match connection.login(&self.credentials).await {
+            Ok(()) => {
+                info!("Logged in as {}", username);
+                Some(true)
+            }
+            Err(AmiError::ConnectionClosed) => None,
+            Err(e) => {
+                warn!("Cannot log in as {}: {}", username, e);
+                Some(false)
+            }
+        }
+    }
+}
+
+/// Checks if a response reports an error
+pub fn is_error(resp: &[asterisk_ami::Packet]) -> bool {
+    resp.first()
+        .and_then(|pkt| find_tag(pkt, "Response"))
+        .is_none_or(|r| r.eq_ignore_ascii_case("Error"))
+}
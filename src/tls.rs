@@ -0,0 +1,120 @@
+use asterisk_ami::AmiConnection;
+use log::trace;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{ring, WebPkiSupportedAlgorithms};
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+
+/// How to secure the connection to the server
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// PEM file with the CA certificates to trust instead of the Mozilla root store
+    pub ca_cert: Option<String>,
+    /// PEM file with the client certificate chain followed by its private key
+    pub client_cert: Option<String>,
+    /// Accept any server certificate
+    pub insecure: bool,
+}
+
+impl TlsOptions {
+    pub fn connector(&self) -> Result<TlsConnector, Box<dyn Error>> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_cert {
+            Some(path) => {
+                for cert in CertificateDer::pem_file_iter(path)? {
+                    roots.add(cert?)?;
+                }
+            }
+            None => {
+                roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+            }
+        }
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let mut config = match &self.client_cert {
+            Some(path) => builder.with_client_auth_cert(
+                CertificateDer::pem_file_iter(path)?
+                    .collect::<Result<_, _>>()?,
+                PrivateKeyDer::from_pem_file(path)?,
+            )?,
+            None => builder.with_no_client_auth(),
+        };
+        if self.insecure {
+            config.dangerous().set_certificate_verifier(Arc::new(
+                AcceptAnyCertificate(
+                    ring::default_provider().signature_verification_algorithms,
+                ),
+            ));
+        }
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+/// Connects to `server` (`host:port`) using TLS
+pub async fn connect(
+    server: &str,
+    connector: &TlsConnector,
+) -> Result<AmiConnection, Box<dyn Error>> {
+    let host = server
+        .rsplit_once(':')
+        .map_or(server, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let name = ServerName::try_from(host.to_string())?;
+    trace!("Connecting to {} using TLS", server);
+    let stream = TcpStream::connect(server).await?;
+    let stream = connector.connect(name, stream).await?;
+    Ok(AmiConnection::from_stream(stream).await?)
+}
+
+/// Skips the verification of the server certificate for `--insecure`
+#[derive(Debug)]
+struct AcceptAnyCertificate(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls12_signature(
+            message, cert, dss, &self.0,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls13_signature(
+            message, cert, dss, &self.0,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
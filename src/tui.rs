@@ -0,0 +1,64 @@
+use asterisk_ami::AmiConnection;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent};
+use ratatui::Frame;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+
+/// Renders a live view until `q` or Esc has been pressed or the connection has been closed
+///
+/// The view is redrawn whenever a change is received and once a second for the durations
+/// shown. Logging is disabled while the view is shown.
+pub async fn run<C: Clone>(
+    connection: &AmiConnection,
+    mut changes: broadcast::Receiver<C>,
+    mut draw: impl FnMut(&mut Frame),
+) -> std::io::Result<()> {
+    let (keys_tx, mut keys_rx) = mpsc::channel(8);
+    std::thread::spawn(move || loop {
+        match event::read() {
+            Ok(Event::Key(key)) if keys_tx.blocking_send(key).is_err() => break,
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    });
+
+    let log_level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+    let mut terminal = ratatui::init();
+    let mut closed = connection.events();
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
+    let result = loop {
+        if let Err(e) = terminal.draw(&mut draw) {
+            break Err(e);
+        }
+        tokio::select! {
+            change = changes.recv() => {
+                if let Err(RecvError::Closed) = change {
+                    break Ok(());
+                }
+            }
+            event = closed.recv() => {
+                if let Ok(None) | Err(RecvError::Closed) = event {
+                    break Ok(());
+                }
+            }
+            key = keys_rx.recv() => match key {
+                Some(KeyEvent { code: KeyCode::Char('q') | KeyCode::Esc, .. })
+                | None => break Ok(()),
+                Some(_) => {}
+            },
+            _ = tick.tick() => {}
+        }
+    };
+    ratatui::restore();
+    log::set_max_level(log_level);
+    result
+}
+
+/// Formats a duration as `h:mm:ss`
+pub fn duration(d: Duration) -> String {
+    let s = d.as_secs();
+    format!("{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
@@ -0,0 +1,113 @@
+use crate::filter::EventFilter;
+use crate::output::Output;
+use crate::server::Server;
+use asterisk_ami::{find_tag, Packet};
+use log::warn;
+use std::collections::HashSet;
+use std::error::Error;
+use tokio::sync::broadcast::error::RecvError;
+
+/// What the `watch` subcommand follows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// A channel name like `PJSIP/100-00000001`, or its prefix `PJSIP/100`
+    Channel(String),
+    /// An endpoint like `PJSIP/100`, or just `100`, including its status changes
+    Peer(String),
+}
+
+/// Headers naming a channel
+const CHANNEL_HEADERS: &[&str] = &["Channel", "DestChannel"];
+/// Headers naming an endpoint in status events like `PeerStatus` and `ContactStatus`
+const PEER_HEADERS: &[&str] = &["Peer", "Endpoint", "EndpointName", "Device"];
+/// Headers linking an event to a call
+const ID_HEADERS: &[&str] =
+    &["Uniqueid", "Linkedid", "DestUniqueid", "DestLinkedid"];
+
+/// Selects the events relating to a target
+///
+/// Once an event names a matching channel, its Uniqueid and Linkedid are followed, so the
+/// other legs of the call and events without a channel name are shown as well.
+#[derive(Debug)]
+struct Watcher {
+    target: Target,
+    ids: HashSet<String>,
+}
+
+impl Watcher {
+    fn new(target: Target) -> Watcher {
+        Watcher {
+            target,
+            ids: HashSet::new(),
+        }
+    }
+
+    fn is_target_channel(&self, channel: &str) -> bool {
+        match &self.target {
+            Target::Channel(name) => {
+                channel == name
+                    || channel
+                        .strip_prefix(name.as_str())
+                        .is_some_and(|rest| rest.starts_with('-'))
+            }
+            Target::Peer(_) => channel
+                .rsplit_once('-')
+                .is_some_and(|(endpoint, _)| self.is_target_peer(endpoint)),
+        }
+    }
+
+    fn is_target_peer(&self, endpoint: &str) -> bool {
+        match &self.target {
+            Target::Channel(_) => false,
+            Target::Peer(peer) => {
+                endpoint == peer
+                    || endpoint
+                        .split_once('/')
+                        .is_some_and(|(_, resource)| resource == peer)
+            }
+        }
+    }
+
+    /// Checks if an event relates to the target, following its ids if it does
+    fn matches(&mut self, event: &Packet) -> bool {
+        let header = |keys: &'static [&str]| {
+            keys.iter().filter_map(move |key| find_tag(event, key))
+        };
+        let matches = header(CHANNEL_HEADERS)
+            .any(|c| self.is_target_channel(c))
+            || header(PEER_HEADERS).any(|p| self.is_target_peer(p))
+            || header(ID_HEADERS).any(|id| self.ids.contains(id));
+        if matches {
+            self.ids.extend(header(ID_HEADERS).cloned());
+        }
+        matches
+    }
+}
+
+/// Prints the events relating to `target` until the connection is closed
+pub async fn run(
+    server: &Server,
+    target: Target,
+    filter: &EventFilter,
+    output: Output,
+) -> Result<(), Box<dyn Error>> {
+    let connection = server.connect().await?;
+    let mut events = connection.events();
+    if !server.login(&connection).await.ok_or("Connection closed")? {
+        return Err("Login failed".into());
+    }
+    let mut watcher = Watcher::new(target);
+    loop {
+        match events.recv().await {
+            Ok(Some(event)) => {
+                if watcher.matches(&event) && filter.matches(&event) {
+                    output.event(&event)
+                }
+            }
+            Err(RecvError::Lagged(n)) => warn!("Missed {} events", n),
+            Ok(None) | Err(RecvError::Closed) => {
+                return Err("Connection closed".into())
+            }
+        }
+    }
+}